@@ -3,19 +3,46 @@
 extern crate nalgebra;
 #[macro_use]
 extern crate glium;
+#[macro_use]
+extern crate log;
 extern crate mimas_server;
 
 use mimas_server::map::{MapChunkData,
-	CHUNKSIZE};
-use mimas_server::game_params::{GameParamsHdl, DrawStyle, Id};
+	CHUNKSIZE, Dir6};
+use mimas_server::game_params::{GameParamsHdl, DrawStyle, Id, Transparency};
 use mimas_server::map::MapBlock;
+use mimas_server::logging::TARGET_RENDER;
 use nalgebra::Vector3;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Number of layers in each texture atlas
+///
+/// OpenGL 3 guarantees support for at least 256 layers in an array texture.
+pub const ATLAS_LAYERS :u16 = 256;
+/// Number of texture atlases the shaders can sample from
+pub const MAX_ATLASES :u16 = 4;
 
+/// Index of a texture, made up of an atlas index and a layer inside that atlas
 #[repr(transparent)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct TextureId(pub u16);
 
-#[derive(Copy, Clone)]
+impl TextureId {
+	pub fn new(atlas :u16, layer :u16) -> Self {
+		assert!(atlas < MAX_ATLASES && layer < ATLAS_LAYERS,
+			"texture id out of range: atlas {} layer {}", atlas, layer);
+		TextureId(atlas * ATLAS_LAYERS + layer)
+	}
+	pub fn atlas(self) -> u16 {
+		self.0 / ATLAS_LAYERS
+	}
+	pub fn layer(self) -> u16 {
+		self.0 % ATLAS_LAYERS
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Vertex {
 	pub tex_ind :u16,
 	pub tex_pos :[f32; 2],
@@ -31,6 +58,23 @@ pub enum MeshDrawStyle {
 	Crossed(TextureId),
 }
 
+/// The height of crossed blocks relative to the block size
+const CROSSED_HEIGHT :f32 = 0.95;
+
+/// The bounding box of the mesh of a block with the given style
+///
+/// The box is relative to the position of the block.
+pub fn block_aabb(mds :Option<MeshDrawStyle>) -> ([f32; 3], [f32; 3]) {
+	match mds {
+		Some(MeshDrawStyle::Crossed(_)) => {
+			// Half of the extent of the crossed faces
+			let sqh = std::f32::consts::SQRT_2 * 0.25;
+			([0.5 - sqh, 0.5 - sqh, 0.0], [0.5 + sqh, 0.5 + sqh, CROSSED_HEIGHT])
+		},
+		_ => ([0.0; 3], [1.0; 3]),
+	}
+}
+
 impl MeshDrawStyle {
 	fn blocky(&self) -> Option<BlockTextureIds> {
 		if let MeshDrawStyle::Blocky(bti) = self {
@@ -68,21 +112,46 @@ impl BlockTextureIds {
 			id_sides, id_top, id_bottom,
 		}
 	}
+	/// The texture of the given face of a block facing into the given direction
+	///
+	/// The top texture is put onto the face the block faces to,
+	/// the bottom texture onto the opposite one.
+	pub fn face_texture(&self, facing :Dir6, face :Dir6) -> TextureId {
+		if face == facing {
+			self.id_top
+		} else if face == facing.opposite() {
+			self.id_bottom
+		} else {
+			self.id_sides
+		}
+	}
 }
 
 #[derive(Clone)]
 pub struct TextureIdCache {
+	/// The ids used for blocks without registered textures
 	fallback_id :BlockTextureIds,
 	block_texture_ids :Vec<Option<BlockTextureIds>>,
 	mesh_draw_styles :Vec<Option<MeshDrawStyle>>,
+	transparency :Vec<Transparency>,
+	opaque :Vec<bool>,
+	/// The blocks a missing texture has been reported for,
+	/// shared between the clones so that it's reported once
+	missing_reported :Arc<Mutex<HashSet<u8>>>,
 }
 
 impl TextureIdCache {
-	pub fn from_hdl(hdl :&GameParamsHdl,
-			mut style_to_id :impl FnMut(&DrawStyle) -> MeshDrawStyle) -> Self {
-		let fallback_id = style_to_id(&DrawStyle::Colored([0.0, 0.0, 0.0, 1.0])).blocky().unwrap();
+	pub fn from_hdl(hdl :&GameParamsHdl, missing_texture :TextureId,
+			mut style_to_id :impl FnMut(&DrawStyle, Transparency) -> MeshDrawStyle) -> Self {
+		let fallback_id = BlockTextureIds::uniform(missing_texture);
 		let mesh_draw_styles = hdl.block_params.iter()
-			.map(|p| p.draw_style.as_ref().map(&mut style_to_id))
+			.map(|p| p.draw_style.as_ref().map(|ds| style_to_id(ds, p.transparency)))
+			.collect::<Vec<_>>();
+		let transparency = hdl.block_params.iter()
+			.map(|p| p.transparency)
+			.collect::<Vec<_>>();
+		let opaque = hdl.block_params.iter()
+			.map(|p| p.opaque)
 			.collect::<Vec<_>>();
 		let block_texture_ids = mesh_draw_styles.iter()
 			.map(|v| v.and_then(|v| v.blocky()))
@@ -91,16 +160,63 @@ impl TextureIdCache {
 			fallback_id,
 			block_texture_ids,
 			mesh_draw_styles,
+			transparency,
+			opaque,
+			missing_reported : Default::default(),
 		}
 	}
 	pub fn get_bl_tex_ids(&self, bl :&MapBlock) -> Option<BlockTextureIds> {
-		self.block_texture_ids.get(bl.id() as usize)
-			.map(|v| *v)
-			.unwrap_or(Some(self.fallback_id))
+		if let Some(ids) = self.block_texture_ids.get(bl.id() as usize) {
+			*ids
+		} else {
+			self.report_missing(bl);
+			Some(self.fallback_id)
+		}
 	}
 	pub fn get_mesh_draw_style(&self, bl :&MapBlock) -> Option<MeshDrawStyle> {
-		self.mesh_draw_styles.get(bl.id() as usize)
-			.and_then(|v| *v)
+		if let Some(mds) = self.mesh_draw_styles.get(bl.id() as usize) {
+			*mds
+		} else {
+			self.report_missing(bl);
+			Some(MeshDrawStyle::Blocky(self.fallback_id))
+		}
+	}
+	/// Logs that the block has no registered texture, once per block
+	fn report_missing(&self, bl :&MapBlock) {
+		if self.missing_reported.lock().unwrap().insert(bl.id()) {
+			warn!(target : TARGET_RENDER, "No texture registered for block {}, \
+				using the missing texture", bl.id());
+		}
+	}
+	/// How the block can be seen through
+	pub fn transparency(&self, bl :&MapBlock) -> Transparency {
+		self.transparency.get(bl.id() as usize)
+			.map(|v| *v)
+			.unwrap_or(Transparency::Opaque)
+	}
+	/// Whether the block is drawn in the translucent pass, like water
+	pub fn is_translucent(&self, bl :&MapBlock) -> bool {
+		self.transparency(bl) == Transparency::Blend
+	}
+	/// Whether the block hides the faces of its neighbours
+	pub fn is_opaque(&self, bl :&MapBlock) -> bool {
+		self.opaque.get(bl.id() as usize)
+			.map(|v| *v)
+			.unwrap_or(false)
+	}
+	/// Whether a face of the block is hidden by the neighbouring block
+	///
+	/// Non-opaque blocks don't hide faces of other blocks,
+	/// but faces between two blocks of the same kind drawn as cubes
+	/// are hidden, e.g. the ones between adjacent water blocks.
+	/// Cutout blocks like leaves keep them, as they can be seen
+	/// through the gaps.
+	fn hides_face(&self, blk :&MapBlock, neighbour :&MapBlock) -> bool {
+		if self.get_bl_tex_ids(neighbour).is_none() {
+			return false;
+		}
+		self.is_opaque(neighbour) ||
+			(blk == neighbour && self.transparency(blk) != Transparency::Cutout)
 	}
 }
 
@@ -215,219 +331,841 @@ pub struct ChunkMesh {
 	pub transparent :Vec<Vertex>,
 }
 
+impl ChunkMesh {
+	pub fn empty() -> Self {
+		Self {
+			intransparent : Vec::new(),
+			transparent : Vec::new(),
+		}
+	}
+}
+
 pub fn mesh_for_chunk(offs :Vector3<isize>, chunk :&MapChunkData,
 		cache :&TextureIdCache) -> ChunkMesh {
-	let mut r = Vec::new();
+	ChunkMeshParts::new(offs, chunk, cache).mesh()
+}
 
-	struct Walker<D> {
-		last :Option<(f32, D)>,
-	}
-	impl<D :PartialEq + Copy> Walker<D> {
-		fn new() -> Self {
-			Walker {
-				last : None,
-			}
+struct Walker<D> {
+	last :Option<(f32, D)>,
+}
+
+impl<D :PartialEq + Copy> Walker<D> {
+	fn new() -> Self {
+		Walker {
+			last : None,
 		}
-		fn next<F :FnOnce(D, f32, f32)>(&mut self,
-				v :f32, item :Option<D>, emit :F) {
-			match (item, self.last) {
-				(None, Some((last_v, l_item))) => {
-					// Some mesh ends here. Emit it.
+	}
+	fn next<F :FnOnce(D, f32, f32)>(&mut self,
+			v :f32, item :Option<D>, emit :F) {
+		match (item, self.last) {
+			(None, Some((last_v, l_item))) => {
+				// Some mesh ends here. Emit it.
+				let vlen = v - last_v;
+				emit(l_item, last_v, vlen);
+				self.last = None;
+			},
+			(Some(item), Some((last_v, l_item))) => {
+				if item != l_item {
+					// Item changed. Emit the old item.
 					let vlen = v - last_v;
 					emit(l_item, last_v, vlen);
-					self.last = None;
+					self.last = Some((v, item));
+				} else {
+					// Item is the same. do nothing.
+				}
+			},
+			// Start a new thing.
+			(Some(item), None) => {
+				self.last = Some((v, item));
+			},
+			// Nothing to do if there is no item
+			// and no last item
+			(None, None) => (),
+		}
+	}
+}
+
+/// The faces of the blocks, in the order they are put into the mesh
+const MESH_FACES :[Dir6; 6] = [
+	Dir6::ZNeg, Dir6::YNeg, Dir6::XNeg,
+	Dir6::ZPos, Dir6::YPos, Dir6::XPos,
+];
+
+const LINE_COUNT :usize = (CHUNKSIZE * CHUNKSIZE) as usize;
+
+fn face_offsets(face :Dir6) -> [isize; 3] {
+	match face {
+		Dir6::XPos => [1, 0, 0],
+		Dir6::XNeg => [-1, 0, 0],
+		Dir6::YPos => [0, 1, 0],
+		Dir6::YNeg => [0, -1, 0],
+		Dir6::ZPos => [0, 0, 1],
+		Dir6::ZNeg => [0, 0, -1],
+	}
+}
+
+/// The position of the block at `cinner` in the line `(c1, c2)`
+///
+/// Faces get merged along the lines, which run along x
+/// for the faces pointing along y, and along y otherwise.
+fn line_block_pos(face :Dir6, c1 :isize, c2 :isize, cinner :isize) -> Vector3<isize> {
+	match face {
+		Dir6::YPos | Dir6::YNeg => Vector3::new(cinner, c1, c2),
+		_ => Vector3::new(c1, cinner, c2),
+	}
+}
+
+/// The index of the line of the face the block is in
+fn line_of_block(face :Dir6, pos :Vector3<isize>) -> usize {
+	let c1 = match face {
+		Dir6::YPos | Dir6::YNeg => pos.y,
+		_ => pos.x,
+	};
+	(c1 * CHUNKSIZE + pos.z) as usize
+}
+
+fn blocked(chunk :&MapChunkData,
+		[xo, yo, zo] :[isize; 3], pos :Vector3<isize>,
+		cache :&TextureIdCache) -> bool {
+	let npos = Vector3::new(pos.x + xo, pos.y + yo, pos.z + zo);
+	let outside = npos.map(|v| v < 0 || v >= CHUNKSIZE);
+	if outside.x || outside.y || outside.z {
+		return false;
+	}
+	let neighbour = chunk.get_blk(npos);
+	cache.hides_face(chunk.get_blk(pos), neighbour)
+}
+
+fn get_tex_ind(chunk: &MapChunkData, pos :Vector3<isize>,
+		offsets :[isize; 3], translucent :bool,
+		cache :&TextureIdCache) -> Option<BlockTextureIds> {
+	let blk = chunk.get_blk(pos);
+	if cache.is_translucent(blk) != translucent {
+		return None;
+	}
+	let texture_ids = cache.get_bl_tex_ids(blk);
+	if texture_ids.is_some() && blocked(chunk, offsets, pos, cache) {
+		None
+	} else {
+		texture_ids
+	}
+}
+
+/// Pushes the faces of a line of blocks, merging adjacent faces with the same texture
+fn mesh_line(out :&mut Vec<Vertex>, offs :Vector3<isize>, chunk :&MapChunkData,
+		face :Dir6, translucent :bool, line :usize, cache :&TextureIdCache) {
+	let siz = 1.0;
+	let (c1, c2) = (line as isize / CHUNKSIZE, line as isize % CHUNKSIZE);
+	let offsets = face_offsets(face);
+	let mut walker = Walker::new();
+	for cinner in 0 ..= CHUNKSIZE {
+		let rel_pos = line_block_pos(face, c1, c2, cinner);
+		let tex_ind = if cinner < CHUNKSIZE {
+			get_tex_ind(chunk, rel_pos, offsets, translucent, cache)
+				.map(|bti| bti.face_texture(chunk.get_blk_facing(rel_pos), face))
+		} else {
+			None
+		};
+		let pos = offs + rel_pos;
+		let (x, y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
+		let v = match face {
+			Dir6::YPos | Dir6::YNeg => x,
+			_ => y,
+		};
+		walker.next(v, tex_ind, |tx :TextureId, last, len| {
+			match face {
+				Dir6::ZNeg => {
+					rpush_face!(out, (x, last, z), (siz, 0.0, len, 0.0), tx.0);
 				},
-				(Some(item), Some((last_v, l_item))) => {
-					if item != l_item {
-						// Item changed. Emit the old item.
-						let vlen = v - last_v;
-						emit(l_item, last_v, vlen);
-						self.last = Some((v, item));
-					} else {
-						// Item is the same. do nothing.
-					}
+				Dir6::YNeg => {
+					rpush_face_rev!(out, (last, y, z), (len, 0.0, 0.0, siz), tx.0);
 				},
-				// Start a new thing.
-				(Some(item), None) => {
-					self.last = Some((v, item));
+				Dir6::XNeg => {
+					rpush_face!(out, (x, last, z), (0.0, len, 0.0, siz), tx.0);
+				},
+				Dir6::ZPos => {
+					rpush_face_rev!(out, (x, last, z + siz), (siz, 0.0, len, 0.0), tx.0);
+				},
+				Dir6::YPos => {
+					rpush_face!(out, (last, y + siz, z), (len, 0.0, 0.0, siz), tx.0);
+				},
+				Dir6::XPos => {
+					rpush_face_rev!(out, (x + siz, last, z), (0.0, len, 0.0, siz), tx.0);
 				},
-				// Nothing to do if there is no item
-				// and no last item
-				(None, None) => (),
 			}
+		});
+	}
+}
+
+/// Pushes the crossed blocks of a line along z
+fn mesh_crossed_line(out :&mut Vec<Vertex>, offs :Vector3<isize>, chunk :&MapChunkData,
+		line :usize, cache :&TextureIdCache) {
+	let siz = 1.0;
+	let (x, y) = (line as isize / CHUNKSIZE, line as isize % CHUNKSIZE);
+	for z in 0 .. CHUNKSIZE {
+		let rel_pos = Vector3::new(x, y, z);
+		let blk = chunk.get_blk(rel_pos);
+		let mds = cache.get_mesh_draw_style(blk);
+		let tx = if let Some(MeshDrawStyle::Crossed(id)) = mds {
+			id
+		} else {
+			continue;
+		};
+		let pos = offs + rel_pos;
+
+		let sq = siz * std::f32::consts::SQRT_2 * 0.5;
+		let sqh = sq * 0.5;
+
+		let (x, y, z) = (pos.x as f32 + 0.5 * siz, pos.y as f32 + 0.5 * siz, pos.z as f32);
+
+		let tsiz = siz * 0.5;
+		// X-Z
+		rpush_face_bidi!(out, (x - sqh, y - sqh, z), (sq, sq, 0.0, siz * CROSSED_HEIGHT), (tsiz, tsiz, 0.0, siz * CROSSED_HEIGHT), tx.0);
+		// Y-Z
+		rpush_face_bidi!(out, (x + sqh, y - sqh, z), (-sq, sq, 0.0, siz * CROSSED_HEIGHT), (tsiz, tsiz, 0.0, siz * CROSSED_HEIGHT), tx.0);
+	}
+}
+
+/// Vertices grouped into the lines of blocks they were generated from
+struct LineVertices {
+	vertices :Vec<Vertex>,
+	/// Where each line starts in `vertices`, plus the total length
+	starts :Vec<usize>,
+}
+
+impl LineVertices {
+	fn new(mut gen_line :impl FnMut(&mut Vec<Vertex>, usize)) -> Self {
+		let mut vertices = Vec::new();
+		let mut starts = Vec::with_capacity(LINE_COUNT + 1);
+		for line in 0 .. LINE_COUNT {
+			starts.push(vertices.len());
+			gen_line(&mut vertices, line);
+		}
+		starts.push(vertices.len());
+		Self {
+			vertices,
+			starts,
 		}
 	}
-	fn blocked(chunk :&MapChunkData,
-			[xo, yo, zo] :[isize; 3], pos :Vector3<isize>,
-			cache :&TextureIdCache) -> bool {
-		let pos = Vector3::new(pos.x + xo, pos.y + yo, pos.z + zo);
-		let outside = pos.map(|v| v < 0 || v >= CHUNKSIZE);
-		if outside.x || outside.y || outside.z {
-			return false;
+	fn replace_line(&mut self, line :usize, new :Vec<Vertex>) {
+		let range = self.starts[line] .. self.starts[line + 1];
+		let diff = new.len() as isize - range.len() as isize;
+		self.vertices.splice(range, new);
+		for start in self.starts[line + 1 ..].iter_mut() {
+			*start = (*start as isize + diff) as usize;
 		}
-		let blk = chunk.get_blk(pos);
-		cache.get_bl_tex_ids(blk).is_some()
-	};
-	fn get_tex_ind(chunk: &MapChunkData, pos :Vector3<isize>,
-			offsets :[isize; 3], cache :&TextureIdCache) -> Option<BlockTextureIds> {
-		let blk = chunk.get_blk(pos);
-		let texture_ids = cache.get_bl_tex_ids(blk);
-		if texture_ids.is_some() && blocked(chunk, offsets, pos, cache) {
-			None
-		} else {
-			texture_ids
+	}
+}
+
+/// The mesh of a chunk, kept in parts that can be regenerated separately
+///
+/// Faces only get merged along lines of blocks, so after a block edit
+/// only the lines through the block and its neighbours need to be
+/// regenerated. Faces at the chunk borders are never hidden by the
+/// neighbouring chunks, so the meshes of those stay valid.
+pub struct ChunkMeshParts {
+	offs :Vector3<isize>,
+	/// The lines of each face in `MESH_FACES`, first the
+	/// opaque ones, then the translucent ones
+	faces :Vec<LineVertices>,
+	crossed :LineVertices,
+}
+
+impl ChunkMeshParts {
+	pub fn new(offs :Vector3<isize>, chunk :&MapChunkData,
+			cache :&TextureIdCache) -> Self {
+		let faces = [false, true].iter()
+			.flat_map(|&translucent| MESH_FACES.iter().map(move |&face| (translucent, face)))
+			.map(|(translucent, face)| LineVertices::new(|out, line| {
+				mesh_line(out, offs, chunk, face, translucent, line, cache)
+			}))
+			.collect();
+		let crossed = LineVertices::new(|out, line| {
+			mesh_crossed_line(out, offs, chunk, line, cache)
+		});
+		Self {
+			offs,
+			faces,
+			crossed,
 		}
-	};
-	fn walk_for_all_blocks<G :FnMut(&mut Walker<TextureId>, Option<TextureId>, Vector3<isize>)>(
-			f :fn(isize, isize, isize) -> Vector3<isize>,
-			face :fn(BlockTextureIds) -> TextureId,
-			offsets :[isize; 3],
-			chunk :&MapChunkData, g :&mut G,
+	}
+	/// Regenerates the parts affected by a change of the block at `rel_pos`
+	///
+	/// The chunk has to be the previously meshed one with the change applied.
+	pub fn update_block(&mut self, chunk :&MapChunkData, rel_pos :Vector3<isize>,
 			cache :&TextureIdCache) {
-		for c1 in 0 .. CHUNKSIZE {
-			for c2 in 0 .. CHUNKSIZE {
-				let mut walker = Walker::new();
-				for cinner in 0 .. CHUNKSIZE {
-					let rel_pos = f(c1, c2, cinner);
-					let texture_ids = get_tex_ind(chunk, rel_pos, offsets, cache);
-					let tex_ind = texture_ids.map(face);
-					g(&mut walker, tex_ind, rel_pos)
+		let offs = self.offs;
+		let affected = std::iter::once([0, 0, 0])
+			.chain(MESH_FACES.iter().map(|face| face_offsets(*face)))
+			.map(|[x, y, z]| rel_pos + Vector3::new(x, y, z))
+			.filter(|p| p.iter().all(|v| (0 .. CHUNKSIZE).contains(v)))
+			.collect::<Vec<_>>();
+		for (i, lines) in self.faces.iter_mut().enumerate() {
+			let translucent = i >= MESH_FACES.len();
+			let face = MESH_FACES[i % MESH_FACES.len()];
+			let mut updated = Vec::new();
+			for p in affected.iter() {
+				let line = line_of_block(face, *p);
+				if updated.contains(&line) {
+					continue;
 				}
-				let rel_pos = f(c1, c2, CHUNKSIZE);
-				g(&mut walker, None, rel_pos)
+				updated.push(line);
+				let mut new = Vec::new();
+				mesh_line(&mut new, offs, chunk, face, translucent, line, cache);
+				lines.replace_line(line, new);
 			}
 		}
+		let line = (rel_pos.x * CHUNKSIZE + rel_pos.y) as usize;
+		let mut new = Vec::new();
+		mesh_crossed_line(&mut new, offs, chunk, line, cache);
+		self.crossed.replace_line(line, new);
 	}
-	let siz = 1.0;
+	pub fn mesh(&self) -> ChunkMesh {
+		let (opaque, translucent) = self.faces.split_at(MESH_FACES.len());
+		let mut r = Vec::new();
+		for lines in opaque {
+			r.extend_from_slice(&lines.vertices);
+		}
+		let mut rt = Vec::new();
+		for lines in translucent {
+			rt.extend_from_slice(&lines.vertices);
+		}
+		// Make translucent faces visible from behind as well,
+		// e.g. the water surface when looking at it from below.
+		push_back_faces(&mut rt);
+		rt.extend_from_slice(&self.crossed.vertices);
+		ChunkMesh {
+			intransparent : r,
+			transparent : rt,
+		}
+	}
+}
 
-	// X-Y face (unify over y)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_bottom,
-		[0, 0, -1],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.y as f32, color, |tx, last_y, ylen| {
-				let (x, _y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face!(r, (x, last_y, z), (siz, 0.0, ylen, 0.0), tx.0);
-			});
-		},
-		cache
-	);
-
-	// X-Z face (unify over x)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(cinner, c1, c2),
-		|bti| bti.id_sides,
-		[0, -1, 0],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.x as f32, color, |tx, last_x, xlen| {
-				let (_x, y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face_rev!(r, (last_x, y, z), (xlen, 0.0, 0.0, siz), tx.0);
-			});
-		},
-		cache
-	);
-
-	// Y-Z face (unify over y)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_sides,
-		[-1, 0, 0],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.y as f32, color, |tx, last_y, ylen| {
-				let (x, _y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face!(r, (x, last_y, z), (0.0, ylen, 0.0, siz), tx.0);
-			});
-		},
-		cache
-	);
-
-	// X-Y face (z+1) (unify over y)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_top,
-		[0, 0, 1],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.y as f32, color, |tx, last_y, ylen| {
-				let (x, _y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face_rev!(r, (x, last_y, z + siz), (siz, 0.0, ylen, 0.0), tx.0);
-			});
-		},
-		cache
-	);
-
-	// X-Z face (y+1) (unify over x)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(cinner, c1, c2),
-		|bti| bti.id_sides,
-		[0, 1, 0],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.x as f32, color, |tx, last_x, xlen| {
-				let (_x, y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face!(r, (last_x, y + siz, z), (xlen, 0.0, 0.0, siz), tx.0);
-			});
-		},
-		cache
-	);
-
-	// Y-Z face (x+1) (unify over y)
-	walk_for_all_blocks(
-		|c1, c2, cinner| Vector3::new(c1, cinner, c2),
-		|bti| bti.id_sides,
-		[1, 0, 0],
-		chunk,
-		&mut |walker, color, rel_pos| {
-			let pos = offs + rel_pos;
-			walker.next(pos.y as f32, color, |tx, last_y, ylen| {
-				let (x, _y, z) = (pos.x as f32, pos.y as f32, pos.z as f32);
-				rpush_face_rev!(r, (x + siz, last_y, z), (0.0, ylen, 0.0, siz), tx.0);
-			});
-		},
-		cache
-	);
+/// Maximum number of chunks whose mesh parts are kept by `MeshPartsCache`
+const MAX_CACHED_MESH_PARTS :usize = 64;
 
-	let mut rt = Vec::new();
+/// The mesh parts of the most recently meshed chunks
+///
+/// Edited chunks get remeshed partially if their parts are still present.
+#[derive(Default)]
+pub struct MeshPartsCache {
+	parts :HashMap<Vector3<isize>, (ChunkMeshParts, u64)>,
+	counter :u64,
+}
+
+impl MeshPartsCache {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// Meshes the chunk, given the position of the block edited since the last meshing
+	///
+	/// Without an edited block, or without cached parts, the chunk gets fully meshed.
+	pub fn mesh(&mut self, pos :Vector3<isize>, chunk :&MapChunkData,
+			edited :Option<Vector3<isize>>, cache :&TextureIdCache) -> ChunkMesh {
+		self.counter += 1;
+		let counter = self.counter;
+		if let (Some(rel_pos), Some((parts, last_use))) = (edited, self.parts.get_mut(&pos)) {
+			parts.update_block(chunk, rel_pos, cache);
+			*last_use = counter;
+			return parts.mesh();
+		}
+		let parts = ChunkMeshParts::new(pos, chunk, cache);
+		let mesh = parts.mesh();
+		if self.parts.len() >= MAX_CACHED_MESH_PARTS && !self.parts.contains_key(&pos) {
+			let oldest = self.parts.iter()
+				.min_by_key(|(_, (_, last_use))| *last_use)
+				.map(|(p, _)| *p)
+				.unwrap();
+			self.parts.remove(&oldest);
+		}
+		self.parts.insert(pos, (parts, counter));
+		mesh
+	}
+	/// Forgets the parts of the chunk, e.g. because it got occluded
+	pub fn remove(&mut self, pos :Vector3<isize>) {
+		self.parts.remove(&pos);
+	}
+}
+
+/// Appends the back sides of all the triangles
+fn push_back_faces(vertices :&mut Vec<Vertex>) {
+	let len = vertices.len();
+	for i in (0 .. len).step_by(3) {
+		for j in (0 .. 3).rev() {
+			let mut v = vertices[i + j];
+			v.normal = [-v.normal[0], -v.normal[1], -v.normal[2]];
+			vertices.push(v);
+		}
+	}
+}
 
-	// Crossed nodes
+/// Whether every block of the chunk hides the faces behind it
+pub fn chunk_fully_opaque(chunk :&MapChunkData, cache :&TextureIdCache) -> bool {
 	for x in 0 .. CHUNKSIZE {
 		for y in 0 .. CHUNKSIZE {
 			for z in 0 .. CHUNKSIZE {
-				let rel_pos = Vector3::new(x, y, z);
-				let blk = chunk.get_blk(rel_pos);
-				let mds = cache.get_mesh_draw_style(blk);
-				let tx = if let Some(MeshDrawStyle::Crossed(id)) = mds {
-					id
-				} else {
-					continue;
-				};
-				let pos = offs + rel_pos;
+				let blk = chunk.get_blk(Vector3::new(x, y, z));
+				if !cache.is_opaque(blk) {
+					return false;
+				}
+			}
+		}
+	}
+	true
+}
+
+const NEIGHBOUR_OFFSETS :[[isize; 3]; 6] = [
+	[-1, 0, 0], [1, 0, 0],
+	[0, -1, 0], [0, 1, 0],
+	[0, 0, -1], [0, 0, 1],
+];
+
+fn neighbours(pos :Vector3<isize>) -> impl Iterator<Item = Vector3<isize>> {
+	NEIGHBOUR_OFFSETS.iter()
+		.map(move |[x, y, z]| pos + Vector3::new(*x, *y, *z) * CHUNKSIZE)
+}
+
+/// Whether the chunk at the given position is hidden because
+/// all of its six neighbours are fully opaque
+pub fn chunk_occluded(pos :Vector3<isize>,
+		opaque :impl FnMut(Vector3<isize>) -> bool) -> bool {
+	neighbours(pos).all(opaque)
+}
+
+/// Tracks which chunks are hidden by their neighbours
+///
+/// Only the opacity of each chunk is kept, the contents of
+/// chunks that become visible again have to come from the map.
+/// The chunk the camera is in is never hidden, as its
+/// neighbours don't cover it when viewed from inside.
+#[derive(Default)]
+pub struct OcclusionTracker {
+	opaque :HashMap<Vector3<isize>, bool>,
+	occluded :HashSet<Vector3<isize>>,
+	camera_chunk :Option<Vector3<isize>>,
+}
+
+impl OcclusionTracker {
+	pub fn new() -> Self {
+		Self {
+			opaque : HashMap::new(),
+			occluded : HashSet::new(),
+			camera_chunk : None,
+		}
+	}
+	fn is_opaque(&self, pos :Vector3<isize>) -> bool {
+		self.opaque.get(&pos).copied().unwrap_or(false)
+	}
+	fn is_occluded(&self, pos :Vector3<isize>) -> bool {
+		self.camera_chunk != Some(pos) && chunk_occluded(pos, |n| self.is_opaque(n))
+	}
+	/// Sets the chunk the camera is in and returns the positions
+	/// whose occlusion state changed, like `update`
+	pub fn set_camera_chunk(&mut self, pos :Vector3<isize>) -> Vec<(Vector3<isize>, bool)> {
+		let prev = self.camera_chunk.replace(pos);
+		let mut res = Vec::new();
+		for p in prev.into_iter().chain(Some(pos)) {
+			if !self.opaque.contains_key(&p) {
+				continue;
+			}
+			let occluded = self.is_occluded(p);
+			if self.set_occluded(p, occluded) {
+				res.push((p, occluded));
+			}
+		}
+		res
+	}
+	/// Stores the opacity of the changed chunk and returns the positions
+	/// whose mesh needs to be updated, together with
+	/// whether they are occluded.
+	///
+	/// The changed chunk itself is always included. Neighbours
+	/// are only included if their occlusion state changed.
+	pub fn update(&mut self, pos :Vector3<isize>,
+			opaque :bool) -> Vec<(Vector3<isize>, bool)> {
+		let prev_opaque = self.opaque.insert(pos, opaque);
+		let mut res = Vec::new();
+		let occluded = self.is_occluded(pos);
+		self.set_occluded(pos, occluded);
+		res.push((pos, occluded));
+		if prev_opaque == Some(opaque) {
+			return res;
+		}
+		for n in neighbours(pos) {
+			if !self.opaque.contains_key(&n) {
+				continue;
+			}
+			let occluded = self.is_occluded(n);
+			if self.set_occluded(n, occluded) {
+				res.push((n, occluded));
+			}
+		}
+		res
+	}
+	/// Returns whether the occlusion state changed
+	fn set_occluded(&mut self, pos :Vector3<isize>, occluded :bool) -> bool {
+		if occluded {
+			self.occluded.insert(pos)
+		} else {
+			self.occluded.remove(&pos)
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_occlusion() {
+	let pos = Vector3::new(16, -32, 48);
+	let all_neighbours = neighbours(pos).collect::<HashSet<_>>();
+	assert_eq!(all_neighbours.len(), 6);
+	assert!(!all_neighbours.contains(&pos));
+
+	// All neighbours opaque
+	assert!(chunk_occluded(pos, |n| all_neighbours.contains(&n)));
+	// No neighbour opaque
+	assert!(!chunk_occluded(pos, |_| false));
+	// Any single neighbour not being opaque makes the chunk visible
+	for missing in all_neighbours.iter() {
+		assert!(!chunk_occluded(pos, |n| n != *missing && all_neighbours.contains(&n)));
+	}
+
+	// Invalidation through the tracker
+	let mut tracker = OcclusionTracker::new();
+	for n in all_neighbours.iter() {
+		tracker.update(*n, true);
+	}
+	assert_eq!(tracker.update(pos, false), vec![(pos, true)]);
+	// Re-sending an unchanged neighbour doesn't touch the occluded chunk
+	let n = *all_neighbours.iter().next().unwrap();
+	assert_eq!(tracker.update(n, true), vec![(n, false)]);
+	// A neighbour becoming non-opaque makes the chunk visible again
+	assert_eq!(tracker.update(n, false), vec![(n, false), (pos, false)]);
+	// And opaque again hides it
+	assert_eq!(tracker.update(n, true), vec![(n, false), (pos, true)]);
+
+	// The chunk the camera is in is never hidden
+	assert_eq!(tracker.set_camera_chunk(pos), vec![(pos, false)]);
+	assert_eq!(tracker.update(pos, false), vec![(pos, false)]);
+	assert_eq!(tracker.update(n, false), vec![(n, false)]);
+	assert_eq!(tracker.update(n, true), vec![(n, false)]);
+	// Until the camera leaves it
+	assert_eq!(tracker.set_camera_chunk(n), vec![(pos, true)]);
+	assert_eq!(tracker.set_camera_chunk(n), vec![]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_directional_block_textures() {
+	use mimas_server::game_params::NameIdMap;
+	use mimas_server::map::MetadataEntry;
+
+	// Placing a block records the facing towards the player
+	let looks = [
+		(Vector3::new(1.0, 0.2, -0.3), Dir6::XNeg),
+		(Vector3::new(-1.0, 0.2, -0.3), Dir6::XPos),
+		(Vector3::new(0.1, 0.9, 0.3), Dir6::YNeg),
+		(Vector3::new(0.1, -0.9, 0.3), Dir6::YPos),
+		(Vector3::new(0.1, 0.2, 0.9), Dir6::ZNeg),
+		(Vector3::new(0.1, 0.2, -0.9), Dir6::ZPos),
+	];
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let tree = nm.get_id("default:tree").unwrap();
+	let mut block_texture_ids = vec![None; nm.names().len()];
+	let bti = BlockTextureIds::new(TextureId(1), TextureId(2), TextureId(3));
+	block_texture_ids[tree.id() as usize] = Some(bti);
+	let cache = TextureIdCache {
+		fallback_id : BlockTextureIds::uniform(TextureId(0)),
+		mesh_draw_styles : block_texture_ids.iter()
+			.map(|v| v.map(MeshDrawStyle::Blocky))
+			.collect(),
+		transparency : vec![Transparency::Opaque; block_texture_ids.len()],
+		opaque : block_texture_ids.iter().map(|v| v.is_some()).collect(),
+		block_texture_ids,
+		missing_reported : Default::default(),
+	};
+	let pos = Vector3::new(1, 1, 1);
+	for &(look, facing) in looks.iter() {
+		assert_eq!(Dir6::placement_facing(look), facing);
+		let mut chunk = MapChunkData::filled_with(air);
+		*chunk.get_blk_mut(pos) = tree;
+		assert_eq!(chunk.get_blk_facing(pos), Dir6::ZPos);
+		chunk.get_blk_meta_entry(pos)
+			.or_insert(MetadataEntry::Facing(facing));
+		assert_eq!(chunk.get_blk_facing(pos), facing);
+
+		// The mesher puts the top texture onto the facing side,
+		// the bottom texture onto the opposite side.
+		let mesh = mesh_for_chunk(Vector3::new(0, 0, 0), &chunk, &cache);
+		assert_eq!(mesh.intransparent.len(), 6 * 6);
+		for face in mesh.intransparent.chunks(6) {
+			let p = face[0].position;
+			let axis = (0 .. 3)
+				.find(|&a| face.iter().all(|v| v.position[a] == p[a]))
+				.unwrap();
+			let positive = p[axis] == 2.0;
+			let dir = match (axis, positive) {
+				(0, true) => Dir6::XPos,
+				(0, false) => Dir6::XNeg,
+				(1, true) => Dir6::YPos,
+				(1, false) => Dir6::YNeg,
+				(_, true) => Dir6::ZPos,
+				(_, false) => Dir6::ZNeg,
+			};
+			let expected = if dir == facing {
+				2
+			} else if dir == facing.opposite() {
+				3
+			} else {
+				1
+			};
+			assert!(face.iter().all(|v| v.tex_ind == expected), "{:?} {:?}", facing, dir);
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_water_face_culling() {
+	use mimas_server::game_params::NameIdMap;
+
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let water = nm.get_id("default:water").unwrap();
+	let stone = nm.get_id("default:stone").unwrap();
+	let leaves = nm.get_id("default:leaves").unwrap();
+	let mut block_texture_ids = vec![None; nm.names().len()];
+	block_texture_ids[water.id() as usize] = Some(BlockTextureIds::uniform(TextureId(1)));
+	block_texture_ids[stone.id() as usize] = Some(BlockTextureIds::uniform(TextureId(2)));
+	block_texture_ids[leaves.id() as usize] = Some(BlockTextureIds::uniform(TextureId(3)));
+	let mut transparency = vec![Transparency::Opaque; nm.names().len()];
+	transparency[water.id() as usize] = Transparency::Blend;
+	transparency[leaves.id() as usize] = Transparency::Cutout;
+	let mut opaque = vec![false; nm.names().len()];
+	opaque[stone.id() as usize] = true;
+	let cache = TextureIdCache {
+		fallback_id : BlockTextureIds::uniform(TextureId(0)),
+		mesh_draw_styles : block_texture_ids.iter()
+			.map(|v| v.map(MeshDrawStyle::Blocky))
+			.collect(),
+		transparency,
+		opaque,
+		block_texture_ids,
+		missing_reported : Default::default(),
+	};
 
-				let sq = siz * std::f32::consts::SQRT_2 * 0.5;
-				let sqh = sq * 0.5;
+	assert!(cache.hides_face(&water, &water));
+	assert!(cache.hides_face(&water, &stone));
+	assert!(!cache.hides_face(&stone, &water));
+	assert!(!cache.hides_face(&water, &air));
+	// Leaves can be seen through without being translucent,
+	// so faces between them are kept
+	assert!(!cache.hides_face(&leaves, &leaves));
+	assert!(!cache.hides_face(&stone, &leaves));
+	assert!(!cache.is_translucent(&leaves));
+	assert!(!chunk_fully_opaque(&MapChunkData::filled_with(leaves), &cache));
+	assert!(!chunk_fully_opaque(&MapChunkData::filled_with(water), &cache));
+	assert!(chunk_fully_opaque(&MapChunkData::filled_with(stone), &cache));
 
-				let (x, y, z) = (pos.x as f32 + 0.5 * siz, pos.y as f32 + 0.5 * siz, pos.z as f32);
+	// Two water blocks next to each other, followed by a stone block
+	let mut chunk = MapChunkData::filled_with(air);
+	*chunk.get_blk_mut(Vector3::new(1, 1, 1)) = water;
+	*chunk.get_blk_mut(Vector3::new(2, 1, 1)) = water;
+	*chunk.get_blk_mut(Vector3::new(3, 1, 1)) = stone;
+	let mesh = mesh_for_chunk(Vector3::new(0, 0, 0), &chunk, &cache);
+	let in_x_plane = |tri :&[Vertex], x :f32| tri.iter().all(|v| v.position[0] == x);
 
-				let tsiz = siz * 0.5;
-				// X-Z
-				rpush_face_bidi!(rt, (x - sqh, y - sqh, z), (sq, sq, 0.0, siz * 0.95), (tsiz, tsiz, 0.0, siz * 0.95), tx.0);
-				// Y-Z
-				rpush_face_bidi!(rt, (x + sqh, y - sqh, z), (-sq, sq, 0.0, siz * 0.95), (tsiz, tsiz, 0.0, siz * 0.95), tx.0);
+	// No faces between the water blocks, nor from the water towards the stone
+	assert!(mesh.transparent.iter().all(|v| v.tex_ind == 1));
+	assert!(!mesh.transparent.chunks(3).any(|t| in_x_plane(t, 2.0) || in_x_plane(t, 3.0)));
+	// Seven water faces remain, each one with a back side
+	assert_eq!(mesh.transparent.len(), 7 * 6 * 2);
+	// The stone is not hidden by the water
+	assert_eq!(mesh.intransparent.len(), 6 * 6);
+	assert!(mesh.intransparent.iter().all(|v| v.tex_ind == 2));
+	assert!(mesh.intransparent.chunks(3).any(|t| in_x_plane(t, 3.0)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_cutout_face_culling() {
+	use mimas_server::game_params::NameIdMap;
+
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let stone = nm.get_id("default:stone").unwrap();
+	let leaves = nm.get_id("default:leaves").unwrap();
+	let mut block_texture_ids = vec![None; nm.names().len()];
+	block_texture_ids[stone.id() as usize] = Some(BlockTextureIds::uniform(TextureId(1)));
+	block_texture_ids[leaves.id() as usize] = Some(BlockTextureIds::uniform(TextureId(2)));
+	let mut transparency = vec![Transparency::Opaque; nm.names().len()];
+	transparency[leaves.id() as usize] = Transparency::Cutout;
+	let mut opaque = vec![false; nm.names().len()];
+	opaque[stone.id() as usize] = true;
+	let cache = TextureIdCache {
+		fallback_id : BlockTextureIds::uniform(TextureId(0)),
+		mesh_draw_styles : block_texture_ids.iter()
+			.map(|v| v.map(MeshDrawStyle::Blocky))
+			.collect(),
+		transparency,
+		opaque,
+		block_texture_ids,
+		missing_reported : Default::default(),
+	};
+	assert!(!cache.hides_face(&leaves, &leaves));
+	assert!(cache.hides_face(&stone, &stone));
+
+	let in_x_plane = |tri :&[Vertex], x :f32| tri.iter().all(|v| v.position[0] == x);
+	let shared_faces = |blk :MapBlock| {
+		let mut chunk = MapChunkData::filled_with(air);
+		*chunk.get_blk_mut(Vector3::new(1, 1, 1)) = blk;
+		*chunk.get_blk_mut(Vector3::new(2, 1, 1)) = blk;
+		let mesh = mesh_for_chunk(Vector3::new(0, 0, 0), &chunk, &cache);
+		// Cutout blocks are drawn in the opaque pass
+		assert!(mesh.transparent.is_empty());
+		mesh.intransparent.chunks(6)
+			.filter(|face| in_x_plane(face, 2.0))
+			.count()
+	};
+	// The faces of both leaves between them are kept
+	assert_eq!(shared_faces(leaves), 2);
+	// Between two stone blocks, they are culled
+	assert_eq!(shared_faces(stone), 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_partial_remesh() {
+	use mimas_server::game_params::NameIdMap;
+
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let water = nm.get_id("default:water").unwrap();
+	let stone = nm.get_id("default:stone").unwrap();
+	let tree = nm.get_id("default:tree").unwrap();
+	// Any block can be drawn crossed, like a flower
+	let flower = nm.get_id("default:leaves").unwrap();
+	let mut mesh_draw_styles = vec![None; nm.names().len()];
+	mesh_draw_styles[water.id() as usize] = Some(MeshDrawStyle::Blocky(BlockTextureIds::uniform(TextureId(1))));
+	mesh_draw_styles[stone.id() as usize] = Some(MeshDrawStyle::Blocky(BlockTextureIds::uniform(TextureId(2))));
+	let tree_ids = BlockTextureIds::new(TextureId(3), TextureId(4), TextureId(5));
+	mesh_draw_styles[tree.id() as usize] = Some(MeshDrawStyle::Blocky(tree_ids));
+	mesh_draw_styles[flower.id() as usize] = Some(MeshDrawStyle::Crossed(TextureId(6)));
+	let mut transparency = vec![Transparency::Opaque; nm.names().len()];
+	transparency[water.id() as usize] = Transparency::Blend;
+	let cache = TextureIdCache {
+		fallback_id : BlockTextureIds::uniform(TextureId(0)),
+		block_texture_ids : mesh_draw_styles.iter()
+			.map(|v| v.and_then(|v| v.blocky()))
+			.collect(),
+		opaque : mesh_draw_styles.iter().zip(transparency.iter())
+			.map(|(v, t)| v.and_then(|v| v.blocky()).is_some() && *t == Transparency::Opaque)
+			.collect(),
+		mesh_draw_styles,
+		transparency,
+		missing_reported : Default::default(),
+	};
+
+	// A chunk with stone and water at the bottom, and some trees and flowers
+	let offs = Vector3::new(32, -16, 48);
+	let mut chunk = MapChunkData::filled_with(air);
+	for x in 0 .. CHUNKSIZE {
+		for y in 0 .. CHUNKSIZE {
+			for z in 0 .. 6 {
+				let b = if z > 3 && x > 8 { water } else { stone };
+				*chunk.get_blk_mut(Vector3::new(x, y, z)) = b;
 			}
 		}
 	}
+	for &(x, y) in [(2, 3), (7, 7), (CHUNKSIZE - 1, 0)].iter() {
+		*chunk.get_blk_mut(Vector3::new(x, y, 6)) = tree;
+		*chunk.get_blk_mut(Vector3::new(x + 1 - x / 8, y + 1, 6)) = flower;
+	}
+	let mut parts = ChunkMeshParts::new(offs, &chunk, &cache);
+	let edits = [
+		// Digging into the stone, also at the chunk borders
+		(Vector3::new(4, 4, 5), air),
+		(Vector3::new(0, 0, 0), air),
+		(Vector3::new(CHUNKSIZE - 1, CHUNKSIZE - 1, 5), air),
+		// Splitting a merged line of water faces
+		(Vector3::new(12, 8, 5), stone),
+		(Vector3::new(12, 8, 5), air),
+		// Placing and removing crossed blocks
+		(Vector3::new(4, 4, 4), flower),
+		(Vector3::new(2, 3, 6), flower),
+		(Vector3::new(7, 7, 6), air),
+		(Vector3::new(5, 10, 9), tree),
+	];
+	for (pos, b) in edits.iter() {
+		*chunk.get_blk_mut(*pos) = *b;
+		parts.update_block(&chunk, *pos, &cache);
+		let partial = parts.mesh();
+		let full = mesh_for_chunk(offs, &chunk, &cache);
+		assert_eq!(partial.intransparent, full.intransparent, "{:?}", pos);
+		assert_eq!(partial.transparent, full.transparent, "{:?}", pos);
+	}
 
-	ChunkMesh {
-		intransparent : r,
-		transparent : rt,
+	// The cache remeshes partially only if given the edited block
+	let mut parts_cache = MeshPartsCache::new();
+	parts_cache.mesh(offs, &chunk, None, &cache);
+	let pos = Vector3::new(3, 3, 6);
+	*chunk.get_blk_mut(pos) = stone;
+	let mesh = parts_cache.mesh(offs, &chunk, Some(pos), &cache);
+	assert_eq!(mesh.intransparent, mesh_for_chunk(offs, &chunk, &cache).intransparent);
+}
+
+#[cfg(test)]
+#[test]
+fn test_missing_texture_fallback() {
+	use mimas_server::game_params::NameIdMap;
+	use log::{Log, Record, Metadata, Level, LevelFilter};
+
+	struct CapturingLogger {
+		captured :Mutex<Vec<String>>,
 	}
+	impl Log for CapturingLogger {
+		fn enabled(&self, metadata :&Metadata) -> bool {
+			metadata.target() == TARGET_RENDER && metadata.level() <= Level::Warn
+		}
+		fn log(&self, record :&Record) {
+			if self.enabled(record.metadata()) {
+				self.captured.lock().unwrap().push(format!("{}", record.args()));
+			}
+		}
+		fn flush(&self) {}
+	}
+	let logger :&'static CapturingLogger = Box::leak(Box::new(CapturingLogger {
+		captured : Mutex::new(Vec::new()),
+	}));
+	log::set_logger(logger).unwrap();
+	log::set_max_level(LevelFilter::Warn);
+
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let tree = nm.get_id("default:tree").unwrap();
+	// Only the blocks before the tree have textures registered
+	let registered = tree.id() as usize;
+	let mut block_texture_ids = vec![None; registered];
+	block_texture_ids[stone.id() as usize] = Some(BlockTextureIds::uniform(TextureId(2)));
+	let cache = TextureIdCache {
+		fallback_id : BlockTextureIds::uniform(TextureId(7)),
+		mesh_draw_styles : block_texture_ids.iter()
+			.map(|v| v.map(MeshDrawStyle::Blocky))
+			.collect(),
+		transparency : vec![Transparency::Opaque; registered],
+		opaque : vec![true; registered],
+		block_texture_ids,
+		missing_reported : Default::default(),
+	};
+	assert!(cache.get_bl_tex_ids(&stone).unwrap().id_sides == TextureId(2));
+	assert!(logger.captured.lock().unwrap().is_empty());
+
+	let ids = cache.get_bl_tex_ids(&tree).unwrap();
+	assert!(ids.id_sides == TextureId(7) && ids.id_top == TextureId(7));
+	let blocky = cache.get_mesh_draw_style(&tree).and_then(|mds| mds.blocky());
+	assert!(blocky.unwrap().id_bottom == TextureId(7));
+	// Clones share the record of reported blocks
+	assert!(cache.clone().get_bl_tex_ids(&tree).is_some());
+	assert_eq!(*logger.captured.lock().unwrap(), vec![format!("No texture registered \
+		for block {}, using the missing texture", tree.id())]);
 }
@@ -0,0 +1,108 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::logging::TARGET_SERVER;
+
+/// A transcript of the chat, appended to a file
+pub struct ChatLog {
+	file :Option<File>,
+}
+
+impl ChatLog {
+	/// A chat log that doesn't write anything
+	pub fn disabled() -> Self {
+		Self {
+			file : None,
+		}
+	}
+	/// Opens the file at the path for appending
+	///
+	/// If the file can't be opened, a warning is
+	/// printed and the chat isn't logged.
+	pub fn open(path :impl AsRef<Path>) -> Self {
+		let path = path.as_ref();
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path);
+		match file {
+			Ok(file) => Self {
+				file : Some(file),
+			},
+			Err(e) => {
+				warn!(target : TARGET_SERVER, "Couldn't open the chat log {}: {}", path.display(), e);
+				Self::disabled()
+			},
+		}
+	}
+	pub fn log(&mut self, msg :&str) {
+		self.log_at(SystemTime::now(), msg);
+	}
+	fn log_at(&mut self, time :SystemTime, msg :&str) {
+		let file = if let Some(file) = &mut self.file {
+			file
+		} else {
+			return;
+		};
+		let secs = time.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		if let Err(e) = writeln!(file, "[{}] {}", format_utc(secs), msg) {
+			warn!(target : TARGET_SERVER, "Couldn't write to the chat log: {}", e);
+		}
+	}
+}
+
+/// Formats the unix time as UTC date and time
+fn format_utc(secs :u64) -> String {
+	let days = (secs / 86400) as i64;
+	let secs_of_day = secs % 86400;
+	// Converts days to the civil date, after
+	// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day,
+		secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_utc() {
+	assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+	assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+	assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20");
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_log() {
+	use std::time::Duration;
+
+	let path = std::env::temp_dir()
+		.join(format!("mimas-test-{}-chat.log", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+	let mut log = ChatLog::open(&path);
+	log.log_at(time, "<alice> hello");
+	log.log_at(time + Duration::from_secs(61), "<bob> hi alice");
+	// Reopening appends
+	let mut log = ChatLog::open(&path);
+	log.log_at(time + Duration::from_secs(3600), "<alice> /spawn");
+	let contents = std::fs::read_to_string(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(contents, "[2023-11-14 22:13:20] <alice> hello\n\
+		[2023-11-14 22:14:21] <bob> hi alice\n\
+		[2023-11-14 23:13:20] <alice> /spawn\n");
+
+	// Failing to open the file doesn't prevent chatting
+	let mut log = ChatLog::open(std::env::temp_dir().join("nonexistent-dir/chat.log"));
+	log.log("<alice> still works");
+}
@@ -0,0 +1,159 @@
+use toml::from_str;
+use std::str;
+use super::StrErr;
+
+/// Number of hours in a day
+pub const HOURS_PER_DAY :f32 = 24.0;
+/// Length of a full day in real time seconds
+const DAY_LENGTH_SECS :f32 = 1200.0;
+/// The time of day `/time set day` sets the clock to
+pub const DAY_TIME :f32 = 8.0;
+/// The time of day `/time set night` sets the clock to
+pub const NIGHT_TIME :f32 = 20.0;
+/// How much of the light is taken away at midnight
+const MAX_DARKNESS :f32 = 0.75;
+
+/// The day/night clock of the world
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WorldClock {
+	/// Time of day in hours since midnight
+	time :f32,
+}
+
+impl Default for WorldClock {
+	fn default() -> Self {
+		Self {
+			time : DAY_TIME,
+		}
+	}
+}
+
+impl WorldClock {
+	/// The time of day in hours since midnight
+	pub fn time(&self) -> f32 {
+		self.time
+	}
+	/// Sets the time of day, in hours since midnight
+	pub fn set_time(&mut self, time :f32) -> Result<(), StrErr> {
+		if !(0.0 .. HOURS_PER_DAY).contains(&time) {
+			Err(format!("Time {} is out of range, must be in [0, {})",
+				time, HOURS_PER_DAY))?;
+		}
+		self.time = time;
+		Ok(())
+	}
+	/// Advance the clock according to the real time passed
+	pub fn tick(&mut self, dt :f32) {
+		let hours = dt / DAY_LENGTH_SECS * HOURS_PER_DAY;
+		self.time = (self.time + hours).rem_euclid(HOURS_PER_DAY);
+	}
+	/// How much darker than at noon the world is
+	///
+	/// Zero during the day, `MAX_DARKNESS` in the night,
+	/// with a transition at dawn and dusk.
+	pub fn darkness(&self) -> f32 {
+		let angle = self.time / HOURS_PER_DAY * 2.0 * std::f32::consts::PI;
+		// Height of the sun, 1.0 at noon and -1.0 at midnight
+		let sun = -angle.cos();
+		let light = (sun * 3.0 + 0.5).clamp(0.0, 1.0);
+		(1.0 - light) * MAX_DARKNESS
+	}
+	/// How far it is into the night, zero during the day and one in the night
+	pub fn night_fraction(&self) -> f32 {
+		self.darkness() / MAX_DARKNESS
+	}
+	pub fn display(&self) -> String {
+		let minutes = (self.time * 60.0) as u32;
+		format!("{:02}:{:02}", minutes / 60, minutes % 60)
+	}
+	pub fn serialize(&self) -> Result<Vec<u8>, StrErr> {
+		Ok(toml::to_string(self)?.into())
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		let serialized_str = str::from_utf8(buf)?;
+		let deserialized :Self = from_str(serialized_str)?;
+		let mut res = Self::default();
+		res.set_time(deserialized.time)?;
+		Ok(res)
+	}
+}
+
+/// A parsed `/time` command
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeCommand {
+	Query,
+	Set(f32),
+}
+
+impl TimeCommand {
+	/// Parses the parameters of the `/time` command
+	pub fn parse(params :&[&str]) -> Result<Self, String> {
+		const USAGE :&str = "Usage: /time query or /time set <day|night|hours>";
+		match params {
+			["query"] => Ok(TimeCommand::Query),
+			["set", "day"] => Ok(TimeCommand::Set(DAY_TIME)),
+			["set", "night"] => Ok(TimeCommand::Set(NIGHT_TIME)),
+			["set", value] => {
+				let time = value.parse::<f32>()
+					.map_err(|_| format!("Invalid time {}. {}", value, USAGE))?;
+				if !(0.0 .. HOURS_PER_DAY).contains(&time) {
+					return Err(format!("Time {} is out of range, must be in [0, {})",
+						time, HOURS_PER_DAY));
+				}
+				Ok(TimeCommand::Set(time))
+			},
+			_ => Err(USAGE.to_owned()),
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_time_command_parsing() {
+	assert_eq!(TimeCommand::parse(&["query"]), Ok(TimeCommand::Query));
+	assert_eq!(TimeCommand::parse(&["set", "day"]), Ok(TimeCommand::Set(DAY_TIME)));
+	assert_eq!(TimeCommand::parse(&["set", "night"]), Ok(TimeCommand::Set(NIGHT_TIME)));
+	assert_eq!(TimeCommand::parse(&["set", "0"]), Ok(TimeCommand::Set(0.0)));
+	assert_eq!(TimeCommand::parse(&["set", "13.5"]), Ok(TimeCommand::Set(13.5)));
+
+	// Out of range and invalid values are rejected
+	for invalid in &[
+		&["set", "24"][..],
+		&["set", "-1"],
+		&["set", "NaN"],
+		&["set", "noon"],
+		&["set"],
+		&["query", "now"],
+		&[],
+		&["rewind"],
+	] {
+		assert!(TimeCommand::parse(invalid).is_err(), "{:?}", invalid);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_world_clock() {
+	let mut clock = WorldClock::default();
+	assert_eq!(clock.darkness(), 0.0);
+	assert!(clock.set_time(24.0).is_err());
+	assert!(clock.set_time(-0.5).is_err());
+	assert_eq!(clock.time(), DAY_TIME);
+
+	clock.set_time(0.0).unwrap();
+	assert_eq!(clock.darkness(), MAX_DARKNESS);
+	assert_eq!(clock.night_fraction(), 1.0);
+	clock.set_time(12.0).unwrap();
+	assert_eq!(clock.darkness(), 0.0);
+	assert_eq!(clock.night_fraction(), 0.0);
+	assert_eq!(clock.display(), "12:00");
+
+	// A full day passes in DAY_LENGTH_SECS
+	clock.tick(DAY_LENGTH_SECS / 2.0);
+	assert!(clock.time() < 0.01 || clock.time() > HOURS_PER_DAY - 0.01);
+	clock.tick(DAY_LENGTH_SECS / 4.0);
+	assert!((clock.time() - 6.0).abs() < 0.01);
+
+	let ser = clock.serialize().unwrap();
+	assert_eq!(WorldClock::deserialize(&ser).unwrap(), clock);
+}
@@ -0,0 +1,283 @@
+use nalgebra::Vector3;
+
+fn fmin(a :f32, b :f32) -> f32 {
+	if a < b {
+		a
+	} else {
+		b
+	}
+}
+
+fn fmax(a :f32, b :f32) -> f32 {
+	if a > b {
+		a
+	} else {
+		b
+	}
+}
+
+fn overlap(a_min :f32, a_max :f32, b_min :f32, b_max :f32) -> Option<f32> {
+	if a_min < b_max && b_min < a_max {
+		Some(fmin(a_max, b_max) - fmax(a_min, b_min))
+	} else {
+		None
+	}
+}
+
+fn overlap_fn<T, F :Fn(T) -> f32>(f :F, a_min :T, a_max :T, b_min :T, b_max :T) -> Option<f32> {
+	overlap(f(a_min), f(a_max), f(b_min), f(b_max))
+}
+
+/// Returns whether the player collides with the box at position pos
+/// and if yes, returns the normal.
+pub fn collide(player_pos :Vector3<f32>, pos :Vector3<isize>) -> Option<Vector3<f32>> {
+	let player_colb_extent = Vector3::new(0.35, 0.35, 0.9);
+	collide_extent(player_pos, player_colb_extent, pos)
+}
+
+/// Returns whether a box with the given half extent centered
+/// at player_pos collides with the box at position pos
+/// and if yes, returns the normal.
+pub fn collide_extent(player_pos :Vector3<f32>, player_colb_extent :Vector3<f32>,
+		pos :Vector3<isize>) -> Option<Vector3<f32>> {
+	let pos = pos.map(|v| v as f32);
+	let pmin = player_pos - player_colb_extent;
+	let pmax = player_pos + player_colb_extent;
+	let cube_extent = Vector3::new(0.5, 0.5, 0.5);
+	let cmin = pos - cube_extent;
+	let cmax = pos + cube_extent;
+
+	let overlaps = (
+		overlap_fn(|p| p.x, pmin, pmax, cmin, cmax),
+		overlap_fn(|p| p.y, pmin, pmax, cmin, cmax),
+		overlap_fn(|p| p.z, pmin, pmax, cmin, cmax),
+	);
+	if let (Some(ox), Some(oy), Some(oz)) = overlaps {
+		let normal;
+		fn f(a :f32, b :f32) -> f32 {
+			if a >= b {
+				1.0
+			} else {
+				-1.0
+			}
+		}
+		const LIMIT :f32 = 0.04;
+		let num_smaller = [ox, oy, oz].iter().filter(|v| **v <= LIMIT).count();
+		let xnormal = Vector3::new(f(player_pos.x, pos.x), 0.0, 0.0);
+		let ynormal = Vector3::new(0.0, f(player_pos.y, pos.y), 0.0);
+		let znormal = Vector3::new(0.0, 0.0, f(player_pos.z, pos.z));
+		fn c(a :&[Vector3<f32>]) -> Vector3<f32> {
+			a.iter().fold(nalgebra::zero(), |v :Vector3<f32>, w| v + w).normalize()
+		}
+		if num_smaller <= 1 {
+			if ox < oy && ox < oz {
+				normal = xnormal;
+			} else if oy < oz {
+				normal = ynormal;
+			} else {
+				normal = znormal;
+			}
+		} else if num_smaller == 2 {
+			if ox > oy && ox > oz {
+				normal = c(&[ynormal, znormal]);
+			} else if oy < oz {
+				normal = c(&[xnormal, znormal]);
+			} else {
+				normal = c(&[xnormal, ynormal]);
+			}
+		} else {
+			debug_assert_eq!(num_smaller, 3);
+			normal = c(&[xnormal, ynormal, znormal]);
+		}
+		Some(normal)
+	} else {
+		None
+	}
+}
+
+/// Returns how far the player needs to be raised to step onto a ledge
+///
+/// The player at player_pos (as passed to `collide`) wants to move
+/// horizontally by delta_pos. Stepping up is possible if the player stands
+/// on the ground and is blocked by a ledge one block high with enough
+/// space above it. All solid blocks are full cubes, so ledges are always
+/// exactly one block high.
+pub fn step_up_height(player_pos :Vector3<f32>, delta_pos :Vector3<f32>,
+		cubes :&[Vector3<isize>]) -> Option<f32> {
+	const STEP_HEIGHT :f32 = 1.0;
+	// Offset to not count touching the ground as collision
+	const EPSILON :f32 = 0.05;
+	let collides = |p :Vector3<f32>| {
+		cubes.iter().any(|c| collide(p, *c).is_some())
+	};
+	let grounded = collides(player_pos - Vector3::new(0.0, 0.0, EPSILON));
+	let moved = player_pos + Vector3::new(delta_pos.x, delta_pos.y, EPSILON);
+	if !grounded || !collides(moved) {
+		return None;
+	}
+	let raised = moved + Vector3::new(0.0, 0.0, STEP_HEIGHT);
+	if collides(raised) {
+		return None;
+	}
+	Some(STEP_HEIGHT)
+}
+
+/// Blocks the player at player_pos (as passed to `collide`) overlaps with
+fn overlapped_blocks(player_pos :Vector3<f32>) -> Vec<Vector3<isize>> {
+	let player_colb_extent = Vector3::new(0.35, 0.35, 0.9);
+	let lo = (player_pos - player_colb_extent).map(|v| (v - 0.5).floor() as isize);
+	let hi = (player_pos + player_colb_extent).map(|v| (v + 0.5).ceil() as isize);
+	let mut res = Vec::new();
+	for x in lo.x ..= hi.x {
+		for y in lo.y ..= hi.y {
+			for z in lo.z ..= hi.z {
+				let p = Vector3::new(x, y, z);
+				if collide(player_pos, p).is_some() {
+					res.push(p);
+				}
+			}
+		}
+	}
+	res
+}
+
+/// Whether every block the player overlaps with is solid
+pub fn embedded(player_pos :Vector3<f32>, is_solid :impl Fn(Vector3<isize>) -> bool) -> bool {
+	overlapped_blocks(player_pos).into_iter().all(is_solid)
+}
+
+/// How far up to search for air when the player is stuck
+const UNSTUCK_SEARCH_HEIGHT :isize = 64;
+
+/// Number of consecutive collision steps the player has to
+/// be embedded in solid blocks before being moved out
+pub const STUCK_STEPS :u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unstuck {
+	/// Move the player to the given position
+	Teleport(Vector3<f32>),
+	/// There is no free space above, move the player to the spawn
+	ToSpawn,
+}
+
+/// Detects players that are stuck inside solid blocks
+#[derive(Default)]
+pub struct StuckDetector {
+	steps :u32,
+}
+
+impl StuckDetector {
+	/// Call once per collision step with the position as passed to `collide`
+	///
+	/// `is_solid` returns `None` for blocks that aren't loaded yet.
+	/// Nobody is considered stuck while they are next to such blocks,
+	/// e.g. right after joining.
+	pub fn check(&mut self, player_pos :Vector3<f32>,
+			is_solid :impl Fn(Vector3<isize>) -> Option<bool>) -> Option<Unstuck> {
+		let overlapped = overlapped_blocks(player_pos).into_iter()
+			.map(&is_solid)
+			.collect::<Option<Vec<bool>>>();
+		if overlapped.map(|o| !o.iter().all(|s| *s)).unwrap_or(true) {
+			self.steps = 0;
+			return None;
+		}
+		self.steps += 1;
+		if self.steps < STUCK_STEPS {
+			return None;
+		}
+		self.steps = 0;
+		for h in 1 ..= UNSTUCK_SEARCH_HEIGHT {
+			let p = player_pos + Vector3::new(0.0, 0.0, h as f32);
+			let overlapped = overlapped_blocks(p).into_iter()
+				.map(&is_solid)
+				.collect::<Option<Vec<bool>>>();
+			match overlapped {
+				// Wait for the blocks above to arrive
+				None => return None,
+				Some(o) if !o.contains(&true) => return Some(Unstuck::Teleport(p)),
+				Some(_) => (),
+			}
+		}
+		Some(Unstuck::ToSpawn)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_stuck_detector() {
+	// Solid below z = 10, air above
+	let is_solid = |p :Vector3<isize>| p.z < 10;
+	let player_pos = Vector3::new(0.2, 0.0, 5.3);
+	assert!(embedded(player_pos, is_solid));
+	let loaded = |p| Some(is_solid(p));
+
+	let mut detector = StuckDetector::default();
+	for _ in 1 .. STUCK_STEPS {
+		assert_eq!(detector.check(player_pos, loaded), None);
+	}
+	let pos = match detector.check(player_pos, loaded) {
+		Some(Unstuck::Teleport(pos)) => pos,
+		r => panic!("Expected a teleport, got {:?}", r),
+	};
+	assert_eq!((pos.x, pos.y), (player_pos.x, player_pos.y));
+	assert!(!overlapped_blocks(pos).into_iter().any(is_solid));
+	// It is the lowest free position
+	assert!(overlapped_blocks(pos - Vector3::new(0.0, 0.0, 1.0)).into_iter().any(is_solid));
+
+	// Being free resets the count
+	let mut detector = StuckDetector::default();
+	for _ in 1 .. STUCK_STEPS {
+		assert_eq!(detector.check(player_pos, loaded), None);
+	}
+	assert_eq!(detector.check(Vector3::new(0.2, 0.0, 20.0), loaded), None);
+	assert_eq!(detector.check(player_pos, loaded), None);
+
+	// Partially overlapping solid blocks isn't being stuck
+	assert!(!embedded(Vector3::new(0.2, 0.0, 9.8), is_solid));
+
+	// Without any air above, the player is sent to the spawn
+	let mut detector = StuckDetector::default();
+	let all_solid = |_ :Vector3<isize>| Some(true);
+	let res = (0 .. STUCK_STEPS)
+		.filter_map(|_| detector.check(player_pos, all_solid))
+		.collect::<Vec<_>>();
+	assert_eq!(res, vec![Unstuck::ToSpawn]);
+
+	// Blocks that aren't loaded yet don't count as solid,
+	// so nobody gets sent to the spawn while chunks are missing
+	let mut detector = StuckDetector::default();
+	let unloaded = |_ :Vector3<isize>| None;
+	assert!((0 .. 2 * STUCK_STEPS).all(|_| detector.check(player_pos, unloaded).is_none()));
+	let partly_loaded = |p :Vector3<isize>| if p.z < 6 { Some(true) } else { None };
+	assert!((0 .. 2 * STUCK_STEPS).all(|_| detector.check(player_pos, partly_loaded).is_none()));
+	// Nor while the blocks above are missing
+	let unloaded_above = |p :Vector3<isize>| if p.z < 10 { Some(true) } else { None };
+	assert!((0 .. 2 * STUCK_STEPS).all(|_| detector.check(player_pos, unloaded_above).is_none()));
+}
+
+#[cfg(test)]
+#[test]
+fn test_step_up_height() {
+	let mut cubes = Vec::new();
+	for x in -3 ..= 3 {
+		for y in -3 ..= 3 {
+			cubes.push(Vector3::new(x, y, 0));
+		}
+	}
+	// The ledge, one block high
+	cubes.push(Vector3::new(1, 0, 1));
+	// The player stands on the ground, right in front of the ledge
+	let player_pos = Vector3::new(0.15, 0.0, 1.4);
+	let towards = Vector3::new(0.1, 0.0, 0.0);
+	let away = Vector3::new(-0.1, 0.0, 0.0);
+	assert_eq!(step_up_height(player_pos, towards, &cubes), Some(1.0));
+	assert_eq!(step_up_height(player_pos, away, &cubes), None);
+	// No stepping up while in the air
+	let in_air = player_pos + Vector3::new(0.0, 0.0, 0.5);
+	assert_eq!(step_up_height(in_air, towards, &cubes), None);
+
+	// A wall two blocks high can't be stepped onto
+	cubes.push(Vector3::new(1, 0, 2));
+	assert_eq!(step_up_height(player_pos, towards, &cubes), None);
+}
@@ -1,23 +1,105 @@
-use std::fs::read_to_string;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::fs::{self, read_to_string};
+use std::path::{Path, PathBuf};
 use toml::from_str;
+use directories::ProjectDirs;
+use serde::{Deserialize, Deserializer};
 use super::StrErr;
+use crate::map_storage::CompressionKind;
+use crate::sqlite_generic::SqliteSynchronous;
+use crate::mapgen::{MapgenParams, MapgenMode, seed_from_str};
+use crate::difficulty::Difficulty;
+use crate::game_mode::GameMode;
+use crate::inventory::HUD_SLOT_COUNT;
+use crate::MAX_SPAWN_PROTECTION_RADIUS;
+use crate::fragment::DEFAULT_FRAGMENT_SIZE;
+use crate::logging::TARGET_CONFIG;
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
 	// Server settings
 
-	#[serde(default = "mapgen_seed_default")]
+	#[serde(default = "mapgen_seed_default", deserialize_with = "deserialize_seed")]
 	pub mapgen_seed :u64,
 	#[serde(default = "mapgen_radius_xy_default")]
 	pub mapgen_radius_xy :isize,
 	#[serde(default = "mapgen_radius_z_default")]
 	pub mapgen_radius_z :isize,
+	#[serde(default = "mapgen_min_padding_default")]
+	pub mapgen_min_padding :isize,
 	#[serde(default = "sent_chunks_radius_xy_default")]
 	pub sent_chunks_radius_xy :isize,
 	#[serde(default = "sent_chunks_radius_z_default")]
 	pub sent_chunks_radius_z :isize,
 	#[serde(default)]
 	pub map_storage_path :Option<String>,
+	/// Radius in chunks around the spawn to generate when creating a world
+	#[serde(default)]
+	pub initial_gen_radius :isize,
+	#[serde(default)]
+	pub chunk_compression :CompressionKind,
+	#[serde(default = "sqlite_cache_kb_default")]
+	pub sqlite_cache_kb :u32,
+	#[serde(default)]
+	pub sqlite_synchronous :SqliteSynchronous,
+	#[serde(default = "generate_terrain_default")]
+	pub generate_terrain :bool,
+	#[serde(default = "tick_rate_default")]
+	pub tick_rate :u32,
+	#[serde(default)]
+	pub drop_item_entities :bool,
+	/// Whether players keep their inventory when they die
+	#[serde(default)]
+	pub keep_inventory :bool,
+	/// The number of blocks per chunk updated randomly each tick
+	#[serde(default = "random_tick_speed_default")]
+	pub random_tick_speed :u32,
+	/// Radius in blocks around the spawn in which players are spawned randomly
+	#[serde(default)]
+	pub spawn_radius :isize,
+	/// Radius in blocks around the spawn in which only admins can edit
+	#[serde(default)]
+	pub spawn_protection_radius :isize,
+	/// Maximum distance in blocks at which players can dig and place blocks
+	#[serde(default = "reach_distance_default")]
+	pub reach_distance :f32,
+	#[serde(default)]
+	pub mapgen_mode :MapgenMode,
+	#[serde(default)]
+	pub mapgen :MapgenParams,
+	#[serde(default)]
+	pub difficulty :Difficulty,
+	#[serde(default)]
+	pub game_mode :GameMode,
+	#[serde(default)]
+	pub whitelist_enabled :bool,
+	#[serde(default)]
+	pub admins :Vec<String>,
+	/// If set, the chat gets appended to the file at this path
+	#[serde(default)]
+	pub chat_log_path :Option<String>,
+	/// Whether to include commands in the chat log
+	#[serde(default)]
+	pub chat_log_commands :bool,
+	/// If set, all block edits get recorded to the file at this path
+	#[serde(default)]
+	pub edit_recording_path :Option<String>,
+	/// The number of homes each player can set with /sethome
+	#[serde(default = "max_homes_per_player_default")]
+	pub max_homes_per_player :usize,
+	/// The number of inventory presets each player can save with /inv save
+	#[serde(default = "max_inventory_presets_per_player_default")]
+	pub max_inventory_presets_per_player :usize,
+	/// Seconds between a teleport command (including /kill) and the teleport
+	#[serde(default)]
+	pub teleport_warmup :f32,
+	/// Minimum seconds between two teleports of a player
+	#[serde(default)]
+	pub teleport_cooldown :f32,
+	/// The maximum payload size of the packets messages are split into
+	#[serde(default = "net_fragment_size_default")]
+	pub net_fragment_size :usize,
 
 	// Client settings
 
@@ -25,10 +107,118 @@ pub struct Config {
 	pub draw_poly_lines :bool,
 	#[serde(default = "viewing_range_default")]
 	pub viewing_range :f32,
-	#[serde(default = "fog_near_default")]
-	pub fog_near :f32,
-	#[serde(default = "fog_far_default")]
-	pub fog_far :f32,
+	#[serde(default)]
+	pub auto_jump :bool,
+	/// Whether blocks break instantly in creative mode
+	#[serde(default = "creative_instabreak_default")]
+	pub creative_instabreak :bool,
+	#[serde(default)]
+	pub keyboard_layout :KeyboardLayout,
+	#[serde(default)]
+	pub keys :KeyBindings,
+	#[serde(default)]
+	pub window_mode :WindowMode,
+	/// The number of inventory slots shown in the hud
+	#[serde(default = "hud_slot_count_default")]
+	pub hud_slot_count :usize,
+	/// The number of chunk meshes uploaded to the GPU per frame
+	#[serde(default = "max_mesh_uploads_per_frame_default")]
+	pub max_mesh_uploads_per_frame :usize,
+	#[serde(default)]
+	pub crosshair :CrosshairStyle,
+	/// The RGBA color of the crosshair
+	#[serde(default = "crosshair_color_default")]
+	pub crosshair_color :[f32; 4],
+	/// Whether the crosshair inverts the colors behind it
+	#[serde(default = "crosshair_inverted_default")]
+	pub crosshair_inverted :bool,
+	/// The RGBA color of the sky during the day
+	#[serde(default = "sky_color_default")]
+	pub sky_color :[f32; 4],
+	/// The RGBA color of the sky in the night
+	#[serde(default = "sky_color_night_default")]
+	pub sky_color_night :[f32; 4],
+	/// The RGBA color of the fog during the day
+	#[serde(default = "fog_color_default")]
+	pub fog_color :[f32; 4],
+	/// The RGBA color of the fog in the night
+	#[serde(default = "fog_color_night_default")]
+	pub fog_color_night :[f32; 4],
+}
+
+/// How the client window is displayed
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowMode {
+	/// A decorated window with the given inner size in pixels
+	Windowed { w :u32, h :u32 },
+	/// A window covering the entire monitor, without changing its video mode
+	Borderless,
+	/// Exclusive fullscreen, in the largest video mode of the monitor
+	Fullscreen,
+}
+
+impl Default for WindowMode {
+	fn default() -> Self {
+		WindowMode::Windowed { w : 1024, h : 768 }
+	}
+}
+
+/// The shape of the crosshair in the center of the screen
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CrosshairStyle {
+	Cross,
+	Dot,
+}
+
+impl Default for CrosshairStyle {
+	fn default() -> Self {
+		CrosshairStyle::Cross
+	}
+}
+
+/// Keyboard layout presets for the default movement keys
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardLayout {
+	Qwerty,
+	Azerty,
+	Dvorak,
+}
+
+impl Default for KeyboardLayout {
+	fn default() -> Self {
+		KeyboardLayout::Qwerty
+	}
+}
+
+/// Names of keys that override the ones of the layout preset
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct KeyBindings {
+	#[serde(default)]
+	pub forward :Option<String>,
+	#[serde(default)]
+	pub left :Option<String>,
+	#[serde(default)]
+	pub backward :Option<String>,
+	#[serde(default)]
+	pub right :Option<String>,
+}
+
+/// A seed given either as number or as text
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SeedValue {
+	Number(u64),
+	Text(String),
+}
+
+fn deserialize_seed<'de, D :Deserializer<'de>>(d :D) -> Result<u64, D::Error> {
+	Ok(match SeedValue::deserialize(d)? {
+		SeedValue::Number(v) => v,
+		SeedValue::Text(s) => seed_from_str(&s),
+	})
 }
 
 // Long-term missing feature of serde
@@ -37,11 +227,27 @@ pub struct Config {
 fn mapgen_seed_default() -> u64 { 78 }
 fn mapgen_radius_xy_default() -> isize { 5 }
 fn mapgen_radius_z_default() -> isize { 2 }
+fn mapgen_min_padding_default() -> isize { 1 }
 fn sent_chunks_radius_xy_default() -> isize { 6 }
 fn sent_chunks_radius_z_default() -> isize { 3 }
+fn sqlite_cache_kb_default() -> u32 { 2000 }
+fn generate_terrain_default() -> bool { true }
+fn tick_rate_default() -> u32 { 60 }
+fn random_tick_speed_default() -> u32 { 3 }
+fn reach_distance_default() -> f32 { 10.0 }
+fn max_homes_per_player_default() -> usize { 5 }
+fn max_inventory_presets_per_player_default() -> usize { 5 }
+fn net_fragment_size_default() -> usize { DEFAULT_FRAGMENT_SIZE }
 fn viewing_range_default() -> f32 { 128.0 }
-fn fog_near_default() -> f32 { 40.0 }
-fn fog_far_default() -> f32 { 60.0 }
+fn creative_instabreak_default() -> bool { true }
+fn hud_slot_count_default() -> usize { HUD_SLOT_COUNT }
+fn max_mesh_uploads_per_frame_default() -> usize { 16 }
+fn crosshair_color_default() -> [f32; 4] { [1.0, 1.0, 1.0, 1.0] }
+fn crosshair_inverted_default() -> bool { true }
+fn sky_color_default() -> [f32; 4] { [0.05, 0.01, 0.6, 1.0] }
+fn sky_color_night_default() -> [f32; 4] { [0.0125, 0.0025, 0.15, 1.0] }
+fn fog_color_default() -> [f32; 4] { [0.5, 0.5, 0.5, 1.0] }
+fn fog_color_night_default() -> [f32; 4] { [0.125, 0.125, 0.125, 1.0] }
 
 impl Default for Config {
 	fn default() -> Self {
@@ -49,27 +255,350 @@ impl Default for Config {
 			mapgen_seed : 78,
 			mapgen_radius_xy : 5,
 			mapgen_radius_z : 2,
+			mapgen_min_padding : 1,
 			sent_chunks_radius_xy : 6,
 			sent_chunks_radius_z : 3,
 			map_storage_path : None,
+			initial_gen_radius : 0,
+			chunk_compression : CompressionKind::Gzip,
+			sqlite_cache_kb : 2000,
+			sqlite_synchronous : SqliteSynchronous::Full,
+			generate_terrain : true,
+			tick_rate : 60,
+			drop_item_entities : false,
+			keep_inventory : false,
+			random_tick_speed : 3,
+			spawn_radius : 0,
+			spawn_protection_radius : 0,
+			reach_distance : 10.0,
+			mapgen_mode : MapgenMode::Heightmap,
+			mapgen : MapgenParams::default(),
+			difficulty : Difficulty::Normal,
+			game_mode : GameMode::Survival,
+			whitelist_enabled : false,
+			admins : Vec::new(),
+			chat_log_path : None,
+			chat_log_commands : false,
+			edit_recording_path : None,
+			max_homes_per_player : 5,
+			max_inventory_presets_per_player : 5,
+			teleport_warmup : 0.0,
+			teleport_cooldown : 0.0,
+			net_fragment_size : DEFAULT_FRAGMENT_SIZE,
 
 			draw_poly_lines : false,
 			viewing_range : 128.0,
-			fog_near : 40.0,
-			fog_far : 60.0,
+			auto_jump : false,
+			creative_instabreak : true,
+			keyboard_layout : KeyboardLayout::Qwerty,
+			keys : KeyBindings::default(),
+			window_mode : WindowMode::default(),
+			hud_slot_count : HUD_SLOT_COUNT,
+			max_mesh_uploads_per_frame : 16,
+			crosshair : CrosshairStyle::Cross,
+			crosshair_color : crosshair_color_default(),
+			crosshair_inverted : true,
+			sky_color : sky_color_default(),
+			sky_color_night : sky_color_night_default(),
+			fog_color : fog_color_default(),
+			fog_color_night : fog_color_night_default(),
 		}
 	}
 }
 
-pub fn load_config_failible() -> Result<Config, StrErr> {
-	let file_str = read_to_string("settings.toml")?;
-	let res = from_str(&file_str)?;
+/// The settings that changed when reloading the config
+#[derive(Default, Debug)]
+pub struct ReloadReport {
+	/// Settings that were applied
+	pub changed :Vec<&'static str>,
+	/// Settings that differ but only take effect after a restart
+	pub needs_restart :Vec<&'static str>,
+	/// Settings that differ but keep the value commands set at runtime
+	pub kept :Vec<&'static str>,
+}
+
+/// Clamps the setting into the given range, warning if it was outside
+fn clamp_setting<T :PartialOrd + Copy + Debug>(name :&str, v :&mut T, min :T, max :T) {
+	// NaN is not comparable, and gets replaced by the minimum
+	let clamped = match (*v).partial_cmp(&min) {
+		None | Some(Ordering::Less) => min,
+		_ if *v > max => max,
+		_ => return,
+	};
+	warn!(target : TARGET_CONFIG, "Setting {} = {:?} is outside of {:?} ..= {:?}, using {:?}",
+		name, v, min, max, clamped);
+	*v = clamped;
+}
+
+impl Config {
+	/// Clamps the settings to the ranges documented in settings.toml.example
+	pub fn validate(&mut self) {
+		clamp_setting("mapgen_radius_xy", &mut self.mapgen_radius_xy, 0, 32);
+		clamp_setting("mapgen_radius_z", &mut self.mapgen_radius_z, 0, 16);
+		clamp_setting("mapgen_min_padding", &mut self.mapgen_min_padding, 0, 8);
+		clamp_setting("sent_chunks_radius_xy", &mut self.sent_chunks_radius_xy, 1, 32);
+		clamp_setting("sent_chunks_radius_z", &mut self.sent_chunks_radius_z, 1, 16);
+		clamp_setting("initial_gen_radius", &mut self.initial_gen_radius, 0, 64);
+		clamp_setting("sqlite_cache_kb", &mut self.sqlite_cache_kb, 64, 1 << 20);
+		clamp_setting("tick_rate", &mut self.tick_rate, 1, 1000);
+		clamp_setting("random_tick_speed", &mut self.random_tick_speed, 0, 4096);
+		clamp_setting("spawn_radius", &mut self.spawn_radius, 0, 64);
+		clamp_setting("spawn_protection_radius", &mut self.spawn_protection_radius,
+			0, MAX_SPAWN_PROTECTION_RADIUS);
+		clamp_setting("reach_distance", &mut self.reach_distance, 1.0, 64.0);
+		clamp_setting("max_homes_per_player", &mut self.max_homes_per_player, 0, 100);
+		clamp_setting("max_inventory_presets_per_player", &mut self.max_inventory_presets_per_player, 0, 100);
+		clamp_setting("teleport_warmup", &mut self.teleport_warmup, 0.0, 60.0);
+		clamp_setting("teleport_cooldown", &mut self.teleport_cooldown, 0.0, 3600.0);
+		clamp_setting("net_fragment_size", &mut self.net_fragment_size, 512, 1 << 20);
+		clamp_setting("viewing_range", &mut self.viewing_range, 16.0, 2048.0);
+		clamp_setting("hud_slot_count", &mut self.hud_slot_count, 1, 10);
+		clamp_setting("max_mesh_uploads_per_frame", &mut self.max_mesh_uploads_per_frame, 1, 4096);
+		for (name, color) in [
+			("crosshair_color", &mut self.crosshair_color),
+			("sky_color", &mut self.sky_color),
+			("sky_color_night", &mut self.sky_color_night),
+			("fog_color", &mut self.fog_color),
+			("fog_color_night", &mut self.fog_color_night),
+		].iter_mut() {
+			for component in color.iter_mut() {
+				clamp_setting(name, component, 0.0, 1.0);
+			}
+		}
+		if let WindowMode::Windowed { w, h } = &mut self.window_mode {
+			clamp_setting("window_mode.windowed.w", w, 64, 16384);
+			clamp_setting("window_mode.windowed.h", h, 64, 16384);
+		}
+	}
+	/// The parameters of the terrain generator, including the mode
+	pub fn mapgen_params(&self) -> MapgenParams {
+		MapgenParams {
+			mode : self.mapgen_mode,
+			.. self.mapgen.clone()
+		}
+	}
+	/// Whether the player with the given nick is listed in `admins`
+	pub fn is_admin(&self, nick :&str) -> bool {
+		self.admins.iter().any(|a| a == nick)
+	}
+	/// Applies the settings of the reloaded config that can be changed at runtime
+	///
+	/// Settings that commands change at runtime, like the difficulty,
+	/// keep their runtime value, so that a reload doesn't undo them.
+	pub fn apply_reload(&mut self, new :&Config) -> ReloadReport {
+		let mut report = ReloadReport::default();
+		macro_rules! reload {
+			($($field:ident),*) => {
+				$(if self.$field != new.$field {
+					self.$field = new.$field.clone();
+					report.changed.push(stringify!($field));
+				})*
+			};
+		}
+		macro_rules! restart {
+			($($field:ident),*) => {
+				$(if self.$field != new.$field {
+					report.needs_restart.push(stringify!($field));
+				})*
+			};
+		}
+		macro_rules! keep {
+			($($field:ident),*) => {
+				$(if self.$field != new.$field {
+					report.kept.push(stringify!($field));
+				})*
+			};
+		}
+		reload!(mapgen_radius_xy, mapgen_radius_z, mapgen_min_padding,
+			sent_chunks_radius_xy, sent_chunks_radius_z,
+			drop_item_entities, random_tick_speed, spawn_radius,
+			reach_distance, game_mode,
+			whitelist_enabled, admins, chat_log_commands, max_homes_per_player,
+			max_inventory_presets_per_player, teleport_warmup, teleport_cooldown);
+		restart!(mapgen_seed, map_storage_path, chunk_compression,
+			sqlite_cache_kb, sqlite_synchronous, generate_terrain, tick_rate,
+			chat_log_path, edit_recording_path, net_fragment_size,
+			initial_gen_radius, mapgen_mode);
+		keep!(difficulty, keep_inventory, spawn_protection_radius, mapgen);
+		report
+	}
+}
+
+const CONFIG_FILE_NAME :&str = "settings.toml";
+
+/// The config written on the first run, with all settings commented out
+const DEFAULT_CONFIG :&str = include_str!("../settings.toml.example");
+
+pub fn load_config_from(path :impl AsRef<Path>) -> Result<Config, StrErr> {
+	let path = path.as_ref();
+	let file_str = read_to_string(path)?;
+	let mut res :Config = from_str(&file_str)
+		.map_err(|e| format!("Malformed config file {}: {}", path.display(), e))?;
+	res.validate();
 	Ok(res)
 }
 
-pub fn load_config() -> Config {
-	load_config_failible().unwrap_or_else(|e| {
-		println!("Using default configuration due to error: {:?}", e);
-		Default::default()
-	})
+/// The directories searched for the config file, in order
+///
+/// The current directory comes first, then the config directory of the OS.
+pub fn config_dirs() -> Vec<PathBuf> {
+	let mut res = vec![PathBuf::from(".")];
+	if let Some(dirs) = ProjectDirs::from("", "", "mimas") {
+		res.push(dirs.config_dir().to_owned());
+	}
+	res
+}
+
+/// Loads the config file from the first of the directories containing one
+///
+/// If none of them does, a default config file is written
+/// into the last of the directories and loaded.
+pub fn load_config_in(dirs :&[PathBuf]) -> Result<Config, StrErr> {
+	if let Some(path) = dirs.iter()
+			.map(|dir| dir.join(CONFIG_FILE_NAME))
+			.find(|path| path.is_file()) {
+		return load_config_from(path);
+	}
+	let dir = dirs.last()
+		.ok_or("No directory to put the config file into")?;
+	fs::create_dir_all(dir)?;
+	let path = dir.join(CONFIG_FILE_NAME);
+	fs::write(&path, DEFAULT_CONFIG)?;
+	info!(target : TARGET_CONFIG, "Wrote a default config file to {}", path.display());
+	load_config_from(path)
+}
+
+pub fn load_config() -> Result<Config, StrErr> {
+	load_config_in(&config_dirs())
+}
+
+#[cfg(test)]
+#[test]
+fn test_config_reload() {
+	let path = std::env::temp_dir()
+		.join(format!("mimas-test-{}-settings.toml", std::process::id()));
+	let mut config = Config::default();
+
+	std::fs::write(&path, "sent_chunks_radius_xy = 9\nmapgen_seed = 1234\n\
+		spawn_protection_radius = 7\ninitial_gen_radius = 2\nkeep_inventory = true\n").unwrap();
+	let new = load_config_from(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	let report = config.apply_reload(&new);
+
+	// Reloadable settings are applied, the others stay
+	assert_eq!(config.sent_chunks_radius_xy, 9);
+	assert_eq!(config.spawn_protection_radius, 0);
+	assert!(!config.keep_inventory);
+	assert_eq!(config.mapgen_seed, 78);
+	assert_eq!(report.changed, vec!["sent_chunks_radius_xy"]);
+	assert_eq!(report.needs_restart, vec!["mapgen_seed", "initial_gen_radius"]);
+	assert_eq!(report.kept, vec!["keep_inventory", "spawn_protection_radius"]);
+
+	// Reloading the same config again changes nothing
+	let report = config.apply_reload(&new);
+	assert!(report.changed.is_empty());
+	assert_eq!(report.needs_restart, vec!["mapgen_seed", "initial_gen_radius"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_text_seed() {
+	let config :Config = from_str("mapgen_seed = \"mimas\"").unwrap();
+	assert_eq!(config.mapgen_seed, seed_from_str("mimas"));
+	let config :Config = from_str("mapgen_seed = \"1234\"").unwrap();
+	assert_eq!(config.mapgen_seed, 1234);
+	let config :Config = from_str("mapgen_seed = 1234").unwrap();
+	assert_eq!(config.mapgen_seed, 1234);
+}
+
+#[cfg(test)]
+#[test]
+fn test_window_mode() {
+	let config :Config = from_str("").unwrap();
+	assert_eq!(config.window_mode, WindowMode::Windowed { w : 1024, h : 768 });
+	let config :Config = from_str("window_mode = \"borderless\"").unwrap();
+	assert_eq!(config.window_mode, WindowMode::Borderless);
+	let config :Config = from_str("window_mode = \"fullscreen\"").unwrap();
+	assert_eq!(config.window_mode, WindowMode::Fullscreen);
+	let mut config :Config = from_str("window_mode = { windowed = { w = 1280, h = 20 } }").unwrap();
+	config.validate();
+	assert_eq!(config.window_mode, WindowMode::Windowed { w : 1280, h : 64 });
+	assert!(from_str::<Config>("window_mode = \"maximized\"").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_crosshair_settings() {
+	let config :Config = from_str("").unwrap();
+	assert_eq!(config.crosshair, CrosshairStyle::Cross);
+	assert!(config.crosshair_inverted);
+	let mut config :Config = from_str("crosshair = \"dot\"\n\
+		crosshair_color = [0.5, 2.0, -1.0, 1.0]").unwrap();
+	config.validate();
+	assert_eq!(config.crosshair, CrosshairStyle::Dot);
+	assert_eq!(config.crosshair_color, [0.5, 1.0, 0.0, 1.0]);
+	assert!(from_str::<Config>("crosshair = \"circle\"").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_config_validate() {
+	let path = std::env::temp_dir()
+		.join(format!("mimas-test-{}-settings-validate.toml", std::process::id()));
+	std::fs::write(&path, "mapgen_radius_xy = -3\nmapgen_radius_z = 1000\n\
+		mapgen_min_padding = 100\nsent_chunks_radius_xy = 0\n\
+		sent_chunks_radius_z = 0\ninitial_gen_radius = 100000\n\
+		sqlite_cache_kb = 0\ntick_rate = 0\nviewing_range = 100000.0\n").unwrap();
+	let config = load_config_from(&path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(config.mapgen_radius_xy, 0);
+	assert_eq!(config.mapgen_radius_z, 16);
+	assert_eq!(config.mapgen_min_padding, 8);
+	assert_eq!(config.sent_chunks_radius_xy, 1);
+	assert_eq!(config.sent_chunks_radius_z, 1);
+	assert_eq!(config.initial_gen_radius, 64);
+	assert_eq!(config.sqlite_cache_kb, 64);
+	assert_eq!(config.tick_rate, 1);
+	assert_eq!(config.viewing_range, 2048.0);
+
+	// In range values and the defaults are kept
+	let mut config = Config::default();
+	config.tick_rate = 20;
+	config.validate();
+	assert_eq!(config.tick_rate, 20);
+	assert_eq!(config.viewing_range, Config::default().viewing_range);
+	assert_eq!(config.sqlite_cache_kb, Config::default().sqlite_cache_kb);
+}
+
+#[cfg(test)]
+#[test]
+fn test_config_discovery() {
+	let base = std::env::temp_dir()
+		.join(format!("mimas-test-{}-config-discovery", std::process::id()));
+	let cwd = base.join("cwd");
+	let config_dir = base.join("config");
+	fs::create_dir_all(&cwd).unwrap();
+	let dirs = [cwd.clone(), config_dir.clone()];
+
+	// A default config gets written into the config dir
+	let config = load_config_in(&dirs).unwrap();
+	assert_eq!(config.mapgen_seed, Config::default().mapgen_seed);
+	let written = read_to_string(config_dir.join(CONFIG_FILE_NAME)).unwrap();
+	assert_eq!(written, DEFAULT_CONFIG);
+	assert!(!cwd.join(CONFIG_FILE_NAME).exists());
+
+	// Subsequent loads read it back
+	fs::write(config_dir.join(CONFIG_FILE_NAME), "mapgen_seed = 1234\n").unwrap();
+	assert_eq!(load_config_in(&dirs).unwrap().mapgen_seed, 1234);
+
+	// The current dir takes precedence
+	fs::write(cwd.join(CONFIG_FILE_NAME), "mapgen_seed = 42\n").unwrap();
+	assert_eq!(load_config_in(&dirs).unwrap().mapgen_seed, 42);
+
+	// Malformed configs are an error
+	fs::write(cwd.join(CONFIG_FILE_NAME), "sent_chunks_radius_xy = \"no\"\n").unwrap();
+	let err = load_config_in(&dirs).err().unwrap();
+	assert!(err.0.contains("Malformed config file"), "{}", err.0);
+
+	fs::remove_dir_all(&base).unwrap();
 }
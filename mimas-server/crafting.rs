@@ -2,56 +2,312 @@ use crate::inventory::{SelectableInventory, Stack};
 use crate::game_params::GameParams;
 use crate::map::MapBlock;
 
+/// Dimensions of a crafting grid
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct CraftingGrid {
+	pub width :usize,
+	pub height :usize,
+}
+
+impl CraftingGrid {
+	/// The grid every player has in their inventory
+	pub const PERSONAL :Self = CraftingGrid { width : 2, height : 2 };
+	/// The grid offered by crafting tables
+	pub const TABLE :Self = CraftingGrid { width : 3, height : 3 };
+
+	pub fn slot_count(&self) -> usize {
+		self.width * self.height
+	}
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Recipe {
-	pub inputs :Vec<Option<MapBlock>>,
+	/// The item and the number of it each slot of the recipe needs
+	pub inputs :Vec<Option<(MapBlock, u16)>>,
+	/// Width of the recipe, the height is derived from it
+	pub width :usize,
 	pub output :Stack,
 }
 
 impl Recipe {
-	fn matches(&self,
-			inv :&SelectableInventory) -> bool {
-		if inv.stacks().len() < self.inputs.len() {
-			return false;
+	fn height(&self) -> usize {
+		self.inputs.len() / self.width
+	}
+	fn matches(&self, inv :&SelectableInventory, grid :CraftingGrid) -> bool {
+		self.match_offset(inv, grid).is_some()
+	}
+	/// The line and column of the grid at which the inventory matches the recipe
+	fn match_offset(&self, inv :&SelectableInventory,
+			grid :CraftingGrid) -> Option<(usize, usize)> {
+		if inv.stacks().len() != grid.slot_count() {
+			return None;
 		}
-		// TODO we recompute this sqrt here every time.
-		// might be smarter to cache it?
-		let inv_size_sqrt = (inv.stacks().len() as f32).sqrt() as usize;
-		let recipe_size_sqrt = (self.inputs.len() as f32).sqrt() as usize;
-		let size_sqrt_diff = inv_size_sqrt - recipe_size_sqrt;
+		// Recipes that don't fit into the grid can never match
+		let (width_diff, height_diff) = match (grid.width.checked_sub(self.width),
+				grid.height.checked_sub(self.height())) {
+			(Some(w), Some(h)) => (w, h),
+			_ => return None,
+		};
 		// Try all possible offsets
-		for offs_line in 0 ..= size_sqrt_diff {
-			for offs_col in 0 ..= size_sqrt_diff {
+		for offs_line in 0 ..= height_diff {
+			for offs_col in 0 ..= width_diff {
 				let matches = inv.stacks().iter()
 					.enumerate()
 					.all(|(i, stack)| {
-						let stc = stack.content().map(|(m, _c)| m);
-						let line = i / inv_size_sqrt;
-						let col = i % inv_size_sqrt;
+						let line = i / grid.width;
+						let col = i % grid.width;
 						let line_recipe = line.checked_sub(offs_line);
 						let col_recipe = col.checked_sub(offs_col);
 						if let (Some(line_recipe), Some(col_recipe)) = (line_recipe, col_recipe) {
-							if (line_recipe < recipe_size_sqrt) && (col_recipe < recipe_size_sqrt) {
-								let recipe_idx = line_recipe * recipe_size_sqrt + col_recipe;
-								return stc == self.inputs[recipe_idx];
+							if (line_recipe < self.height()) && (col_recipe < self.width) {
+								let recipe_idx = line_recipe * self.width + col_recipe;
+								// At least as many items as needed
+								return match (stack.content(), self.inputs[recipe_idx]) {
+									(Some((m, c)), Some((input, needed))) => m == input && c >= needed,
+									(None, None) => true,
+									_ => false,
+								};
 							}
 						}
 						// If we are outside the recipe, the inventory needs to be empty
-						stc == None
+						stack.is_empty()
 					});
 				// If there is a match for this offset,
 				// return a match for the recipe
 				if matches {
-					return true;
+					return Some((offs_line, offs_col));
 				}
 			}
 		}
 		// No offset found at which there was a match
+		None
+	}
+	/// Takes the inputs of the recipe from the crafting grid
+	///
+	/// Exactly the number each input needs is taken from its slot.
+	/// Returns false and changes nothing if the grid doesn't match.
+	pub fn consume_inputs(&self, craft_inv :&mut SelectableInventory,
+			grid :CraftingGrid) -> bool {
+		let (offs_line, offs_col) = if let Some(offs) = self.match_offset(craft_inv, grid) {
+			offs
+		} else {
+			return false;
+		};
+		for (i, input) in self.inputs.iter().enumerate() {
+			if let Some((_, needed)) = input {
+				let line = i / self.width + offs_line;
+				let col = i % self.width + offs_col;
+				craft_inv.stacks_mut()[line * grid.width + col].take_n(*needed);
+			}
+		}
+		true
+	}
+}
+
+pub fn get_matching_recipe<'p>(inv :&SelectableInventory, grid :CraftingGrid,
+		params :&'p GameParams) -> Option<&'p Recipe> {
+	params.recipes.iter().find(|r| r.matches(inv, grid))
+}
+
+/// Fills the crafting grid with the inputs of the recipe, taken from the inventory
+///
+/// Items already in the grid are put back into the inventory first.
+/// Returns false and changes nothing if the recipe doesn't fit into
+/// the grid or the inventory lacks any of the inputs.
+pub fn fill_grid(recipe :&Recipe, grid :CraftingGrid,
+		craft_inv :&mut SelectableInventory, inv :&mut SelectableInventory) -> bool {
+	if recipe.width > grid.width || recipe.height() > grid.height {
 		return false;
 	}
+	let mut new_inv = inv.clone();
+	for st in craft_inv.stacks().iter() {
+		if !new_inv.put(*st).is_empty() {
+			return false;
+		}
+	}
+	let mut new_craft_inv = SelectableInventory::crafting_inv(grid);
+	for (i, input) in recipe.inputs.iter().enumerate() {
+		let (input, needed) = if let Some(input) = input {
+			*input
+		} else {
+			continue;
+		};
+		let stack = if let Some(stack) = new_inv.take_items(input, needed) {
+			stack
+		} else {
+			return false;
+		};
+		let idx = (i / recipe.width) * grid.width + i % recipe.width;
+		new_craft_inv.stacks_mut()[idx] = stack;
+	}
+	*inv = new_inv;
+	*craft_inv = new_craft_inv;
+	true
+}
+
+#[cfg(test)]
+#[test]
+fn test_fill_grid() {
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let (wood, stone, sand) = (roles.wood, roles.stone, roles.sand);
+	let recipe = Recipe {
+		inputs : vec![Some((wood, 1)), None, Some((wood, 1)), Some((stone, 1))],
+		width : 2,
+		output : Stack::with(sand, 1),
+	};
+	let grid = CraftingGrid::TABLE;
+
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.stacks_mut()[1] = Stack::with(wood, 3);
+	inv.stacks_mut()[3] = Stack::with(stone, 1);
+	let mut craft_inv = SelectableInventory::crafting_inv(grid);
+	craft_inv.stacks_mut()[8] = Stack::with(sand, 2);
+	assert!(fill_grid(&recipe, grid, &mut craft_inv, &mut inv));
+	let filled = craft_inv.stacks().iter()
+		.map(|st| st.content())
+		.collect::<Vec<_>>();
+	assert_eq!(filled, vec![
+		Some((wood, 1)), None, None,
+		Some((wood, 1)), Some((stone, 1)), None,
+		None, None, None,
+	]);
+	// The inputs are deducted and the previous grid contents returned
+	assert_eq!(inv.stacks()[1], Stack::with(wood, 1));
+	assert_eq!(inv.stacks()[3], Stack::Empty);
+	assert!(inv.stacks().contains(&Stack::with(sand, 2)));
+	let mut params = params.p.clone();
+	params.recipes = vec![recipe.clone()];
+	assert!(get_matching_recipe(&craft_inv, grid, &params).is_some());
+
+	// With missing ingredients nothing happens
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.stacks_mut()[0] = Stack::with(wood, 1);
+	inv.stacks_mut()[1] = Stack::with(stone, 1);
+	let inv_before = inv.clone();
+	let mut craft_inv = SelectableInventory::crafting_inv(grid);
+	craft_inv.stacks_mut()[0] = Stack::with(sand, 1);
+	let craft_inv_before = craft_inv.clone();
+	assert!(!fill_grid(&recipe, grid, &mut craft_inv, &mut inv));
+	assert_eq!(inv, inv_before);
+	assert_eq!(craft_inv, craft_inv_before);
+
+	// Recipes too large for the grid can't be filled
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.stacks_mut()[0] = Stack::with(wood, 10);
+	let wide = Recipe {
+		inputs : vec![Some((wood, 1)), Some((wood, 1)), Some((wood, 1))],
+		width : 3,
+		output : Stack::with(sand, 1),
+	};
+	let mut craft_inv = SelectableInventory::crafting_inv(CraftingGrid::PERSONAL);
+	assert!(!fill_grid(&wide, CraftingGrid::PERSONAL, &mut craft_inv, &mut inv));
+	assert_eq!(inv.stacks()[0], Stack::with(wood, 10));
+}
+
+#[cfg(test)]
+#[test]
+fn test_recipe_grid_sizes() {
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let (wood, stone) = (roles.wood, roles.stone);
+
+	let grid_inv = |grid :CraftingGrid, filled :&[(usize, MapBlock)]| {
+		let mut inv = SelectableInventory::crafting_inv(grid);
+		for (idx, m) in filled {
+			inv.stacks_mut()[*idx] = Stack::with(*m, 1);
+		}
+		inv
+	};
+
+	// A 2x1 recipe fits into both grids
+	let small = Recipe {
+		inputs : vec![Some((wood, 1)), Some((wood, 1))],
+		width : 2,
+		output : Stack::with(stone, 1),
+	};
+	let personal = CraftingGrid::PERSONAL;
+	let table = CraftingGrid::TABLE;
+	assert!(small.matches(&grid_inv(personal, &[(0, wood), (1, wood)]), personal));
+	assert!(small.matches(&grid_inv(personal, &[(2, wood), (3, wood)]), personal));
+	assert!(small.matches(&grid_inv(table, &[(4, wood), (5, wood)]), table));
+	assert!(!small.matches(&grid_inv(table, &[(2, wood), (3, wood)]), table));
+
+	// A 3 wide recipe only fits into the table grid
+	let wide = Recipe {
+		inputs : vec![Some((wood, 1)), None, Some((wood, 1))],
+		width : 3,
+		output : Stack::with(stone, 1),
+	};
+	assert!(wide.matches(&grid_inv(table, &[(6, wood), (8, wood)]), table));
+	assert!(!wide.matches(&grid_inv(personal, &[(0, wood), (1, wood)]), personal));
+
+	// The grid size has to match the inventory
+	assert!(!small.matches(&grid_inv(table, &[(0, wood), (1, wood)]), personal));
+
+	let mut params = params.p.clone();
+	params.recipes = vec![wide, small];
+	let inv = grid_inv(personal, &[(0, wood), (1, wood)]);
+	let found = get_matching_recipe(&inv, personal, &params).unwrap();
+	assert_eq!(found.width, 2);
+	let inv = grid_inv(table, &[(0, wood), (2, wood)]);
+	let found = get_matching_recipe(&inv, table, &params).unwrap();
+	assert_eq!(found.width, 3);
 }
 
-pub fn get_matching_recipe<'p>(inv :&SelectableInventory, params :&'p GameParams)
-		-> Option<&'p Recipe> {
-	params.recipes.iter().find(|r| r.matches(inv))
+#[cfg(test)]
+#[test]
+fn test_recipe_input_counts() {
+	use crate::inventory_action::{InventoryAction, InvLocation, apply_action};
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let (wood, stone, sand) = (roles.wood, roles.stone, roles.sand);
+	let grid = CraftingGrid::PERSONAL;
+	// Two wood and one stone make four sand
+	let recipe = Recipe {
+		inputs : vec![Some((wood, 2)), Some((stone, 1))],
+		width : 2,
+		output : Stack::with(sand, 4),
+	};
+	let mut params = params.p.clone();
+	params.recipes = vec![recipe.clone()];
+
+	let mut craft_inv = SelectableInventory::crafting_inv(grid);
+	craft_inv.stacks_mut()[2] = Stack::with(wood, 1);
+	craft_inv.stacks_mut()[3] = Stack::with(stone, 1);
+	// Not enough wood
+	assert!(get_matching_recipe(&craft_inv, grid, &params).is_none());
+	assert!(!recipe.consume_inputs(&mut craft_inv.clone(), grid));
+	craft_inv.stacks_mut()[2] = Stack::with(wood, 5);
+	craft_inv.stacks_mut()[3] = Stack::with(stone, 3);
+	assert!(get_matching_recipe(&craft_inv, grid, &params).is_some());
+
+	// Crafting takes exactly the needed inputs and yields the entire output
+	let inv = SelectableInventory::empty_with_size(4);
+	let action = InventoryAction::Craft(grid);
+	let changed = apply_action(&action, &params, |loc| match loc {
+		InvLocation::Player => Some(inv.clone()),
+		InvLocation::Craft(g) if g == grid => Some(craft_inv.clone()),
+		_ => None,
+	}).unwrap();
+	let (_, craft_after) = changed.iter().find(|(loc, _)| *loc == InvLocation::Craft(grid)).unwrap();
+	let (_, inv_after) = changed.iter().find(|(loc, _)| *loc == InvLocation::Player).unwrap();
+	assert_eq!(craft_after.stacks()[2], Stack::with(wood, 3));
+	assert_eq!(craft_after.stacks()[3], Stack::with(stone, 2));
+	assert_eq!(inv_after.stacks()[0], Stack::with(sand, 4));
+
+	// Filling the grid takes the needed number, even across stacks
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.stacks_mut()[0] = Stack::with(wood, 1);
+	inv.stacks_mut()[1] = Stack::with(stone, 1);
+	inv.stacks_mut()[3] = Stack::with(wood, 4);
+	let mut craft_inv = SelectableInventory::crafting_inv(grid);
+	assert!(fill_grid(&recipe, grid, &mut craft_inv, &mut inv));
+	assert_eq!(craft_inv.stacks()[0], Stack::with(wood, 2));
+	assert_eq!(craft_inv.stacks()[1], Stack::with(stone, 1));
+	assert_eq!(inv.stacks()[0], Stack::Empty);
+	assert_eq!(inv.stacks()[3], Stack::with(wood, 3));
+	assert!(recipe.consume_inputs(&mut craft_inv, grid));
+	assert!(craft_inv.is_empty());
 }
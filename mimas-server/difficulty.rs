@@ -0,0 +1,127 @@
+use super::StrErr;
+
+/// Height in blocks that players can fall without taking damage
+const SAFE_FALL_HEIGHT :f32 = 3.0;
+/// Damage per block fallen beyond the safe fall height
+const FALL_DAMAGE_PER_BLOCK :f32 = 1.0;
+
+/// The difficulty of the game
+///
+/// It is stored together with the world so that
+/// changes done at runtime persist.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Difficulty {
+	Peaceful,
+	Easy,
+	Normal,
+	Hard,
+}
+
+impl Default for Difficulty {
+	fn default() -> Self {
+		Difficulty::Normal
+	}
+}
+
+impl Difficulty {
+	pub const ALL :[Difficulty; 4] = [
+		Difficulty::Peaceful,
+		Difficulty::Easy,
+		Difficulty::Normal,
+		Difficulty::Hard,
+	];
+	pub fn name(self) -> &'static str {
+		match self {
+			Difficulty::Peaceful => "Peaceful",
+			Difficulty::Easy => "Easy",
+			Difficulty::Normal => "Normal",
+			Difficulty::Hard => "Hard",
+		}
+	}
+	/// Looks up the difficulty by its name, ignoring case
+	pub fn from_name(name :&str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|d| d.name().eq_ignore_ascii_case(name))
+			.copied()
+	}
+	/// Factor applied to all damage that players take
+	///
+	/// On peaceful, players take no damage at all.
+	pub fn damage_multiplier(self) -> f32 {
+		match self {
+			Difficulty::Peaceful => 0.0,
+			Difficulty::Easy => 0.5,
+			Difficulty::Normal => 1.0,
+			Difficulty::Hard => 1.5,
+		}
+	}
+	/// Factor applied to the rate at which players regain health
+	pub fn health_regen_multiplier(self) -> f32 {
+		match self {
+			Difficulty::Peaceful => 2.0,
+			Difficulty::Easy => 1.5,
+			Difficulty::Normal => 1.0,
+			Difficulty::Hard => 0.5,
+		}
+	}
+	/// Factor applied to the rate at which hunger depletes
+	pub fn hunger_depletion_multiplier(self) -> f32 {
+		match self {
+			Difficulty::Peaceful => 0.0,
+			Difficulty::Easy => 0.5,
+			Difficulty::Normal => 1.0,
+			Difficulty::Hard => 1.5,
+		}
+	}
+	/// The damage a player takes from falling down the given height
+	pub fn fall_damage(self, height :f32) -> f32 {
+		let base = (height - SAFE_FALL_HEIGHT).max(0.0) * FALL_DAMAGE_PER_BLOCK;
+		base * self.damage_multiplier()
+	}
+	pub fn serialize(&self) -> Vec<u8> {
+		self.name().as_bytes().to_vec()
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		let name = std::str::from_utf8(buf)?;
+		let difficulty = Self::from_name(name)
+			.ok_or_else(|| format!("Invalid difficulty '{}'", name))?;
+		Ok(difficulty)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_damage_multiplier() {
+	let multipliers = Difficulty::ALL.iter()
+		.map(|d| d.damage_multiplier())
+		.collect::<Vec<_>>();
+	assert_eq!(multipliers, vec![0.0, 0.5, 1.0, 1.5]);
+	assert_eq!(Difficulty::default().damage_multiplier(), 1.0);
+
+	// Harder difficulties deal more fall damage
+	let height = 10.0;
+	for w in Difficulty::ALL.windows(2) {
+		assert!(w[0].fall_damage(height) < w[1].fall_damage(height));
+	}
+	assert_eq!(Difficulty::Normal.fall_damage(height),
+		(height - SAFE_FALL_HEIGHT) * FALL_DAMAGE_PER_BLOCK);
+	// Short falls are harmless on every difficulty
+	for d in Difficulty::ALL.iter() {
+		assert_eq!(d.fall_damage(SAFE_FALL_HEIGHT), 0.0);
+	}
+
+	for d in Difficulty::ALL.iter() {
+		assert_eq!(Difficulty::deserialize(&d.serialize()).unwrap(), *d);
+	}
+	assert_eq!(Difficulty::from_name("hard"), Some(Difficulty::Hard));
+	assert!(Difficulty::deserialize(b"impossible").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_peaceful_no_fall_damage() {
+	for &height in &[0.0, 4.0, 20.0, 1000.0] {
+		assert_eq!(Difficulty::Peaceful.fall_damage(height), 0.0);
+	}
+	assert!(Difficulty::Easy.fall_damage(1000.0) > 0.0);
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use nalgebra::Vector3;
+use crate::map::{Map, MapBackend, MapBlock, BlockBatch};
+use crate::game_params::{Id, NameIdMap};
+use crate::map_storage::{serialize_name_id_map, deserialize_name_id_map};
+use crate::{btchn, StrErr};
+use crate::logging::TARGET_SERVER;
+
+/// Written at the start of edit recording files
+const MAGIC :&[u8; 4] = b"MMED";
+const VERSION :u8 = 1;
+
+/// Written instead of a tick number before the name id map of a server run
+const NAME_ID_MAP_MARKER :u64 = u64::MAX;
+
+/// A recording of the block edits of the server, appended to a file
+///
+/// Each server run starts with the marker and the serialized name id map
+/// the block ids refer to. Then follow groups of the edits of a tick:
+/// the number of ticks since recording started, the count of edits,
+/// and then for each edit the position and the block id.
+pub struct EditRecorder {
+	file :Option<File>,
+	tick :u64,
+}
+
+impl EditRecorder {
+	/// An edit recorder that doesn't write anything
+	pub fn disabled() -> Self {
+		Self {
+			file : None,
+			tick : 0,
+		}
+	}
+	/// Opens the file at the path for appending
+	///
+	/// If the file can't be opened, a warning is
+	/// printed and the edits aren't recorded.
+	pub fn open(path :impl AsRef<Path>, m :&NameIdMap) -> Self {
+		let path = path.as_ref();
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.and_then(|mut file| {
+				if file.metadata()?.len() == 0 {
+					file.write_all(MAGIC)?;
+					file.write_u8(VERSION)?;
+				}
+				let nm = serialize_name_id_map(m);
+				file.write_u64::<BigEndian>(NAME_ID_MAP_MARKER)?;
+				file.write_u32::<BigEndian>(nm.len() as u32)?;
+				file.write_all(&nm)?;
+				Ok(file)
+			});
+		match file {
+			Ok(file) => Self {
+				file : Some(file),
+				tick : 0,
+			},
+			Err(e) => {
+				warn!(target : TARGET_SERVER, "Couldn't open the edit recording {}: {}", path.display(), e);
+				Self::disabled()
+			},
+		}
+	}
+	/// Records the edits of a tick, to be called once per tick
+	pub fn record(&mut self, edits :&BlockBatch) {
+		let tick = self.tick;
+		self.tick += 1;
+		let file = if let Some(file) = &mut self.file {
+			file
+		} else {
+			return;
+		};
+		if edits.is_empty() {
+			return;
+		}
+		let mut buf = Vec::new();
+		write_tick(&mut buf, tick, edits);
+		if let Err(e) = file.write_all(&buf) {
+			warn!(target : TARGET_SERVER, "Couldn't write to the edit recording: {}", e);
+		}
+	}
+}
+
+fn write_tick(buf :&mut Vec<u8>, tick :u64, edits :&BlockBatch) {
+	buf.write_u64::<BigEndian>(tick).unwrap();
+	buf.write_u32::<BigEndian>(edits.edits.len() as u32).unwrap();
+	for (pos, b) in edits.edits.iter() {
+		buf.write_i32::<BigEndian>(pos.x as i32).unwrap();
+		buf.write_i32::<BigEndian>(pos.y as i32).unwrap();
+		buf.write_i32::<BigEndian>(pos.z as i32).unwrap();
+		buf.write_u8(b.id()).unwrap();
+	}
+}
+
+/// Maps the block ids of a recording to the ones of `m`, by name
+fn id_remapping(recorded :&NameIdMap, m :&NameIdMap) -> Result<Vec<MapBlock>, StrErr> {
+	recorded.names().iter()
+		.map(|name| m.get_id(name.as_str())
+			.ok_or_else(|| format!("Unknown block {} in edit recording", name).into()))
+		.collect()
+}
+
+/// Reads the recorded ticks and their edits
+///
+/// The block ids are translated to the ones of `m`. Recordings
+/// without name id map are assumed to use the ids of `m`.
+pub fn read_recording(mut rdr :impl Read, m :&NameIdMap) -> Result<Vec<(u64, BlockBatch)>, StrErr> {
+	let mut magic = [0; 4];
+	rdr.read_exact(&mut magic)?;
+	if &magic != MAGIC {
+		Err("Not an edit recording")?;
+	}
+	let version = rdr.read_u8()?;
+	if version > VERSION {
+		Err(format!("Unsupported edit recording version {}", version))?;
+	}
+	let mut remapping = id_remapping(m, m)?;
+	let mut res = Vec::new();
+	loop {
+		let tick = match rdr.read_u64::<BigEndian>() {
+			Ok(tick) => tick,
+			Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break,
+			Err(e) => return Err(e.into()),
+		};
+		if version > 0 && tick == NAME_ID_MAP_MARKER {
+			let len = rdr.read_u32::<BigEndian>()? as usize;
+			let mut buf = vec![0; len];
+			rdr.read_exact(&mut buf)?;
+			let recorded = deserialize_name_id_map(&buf)?;
+			remapping = id_remapping(&recorded, m)?;
+			continue;
+		}
+		let count = rdr.read_u32::<BigEndian>()?;
+		let mut edits = BlockBatch::new();
+		for _ in 0 .. count {
+			let x = rdr.read_i32::<BigEndian>()? as isize;
+			let y = rdr.read_i32::<BigEndian>()? as isize;
+			let z = rdr.read_i32::<BigEndian>()? as isize;
+			let b = *remapping.get(rdr.read_u8()? as usize)
+				.ok_or("invalid block id in edit recording")?;
+			edits.push(Vector3::new(x, y, z), b);
+		}
+		res.push((tick, edits));
+	}
+	Ok(res)
+}
+
+/// Replays a recording of block edits into a map
+pub struct EditReplay {
+	ticks :VecDeque<(u64, BlockBatch)>,
+	/// The recorded tick the replay has reached
+	progress :f64,
+	/// Recorded ticks per replayed tick
+	speed :f64,
+}
+
+impl EditReplay {
+	/// Creates a replay of the recorded ticks, played `speed` times as fast
+	///
+	/// When the ticks restart at zero, because recordings of several
+	/// server runs got appended to each other, they are played after
+	/// the ones before.
+	pub fn new(ticks :Vec<(u64, BlockBatch)>, speed :f64) -> Self {
+		let mut offset = 0;
+		let mut last = 0;
+		let ticks = ticks.into_iter()
+			.map(|(tick, edits)| {
+				if tick + offset < last {
+					offset = last + 1;
+				}
+				last = tick + offset;
+				(last, edits)
+			})
+			.collect();
+		Self {
+			ticks,
+			progress : 0.0,
+			speed,
+		}
+	}
+	pub fn open(path :impl AsRef<Path>, m :&NameIdMap, speed :f64) -> Result<Self, StrErr> {
+		let rdr = BufReader::new(File::open(path)?);
+		Ok(Self::new(read_recording(rdr, m)?, speed))
+	}
+	pub fn is_finished(&self) -> bool {
+		self.ticks.is_empty()
+	}
+	/// Advances the replay by one tick, applying the edits that are due
+	///
+	/// If the chunks of the edits aren't loaded yet, their generation is
+	/// requested and the replay waits until they are. Returns the number
+	/// of applied edits.
+	pub fn step<B :MapBackend>(&mut self, map :&mut Map<B>) -> usize {
+		self.progress += self.speed;
+		let mut applied = 0;
+		while let Some((tick, edits)) = self.ticks.front() {
+			if *tick as f64 > self.progress {
+				break;
+			}
+			let missing = edits.edits.iter()
+				.map(|(pos, _b)| btchn(*pos))
+				.filter(|chunk_pos| map.get_chunk(*chunk_pos).is_none())
+				.collect::<Vec<_>>();
+			if !missing.is_empty() {
+				for chunk_pos in missing {
+					map.gen_chunks_in_area(chunk_pos, chunk_pos);
+				}
+				self.progress = *tick as f64;
+				break;
+			}
+			for (pos, b) in edits.edits.iter() {
+				map.get_blk_mut(*pos).unwrap().set(*b);
+			}
+			applied += edits.edits.len();
+			self.ticks.pop_front();
+		}
+		applied
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_edit_recording_replay() {
+	use crate::map::{ClientMap, MapChunkData, CHUNKSIZE};
+
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let stone = nm.get_id("default:stone").unwrap();
+	let wood = nm.get_id("default:wood").unwrap();
+	let chunk_positions = [Vector3::new(0, 0, 0), Vector3::new(-CHUNKSIZE, 0, 0)];
+	let new_map = || {
+		let mut map = ClientMap::new();
+		for pos in chunk_positions.iter() {
+			map.set_chunk(*pos, MapChunkData::filled_with(air));
+		}
+		map
+	};
+
+	let path = std::env::temp_dir()
+		.join(format!("mimas-test-{}-edits.rec", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let ticks = vec![
+		vec![(Vector3::new(1, 2, 3), stone), (Vector3::new(-5, 2, 3), wood)],
+		vec![],
+		vec![(Vector3::new(1, 2, 3), air), (Vector3::new(1, 2, 4), stone)],
+		vec![(Vector3::new(-1, 0, 0), stone)],
+	];
+	let mut original = new_map();
+	let mut recorder = EditRecorder::open(&path, &nm);
+	for edits in ticks.iter() {
+		let mut batch = BlockBatch::new();
+		for (pos, b) in edits.iter() {
+			original.get_blk_mut(*pos).unwrap().set(*b);
+			batch.push(*pos, *b);
+		}
+		recorder.record(&batch);
+	}
+	// Recordings of a restarted server get appended,
+	// even if the block ids have changed in the meantime
+	let restarted_nm = NameIdMap::from_name_list(nm.names().iter().rev().cloned().collect());
+	let mut recorder = EditRecorder::open(&path, &restarted_nm);
+	let mut batch = BlockBatch::new();
+	batch.push(Vector3::new(-1, 0, 0), restarted_nm.get_id("default:wood").unwrap());
+	original.get_blk_mut(Vector3::new(-1, 0, 0)).unwrap().set(wood);
+	recorder.record(&batch);
+
+	let recorded = read_recording(File::open(&path).unwrap(), &nm).unwrap();
+	std::fs::remove_file(&path).unwrap();
+	let recorded_ticks = recorded.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+	assert_eq!(recorded_ticks, vec![0, 2, 3, 0]);
+	assert_eq!(recorded[3].1.edits, vec![(Vector3::new(-1, 0, 0), wood)]);
+
+	// Replay at half speed into a fresh map
+	let mut replayed = new_map();
+	let mut replay = EditReplay::new(recorded, 0.5);
+	let mut steps = 0;
+	while !replay.is_finished() {
+		replay.step(&mut replayed);
+		steps += 1;
+	}
+	assert_eq!(steps, 8);
+	for pos in chunk_positions.iter() {
+		assert_eq!(&replayed.get_chunk(*pos).unwrap().0[..],
+			&original.get_chunk(*pos).unwrap().0[..]);
+	}
+	assert_eq!(replayed.get_blk(Vector3::new(-1, 0, 0)), Some(wood));
+
+	// Edits in unloaded chunks wait until the chunk is there
+	let mut batch = BlockBatch::new();
+	batch.push(Vector3::new(100, 0, 0), stone);
+	let mut replay = EditReplay::new(vec![(0, batch)], 1.0);
+	assert_eq!(replay.step(&mut replayed), 0);
+	assert!(!replay.is_finished());
+	replayed.set_chunk(btchn(Vector3::new(100, 0, 0)), MapChunkData::filled_with(air));
+	assert_eq!(replay.step(&mut replayed), 1);
+	assert_eq!(replayed.get_blk(Vector3::new(100, 0, 0)), Some(stone));
+}
@@ -0,0 +1,251 @@
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+use crate::map::{Map, MapBackend};
+use crate::game_params::{GameParams, is_solid};
+use crate::inventory::{SelectableInventory, Stack};
+use crate::collide::collide_extent;
+
+const GRAVITY :f32 = 9.81 * 3.0;
+const MAX_FALLING_SPEED :f32 = 40.0;
+/// Half the edge length of the collision box of dropped items
+const DROPPED_ITEM_EXTENT :f32 = 0.15;
+/// Distance from the player's body center within which
+/// dropped items are picked up
+pub const PICKUP_RANGE :f32 = 1.5;
+/// Distance from the eyes of the player to the center of its body
+const PLAYER_EYES_TO_CENTER :f32 = 0.8;
+/// Seconds after which dropped items despawn
+pub const DESPAWN_TIME :f32 = 300.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub struct EntityId(u64);
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum EntityKind {
+	DroppedItem(Stack),
+}
+
+#[derive(Clone, Debug)]
+pub struct Entity {
+	pub id :EntityId,
+	/// Position of the center of the entity
+	pub pos :Vector3<f32>,
+	pub velocity :Vector3<f32>,
+	pub kind :EntityKind,
+	age :f32,
+}
+
+impl Entity {
+	fn extent(&self) -> Vector3<f32> {
+		match self.kind {
+			EntityKind::DroppedItem(_) => Vector3::new(DROPPED_ITEM_EXTENT,
+				DROPPED_ITEM_EXTENT, DROPPED_ITEM_EXTENT),
+		}
+	}
+	fn tick<B :MapBackend>(&mut self, dt :f32, map :&Map<B>, params :&GameParams) {
+		self.age += dt;
+		self.velocity.z = (self.velocity.z - GRAVITY * dt).max(-MAX_FALLING_SPEED);
+		let mut delta_pos = self.velocity * dt;
+
+		let extent = self.extent();
+		let pos = self.pos.map(|v| v.floor() as isize);
+		let new_pos = (self.pos + delta_pos).map(|v| v.floor() as isize);
+		let d = 1;
+		let cubes_min = pos.zip_map(&new_pos, |a, b| a.min(b) - d);
+		let cubes_max = pos.zip_map(&new_pos, |a, b| a.max(b) + d);
+		// Blocks occupy the unit cube starting at their position,
+		// while the collision code assumes them to be centered
+		let coll_pos = self.pos - Vector3::new(0.5, 0.5, 0.5);
+		let mut touches_ground = false;
+		for x in cubes_min.x ..= cubes_max.x {
+			for y in cubes_min.y ..= cubes_max.y {
+				for z in cubes_min.z ..= cubes_max.z {
+					let p = Vector3::new(x, y, z);
+					// Unloaded chunks are regarded as solid,
+					// so that nothing falls through the map.
+					let solid = map.get_blk(p)
+						.map(|b| is_solid(b, params))
+						.unwrap_or(true);
+					if !solid {
+						continue;
+					}
+					for axis in 0 .. 3 {
+						let mut axis_delta = Vector3::zeros();
+						axis_delta[axis] = delta_pos[axis];
+						if let Some(normal) = collide_extent(coll_pos + axis_delta, extent, p) {
+							let d = delta_pos.dot(&normal);
+							if axis == 2 && normal.z > 0.0 {
+								touches_ground = true;
+							}
+							if d < 0.0 {
+								delta_pos -= d * normal;
+							}
+						}
+					}
+				}
+			}
+		}
+		if touches_ground {
+			self.velocity = Vector3::zeros();
+		}
+		self.pos += delta_pos;
+	}
+}
+
+/// The set of entities that live on the server
+#[derive(Default)]
+pub struct Entities {
+	next_id :u64,
+	entities :BTreeMap<EntityId, Entity>,
+}
+
+impl Entities {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.entities.is_empty()
+	}
+	pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+		self.entities.values()
+	}
+	pub fn get(&self, id :EntityId) -> Option<&Entity> {
+		self.entities.get(&id)
+	}
+	pub fn spawn(&mut self, pos :Vector3<f32>, velocity :Vector3<f32>,
+			kind :EntityKind) -> EntityId {
+		let id = EntityId(self.next_id);
+		self.next_id += 1;
+		self.entities.insert(id, Entity {
+			id,
+			pos,
+			velocity,
+			kind,
+			age : 0.0,
+		});
+		id
+	}
+	/// Spawns a dropped item in the middle of the block at the given position
+	pub fn spawn_dropped_item(&mut self, pos :Vector3<isize>, stack :Stack) -> Option<EntityId> {
+		if stack.is_empty() {
+			return None;
+		}
+		let center = pos.map(|v| v as f32) + Vector3::new(0.5, 0.5, 0.5);
+		// Make it jump up a little
+		let velocity = Vector3::new(0.0, 0.0, 4.0);
+		Some(self.spawn(center, velocity, EntityKind::DroppedItem(stack)))
+	}
+	/// Moves all entities and despawns the ones that are too old
+	pub fn tick<B :MapBackend>(&mut self, dt :f32, map :&Map<B>, params :&GameParams) {
+		for entity in self.entities.values_mut() {
+			entity.tick(dt, map, params);
+		}
+		self.entities.retain(|_id, e| e.age < DESPAWN_TIME);
+	}
+	/// Puts the dropped items in the pickup range of
+	/// the player at the given position into its inventory
+	///
+	/// Returns whether the inventory was changed.
+	pub fn pick_up(&mut self, player_pos :Vector3<f32>,
+			inv :&mut SelectableInventory) -> bool {
+		let center = player_pos - Vector3::new(0.0, 0.0, PLAYER_EYES_TO_CENTER);
+		let mut changed = false;
+		let mut picked_up = Vec::new();
+		for (id, entity) in self.entities.iter_mut() {
+			if (entity.pos - center).norm() > PICKUP_RANGE {
+				continue;
+			}
+			let EntityKind::DroppedItem(stack) = &mut entity.kind;
+			let rest = inv.put(*stack);
+			if rest != *stack {
+				changed = true;
+			}
+			*stack = rest;
+			if stack.is_empty() {
+				picked_up.push(*id);
+			}
+		}
+		for id in picked_up {
+			self.entities.remove(&id);
+		}
+		changed
+	}
+}
+
+#[cfg(test)]
+fn test_map(params :&GameParams) -> Map<crate::map::ClientBackend> {
+	use crate::map::{MapChunkData, ClientBackend, CHUNKSIZE};
+	let roles = &params.block_roles;
+	let mut map = Map::from_backend(ClientBackend);
+	for z in -1 ..= 1 {
+		let fill = if z < 0 { roles.stone } else { roles.air };
+		map.set_chunk(Vector3::new(0, 0, z * CHUNKSIZE), MapChunkData::filled_with(fill));
+	}
+	map
+}
+
+#[cfg(test)]
+#[test]
+fn test_entity_gravity() {
+	let params = crate::game_params::test_params();
+	let map = test_map(&params.p);
+	let mut entities = Entities::new();
+	let stack = Stack::with(params.p.block_roles.stone, 1);
+	let id = entities.spawn_dropped_item(Vector3::new(5, 5, 8), stack).unwrap();
+	for _ in 0 .. 300 {
+		entities.tick(1.0 / 60.0, &map, &params.p);
+	}
+	// The item settles on top of the floor at z = 0
+	let entity = entities.get(id).unwrap();
+	assert!((entity.pos.z - DROPPED_ITEM_EXTENT).abs() < 0.05, "{}", entity.pos.z);
+	assert_eq!(entity.velocity, Vector3::zeros());
+	assert_eq!(entity.pos.xy(), Vector3::new(5.5, 5.5, 0.0).xy());
+}
+
+#[cfg(test)]
+#[test]
+fn test_entity_pickup() {
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let mut entities = Entities::new();
+	let pos = Vector3::new(5, 5, 0);
+	entities.spawn_dropped_item(pos, Stack::with(roles.stone, 3));
+	entities.spawn_dropped_item(pos, Stack::with(roles.wood, 2));
+
+	let mut inv = SelectableInventory::new();
+	inv.put(Stack::with(roles.stone, 1));
+
+	// Too far away
+	let far = Vector3::new(20.0, 5.5, 2.0);
+	assert!(!entities.pick_up(far, &mut inv));
+	assert_eq!(entities.iter().count(), 2);
+
+	// Picked up and merged into the existing stack
+	let near = Vector3::new(5.5, 5.5, 2.0);
+	assert!(entities.pick_up(near, &mut inv));
+	assert!(entities.is_empty());
+	assert_eq!(inv.stacks()[0], Stack::with(roles.stone, 4));
+	assert_eq!(inv.stacks()[1], Stack::with(roles.wood, 2));
+
+	// Full inventories only take what fits
+	let mut full_inv = SelectableInventory::from_stacks(vec![
+		Stack::with(roles.stone, 1)].into_boxed_slice());
+	entities.spawn_dropped_item(pos, Stack::with(roles.wood, 1));
+	assert!(!entities.pick_up(near, &mut full_inv));
+	assert_eq!(entities.iter().count(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_entity_despawn() {
+	let params = crate::game_params::test_params();
+	let map = test_map(&params.p);
+	let mut entities = Entities::new();
+	let stack = Stack::with(params.p.block_roles.stone, 1);
+	let id = entities.spawn_dropped_item(Vector3::new(5, 5, 0), stack).unwrap();
+	entities.tick(DESPAWN_TIME / 2.0, &map, &params.p);
+	assert!(entities.get(id).is_some());
+	entities.tick(DESPAWN_TIME / 2.0, &map, &params.p);
+	assert!(entities.get(id).is_none());
+	assert!(entities.is_empty());
+}
@@ -0,0 +1,118 @@
+use std::fmt::{self, Display};
+use std::error::Error;
+use std::io;
+use crate::StrErr;
+
+/// An error of the server, grouped by the subsystem it originates from
+///
+/// Unlike `StrErr`, callers can match on the variants to handle
+/// specific failures. It converts into `StrErr` through its
+/// `Display` impl, keeping the message.
+#[derive(Debug)]
+pub enum MimasError {
+	Storage(StorageError),
+	Network(String),
+	Config(String),
+	Mapgen(String),
+	Protocol(String),
+}
+
+/// An error of the map storage
+#[derive(Debug)]
+pub enum StorageError {
+	/// The database was written by a newer version
+	VersionMismatch { found :u16, supported :u16 },
+	/// The database belongs to a different application
+	AppIdMismatch { found :i32, expected :i32 },
+	/// Stored data could not be decoded
+	Corrupted(String),
+	Sqlite(rusqlite::Error),
+	Io(io::Error),
+}
+
+impl StorageError {
+	pub fn corrupted(msg :impl Into<String>) -> Self {
+		StorageError::Corrupted(msg.into())
+	}
+}
+
+impl Display for MimasError {
+	fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MimasError::Storage(e) => write!(f, "{}", e),
+			MimasError::Network(msg) => write!(f, "Network error: {}", msg),
+			MimasError::Config(msg) => write!(f, "Config error: {}", msg),
+			MimasError::Mapgen(msg) => write!(f, "Mapgen error: {}", msg),
+			MimasError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+		}
+	}
+}
+
+impl Display for StorageError {
+	fn fmt(&self, f :&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StorageError::VersionMismatch { found, supported } =>
+				write!(f, "user_version of database {} newer than maximum supported {}",
+					found, supported),
+			StorageError::AppIdMismatch { found, expected } =>
+				write!(f, "expected app id {} but was {}", expected, found),
+			StorageError::Corrupted(msg) => write!(f, "{}", msg),
+			StorageError::Sqlite(e) => write!(f, "{}", e),
+			StorageError::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl Error for MimasError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			MimasError::Storage(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl Error for StorageError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			StorageError::Sqlite(e) => Some(e),
+			StorageError::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<StorageError> for MimasError {
+	fn from(e :StorageError) -> Self {
+		MimasError::Storage(e)
+	}
+}
+
+impl From<rusqlite::Error> for MimasError {
+	fn from(e :rusqlite::Error) -> Self {
+		MimasError::Storage(StorageError::Sqlite(e))
+	}
+}
+
+impl From<io::Error> for MimasError {
+	fn from(e :io::Error) -> Self {
+		MimasError::Storage(StorageError::Io(e))
+	}
+}
+
+impl From<StrErr> for StorageError {
+	fn from(e :StrErr) -> Self {
+		StorageError::Corrupted(e.0)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_error_messages() {
+	let e :MimasError = StorageError::VersionMismatch { found : 3, supported : 2 }.into();
+	let s :StrErr = e.into();
+	assert_eq!(s.0, "user_version of database 3 newer than maximum supported 2");
+	let e = MimasError::Protocol("unexpected message".to_string());
+	assert_eq!(e.to_string(), "Protocol error: unexpected message");
+	assert!(e.source().is_none());
+}
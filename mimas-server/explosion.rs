@@ -0,0 +1,167 @@
+use nalgebra::Vector3;
+use rand::Rng;
+use crate::map::{Map, MapBackend, MetadataEntry};
+use crate::game_params::{GameParams, drops_for_roll, is_indestructible};
+use crate::inventory::Stack;
+use crate::falling;
+
+/// The maximum radius of explosions, larger ones get clamped
+pub const MAX_EXPLOSION_RADIUS :isize = 8;
+
+/// The chance of a removed block to yield its drops
+const DROP_CHANCE :f64 = 0.3;
+
+/// What an explosion did
+#[derive(Default, Debug)]
+pub struct Explosion {
+	/// The positions of the removed blocks
+	pub removed :Vec<Vector3<isize>>,
+	/// The drops of the removed blocks, with the position they were removed at
+	///
+	/// The items of removed chests are always among them.
+	pub drops :Vec<(Vector3<isize>, Stack)>,
+}
+
+/// Removes the blocks within the sphere of the radius around the center
+///
+/// Air and indestructible blocks stay, as do blocks in unloaded chunks.
+/// Afterwards, the blocks around the sphere get updated, so that
+/// falling blocks above it drop down and attached ones vanish.
+/// Given the same rng state, the drops are the same.
+pub fn explode<B :MapBackend>(map :&mut Map<B>, center :Vector3<isize>,
+		radius :isize, params :&GameParams, rng :&mut impl Rng) -> Explosion {
+	let radius = radius.clamp(0, MAX_EXPLOSION_RADIUS);
+	let air = params.block_roles.air;
+	let mut res = Explosion::default();
+	let mut edits = Vec::new();
+	for x in -radius ..= radius {
+		for y in -radius ..= radius {
+			for z in -radius ..= radius {
+				let offs = Vector3::new(x, y, z);
+				if offs.dot(&offs) > radius * radius {
+					continue;
+				}
+				let pos = center + offs;
+				let blk = match map.get_blk(pos) {
+					Some(blk) if blk != air && !is_indestructible(blk, params) => blk,
+					_ => continue,
+				};
+				if let Some(Some(MetadataEntry::Inventory(inv))) = map.get_blk_meta(pos) {
+					for stack in inv.stacks().iter().filter(|st| !st.is_empty()) {
+						res.drops.push((pos, *stack));
+					}
+				}
+				if rng.gen::<f64>() < DROP_CHANCE {
+					let roll = rng.gen::<f64>();
+					if let Some(drops) = drops_for_roll(blk, params, roll) {
+						res.drops.push((pos, drops));
+					}
+				}
+				edits.push((pos, air));
+				res.removed.push(pos);
+			}
+		}
+	}
+	map.set_blocks(&edits);
+	// Only done now, so that blocks fall through the entire crater
+	for pos in res.removed.iter() {
+		falling::remove_unsupported(map, *pos, params);
+		falling::settle_falling(map, *pos, params);
+	}
+	res
+}
+
+#[cfg(test)]
+#[test]
+fn test_explosion() {
+	use std::collections::HashSet;
+	use rand_pcg::Pcg32;
+	use crate::map::{ClientBackend, MapChunkData, MapBlock};
+	use crate::inventory::SelectableInventory;
+	use crate::game_params::{NameIdMap, load_params_failible};
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let bedrock = params.p.search_block_name("default:bedrock").unwrap();
+	let mut map = Map::from_backend(ClientBackend);
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.stone));
+	let set = |map :&mut Map<ClientBackend>, pos :Vector3<isize>, b :MapBlock| {
+		map.get_blk_mut(pos).unwrap().set(b);
+	};
+	let center = Vector3::new(8, 8, 6);
+	let bedrock_pos = Vector3::new(8, 9, 5);
+	set(&mut map, bedrock_pos, bedrock);
+	// A sand pillar standing on the top of the sphere
+	for z in 9 .. 12 {
+		set(&mut map, Vector3::new(8, 8, z), roles.sand);
+	}
+
+	let mut rng = Pcg32::new(42, 7);
+	let explosion = explode(&mut map, center, 2, &params.p, &mut rng);
+	let removed = explosion.removed.iter().copied().collect::<HashSet<_>>();
+	let mut expected = HashSet::new();
+	for x in -2 ..= 2 {
+		for y in -2 ..= 2 {
+			for z in -2 ..= 2 {
+				if x * x + y * y + z * z <= 4 {
+					expected.insert(center + Vector3::new(x, y, z));
+				}
+			}
+		}
+	}
+	expected.remove(&bedrock_pos);
+	assert_eq!(removed, expected);
+	assert_eq!(explosion.removed.len(), 32);
+	assert_eq!(map.get_blk(bedrock_pos), Some(bedrock));
+	assert_eq!(map.get_blk(center + Vector3::new(1, 0, 0)), Some(roles.air));
+	// Corners of the cube around the sphere stay
+	assert_eq!(map.get_blk(center + Vector3::new(2, 2, 0)), Some(roles.stone));
+	assert_eq!(map.get_blk(center + Vector3::new(1, 1, 2)), Some(roles.stone));
+
+	// Stone drops itself, some of the blocks dropped something
+	assert!(!explosion.drops.is_empty() && explosion.drops.len() < removed.len());
+	assert!(explosion.drops.iter().all(|(p, st)| removed.contains(p) &&
+		*st == Stack::with(roles.stone, 1)));
+	// The same rng state yields the same drops
+	let mut map_b = Map::from_backend(ClientBackend);
+	map_b.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.stone));
+	set(&mut map_b, bedrock_pos, bedrock);
+	let mut rng = Pcg32::new(42, 7);
+	let explosion_b = explode(&mut map_b, center, 2, &params.p, &mut rng);
+	assert_eq!(explosion_b.drops, explosion.drops);
+
+	// The sand pillar has fallen into the crater, down to the bottom
+	for z in 4 ..= 6 {
+		assert_eq!(map.get_blk(Vector3::new(8, 8, z)), Some(roles.sand));
+	}
+	for z in 7 ..= 11 {
+		assert_eq!(map.get_blk(Vector3::new(8, 8, z)), Some(roles.air));
+	}
+	assert_eq!(map.get_blk(Vector3::new(8, 8, 12)), Some(roles.stone));
+
+	// The items of chests are dropped
+	let params = std::sync::Arc::new(load_params_failible(NameIdMap::builtin_name_list()).unwrap());
+	let roles = &params.p.block_roles;
+	let chest = params.p.search_block_name("default:chest").unwrap();
+	let mut map_c = Map::from_backend(ClientBackend);
+	map_c.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.air));
+	let chest_pos = center + Vector3::new(1, 0, 0);
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.put(Stack::with(roles.stone, 12));
+	inv.put(Stack::with(roles.wood, 3));
+	set(&mut map_c, chest_pos, chest);
+	map_c.get_blk_meta_mut(chest_pos).unwrap().set(MetadataEntry::Inventory(inv));
+	let explosion = explode(&mut map_c, center, 2, &params.p, &mut rng);
+	assert_eq!(explosion.removed, vec![chest_pos]);
+	let chest_drops = explosion.drops.iter()
+		.filter(|(_, st)| st.content().map(|(it, _)| it) != Some(chest))
+		.collect::<Vec<_>>();
+	assert_eq!(chest_drops, vec![&(chest_pos, Stack::with(roles.stone, 12)),
+		&(chest_pos, Stack::with(roles.wood, 3))]);
+	assert_eq!(map_c.get_blk(chest_pos), Some(roles.air));
+
+	// The radius is bounded
+	let explosion = explode(&mut map, center, 100, &params.p, &mut rng);
+	assert!(explosion.removed.iter()
+		.all(|p| (p - center).map(|v| v * v).sum() <= MAX_EXPLOSION_RADIUS * MAX_EXPLOSION_RADIUS));
+}
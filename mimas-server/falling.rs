@@ -0,0 +1,154 @@
+use nalgebra::Vector3;
+use crate::map::{Map, MapBackend, MapBlock};
+use crate::game_params::{GameParams, is_falling, is_attached, is_solid};
+
+/// How far a block may fall at once
+const MAX_FALL_HEIGHT :isize = 64;
+
+/// Whether a falling block can move into the given block
+fn can_fall_into(blk :MapBlock, params :&GameParams) -> bool {
+	let roles = &params.block_roles;
+	blk == roles.air || blk == roles.water
+}
+
+/// Lets the falling blocks at and above pos drop down
+///
+/// Call this after the block at pos has changed. Starting at pos,
+/// each falling block of the stack above moves down until it rests
+/// on a block it can't fall into. Returns the number of blocks moved.
+pub fn settle_falling<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>,
+		params :&GameParams) -> usize {
+	let air = params.block_roles.air;
+	let mut moved = 0;
+	let mut p = pos;
+	while let Some(blk) = map.get_blk(p) {
+		if !is_falling(blk, params) {
+			if p == pos {
+				// The changed block itself might have supported the stack
+				p.z += 1;
+				continue;
+			}
+			break;
+		}
+		let mut target = p;
+		while p.z - target.z < MAX_FALL_HEIGHT {
+			let below = target - Vector3::new(0, 0, 1);
+			match map.get_blk(below) {
+				Some(b) if can_fall_into(b, params) => target = below,
+				_ => break,
+			}
+		}
+		if target != p {
+			if let Some(mut hdl) = map.get_blk_mut(target) {
+				hdl.set(blk);
+			}
+			if let Some(mut hdl) = map.get_blk_mut(p) {
+				hdl.set(air);
+			}
+			remove_unsupported(map, p, params);
+			moved += 1;
+		}
+		p.z += 1;
+	}
+	moved
+}
+
+/// Removes the attached block above pos if pos doesn't support it any more
+///
+/// Call this after the block at pos has changed.
+/// Returns whether a block was removed.
+pub fn remove_unsupported<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>,
+		params :&GameParams) -> bool {
+	let above = pos + Vector3::new(0, 0, 1);
+	let supported = map.get_blk(pos)
+		.map(|b| is_solid(b, params))
+		.unwrap_or(true);
+	match map.get_blk(above) {
+		Some(b) if !supported && is_attached(b, params) => {
+			map.get_blk_mut(above).unwrap().set(params.block_roles.air);
+			true
+		},
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_falling_blocks() {
+	use crate::map::{ClientBackend, MapChunkData};
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let mut map = Map::from_backend(ClientBackend);
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.air));
+	let set = |map :&mut Map<ClientBackend>, pos :Vector3<isize>, b :MapBlock| {
+		map.get_blk_mut(pos).unwrap().set(b);
+	};
+	let floor = Vector3::new(4, 4, 1);
+	set(&mut map, floor, roles.stone);
+
+	// Unsupported gravel falls down onto the floor
+	let pos = Vector3::new(4, 4, 6);
+	set(&mut map, pos, roles.gravel);
+	assert_eq!(settle_falling(&mut map, pos, &params.p), 1);
+	assert_eq!(map.get_blk(pos), Some(roles.air));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 2)), Some(roles.gravel));
+	// Supported gravel stays
+	assert_eq!(settle_falling(&mut map, Vector3::new(4, 4, 2), &params.p), 0);
+
+	// Removing the support makes the whole stack above fall
+	set(&mut map, Vector3::new(4, 4, 3), roles.sand);
+	set(&mut map, Vector3::new(4, 4, 4), roles.gravel);
+	set(&mut map, Vector3::new(4, 4, 5), roles.stone);
+	set(&mut map, floor, roles.air);
+	assert_eq!(settle_falling(&mut map, floor, &params.p), 3);
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 0)), Some(roles.gravel));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 1)), Some(roles.sand));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 2)), Some(roles.gravel));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 3)), Some(roles.air));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 4)), Some(roles.air));
+	// Stone doesn't fall
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 5)), Some(roles.stone));
+
+	// Falling stops at the edge of the loaded area
+	let pos = Vector3::new(8, 8, 3);
+	set(&mut map, pos, roles.gravel);
+	settle_falling(&mut map, pos, &params.p);
+	assert_eq!(map.get_blk(Vector3::new(8, 8, 0)), Some(roles.gravel));
+}
+
+#[cfg(test)]
+#[test]
+fn test_attached_blocks() {
+	use crate::map::{ClientBackend, MapChunkData};
+	use crate::game_params::{NameIdMap, load_params_failible};
+
+	let params = load_params_failible(NameIdMap::builtin_name_list()).unwrap();
+	let roles = &params.p.block_roles;
+	let flower = params.p.name_id_map.get_id("flowers:rose").unwrap();
+	let mut map = Map::from_backend(ClientBackend);
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.air));
+	let set = |map :&mut Map<ClientBackend>, pos :Vector3<isize>, b :MapBlock| {
+		map.get_blk_mut(pos).unwrap().set(b);
+	};
+	let ground = Vector3::new(4, 4, 1);
+	let above = Vector3::new(4, 4, 2);
+	set(&mut map, ground, roles.ground_top);
+	set(&mut map, above, flower);
+
+	// Supported decorations stay
+	assert!(!remove_unsupported(&mut map, ground, &params.p));
+	assert_eq!(map.get_blk(above), Some(flower));
+	// Removing the ground removes the decoration, too
+	set(&mut map, ground, roles.air);
+	assert!(remove_unsupported(&mut map, ground, &params.p));
+	assert_eq!(map.get_blk(above), Some(roles.air));
+
+	// Decorations on falling blocks vanish when they fall
+	set(&mut map, Vector3::new(4, 4, 0), roles.stone);
+	set(&mut map, Vector3::new(4, 4, 3), roles.sand);
+	set(&mut map, Vector3::new(4, 4, 4), flower);
+	assert_eq!(settle_falling(&mut map, Vector3::new(4, 4, 3), &params.p), 1);
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 1)), Some(roles.sand));
+	assert_eq!(map.get_blk(Vector3::new(4, 4, 4)), Some(roles.air));
+}
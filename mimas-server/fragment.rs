@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use crate::StrErr;
+
+/// The default maximum payload size of a fragment, in bytes
+pub const DEFAULT_FRAGMENT_SIZE :usize = 16 * 1024;
+
+/// How long to wait for the missing fragments of a message before dropping it
+pub const REASSEMBLY_TIMEOUT :Duration = Duration::from_secs(10);
+
+/// The maximum size of a message put together from fragments, in bytes
+pub const MAX_MESSAGE_SIZE :usize = 64 << 20;
+
+/// The maximum number of messages still missing fragments per stream
+const MAX_PENDING_MESSAGES :usize = 16;
+
+/// The maximum number of bytes buffered for the incomplete messages of a stream
+const MAX_BUFFERED_BYTES :usize = 2 * MAX_MESSAGE_SIZE;
+
+/// Splits messages into fragments with a payload of at most a given size
+///
+/// Each fragment starts with a header of the message id, the index
+/// of the fragment and the number of fragments of the message.
+/// Messages that fit into a fragment are sent as a single one.
+pub struct Fragmenter {
+	fragment_size :usize,
+	next_id :u32,
+}
+
+impl Fragmenter {
+	pub fn new(fragment_size :usize) -> Self {
+		Self {
+			fragment_size : fragment_size.max(1),
+			next_id : 0,
+		}
+	}
+	pub fn fragment(&mut self, msg :&[u8]) -> Vec<Vec<u8>> {
+		let id = self.next_id;
+		self.next_id = self.next_id.wrapping_add(1);
+		// Use larger fragments if the count wouldn't fit into the header
+		let max_count = u16::MAX as usize;
+		let size = self.fragment_size.max(msg.len().div_ceil(max_count));
+		let payloads = if msg.is_empty() {
+			vec![msg]
+		} else {
+			msg.chunks(size).collect::<Vec<_>>()
+		};
+		let count = payloads.len() as u16;
+		payloads.iter()
+			.enumerate()
+			.map(|(idx, payload)| {
+				let mut frag = Vec::with_capacity(8 + payload.len());
+				frag.write_u32::<BigEndian>(id).unwrap();
+				frag.write_u16::<BigEndian>(idx as u16).unwrap();
+				frag.write_u16::<BigEndian>(count).unwrap();
+				frag.extend_from_slice(payload);
+				frag
+			})
+			.collect()
+	}
+}
+
+/// A message of which only some fragments have arrived
+struct PartialMsg {
+	fragments :Vec<Option<Vec<u8>>>,
+	missing :usize,
+	started :Instant,
+	/// The payload size of all fragments but the last one
+	fragment_size :Option<usize>,
+	/// The number of payload bytes received so far
+	bytes :usize,
+}
+
+/// Puts the fragments created by a `Fragmenter` back together
+///
+/// As the headers come from the peer, the size of the messages
+/// and the number of incomplete messages are limited.
+pub struct Reassembler {
+	timeout :Duration,
+	partial :HashMap<u32, PartialMsg>,
+	/// The number of payload bytes of all incomplete messages
+	buffered :usize,
+}
+
+impl Reassembler {
+	pub fn new(timeout :Duration) -> Self {
+		Self {
+			timeout,
+			partial : HashMap::new(),
+			buffered : 0,
+		}
+	}
+	/// Adds a fragment, returning the message once all of its fragments arrived
+	///
+	/// The fragments of a message may arrive in any order.
+	pub fn add(&mut self, frag :&[u8], now :Instant) -> Result<Option<Vec<u8>>, StrErr> {
+		let mut rdr = frag;
+		let id = rdr.read_u32::<BigEndian>()?;
+		let idx = rdr.read_u16::<BigEndian>()? as usize;
+		let count = rdr.read_u16::<BigEndian>()? as usize;
+		if idx >= count {
+			Err(format!("Fragment index {} out of range for count {}", idx, count))?;
+		}
+		if rdr.len() > MAX_MESSAGE_SIZE {
+			Err(format!("Fragment of {} bytes exceeds the maximum message size", rdr.len()))?;
+		}
+		if count == 1 {
+			return Ok(Some(rdr.to_vec()));
+		}
+		if !self.partial.contains_key(&id) && self.partial.len() >= MAX_PENDING_MESSAGES {
+			Err(format!("More than {} incomplete messages", MAX_PENDING_MESSAGES))?;
+		}
+		let partial = self.partial.entry(id).or_insert_with(|| PartialMsg {
+			fragments : vec![None; count],
+			missing : count,
+			started : now,
+			fragment_size : None,
+			bytes : 0,
+		});
+		if partial.fragments.len() != count {
+			Err(format!("Mismatching fragment count for message {}", id))?;
+		}
+		if partial.fragments[idx].is_some() {
+			// Duplicate fragment
+			return Ok(None);
+		}
+		// The Fragmenter only makes the last fragment smaller
+		let last = idx + 1 == count;
+		match partial.fragment_size {
+			Some(size) if size != rdr.len() && !last => {
+				Err(format!("Mismatching fragment size for message {}", id))?;
+			},
+			Some(size) if rdr.len() > size => {
+				Err(format!("Last fragment of message {} too large", id))?;
+			},
+			None if !last => {
+				// A last fragment that arrived first is checked now
+				let last_len = partial.fragments[count - 1].as_ref().map_or(0, |f| f.len());
+				if last_len > rdr.len() {
+					Err(format!("Last fragment of message {} too large", id))?;
+				}
+				partial.fragment_size = Some(rdr.len());
+			},
+			_ => (),
+		}
+		let size = partial.fragment_size.unwrap_or(rdr.len()).saturating_mul(count - 1);
+		if size > MAX_MESSAGE_SIZE {
+			Err(format!("Message {} of {} fragments exceeds the maximum message size", id, count))?;
+		}
+		self.buffered += rdr.len();
+		if self.buffered > MAX_BUFFERED_BYTES {
+			Err(format!("More than {} bytes of incomplete messages", MAX_BUFFERED_BYTES))?;
+		}
+		partial.bytes += rdr.len();
+		partial.missing -= 1;
+		partial.fragments[idx] = Some(rdr.to_vec());
+		if partial.missing > 0 {
+			return Ok(None);
+		}
+		let partial = self.partial.remove(&id).unwrap();
+		self.buffered -= partial.bytes;
+		Ok(Some(partial.fragments.into_iter().flatten().flatten().collect()))
+	}
+	/// Drops the messages still missing fragments after the timeout
+	///
+	/// Returns the number of dropped messages.
+	pub fn drop_expired(&mut self, now :Instant) -> usize {
+		let timeout = self.timeout;
+		let count_before = self.partial.len();
+		let buffered = &mut self.buffered;
+		self.partial.retain(|_id, p| {
+			let keep = now.duration_since(p.started) < timeout;
+			if !keep {
+				*buffered -= p.bytes;
+			}
+			keep
+		});
+		count_before - self.partial.len()
+	}
+	/// The number of messages still missing fragments
+	pub fn pending_count(&self) -> usize {
+		self.partial.len()
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_fragmentation() {
+	let msg = (0 .. 10_000u32).map(|v| v as u8).collect::<Vec<_>>();
+	let mut fragmenter = Fragmenter::new(1000);
+	let mut reassembler = Reassembler::new(Duration::from_secs(10));
+	let start = Instant::now();
+
+	// Small messages are a single fragment
+	let frags = fragmenter.fragment(&msg[.. 1000]);
+	assert_eq!(frags.len(), 1);
+	assert_eq!(reassembler.add(&frags[0], start).unwrap().unwrap(), &msg[.. 1000]);
+	let frags = fragmenter.fragment(&[]);
+	assert_eq!(reassembler.add(&frags[0], start).unwrap().unwrap(), &[] as &[u8]);
+
+	// Fragments arriving out of order, interleaved with another message
+	let frags = fragmenter.fragment(&msg);
+	assert_eq!(frags.len(), 10);
+	let other = fragmenter.fragment(&msg[.. 1500]);
+	assert_eq!(other.len(), 2);
+	assert_eq!(reassembler.add(&other[1], start).unwrap(), None);
+	for &idx in &[3, 0, 9, 1, 2, 8, 4, 6, 5] {
+		assert_eq!(reassembler.add(&frags[idx], start).unwrap(), None);
+	}
+	// Duplicates don't complete a message
+	assert_eq!(reassembler.add(&frags[5], start).unwrap(), None);
+	assert_eq!(reassembler.pending_count(), 2);
+	assert_eq!(reassembler.add(&frags[7], start).unwrap().unwrap(), msg);
+	assert_eq!(reassembler.add(&other[0], start).unwrap().unwrap(), &msg[.. 1500]);
+	assert_eq!(reassembler.pending_count(), 0);
+
+	// A message with a dropped fragment gets dropped after the timeout
+	let frags = fragmenter.fragment(&msg);
+	for frag in frags.iter().skip(1) {
+		assert_eq!(reassembler.add(frag, start).unwrap(), None);
+	}
+	assert_eq!(reassembler.drop_expired(start + Duration::from_secs(9)), 0);
+	assert_eq!(reassembler.drop_expired(start + Duration::from_secs(10)), 1);
+	assert_eq!(reassembler.pending_count(), 0);
+	// The late fragment doesn't complete the message any more
+	assert_eq!(reassembler.add(&frags[0], start).unwrap(), None);
+
+	// Malformed fragments are rejected
+	assert!(reassembler.add(&[0, 0], start).is_err());
+	assert!(reassembler.add(&[0, 0, 0, 0, 0, 2, 0, 2], start).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_reassembly_limits() {
+	let start = Instant::now();
+	let header = |id :u32, idx :u16, count :u16| {
+		let mut frag = Vec::new();
+		frag.write_u32::<BigEndian>(id).unwrap();
+		frag.write_u16::<BigEndian>(idx).unwrap();
+		frag.write_u16::<BigEndian>(count).unwrap();
+		frag
+	};
+
+	// A header announcing more than the maximum message size
+	let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+	let mut frag = header(0, 0, 1000);
+	frag.extend_from_slice(&vec![0; 128 * 1024]);
+	assert!(reassembler.add(&frag, start).is_err());
+
+	// Fragments of one message need to have the same size
+	let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+	let mut frag = header(0, 0, 3);
+	frag.extend_from_slice(&[1; 10]);
+	assert_eq!(reassembler.add(&frag, start).unwrap(), None);
+	let mut frag = header(0, 1, 3);
+	frag.extend_from_slice(&[1; 20]);
+	assert!(reassembler.add(&frag, start).is_err());
+
+	// Also when the last fragment arrives first
+	let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+	let mut frag = header(0, 2, 3);
+	frag.extend_from_slice(&[1; 20]);
+	assert_eq!(reassembler.add(&frag, start).unwrap(), None);
+	let mut frag = header(0, 0, 3);
+	frag.extend_from_slice(&[1; 10]);
+	assert!(reassembler.add(&frag, start).is_err());
+	let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+	let mut frag = header(0, 2, 3);
+	frag.extend_from_slice(&[3; 5]);
+	assert_eq!(reassembler.add(&frag, start).unwrap(), None);
+	for idx in 0 .. 2 {
+		let mut frag = header(0, idx, 3);
+		frag.extend_from_slice(&[idx as u8; 10]);
+		let res = reassembler.add(&frag, start).unwrap();
+		assert_eq!(res.is_some(), idx == 1);
+	}
+
+	// The number of incomplete messages is limited
+	let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+	for id in 0 .. MAX_PENDING_MESSAGES as u32 {
+		let mut frag = header(id, 0, 2);
+		frag.extend_from_slice(&[1; 10]);
+		assert_eq!(reassembler.add(&frag, start).unwrap(), None);
+	}
+	let mut frag = header(1000, 0, 2);
+	frag.extend_from_slice(&[1; 10]);
+	assert!(reassembler.add(&frag, start).is_err());
+	// Completing a message makes room again
+	let mut frag = header(0, 1, 2);
+	frag.extend_from_slice(&[2; 5]);
+	let msg = reassembler.add(&frag, start).unwrap().unwrap();
+	assert_eq!(msg, [&[1; 10][..], &[2; 5][..]].concat());
+	assert_eq!(reassembler.buffered, 10 * (MAX_PENDING_MESSAGES - 1));
+	let mut frag = header(1000, 0, 2);
+	frag.extend_from_slice(&[1; 10]);
+	assert_eq!(reassembler.add(&frag, start).unwrap(), None);
+	assert_eq!(reassembler.drop_expired(start + REASSEMBLY_TIMEOUT), MAX_PENDING_MESSAGES);
+	assert_eq!(reassembler.buffered, 0);
+}
@@ -0,0 +1,78 @@
+/// The game mode of a player
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameMode {
+	Survival,
+	/// Like survival, but any block can be picked into the inventory
+	Creative,
+	/// Flies through all terrain, invisible to others and unable to edit
+	///
+	/// Useful for moderation.
+	Spectator,
+}
+
+impl Default for GameMode {
+	fn default() -> Self {
+		GameMode::Survival
+	}
+}
+
+impl GameMode {
+	pub const ALL :[GameMode; 3] = [
+		GameMode::Survival,
+		GameMode::Creative,
+		GameMode::Spectator,
+	];
+	pub fn name(self) -> &'static str {
+		match self {
+			GameMode::Survival => "Survival",
+			GameMode::Creative => "Creative",
+			GameMode::Spectator => "Spectator",
+		}
+	}
+	/// Looks up the game mode by its name, ignoring case
+	pub fn from_name(name :&str) -> Option<Self> {
+		Self::ALL.iter()
+			.find(|m| m.name().eq_ignore_ascii_case(name))
+			.copied()
+	}
+	/// Whether the player may dig, place or otherwise edit blocks
+	pub fn can_edit(self) -> bool {
+		self != GameMode::Spectator
+	}
+	/// Whether the player may get stacks of any block by picking it
+	pub fn can_pick_blocks(self) -> bool {
+		self == GameMode::Creative
+	}
+	/// Whether blocks break without dig time, and tools don't wear
+	pub fn digs_instantly(self) -> bool {
+		self == GameMode::Creative
+	}
+	/// Whether the player collides with the terrain
+	pub fn has_collision(self) -> bool {
+		self != GameMode::Spectator
+	}
+	/// Whether other players can see the player
+	pub fn is_visible(self) -> bool {
+		self != GameMode::Spectator
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_game_mode_names() {
+	for mode in GameMode::ALL.iter() {
+		assert_eq!(GameMode::from_name(mode.name()), Some(*mode));
+	}
+	assert_eq!(GameMode::from_name("spectator"), Some(GameMode::Spectator));
+	assert_eq!(GameMode::from_name("creative"), Some(GameMode::Creative));
+	assert_eq!(GameMode::from_name("adventure"), None);
+	assert!(GameMode::Survival.can_edit());
+	assert!(GameMode::Creative.can_edit());
+	assert!(GameMode::Creative.can_pick_blocks());
+	assert!(!GameMode::Survival.can_pick_blocks());
+	assert!(GameMode::Creative.digs_instantly());
+	assert!(!GameMode::Survival.digs_instantly());
+	assert!(!GameMode::Spectator.can_edit());
+	assert!(!GameMode::Spectator.has_collision());
+	assert!(!GameMode::Spectator.is_visible());
+}
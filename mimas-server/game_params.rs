@@ -1,4 +1,4 @@
-use crate::crafting::Recipe;
+use crate::crafting::{Recipe, CraftingGrid};
 use std::sync::Arc;
 use toml::from_str;
 use toml::value::{Value, Array, Table};
@@ -14,6 +14,8 @@ use std::str::FromStr;
 use std::io::Read;
 use crate::inventory::Stack;
 use crate::mapgen::{Schematic, self};
+use crate::logging::TARGET_SERVER;
+use nalgebra::Vector3;
 use sha2::{Sha256, Digest};
 
 pub type GameParamsHdl = Arc<GameParams>;
@@ -41,10 +43,94 @@ pub struct BlockParams {
 	pub placeable :bool,
 	pub solid :bool,
 	pub inventory :Option<u8>,
+	pub food :Option<u8>,
+	pub crafting_grid :Option<CraftingGrid>,
+	/// Whether the block records the direction it faces when placed
+	pub directional :bool,
+	/// How the block can be seen through
+	pub transparency :Transparency,
+	/// Whether the block hides the faces of its neighbours and blocks light
+	///
+	/// Defaults to true for opaque blocks drawn as cubes.
+	pub opaque :bool,
+	/// The number of uses if the item is a tool that wears down
+	pub durability :Option<u16>,
 	pub display_name :String,
 	pub drops :Stack,
 	pub dig_group :DigGroup,
 	pub tool_groups :Vec<ToolGroup>,
+	/// The sounds for placing and digging the block
+	pub sound_group :SoundGroup,
+	/// Whether the block falls down when there is nothing below it
+	pub falling :bool,
+	/// Whether the block needs a solid block below it,
+	/// and vanishes without drops when that one is removed
+	pub attached :bool,
+	/// A drop that occasionally replaces the usual one, with its chance
+	pub rare_drop :Option<(Stack, f64)>,
+	/// Whether explosions leave the block in place, like bedrock
+	pub indestructible :bool,
+}
+
+/// The material category of a block, deciding the sounds
+/// played when placing, digging or walking on it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum SoundGroup {
+	Generic,
+	Stone,
+	Wood,
+	Sand,
+	Grass,
+	Leaves,
+}
+
+impl Default for SoundGroup {
+	fn default() -> Self {
+		SoundGroup::Generic
+	}
+}
+
+impl SoundGroup {
+	pub fn from_name(name :&str) -> Option<Self> {
+		Some(match name {
+			"generic" => SoundGroup::Generic,
+			"stone" => SoundGroup::Stone,
+			"wood" => SoundGroup::Wood,
+			"sand" => SoundGroup::Sand,
+			"grass" => SoundGroup::Grass,
+			"leaves" => SoundGroup::Leaves,
+			_ => return None,
+		})
+	}
+}
+
+/// How a block can be seen through
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Transparency {
+	Opaque,
+	/// Fully transparent where the texture is, like the gaps of leaves
+	///
+	/// Faces between two such blocks are kept.
+	Cutout,
+	/// Uniformly see-through, like water
+	Blend,
+}
+
+impl Default for Transparency {
+	fn default() -> Self {
+		Transparency::Opaque
+	}
+}
+
+impl Transparency {
+	pub fn from_name(name :&str) -> Option<Self> {
+		Some(match name {
+			"opaque" => Transparency::Opaque,
+			"cutout" => Transparency::Cutout,
+			"blend" => Transparency::Blend,
+			_ => return None,
+		})
+	}
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
@@ -70,6 +156,7 @@ pub struct BlockRoles {
 	pub air :MapBlock,
 	pub water :MapBlock,
 	pub sand :MapBlock,
+	pub gravel :MapBlock,
 	pub ground :MapBlock,
 	pub ground_top :MapBlock,
 	pub wood :MapBlock,
@@ -132,10 +219,30 @@ pub struct Ore {
 	pub(crate) limit_boundary :isize,
 }
 
+/// The kinds of terrain surface, each with its own decorations
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+	Grassland,
+	Desert,
+}
+
+impl Biome {
+	pub const ALL :&'static [Biome] = &[Biome::Grassland, Biome::Desert];
+	pub fn from_name(name :&str) -> Option<Self> {
+		Some(match name {
+			"grassland" => Biome::Grassland,
+			"desert" => Biome::Desert,
+			_ => return None,
+		})
+	}
+}
+
 pub struct Plant {
 	pub(crate) block :MapBlock,
 	pub(crate) pcg_seed :[u8; 8],
 	pub(crate) pcg_limit :f64,
+	/// The biome the plant grows in
+	pub(crate) biome :Biome,
 }
 
 pub struct MapgenParams {
@@ -173,9 +280,20 @@ impl Default for BlockParams {
 			solid : true,
 			display_name : String::new(),
 			inventory : None,
+			food : None,
+			crafting_grid : None,
+			directional : false,
+			transparency : Transparency::Opaque,
+			opaque : true,
+			durability : None,
 			drops : Stack::Empty,
 			dig_group : DigGroup::default(),
 			tool_groups : Vec::new(),
+			sound_group : SoundGroup::default(),
+			falling : false,
+			attached : false,
+			rare_drop : None,
+			indestructible : false,
 		}
 	}
 }
@@ -186,6 +304,7 @@ impl BlockRoles {
 			air : get_id("default:air")?,
 			water : get_id("default:water")?,
 			sand : get_id("default:sand")?,
+			gravel : get_id("default:gravel")?,
 			ground : get_id("default:ground")?,
 			ground_top : get_id("default:ground_with_grass")?,
 			wood : get_id("default:wood")?,
@@ -219,6 +338,12 @@ impl Schematics {
 			cactus_schematic : mapgen::cactus_schematic(roles),
 		}
 	}
+	/// The largest extent of all schematics, per axis
+	pub fn max_extent(&self) -> Vector3<isize> {
+		let tree = self.tree_schematic.extent();
+		let cactus = self.cactus_schematic.extent();
+		tree.zip_map(&cactus, |a, b| a.max(b))
+	}
 }
 
 impl NameIdMap {
@@ -315,6 +440,32 @@ impl GameParams {
 			format!("{:?}", mb)
 		}
 	}
+	/// Creates a stack, giving tools their full durability
+	///
+	/// Tools have a stack size limit of 1.
+	pub fn stack_with(&self, item :MapBlock, count :u16) -> Stack {
+		let max_durability = self.get_block_params(item)
+			.and_then(|p| p.durability);
+		if let Some(max) = max_durability {
+			Stack::with_durability(item, count.min(1), Some((max, max)))
+		} else {
+			Stack::with(item, count)
+		}
+	}
+	/// Turns stacks from the params file into tool stacks if needed
+	fn tool_stack(&self, st :Stack) -> Result<Stack, StrErr> {
+		if let Some((item, count)) = st.content() {
+			let is_tool = self.get_block_params(item)
+				.and_then(|p| p.durability)
+				.is_some();
+			if is_tool && count > 1 {
+				Err(format!("Tool stacks may not have a count of {}", count))?
+			}
+			Ok(self.stack_with(item, count))
+		} else {
+			Ok(st)
+		}
+	}
 	pub fn search_block_name(&self, name :&str) -> Option<MapBlock> {
 		if let Some(n) = self.name_id_map.get_id(name) {
 			return Some(n);
@@ -358,6 +509,74 @@ pub(crate) fn parse_block_name(name :&str) -> Result<(&str, &str), StrErr> {
 	}
 }
 
+/// What breaking the block yields, if anything
+///
+/// Set by the `drops` key of the block, defaulting to the block
+/// itself. An empty stack specifier means that nothing is dropped.
+pub fn drops_for(block :MapBlock, params :&GameParams) -> Option<Stack> {
+	params.get_block_params(block)
+		.map(|p| p.drops)
+		.filter(|st| !st.is_empty())
+}
+
+/// What breaking the block yields, given a random roll in [0, 1)
+///
+/// Like `drops_for`, but if the block has a `rare_drop`,
+/// it is yielded instead when the roll is below its chance.
+pub fn drops_for_roll(block :MapBlock, params :&GameParams, roll :f64) -> Option<Stack> {
+	let rare = params.get_block_params(block)
+		.and_then(|p| p.rare_drop)
+		.filter(|(_st, chance)| roll < *chance);
+	if let Some((st, _chance)) = rare {
+		return Some(st);
+	}
+	drops_for(block, params)
+}
+
+/// Whether the block falls down when there is nothing below it
+pub fn is_falling(block :MapBlock, params :&GameParams) -> bool {
+	params.get_block_params(block)
+		.map(|p| p.falling)
+		.unwrap_or(false)
+}
+
+/// Whether explosions leave the block in place
+pub fn is_indestructible(block :MapBlock, params :&GameParams) -> bool {
+	params.get_block_params(block)
+		.map(|p| p.indestructible)
+		.unwrap_or(false)
+}
+
+/// Whether the block vanishes when the block below it is removed
+pub fn is_attached(block :MapBlock, params :&GameParams) -> bool {
+	params.get_block_params(block)
+		.map(|p| p.attached)
+		.unwrap_or(false)
+}
+
+/// Whether the block is solid, so that players and entities collide with it
+pub fn is_solid(block :MapBlock, params :&GameParams) -> bool {
+	params.get_block_params(block)
+		.map(|p| p.solid)
+		.unwrap_or(false)
+}
+
+/// Whether the block hides the faces of its neighbours and blocks light
+pub fn is_opaque(block :MapBlock, params :&GameParams) -> bool {
+	params.get_block_params(block)
+		.map(|p| p.opaque)
+		.unwrap_or(false)
+}
+
+/// The sound group of the block
+///
+/// Set by the `sound_group` key of the block, defaulting to generic sounds.
+pub fn sound_group(block :MapBlock, params :&GameParams) -> SoundGroup {
+	params.get_block_params(block)
+		.map(|p| p.sound_group)
+		.unwrap_or_default()
+}
+
 pub fn resolve_stack_specifier(nm :&NameIdMap, sp :&str)
 		-> Result<Stack, StrErr> {
 	if sp.is_empty() {
@@ -370,7 +589,7 @@ pub fn resolve_stack_specifier(nm :&NameIdMap, sp :&str)
 		let count = u16::from_str(count)?;
 		let count = NonZeroU16::new(count)
 			.ok_or_else(|| format!("Count may not be 0. Use \"\" instead."))?;
-		Ok(Stack::Content { item, count })
+		Ok(Stack::Content { item, count, durability : None })
 	} else {
 		Err(format!("Invalid stack specifier '{}'. Must be in format 'modname:name count'.", sp))?;
 		unreachable!()
@@ -517,6 +736,57 @@ fn from_val(val :Value, nm_from_db :NameIdMap) -> Result<ServerGameParams, StrEr
 		} else {
 			None
 		};
+		let food = if let Some(v) = block.get("food") {
+			Some(*v.convert::<i64>()? as u8)
+		} else {
+			None
+		};
+		let crafting_grid = if let Some(v) = block.get("crafting_grid") {
+			let dims = v.convert::<Array>()?;
+			if dims.len() != 2 {
+				Err(format!("crafting_grid needs 2 dimensions but has {}", dims.len()))?
+			}
+			Some(CraftingGrid {
+				width : *dims[0].convert::<i64>()? as usize,
+				height : *dims[1].convert::<i64>()? as usize,
+			})
+		} else {
+			None
+		};
+		let directional = block.get("directional")
+			.unwrap_or(&Value::Boolean(false));
+		let directional = *directional.convert::<bool>()?;
+		// Setting translucent is a shorthand for blend transparency
+		let translucent = block.get("translucent")
+			.unwrap_or(&Value::Boolean(false));
+		let translucent = *translucent.convert::<bool>()?;
+		let transparency = if let Some(t) = block.get("transparency") {
+			let t = t.convert::<str>()?;
+			Transparency::from_name(t)
+				.ok_or_else(|| format!("Unknown transparency '{}'", t))?
+		} else if translucent {
+			Transparency::Blend
+		} else {
+			Transparency::Opaque
+		};
+		let opaque = if let Some(v) = block.get("opaque") {
+			*v.convert::<bool>()?
+		} else {
+			let cube = match &draw_style {
+				Some(DrawStyle::Crossed(_)) | None => false,
+				Some(_) => true,
+			};
+			cube && transparency == Transparency::Opaque
+		};
+		let durability = if let Some(v) = block.get("durability") {
+			let d = *v.convert::<i64>()?;
+			if d <= 0 || d > u16::MAX as i64 {
+				Err(format!("durability {} is out of range", d))?
+			}
+			Some(d as u16)
+		} else {
+			None
+		};
 		let drops = if let Some(drops) = block.get("drops") {
 			let drops_sp = drops.convert::<str>()?;
 			resolve_stack_specifier(&name_id_map, drops_sp)?
@@ -547,6 +817,34 @@ fn from_val(val :Value, nm_from_db :NameIdMap) -> Result<ServerGameParams, StrEr
 			Vec::new()
 		};
 
+		let sound_group = if let Some(sg) = block.get("sound_group") {
+			let sg = sg.convert::<str>()?;
+			SoundGroup::from_name(sg)
+				.ok_or_else(|| format!("Unknown sound group '{}'", sg))?
+		} else {
+			SoundGroup::default()
+		};
+		let falling = block.get("falling")
+			.unwrap_or(&Value::Boolean(false));
+		let falling = *falling.convert::<bool>()?;
+		let attached = block.get("attached")
+			.unwrap_or(&Value::Boolean(false));
+		let attached = *attached.convert::<bool>()?;
+		let rare_drop = if let Some(rd) = block.get("rare_drop") {
+			let rd = resolve_stack_specifier(name_id_map, rd.convert::<str>()?)?;
+			let chance = *block.read::<f64>("rare_drop_chance")?;
+			if !(0.0 ..= 1.0).contains(&chance) {
+				Err(format!("rare_drop_chance {} is out of range", chance))?
+			}
+			Some((rd, chance))
+		} else {
+			None
+		};
+
+		let indestructible = block.get("indestructible")
+			.unwrap_or(&Value::Boolean(false));
+		let indestructible = *indestructible.convert::<bool>()?;
+
 		let block_params = BlockParams {
 			draw_style,
 			pointable,
@@ -554,9 +852,20 @@ fn from_val(val :Value, nm_from_db :NameIdMap) -> Result<ServerGameParams, StrEr
 			solid,
 			display_name,
 			inventory,
+			food,
+			crafting_grid,
+			directional,
+			transparency,
+			opaque,
+			durability,
 			drops,
 			dig_group,
 			tool_groups,
+			sound_group,
+			falling,
+			attached,
+			rare_drop,
+			indestructible,
 		};
 		params.p.block_params[id.id() as usize] = block_params;
 	}
@@ -586,17 +895,31 @@ fn from_val(val :Value, nm_from_db :NameIdMap) -> Result<ServerGameParams, StrEr
 					let name = input.convert::<str>()?;
 					if name == "" {
 						Ok(None)
+					} else if name.contains(' ') {
+						// An input with a count, like "default:wood 2"
+						Ok(resolve_stack_specifier(name_id_map, name)?.content())
 					} else {
 						let mb = name_id_map.get_id(name).ok_or("invalid name")?;
-						Ok(Some(mb))
+						Ok(Some((mb, 1)))
 					}
 				})
-				.collect::<Result<Vec<Option<MapBlock>>, StrErr>>()?;
+				.collect::<Result<Vec<Option<(MapBlock, u16)>>, StrErr>>()?;
+			// Recipes are square unless a width is specified
+			let width = if let Some(w) = recipe.get("width") {
+				*w.convert::<i64>()? as usize
+			} else {
+				(inputs.len() as f32).sqrt() as usize
+			};
+			if width == 0 || inputs.len() % width != 0 {
+				Err(format!("recipe inputs of length {} don't fit width {}",
+					inputs.len(), width))?
+			}
 			let output_sp = recipe.read::<str>("output")?;
 			let output = resolve_stack_specifier(&name_id_map, output_sp)?;
 
 			params.p.recipes.push(Recipe {
 				inputs,
+				width,
 				output,
 			});
 		}
@@ -660,16 +983,34 @@ fn from_val(val :Value, nm_from_db :NameIdMap) -> Result<ServerGameParams, StrEr
 					pcg_seed[4], pcg_seed[5], pcg_seed[6], pcg_seed[7]];
 
 				let pcg_limit = *plant.read::<f64>("pcg_limit")?;
+				let biome = if let Some(b) = plant.get("biome") {
+					let b = b.convert::<str>()?;
+					Biome::from_name(b)
+						.ok_or_else(|| format!("Unknown biome '{}'", b))?
+				} else {
+					Biome::Grassland
+				};
 				params.mapgen_params.plants.push(Plant {
 					block : id,
 					pcg_seed,
 					pcg_limit,
+					biome,
 				});
 			}
 
 		}
 	}
 
+	// Now that all blocks are known, give the tools their durability
+	for i in 0 .. params.p.block_params.len() {
+		let drops = params.p.tool_stack(params.p.block_params[i].drops)?;
+		params.p.block_params[i].drops = drops;
+	}
+	for i in 0 .. params.p.recipes.len() {
+		let output = params.p.tool_stack(params.p.recipes[i].output)?;
+		params.p.recipes[i].output = output;
+	}
+
 	Ok(params)
 }
 
@@ -687,10 +1028,207 @@ fn default_game_params_parse_test() {
 	default_game_params(nm).unwrap();
 }
 
+#[cfg(test)]
+#[test]
+fn test_drops_for() {
+	let nm = NameIdMap::builtin_name_list();
+	let params = default_game_params(nm).unwrap().p;
+	let nm = &params.name_id_map;
+	let id = |name :&str| nm.get_id(name).unwrap();
+	let special = [
+		("default:air", None),
+		("default:ground_with_grass", Some("default:ground")),
+		("default:stone", Some("default:cobble")),
+		("default:grass_1", Some("default:grass_3")),
+		("default:grass_2", Some("default:grass_3")),
+		("default:grass_4", Some("default:grass_3")),
+		("default:grass_5", Some("default:grass_3")),
+	];
+	for (name, drop) in special.iter() {
+		let expected = drop.map(|d| Stack::with(id(d), 1));
+		assert_eq!(drops_for(id(name), &params), expected, "{}", name);
+	}
+	// All other blocks drop themselves
+	for name in nm.names() {
+		if special.iter().any(|(n, _)| n == name) {
+			continue;
+		}
+		let mb = id(name);
+		assert_eq!(drops_for(mb, &params), Some(params.stack_with(mb, 1)), "{}", name);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_gravel_params() {
+	let nm = NameIdMap::builtin_name_list();
+	let params = default_game_params(nm).unwrap().p;
+	let id = |name :&str| params.name_id_map.get_id(name).unwrap();
+	let (gravel, sand, stone, flint) = (id("default:gravel"),
+		id("default:sand"), id("default:stone"), id("default:flint"));
+	assert_eq!(params.block_roles.gravel, gravel);
+	assert!(is_falling(gravel, &params));
+	assert!(is_falling(sand, &params));
+	assert!(!is_falling(stone, &params));
+
+	// Gravel occasionally drops flint instead of itself
+	let (_, chance) = params.get_block_params(gravel).unwrap().rare_drop.unwrap();
+	assert!(chance > 0.0 && chance < 1.0);
+	assert_eq!(drops_for_roll(gravel, &params, 0.0), Some(Stack::with(flint, 1)));
+	assert_eq!(drops_for_roll(gravel, &params, chance), Some(Stack::with(gravel, 1)));
+	assert_eq!(drops_for_roll(gravel, &params, 0.99), Some(Stack::with(gravel, 1)));
+	// Blocks without a rare drop are unaffected by the roll
+	assert_eq!(drops_for_roll(stone, &params, 0.0), drops_for(stone, &params));
+}
+
+#[cfg(test)]
+#[test]
+fn test_sound_group() {
+	let nm = NameIdMap::builtin_name_list();
+	let params = default_game_params(nm).unwrap().p;
+	let nm = &params.name_id_map;
+	let expected = |name :&str| {
+		let (_, block) = name.split_at(name.find(':').unwrap() + 1);
+		match block {
+			"air" | "water" | "apple" | "flint" => SoundGroup::Generic,
+			"stone" | "cobble" | "coal" | "iron_ore" | "copper_ore" |
+				"gold_ore" | "diamond_ore" => SoundGroup::Stone,
+			"wood" | "tree" | "cactus" | "chest" | "crafting_table" => SoundGroup::Wood,
+			"sand" | "gravel" => SoundGroup::Sand,
+			"ground" | "ground_with_grass" => SoundGroup::Grass,
+			"leaves" => SoundGroup::Leaves,
+			_ if block.starts_with("grass_") => SoundGroup::Grass,
+			_ if name.starts_with("flowers:") => SoundGroup::Grass,
+			_ => panic!("no expected sound group for {}", name),
+		}
+	};
+	for name in nm.names() {
+		let mb = nm.get_id(name.as_str()).unwrap();
+		assert_eq!(sound_group(mb, &params), expected(name), "{}", name);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_transparency() {
+	let nm = NameIdMap::builtin_name_list();
+	let params = default_game_params(nm).unwrap().p;
+	let nm = &params.name_id_map;
+	let transparency = |name :&str| {
+		let mb = nm.get_id(name).unwrap();
+		params.get_block_params(mb).unwrap().transparency
+	};
+	assert_eq!(transparency("default:stone"), Transparency::Opaque);
+	assert_eq!(transparency("default:leaves"), Transparency::Cutout);
+	assert_eq!(transparency("default:water"), Transparency::Blend);
+	assert_eq!(Transparency::from_name("cutout"), Some(Transparency::Cutout));
+	assert_eq!(Transparency::from_name("glass"), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_solid_opaque() {
+	let nm = NameIdMap::builtin_name_list();
+	let params = default_game_params(nm).unwrap().p;
+	let nm = &params.name_id_map;
+	// Whether the block is solid and whether it is opaque
+	let expected = |name :&str| {
+		let (_, block) = name.split_at(name.find(':').unwrap() + 1);
+		match block {
+			"air" | "water" => (false, false),
+			"leaves" => (true, false),
+			"ground" | "ground_with_grass" | "sand" | "gravel" | "wood" |
+				"stone" | "cobble" | "tree" | "cactus" | "coal" | "iron_ore" |
+				"copper_ore" | "gold_ore" | "diamond_ore" | "chest" |
+				"crafting_table" | "apple" | "flint" => (true, true),
+			_ if block.starts_with("grass_") => (false, false),
+			_ if name.starts_with("flowers:") => (false, false),
+			_ => panic!("no expected properties for {}", name),
+		}
+	};
+	for name in nm.names() {
+		let mb = nm.get_id(name.as_str()).unwrap();
+		assert_eq!((is_solid(mb, &params), is_opaque(mb, &params)), expected(name), "{}", name);
+	}
+}
+
+/// Minimal game params for tests that don't need any texture files
+#[cfg(test)]
+pub(crate) fn test_params() -> ServerGameParamsHdl {
+	let file_str = r#"
+		override-default = true
+
+		[[block]]
+		name = "default:air"
+		drops = ""
+		color = false
+		pointable = false
+		solid = false
+
+		[[block]]
+		name = "default:water"
+		color = [0.0, 0.0, 1.0, 1.0]
+		solid = false
+
+		[[block]]
+		name = "default:sand"
+		color = [0.9, 0.9, 0.5, 1.0]
+		falling = true
+
+		[[block]]
+		name = "default:gravel"
+		color = [0.45, 0.4, 0.4, 1.0]
+		falling = true
+
+		[[block]]
+		name = "default:ground"
+		color = [0.5, 0.3, 0.1, 1.0]
+
+		[[block]]
+		name = "default:ground_with_grass"
+		drops = "default:ground 1"
+		color = [0.1, 0.6, 0.1, 1.0]
+
+		[[block]]
+		name = "default:wood"
+		color = [0.6, 0.4, 0.2, 1.0]
+
+		[[block]]
+		name = "default:stone"
+		color = [0.5, 0.5, 0.5, 1.0]
+
+		[[block]]
+		name = "default:leaves"
+		color = [0.0, 0.4, 0.0, 1.0]
+
+		[[block]]
+		name = "default:tree"
+		color = [0.4, 0.2, 0.0, 1.0]
+
+		[[block]]
+		name = "default:cactus"
+		color = [0.0, 0.5, 0.0, 1.0]
+
+		[[block]]
+		name = "default:apple"
+		color = [0.8, 0.1, 0.1, 1.0]
+		placeable = false
+		food = 4
+
+		[[block]]
+		name = "default:bedrock"
+		color = [0.1, 0.1, 0.1, 1.0]
+		indestructible = true
+	"#;
+	let val = from_str(file_str).unwrap();
+	let nm = NameIdMap::builtin_name_list();
+	Arc::new(from_val(val, nm).unwrap())
+}
+
 pub fn load_params_failible(nm :NameIdMap) -> Result<ServerGameParams, StrErr> {
 	let file_str = read_to_string("game-params.toml")
 		.unwrap_or_else(|err| {
-			println!("Using default game params because of error: {}", err);
+			warn!(target : TARGET_SERVER, "Using default game params because of error: {}", err);
 			DEFAULT_GAME_PARAMS_STR.to_owned()
 		});
 
@@ -3,9 +3,11 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::net::{TcpStream, TcpListener, SocketAddr, ToSocketAddrs};
 use std::io::{Read, Write, Error as IoError, ErrorKind};
 use std::mem::replace;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::{ClientToServerMsg, ServerToClientMsg};
 use bincode::{serialize, deserialize};
 use crate::StrErr;
+use crate::logging::TARGET_NET;
 
 pub trait NetworkServerSocket {
 	type Conn :NetworkServerConn + 'static;
@@ -13,10 +15,21 @@ pub trait NetworkServerSocket {
 }
 
 pub trait NetworkServerConn {
-	fn try_recv(&mut self) -> Result<Option<ClientToServerMsg>, NetErr>;
+	fn try_recv(&mut self) -> Result<Option<ClientToServerMsg>, NetErr> {
+		Ok(self.try_recv_seq()?.map(|(_seq, msg)| msg))
+	}
+	/// Receives a message along with the number `next_recv_seq` gave it on arrival
+	fn try_recv_seq(&mut self) -> Result<Option<(u64, ClientToServerMsg)>, NetErr>;
 	fn send(&self, msg :ServerToClientMsg) -> Result<(), NetErr>;
 }
 
+static RECV_SEQ :AtomicU64 = AtomicU64::new(0);
+
+/// Numbers the received messages in the order they arrive, across all connections
+pub fn next_recv_seq() -> u64 {
+	RECV_SEQ.fetch_add(1, Ordering::Relaxed)
+}
+
 pub trait NetworkClientConn {
 	fn try_recv(&mut self) -> Result<Option<ServerToClientMsg>, NetErr>;
 	fn send(&self, msg :ClientToServerMsg) -> Result<(), NetErr>;
@@ -37,12 +50,12 @@ pub struct MpscServerSocket {
 
 pub struct MpscServerConn {
 	stc_s :Sender<ServerToClientMsg>,
-	cts_r :Receiver<ClientToServerMsg>,
+	cts_r :Receiver<(u64, ClientToServerMsg)>,
 }
 
 pub struct MpscClientConn {
 	stc_r :Receiver<ServerToClientMsg>,
-	cts_s :Sender<ClientToServerMsg>,
+	cts_s :Sender<(u64, ClientToServerMsg)>,
 }
 
 impl NetworkServerSocket for MpscServerSocket {
@@ -53,7 +66,7 @@ impl NetworkServerSocket for MpscServerSocket {
 }
 
 impl NetworkServerConn for MpscServerConn {
-	fn try_recv(&mut self) -> Result<Option<ClientToServerMsg>, NetErr> {
+	fn try_recv_seq(&mut self) -> Result<Option<(u64, ClientToServerMsg)>, NetErr> {
 		Ok(self.cts_r.try_recv().ok())
 	}
 	fn send(&self, msg :ServerToClientMsg) -> Result<(), NetErr> {
@@ -67,7 +80,7 @@ impl NetworkClientConn for MpscClientConn {
 		Ok(self.stc_r.try_recv().ok())
 	}
 	fn send(&self, msg :ClientToServerMsg) -> Result<(), NetErr> {
-		let _ = self.cts_s.send(msg);
+		let _ = self.cts_s.send((next_recv_seq(), msg));
 		Ok(())
 	}
 }
@@ -100,7 +113,8 @@ impl MpscServerConn {
 
 pub trait MsgStream {
 	fn send_msg(&self, buf :&[u8]) -> Result<(), NetErr>;
-	fn try_recv_msg(&mut self) -> Result<Option<Vec<u8>>, NetErr>;
+	/// Receives a message along with its number from `next_recv_seq`
+	fn try_recv_msg(&mut self) -> Result<Option<(u64, Vec<u8>)>, NetErr>;
 }
 
 pub struct MsgStreamServerConn<M :MsgStream> {
@@ -130,11 +144,11 @@ pub struct TcpMsgStream {
 }
 
 impl<M :MsgStream> NetworkServerConn for MsgStreamServerConn<M> {
-	fn try_recv(&mut self) -> Result<Option<ClientToServerMsg>, NetErr> {
+	fn try_recv_seq(&mut self) -> Result<Option<(u64, ClientToServerMsg)>, NetErr> {
 		let msg = self.stream.try_recv_msg()?;
-		if let Some(msg) = msg {
-			//println!("server recv: {} {:?}", msg.len(), &msg[..4]);
-			Ok(Some(deserialize(&msg).unwrap()))
+		if let Some((seq, msg)) = msg {
+			trace!(target : TARGET_NET, "server recv: {} bytes", msg.len());
+			Ok(Some((seq, deserialize(&msg).unwrap())))
 		} else {
 			Ok(None)
 		}
@@ -142,7 +156,7 @@ impl<M :MsgStream> NetworkServerConn for MsgStreamServerConn<M> {
 	fn send(&self, msg :ServerToClientMsg) -> Result<(), NetErr> {
 		//self.stream.send_msg(&serialize(&msg).unwrap());
 		let buf = &serialize(&msg).unwrap();
-		//println!("server send: {} {:?}", buf.len(), &buf[..4]);
+		trace!(target : TARGET_NET, "server send: {} bytes", buf.len());
 		let _ :ServerToClientMsg = deserialize(&buf).unwrap();
 		self.stream.send_msg(buf)
 	}
@@ -151,8 +165,8 @@ impl<M :MsgStream> NetworkServerConn for MsgStreamServerConn<M> {
 impl<M :MsgStream> NetworkClientConn for MsgStreamClientConn<M> {
 	fn try_recv(&mut self) -> Result<Option<ServerToClientMsg>, NetErr> {
 		let msg = self.stream.try_recv_msg()?;
-		if let Some(msg) = msg {
-			//println!("client recv: {} {:?}", msg.len(), &msg[..4]);
+		if let Some((_seq, msg)) = msg {
+			trace!(target : TARGET_NET, "client recv: {} bytes", msg.len());
 			Ok(Some(deserialize(&msg).unwrap()))
 		} else {
 			Ok(None)
@@ -161,7 +175,7 @@ impl<M :MsgStream> NetworkClientConn for MsgStreamClientConn<M> {
 	fn send(&self, msg :ClientToServerMsg) -> Result<(), NetErr> {
 		//self.stream.send_msg(&serialize(&msg).unwrap());
 		let buf = &serialize(&msg).unwrap();
-		//println!("client send: {} {:?}", buf.len(), &buf[..4]);
+		trace!(target : TARGET_NET, "client send: {} bytes", buf.len());
 		let _ :ClientToServerMsg = deserialize(&buf).unwrap();
 		self.stream.send_msg(buf)
 	}
@@ -204,7 +218,7 @@ impl MsgStream for TcpMsgStream {
 		(&self.tcp_stream).flush()?;
 		Ok(())
 	}
-	fn try_recv_msg(&mut self) -> Result<Option<Vec<u8>>, NetErr> {
+	fn try_recv_msg(&mut self) -> Result<Option<(u64, Vec<u8>)>, NetErr> {
 		// Set it to nonblocking mode because we do support partial receiving of data.
 		self.tcp_stream.set_nonblocking(true)?;
 		if self.len_read < LEN_BYTES {
@@ -228,7 +242,9 @@ impl MsgStream for TcpMsgStream {
 				let ret = replace(&mut self.cached, Vec::new());
 				self.cached_count = 0;
 				self.len_read = 0;
-				return Ok(Some(ret));
+				// Only polled on demand, so this is
+				// when we received the message
+				return Ok(Some((next_recv_seq(), ret)));
 			}
 		}
 		Ok(None)
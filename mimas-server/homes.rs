@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::str;
+use toml::{from_str, to_string};
+use crate::map_storage::PlayerPosition;
+use crate::whitelist::is_valid_nick;
+use super::StrErr;
+
+/// The name of the home used when none is given
+pub const DEFAULT_HOME_NAME :&str = "home";
+
+/// The personal teleport targets of the players
+///
+/// It is stored in the global kv of the map storage as TOML,
+/// with a table of homes for each nick.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Homes {
+	homes :BTreeMap<String, BTreeMap<String, PlayerPosition>>,
+}
+
+impl Homes {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn get(&self, nick :&str, name :&str) -> Option<PlayerPosition> {
+		self.homes.get(nick)?.get(name).copied()
+	}
+	/// Sets the home of the player, replacing any with the same name
+	///
+	/// Fails if the name is invalid, or if it's a new home
+	/// and the player already has `max` homes.
+	pub fn set(&mut self, nick :&str, name :&str, pos :PlayerPosition,
+			max :usize) -> Result<(), StrErr> {
+		// Home names follow the same rules as nicks
+		if !is_valid_nick(name) {
+			Err(format!("Invalid home name '{}'", name))?;
+		}
+		let homes = self.homes.entry(nick.to_owned()).or_default();
+		if !homes.contains_key(name) && homes.len() >= max {
+			Err(format!("You can't have more than {} homes", max))?;
+		}
+		homes.insert(name.to_owned(), pos);
+		Ok(())
+	}
+	/// Removes the home of the player, returning whether it was present
+	pub fn remove(&mut self, nick :&str, name :&str) -> bool {
+		let homes = if let Some(homes) = self.homes.get_mut(nick) {
+			homes
+		} else {
+			return false;
+		};
+		let removed = homes.remove(name).is_some();
+		if homes.is_empty() {
+			self.homes.remove(nick);
+		}
+		removed
+	}
+	/// The names of the homes of the player, in alphabetical order
+	pub fn names(&self, nick :&str) -> Vec<&str> {
+		self.homes.get(nick)
+			.map(|homes| homes.keys().map(|n| n.as_str()).collect())
+			.unwrap_or_default()
+	}
+	pub fn serialize(&self) -> Result<Vec<u8>, StrErr> {
+		Ok(to_string(self)?.into_bytes())
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		Ok(from_str(str::from_utf8(buf)?)?)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_homes() {
+	use nalgebra::Vector3;
+
+	let pos = |x| PlayerPosition::from_pos(Vector3::new(x, 2.0, 3.0));
+	let mut homes = Homes::new();
+	homes.set("alice", DEFAULT_HOME_NAME, pos(1.0), 2).unwrap();
+	homes.set("alice", "mine", pos(2.0), 2).unwrap();
+	homes.set("bob", "mine", pos(3.0), 2).unwrap();
+	assert_eq!(homes.names("alice"), vec!["home", "mine"]);
+	assert_eq!(homes.get("bob", "mine").unwrap().pos(), pos(3.0).pos());
+	assert!(homes.get("bob", DEFAULT_HOME_NAME).is_none());
+	assert!(homes.set("alice", "not valid", pos(4.0), 2).is_err());
+
+	// Replacing a home doesn't count towards the limit
+	assert!(homes.set("alice", "tower", pos(4.0), 2).is_err());
+	homes.set("alice", "mine", pos(5.0), 2).unwrap();
+	assert_eq!(homes.get("alice", "mine").unwrap().pos(), pos(5.0).pos());
+
+	assert!(homes.remove("alice", "mine"));
+	assert!(!homes.remove("alice", "mine"));
+	homes.set("alice", "tower", pos(4.0), 2).unwrap();
+
+	let round_trip = Homes::deserialize(&homes.serialize().unwrap()).unwrap();
+	assert_eq!(round_trip.names("alice"), vec!["home", "tower"]);
+	assert_eq!(round_trip.get("alice", "tower").unwrap().pos(), pos(4.0).pos());
+	assert!(round_trip.names("carol").is_empty());
+}
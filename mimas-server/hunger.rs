@@ -0,0 +1,317 @@
+use nalgebra::Vector3;
+use toml::from_str;
+use std::str;
+use std::time::Instant;
+use super::StrErr;
+use crate::game_params::GameParams;
+use crate::inventory::SelectableInventory;
+use crate::difficulty::Difficulty;
+
+pub const MAX_HUNGER :f32 = 20.0;
+
+/// Hunger lost per second just by being alive
+const BASE_DEPLETION :f32 = 0.02;
+/// Additional hunger lost per second of sprinting
+const SPRINT_DEPLETION :f32 = 0.15;
+/// Hunger lost per jump
+const JUMP_DEPLETION :f32 = 0.1;
+
+/// Horizontal speed in blocks per second above
+/// which a player is regarded as sprinting.
+///
+/// Walking is 10 blocks per second, sprinting 40.
+const SPRINT_SPEED :f32 = 20.0;
+/// Upwards speed in blocks per second above
+/// which a player is regarded as jumping.
+///
+/// This is above the speed of (fast) flying upwards.
+const JUMP_SPEED :f32 = 50.0;
+/// Horizontal speed in blocks per second above which
+/// a position change is regarded as a teleport
+const TELEPORT_SPEED :f32 = 100.0;
+/// Minimum time in seconds over which the horizontal speed is measured
+///
+/// Position updates can arrive in bursts, so single
+/// updates don't say much about the speed.
+const SPRINT_SAMPLE_SECS :f32 = 0.25;
+/// Minimum time in seconds assumed between two position updates
+const MIN_UPDATE_SECS :f32 = 1.0 / 60.0;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Hunger {
+	value :f32,
+}
+
+impl Default for Hunger {
+	fn default() -> Self {
+		Self {
+			value : MAX_HUNGER,
+		}
+	}
+}
+
+impl Hunger {
+	pub fn value(&self) -> f32 {
+		self.value
+	}
+	pub fn can_sprint(&self) -> bool {
+		self.value > 0.0
+	}
+	/// Deplete the hunger according to the time passed
+	/// and the activity of the player during that time
+	pub fn tick(&mut self, dt :f32, sprint_secs :f32, jumps :u32,
+			difficulty :Difficulty) {
+		let mut depletion = BASE_DEPLETION * dt;
+		if self.can_sprint() {
+			depletion += SPRINT_DEPLETION * sprint_secs;
+		}
+		depletion += JUMP_DEPLETION * jumps as f32;
+		depletion *= difficulty.hunger_depletion_multiplier();
+		// TODO once players have health, damage them while starving
+		self.value = (self.value - depletion).max(0.0);
+	}
+	pub fn eat(&mut self, food :u8) {
+		self.value = (self.value + food as f32).min(MAX_HUNGER);
+	}
+	/// Eats one of the selected item if it is edible.
+	///
+	/// Returns whether something was eaten.
+	pub fn eat_selected(&mut self, inv :&mut SelectableInventory,
+			params :&GameParams) -> bool {
+		let food = inv.get_selected()
+			.and_then(|sel| params.get_block_params(sel))
+			.and_then(|p| p.food);
+		if let Some(food) = food {
+			inv.take_selected();
+			self.eat(food);
+			true
+		} else {
+			false
+		}
+	}
+	pub fn serialize(&self) -> Result<Vec<u8>, StrErr> {
+		Ok(toml::to_string(self)?.into())
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		let serialized_str = str::from_utf8(buf)?;
+		let deserialized = from_str(serialized_str)?;
+		Ok(deserialized)
+	}
+}
+
+/// Derives the activity of a player from the positions it reports
+///
+/// The speeds are computed from the time between the position updates.
+#[derive(Default)]
+pub struct MovementTracker {
+	last :Option<(Vector3<f32>, Instant)>,
+	/// Start of the current horizontal speed measurement
+	sample_start :Option<(Vector3<f32>, Instant)>,
+	rising :bool,
+	sprint_secs :f32,
+	jumps :u32,
+}
+
+impl MovementTracker {
+	/// Forgets the last position, e.g. after the player got teleported
+	pub fn reset(&mut self) {
+		self.last = None;
+		self.sample_start = None;
+		self.rising = false;
+	}
+	/// Adds the position the player reported at the given time
+	///
+	/// If the player sprinted without being able to,
+	/// returns the position it may have reached instead.
+	pub fn update(&mut self, pos :Vector3<f32>, now :Instant,
+			can_sprint :bool) -> Option<Vector3<f32>> {
+		let (last_pos, last_time) = if let Some(l) = self.last.replace((pos, now)) {
+			l
+		} else {
+			self.sample_start = Some((pos, now));
+			return None;
+		};
+		let dt = (now - last_time).as_secs_f32().max(MIN_UPDATE_SECS);
+		let velocity = (pos - last_pos) / dt;
+		let rising = velocity.z > JUMP_SPEED;
+		// Only count the start of a jump
+		if rising && !self.rising {
+			self.jumps += 1;
+		}
+		self.rising = rising;
+
+		let (start_pos, start_time) = self.sample_start.unwrap_or((last_pos, last_time));
+		let secs = (now - start_time).as_secs_f32();
+		if secs < SPRINT_SAMPLE_SECS {
+			return None;
+		}
+		self.sample_start = Some((pos, now));
+		let delta = (pos - start_pos).xy();
+		let speed = delta.norm() / secs;
+		if speed <= SPRINT_SPEED || speed > TELEPORT_SPEED {
+			return None;
+		}
+		if can_sprint {
+			self.sprint_secs += secs;
+			return None;
+		}
+		let allowed = delta * (SPRINT_SPEED * secs / delta.norm());
+		let corrected = Vector3::new(start_pos.x + allowed.x, start_pos.y + allowed.y, pos.z);
+		self.last = Some((corrected, now));
+		self.sample_start = Some((corrected, now));
+		Some(corrected)
+	}
+	/// Returns the seconds spent sprinting and the number of
+	/// jumps started since the last call
+	pub fn take_activity(&mut self) -> (f32, u32) {
+		let res = (self.sprint_secs, self.jumps);
+		self.sprint_secs = 0.0;
+		self.jumps = 0;
+		res
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_hunger_depletion() {
+	let mut hunger = Hunger::default();
+	assert_eq!(hunger.value(), MAX_HUNGER);
+
+	// Idle depletion over ticks
+	let dt = 1.0 / 60.0;
+	for _ in 0 .. 60 {
+		hunger.tick(dt, 0.0, 0, Difficulty::Normal);
+	}
+	let idle = MAX_HUNGER - hunger.value();
+	assert!((idle - BASE_DEPLETION).abs() < 0.0001);
+
+	// Sprinting and jumping deplete faster
+	let mut sprinting = Hunger::default();
+	let mut jumping = Hunger::default();
+	for _ in 0 .. 60 {
+		sprinting.tick(dt, dt, 0, Difficulty::Normal);
+	}
+	jumping.tick(dt, 0.0, 1, Difficulty::Normal);
+	assert!(sprinting.value() < hunger.value());
+	assert!(jumping.value() < MAX_HUNGER - JUMP_DEPLETION + 0.0001);
+
+	// Hunger never goes below zero
+	for _ in 0 .. 100_000 {
+		sprinting.tick(1.0, 1.0, 1, Difficulty::Normal);
+	}
+	assert_eq!(sprinting.value(), 0.0);
+
+	// The difficulty scales the depletion
+	let mut peaceful = Hunger::default();
+	let mut hard = Hunger::default();
+	peaceful.tick(1.0, 1.0, 1, Difficulty::Peaceful);
+	hard.tick(1.0, 1.0, 1, Difficulty::Hard);
+	assert_eq!(peaceful.value(), MAX_HUNGER);
+	assert!(hard.value() < MAX_HUNGER - (BASE_DEPLETION + SPRINT_DEPLETION + JUMP_DEPLETION));
+}
+
+#[cfg(test)]
+#[test]
+fn test_hunger_sprint_lockout() {
+	let mut hunger = Hunger::default();
+	assert!(hunger.can_sprint());
+	hunger.tick(100_000.0, 0.0, 0, Difficulty::Normal);
+	assert_eq!(hunger.value(), 0.0);
+	assert!(!hunger.can_sprint());
+	hunger.eat(1);
+	assert!(hunger.can_sprint());
+}
+
+#[cfg(test)]
+#[test]
+fn test_hunger_eating() {
+	use crate::inventory::Stack;
+
+	let params = crate::game_params::test_params();
+	let apple = params.p.search_block_name("default:apple").unwrap();
+	let food = params.p.get_block_params(apple).unwrap().food.unwrap();
+	let stone = params.p.block_roles.stone;
+
+	let mut hunger = Hunger::default();
+	hunger.tick(100_000.0, 0.0, 0, Difficulty::Normal);
+
+	// Non-edible items can't be eaten
+	let mut inv = SelectableInventory::new();
+	inv.put(Stack::with(stone, 1));
+	assert!(!hunger.eat_selected(&mut inv, &params.p));
+	assert_eq!(inv.get_selected(), Some(stone));
+	assert_eq!(hunger.value(), 0.0);
+
+	// Edible items restore hunger and get consumed
+	let mut inv = SelectableInventory::new();
+	inv.put(Stack::with(apple, 1));
+	assert!(hunger.eat_selected(&mut inv, &params.p));
+	assert_eq!(hunger.value(), food as f32);
+	assert_eq!(inv.get_selected(), None);
+	assert!(!hunger.eat_selected(&mut inv, &params.p));
+	// Eating is capped
+	for _ in 0 .. 100 {
+		hunger.eat(food);
+	}
+	assert_eq!(hunger.value(), MAX_HUNGER);
+
+	let ser = hunger.serialize().unwrap();
+	assert_eq!(Hunger::deserialize(&ser).unwrap(), hunger);
+}
+
+#[cfg(test)]
+#[test]
+fn test_movement_tracker() {
+	use std::time::Duration;
+
+	let start = Instant::now();
+	let at = |secs :f32| start + Duration::from_secs_f32(secs);
+	// Moves along x with the given speed, in updates every 0.1 seconds
+	let run = |tracker :&mut MovementTracker, speed :f32, can_sprint :bool| {
+		let mut corrected = None;
+		for i in 0 ..= 5 {
+			let t = i as f32 * 0.1;
+			let pos = Vector3::new(speed * t, 0.0, 0.0);
+			if let Some(p) = tracker.update(pos, at(t), can_sprint) {
+				corrected = Some(p);
+			}
+		}
+		corrected
+	};
+
+	// Walking
+	let mut tracker = MovementTracker::default();
+	assert_eq!(run(&mut tracker, 10.0, true), None);
+	assert_eq!(tracker.take_activity(), (0.0, 0));
+
+	// Sprinting, for the measured time
+	let mut tracker = MovementTracker::default();
+	assert_eq!(run(&mut tracker, 40.0, true), None);
+	let (sprint_secs, jumps) = tracker.take_activity();
+	assert!(sprint_secs >= SPRINT_SAMPLE_SECS && sprint_secs <= 0.5, "{}", sprint_secs);
+	assert_eq!(jumps, 0);
+	assert_eq!(tracker.take_activity(), (0.0, 0));
+
+	// Without the ability to sprint, the player is held back
+	let mut tracker = MovementTracker::default();
+	let corrected = run(&mut tracker, 40.0, false).unwrap();
+	assert!(corrected.x <= SPRINT_SPEED * 0.5 + 0.001, "{}", corrected.x);
+	assert_eq!(tracker.take_activity(), (0.0, 0));
+
+	// Teleports don't count as sprinting
+	let mut tracker = MovementTracker::default();
+	assert_eq!(run(&mut tracker, 1000.0, false), None);
+	assert_eq!(tracker.take_activity(), (0.0, 0));
+
+	// Jumping, only the start is counted, even if
+	// several updates arrive at once
+	let mut tracker = MovementTracker::default();
+	tracker.update(Vector3::new(0.0, 0.0, 0.0), at(0.0), true);
+	tracker.update(Vector3::new(0.0, 0.0, 2.0), at(0.02), true);
+	tracker.update(Vector3::new(0.0, 0.0, 4.0), at(0.02), true);
+	tracker.update(Vector3::new(0.0, 0.0, 3.9), at(0.04), true);
+	assert_eq!(tracker.take_activity(), (0.0, 1));
+	// Flying upwards is no jump
+	tracker.update(Vector3::new(0.0, 0.0, 4.5), at(0.1), true);
+	assert_eq!(tracker.take_activity(), (0.0, 0));
+}
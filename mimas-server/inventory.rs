@@ -3,6 +3,7 @@ use std::num::NonZeroU16;
 use std::io::Read;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use crate::game_params::{NameIdMap, Id};
+use crate::crafting::CraftingGrid;
 use crate::StrErr;
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -17,31 +18,63 @@ pub enum Stack {
 	Content {
 		item :MapBlock,
 		count :NonZeroU16,
+		/// The current and the maximum durability of tools
+		durability :Option<(u16, u16)>,
 	},
 }
 
 impl Stack {
 	pub fn with(item :MapBlock, count :u16) -> Self {
+		Self::with_durability(item, count, None)
+	}
+	pub fn with_durability(item :MapBlock, count :u16,
+			durability :Option<(u16, u16)>) -> Self {
 		if let Some(count) = NonZeroU16::new(count) {
 			Stack::Content {
 				item,
 				count,
+				durability,
 			}
 		} else {
 			Stack::Empty
 		}
 	}
+	/// A single, fresh tool
+	pub fn tool(item :MapBlock, max_durability :u16) -> Self {
+		Self::with_durability(item, 1, Some((max_durability, max_durability)))
+	}
 	pub fn is_empty(&self) -> bool {
 		self == &Stack::Empty
 	}
 	pub fn content(&self) -> Option<(MapBlock, u16)> {
 		match self {
 			Stack::Empty => None,
-			Stack::Content { item, count, } => {
+			Stack::Content { item, count, .. } => {
 				Some((*item, count.get()))
 			},
 		}
 	}
+	pub fn durability(&self) -> Option<(u16, u16)> {
+		match self {
+			Stack::Empty => None,
+			Stack::Content { durability, .. } => *durability,
+		}
+	}
+	/// Decrements the durability of a tool by one use
+	///
+	/// If the durability reaches zero, the tool breaks and
+	/// the stack becomes empty. Returns whether it broke.
+	/// Stacks without durability are left unchanged.
+	pub fn wear(&mut self) -> bool {
+		if let Stack::Content { durability : Some((cur, _max)), .. } = self {
+			*cur = cur.saturating_sub(1);
+			if *cur == 0 {
+				*self = Stack::Empty;
+				return true;
+			}
+		}
+		false
+	}
 	pub fn put(&mut self, other :Stack, allow_empty :bool,
 			limit :u16) -> Stack {
 		if self.is_empty() {
@@ -51,9 +84,10 @@ impl Stack {
 			*self = other;
 			return Stack::Empty;
 		}
-		if let Stack::Content { item : item2, count : count2, } = other {
+		if let Stack::Content { item : item2, count : count2, durability : None } = other {
 			let (item, count) = self.content().unwrap();
-			if item == item2 {
+			// Tools have a stack size limit of 1
+			if item == item2 && self.durability().is_none() {
 				let wanted_count = (count as u32) + (count2.get() as u32);
 				let limit_exceeding = wanted_count.saturating_sub(limit as u32);
 				*self = Stack::with(item, (wanted_count - limit_exceeding) as u16);
@@ -66,8 +100,9 @@ impl Stack {
 		let mut emptied = false;
 		let stack_taken = match self {
 			Stack::Empty => Stack::Empty,
-			Stack::Content { item, count, } => {
+			Stack::Content { item, count, durability } => {
 				let item = *item;
+				let durability = *durability;
 				let new_count = count.get().saturating_sub(n);
 				let items_removed = count.get() - new_count;
 				let new_count_nonzero = NonZeroU16::new(new_count);
@@ -77,7 +112,7 @@ impl Stack {
 					*self = Stack::Empty;
 					emptied = true;
 				}
-				Stack::with(item, items_removed)
+				Stack::with_durability(item, items_removed, durability)
 			},
 		};
 		(stack_taken, emptied)
@@ -91,6 +126,7 @@ impl Stack {
 // Stack size limit
 const STACK_SIZE_LIMIT :u16 = 60;
 
+/// The default number of slots shown in the hud
 pub const HUD_SLOT_COUNT :usize = 8;
 
 impl SelectableInventory {
@@ -106,8 +142,8 @@ impl SelectableInventory {
 			stacks,
 		}
 	}
-	pub fn crafting_inv() -> Self {
-		Self::from_stacks(vec![Stack::Empty; 9].into_boxed_slice())
+	pub fn crafting_inv(grid :CraftingGrid) -> Self {
+		Self::empty_with_size(grid.slot_count())
 	}
 	pub fn is_empty(&self) -> bool {
 		self.stacks.iter().all(Stack::is_empty)
@@ -122,6 +158,12 @@ impl SelectableInventory {
 			self.stacks[idx].take_one().map(|(it, _emptied)| it)
 		})
 	}
+	/// Wears the selected stack, returning whether it broke
+	pub fn wear_selected(&mut self) -> bool {
+		self.selection
+			.map(|idx| self.stacks[idx].wear())
+			.unwrap_or(false)
+	}
 	pub fn merge_or_swap(invs :&mut [SelectableInventory],
 			from :(usize, usize), to :(usize, usize)) {
 		if from == to {
@@ -148,9 +190,29 @@ impl SelectableInventory {
 		// Put back any residue
 		invs[from.0].stacks[from.1].put(new_stack, true, STACK_SIZE_LIMIT);
 	}
-	pub fn rotate(&mut self, forwards :bool) {
+	/// Distributes the stack at `from` over the `to` slots
+	///
+	/// Each slot gets `count` items, or if it is `None`, an even share,
+	/// but at least one item. The remainder stays at `from`.
+	pub fn distribute(invs :&mut [SelectableInventory],
+			from :(usize, usize), to :&[(usize, usize)], count :Option<u16>) {
+		let total = invs[from.0].stacks[from.1].content()
+			.map(|(_, n)| n)
+			.unwrap_or(0);
+		if to.is_empty() || total == 0 {
+			return;
+		}
+		let count = count.unwrap_or(total / to.len() as u16).max(1);
+		for &to in to {
+			if to != from {
+				Self::move_n_if_possible(invs, from, to, count);
+			}
+		}
+	}
+	/// Moves the selection to the next or previous of the first `hud_slot_count` slots
+	pub fn rotate(&mut self, forwards :bool, hud_slot_count :usize) {
 		let selection = self.selection.take().unwrap_or(0);
-		let stack_count = self.stacks.len().min(HUD_SLOT_COUNT);
+		let stack_count = self.stacks.len().min(hud_slot_count).max(1);
 		let idx = if forwards {
 			(selection + 1) % stack_count
 		} else {
@@ -193,6 +255,71 @@ impl SelectableInventory {
 		}
 		stack
 	}
+	/// Moves all stacks into `to` as far as it has room
+	///
+	/// Items are merged into existing stacks first, what
+	/// doesn't fit stays. Returns whether anything was moved.
+	pub fn move_all(&mut self, to :&mut SelectableInventory) -> bool {
+		let mut moved = false;
+		for st in self.stacks.iter_mut() {
+			if st.is_empty() {
+				continue;
+			}
+			let rest = to.put(*st);
+			moved |= rest != *st;
+			*st = rest;
+		}
+		moved
+	}
+	/// Takes `n` items of the given kind, starting with the first stack holding it
+	///
+	/// Returns None and changes nothing if there are fewer.
+	pub fn take_items(&mut self, item :MapBlock, n :u16) -> Option<Stack> {
+		let holds_item = |st :&Stack| st.content().map(|(it, _)| it) == Some(item);
+		let available = self.stacks.iter()
+			.filter(|st| holds_item(st))
+			.map(|st| st.content().unwrap().1 as u32)
+			.sum::<u32>();
+		if n == 0 || available < n as u32 {
+			return None;
+		}
+		let mut taken = Stack::Empty;
+		let mut remaining = n;
+		for st in self.stacks.iter_mut().filter(|st| holds_item(st)) {
+			let (part, _emptied) = st.take_n(remaining);
+			remaining -= part.content().unwrap().1;
+			taken.put(part, true, u16::MAX);
+			if remaining == 0 {
+				break;
+			}
+		}
+		Some(taken)
+	}
+	/// Makes the selected slot hold the item, like a middle click in creative
+	///
+	/// If one of the first `hud_slot_count` slots already holds the
+	/// item, it gets selected. Otherwise, a full stack of the item
+	/// replaces the content of the selected slot.
+	pub fn pick_block(&mut self, item :MapBlock, hud_slot_count :usize) {
+		let hud_slot_count = self.stacks.len().min(hud_slot_count);
+		let existing = self.stacks[.. hud_slot_count].iter()
+			.position(|st| st.content().map(|(it, _)| it) == Some(item));
+		if let Some(idx) = existing {
+			self.selection = Some(idx);
+			return;
+		}
+		let idx = self.selection.unwrap_or(0);
+		if let Some(st) = self.stacks.get_mut(idx) {
+			*st = Stack::with(item, STACK_SIZE_LIMIT);
+			self.selection = Some(idx);
+		}
+	}
+	/// Selects the slot, if the inventory has it
+	pub fn select(&mut self, idx :usize) {
+		if idx < self.stacks.len() {
+			self.selection = Some(idx);
+		}
+	}
 	pub fn selection(&self) -> Option<usize> {
 		self.selection
 	}
@@ -208,7 +335,7 @@ impl SelectableInventory {
 		res
 	}
 	pub fn serialize_to(&self, res :&mut Vec<u8>) {
-		res.write_u8(0).unwrap();
+		res.write_u8(1).unwrap();
 		let selection_id = self.selection.unwrap_or(0) + 1;
 		res.write_u16::<BigEndian>(selection_id as u16).unwrap();
 		res.write_u16::<BigEndian>(self.stacks.len() as u16).unwrap();
@@ -218,6 +345,10 @@ impl SelectableInventory {
 				.unwrap_or((0, 0)); // id doesn't matter if count is 0
 			res.write_u8(id).unwrap();
 			res.write_u16::<BigEndian>(count).unwrap();
+			// A max durability of 0 means the stack has no durability
+			let (cur, max) = st.durability().unwrap_or((0, 0));
+			res.write_u16::<BigEndian>(cur).unwrap();
+			res.write_u16::<BigEndian>(max).unwrap();
 		}
 	}
 	pub fn deserialize(buf :&[u8], m :&NameIdMap) -> Result<Self, StrErr> {
@@ -226,7 +357,7 @@ impl SelectableInventory {
 
 	pub fn deserialize_rdr(mut rdr :impl Read, m :&NameIdMap) -> Result<Self, StrErr> {
 		let version = rdr.read_u8()?;
-		if version != 0 {
+		if version > 1 {
 			// The version is too recent
 			Err(format!("Unsupported serialized inventory version {}", version))?;
 		}
@@ -242,12 +373,24 @@ impl SelectableInventory {
 		for _ in 0 .. cnt {
 			let item_id = rdr.read_u8()?;
 			let count = rdr.read_u16::<BigEndian>()?;
+			let durability = if version >= 1 {
+				let cur = rdr.read_u16::<BigEndian>()?;
+				let max = rdr.read_u16::<BigEndian>()?;
+				if max == 0 {
+					None
+				} else {
+					Some((cur, max))
+				}
+			} else {
+				None
+			};
 			if let Some(count) = NonZeroU16::new(count) {
 				let item = m.mb_from_id(item_id)
 					.ok_or_else(|| "invalid item id".to_owned())?;
 				stacks.push(Stack::Content {
 					item,
 					count,
+					durability,
 				});
 			} else {
 				stacks.push(Stack::Empty);
@@ -259,3 +402,185 @@ impl SelectableInventory {
 		})
 	}
 }
+
+#[cfg(test)]
+fn test_item(name :&str) -> (NameIdMap, MapBlock) {
+	let nm = NameIdMap::builtin_name_list();
+	let item = nm.get_id(name).unwrap();
+	(nm, item)
+}
+
+#[cfg(test)]
+#[test]
+fn test_hud_selection() {
+	let mut inv = SelectableInventory::empty_with_size(16);
+	for &(count, forwards, expected) in &[
+		(9, true, &[1, 2, 3, 4, 5, 6, 7, 8, 0, 1][..]),
+		(9, false, &[8, 7, 6, 5, 4, 3, 2, 1, 0, 8]),
+		(5, true, &[1, 2, 3, 4, 0, 1, 2, 3, 4, 0]),
+		// More slots than the inventory has
+		(20, false, &[15, 14]),
+	] {
+		inv.select(0);
+		let selections = expected.iter()
+			.map(|_| {
+				inv.rotate(forwards, count);
+				inv.selection().unwrap()
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(&selections[..], expected, "{} {}", count, forwards);
+	}
+	inv.select(3);
+	assert_eq!(inv.selection(), Some(3));
+	inv.select(16);
+	assert_eq!(inv.selection(), Some(3));
+}
+
+#[cfg(test)]
+#[test]
+fn test_tool_wear() {
+	let (_nm, pick) = test_item("default:stone");
+	let mut st = Stack::tool(pick, 3);
+	assert_eq!(st.durability(), Some((3, 3)));
+	assert!(!st.wear());
+	assert_eq!(st.durability(), Some((2, 3)));
+	assert!(!st.wear());
+	assert_eq!(st.durability(), Some((1, 3)));
+	// The last use breaks the tool
+	assert!(st.wear());
+	assert_eq!(st, Stack::Empty);
+	assert!(!st.wear());
+
+	// Stacks without durability don't wear
+	let mut st = Stack::with(pick, 5);
+	assert!(!st.wear());
+	assert_eq!(st, Stack::with(pick, 5));
+}
+
+#[cfg(test)]
+#[test]
+fn test_tool_no_stacking() {
+	let (_nm, pick) = test_item("default:stone");
+	let mut inv = SelectableInventory::empty_with_size(3);
+	assert_eq!(inv.put(Stack::tool(pick, 10)), Stack::Empty);
+	assert_eq!(inv.put(Stack::tool(pick, 10)), Stack::Empty);
+	assert_eq!(inv.put(Stack::with(pick, 4)), Stack::Empty);
+	let stacks = inv.stacks();
+	assert_eq!(stacks[0], Stack::tool(pick, 10));
+	assert_eq!(stacks[1], Stack::tool(pick, 10));
+	assert_eq!(stacks[2], Stack::with(pick, 4));
+	// The selected tool wears and breaks
+	inv.selection = Some(1);
+	for _ in 0 .. 9 {
+		assert!(!inv.wear_selected());
+	}
+	assert!(inv.wear_selected());
+	assert_eq!(inv.stacks()[1], Stack::Empty);
+}
+
+#[cfg(test)]
+#[test]
+fn test_inventory_serialization_durability() {
+	let (nm, pick) = test_item("default:stone");
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.put(Stack::tool(pick, 50));
+	inv.put(Stack::with(pick, 7));
+	inv.wear_selected();
+	let buf = inv.serialize();
+	let inv_2 = SelectableInventory::deserialize(&buf, &nm).unwrap();
+	assert_eq!(inv, inv_2);
+	assert_eq!(inv_2.stacks()[0].durability(), Some((49, 50)));
+	assert_eq!(inv_2.stacks()[1].durability(), None);
+
+	// Version 0 inventories have no durability
+	let buf_v0 = [0, 0, 1, 0, 1, pick.id(), 0, 7];
+	let inv_v0 = SelectableInventory::deserialize(&buf_v0, &nm).unwrap();
+	assert_eq!(inv_v0.stacks()[0], Stack::with(pick, 7));
+}
+
+#[cfg(test)]
+#[test]
+fn test_move_all() {
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let ground = nm.get_id("default:ground").unwrap();
+	let wood = nm.get_id("default:wood").unwrap();
+	let mut chest = SelectableInventory::from_stacks(vec![
+		Stack::with(stone, 50),
+		Stack::Empty,
+		Stack::with(ground, 30),
+		Stack::tool(wood, 10),
+	].into_boxed_slice());
+	let mut inv = SelectableInventory::from_stacks(vec![
+		Stack::with(stone, 40),
+		Stack::Empty,
+		Stack::with(ground, 55),
+	].into_boxed_slice());
+	assert!(chest.move_all(&mut inv));
+	// Existing stacks get filled up to the limit before empty ones are used
+	assert_eq!(&inv.stacks()[..], &[
+		Stack::with(stone, 60),
+		Stack::with(stone, 30),
+		Stack::with(ground, 60),
+	]);
+	// What doesn't fit stays in the chest
+	assert_eq!(&chest.stacks()[..], &[
+		Stack::Empty,
+		Stack::Empty,
+		Stack::with(ground, 25),
+		Stack::tool(wood, 10),
+	]);
+	// Nothing changes if the inventory is full
+	assert!(!chest.move_all(&mut inv));
+
+	// Moving back into the now emptier chest
+	assert!(inv.move_all(&mut chest));
+	assert_eq!(&chest.stacks()[..], &[
+		Stack::with(stone, 60),
+		Stack::with(stone, 30),
+		Stack::with(ground, 60),
+		Stack::tool(wood, 10),
+	]);
+	assert_eq!(&inv.stacks()[..], &[
+		Stack::Empty,
+		Stack::Empty,
+		Stack::with(ground, 25),
+	]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_pick_block() {
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let wood = nm.get_id("default:wood").unwrap();
+
+	// The targeted stone ends up in the selected slot
+	let mut inv = SelectableInventory::new();
+	inv.stacks_mut()[2] = Stack::with(wood, 3);
+	inv.select(2);
+	inv.pick_block(stone, HUD_SLOT_COUNT);
+	assert_eq!(inv.selection(), Some(2));
+	assert_eq!(inv.get_selected(), Some(stone));
+	assert_eq!(inv.stacks()[2], Stack::with(stone, STACK_SIZE_LIMIT));
+
+	// If the hud already has the item, it gets selected instead
+	inv.stacks_mut()[5] = Stack::with(wood, 3);
+	inv.pick_block(wood, HUD_SLOT_COUNT);
+	assert_eq!(inv.selection(), Some(5));
+	assert_eq!(inv.stacks()[2], Stack::with(stone, STACK_SIZE_LIMIT));
+	assert_eq!(inv.stacks()[5], Stack::with(wood, 3));
+
+	// Items outside of the hud don't count
+	inv.stacks_mut()[12] = Stack::with(stone, 1);
+	inv.stacks_mut()[2] = Stack::Empty;
+	inv.pick_block(stone, HUD_SLOT_COUNT);
+	assert_eq!(inv.selection(), Some(5));
+	assert_eq!(inv.get_selected(), Some(stone));
+
+	// Without a selection, the first slot is used
+	let mut inv = SelectableInventory::new();
+	inv.pick_block(stone, HUD_SLOT_COUNT);
+	assert_eq!(inv.selection(), Some(0));
+	assert_eq!(inv.get_selected(), Some(stone));
+}
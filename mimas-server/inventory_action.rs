@@ -0,0 +1,230 @@
+use nalgebra::Vector3;
+use crate::crafting::{CraftingGrid, get_matching_recipe, fill_grid};
+use crate::game_params::GameParams;
+use crate::inventory::SelectableInventory;
+use crate::map::MapBlock;
+use crate::StrErr;
+
+/// An inventory the actions of a player can refer to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvLocation {
+	/// The inventory of the player
+	Player,
+	/// The crafting grid of the player with the given size
+	Craft(CraftingGrid),
+	/// The inventory of the chest at the position
+	Chest(Vector3<isize>),
+}
+
+/// A slot of an inventory
+pub type InvSlot = (InvLocation, usize);
+
+/// A change of inventories requested by a client
+///
+/// Clients send these instead of the changed inventories, and
+/// the server applies them to its own copies. This way, clients
+/// can't make up items.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum InventoryAction {
+	/// Selects the slot of the player inventory
+	Select(usize),
+	MergeOrSwap(InvSlot, InvSlot),
+	MoveN(InvSlot, InvSlot, u16),
+	/// See `SelectableInventory::distribute`
+	Distribute(InvSlot, Vec<InvSlot>, Option<u16>),
+	/// Puts the output of the crafting grid into the inventory, using up the inputs
+	Craft(CraftingGrid),
+	/// Fills the crafting grid with the inputs of the recipe with the index
+	FillGrid(CraftingGrid, usize),
+	/// Moves the items of the crafting grid back into the inventory
+	ReturnCrafting(CraftingGrid),
+	/// Moves all stacks from the first inventory into the second one, as far as they fit
+	MoveAll(InvLocation, InvLocation),
+	/// See `SelectableInventory::pick_block`, with the item and the hud slot count
+	///
+	/// Only allowed in creative mode.
+	PickBlock(MapBlock, usize),
+}
+
+impl InventoryAction {
+	/// The inventories the action accesses, without duplicates
+	pub fn locations(&self) -> Vec<InvLocation> {
+		use InventoryAction::*;
+		let all = match self {
+			Select(_) |
+			PickBlock(..) => vec![InvLocation::Player],
+			MergeOrSwap(from, to) |
+			MoveN(from, to, _) => vec![from.0, to.0],
+			Distribute(from, to, _) => {
+				let mut all = vec![from.0];
+				all.extend(to.iter().map(|slot| slot.0));
+				all
+			},
+			Craft(grid) |
+			FillGrid(grid, _) |
+			ReturnCrafting(grid) => vec![InvLocation::Craft(*grid), InvLocation::Player],
+			MoveAll(from, to) => vec![*from, *to],
+		};
+		let mut res = Vec::with_capacity(all.len());
+		for loc in all {
+			if !res.contains(&loc) {
+				res.push(loc);
+			}
+		}
+		res
+	}
+}
+
+/// The index into the accessed inventories and the slot index
+fn resolve(locations :&[InvLocation], invs :&[SelectableInventory],
+		(loc, slot) :InvSlot) -> Result<(usize, usize), StrErr> {
+	let idx = locations.iter().position(|l| *l == loc).unwrap();
+	if slot >= invs[idx].stacks().len() {
+		Err(format!("Slot {} of {:?} doesn't exist", slot, loc))?;
+	}
+	Ok((idx, slot))
+}
+
+/// Applies the action to copies of the inventories it accesses
+///
+/// `get_inv` returns the inventory at the location, or `None` if
+/// the player can't access it. Returns the accessed inventories
+/// after the change, or an error if the action is invalid, e.g.
+/// because it crafts something without the needed inputs.
+pub fn apply_action(action :&InventoryAction, params :&GameParams,
+		mut get_inv :impl FnMut(InvLocation) -> Option<SelectableInventory>)
+		-> Result<Vec<(InvLocation, SelectableInventory)>, StrErr> {
+	use InventoryAction::*;
+	let locations = action.locations();
+	let mut invs = Vec::with_capacity(locations.len());
+	for loc in locations.iter() {
+		let inv = get_inv(*loc)
+			.ok_or_else(|| format!("Can't access the inventory {:?}", loc))?;
+		invs.push(inv);
+	}
+	match action {
+		Select(idx) => {
+			if *idx >= invs[0].stacks().len() {
+				Err(format!("Can't select the missing slot {}", idx))?;
+			}
+			invs[0].select(*idx);
+		},
+		MergeOrSwap(from, to) => {
+			let from = resolve(&locations, &invs, *from)?;
+			let to = resolve(&locations, &invs, *to)?;
+			SelectableInventory::merge_or_swap(&mut invs, from, to);
+		},
+		MoveN(from, to, count) => {
+			let from = resolve(&locations, &invs, *from)?;
+			let to = resolve(&locations, &invs, *to)?;
+			SelectableInventory::move_n_if_possible(&mut invs, from, to, *count);
+		},
+		Distribute(from, to, count) => {
+			let from = resolve(&locations, &invs, *from)?;
+			let to = to.iter()
+				.map(|slot| resolve(&locations, &invs, *slot))
+				.collect::<Result<Vec<_>, _>>()?;
+			SelectableInventory::distribute(&mut invs, from, &to, *count);
+		},
+		Craft(grid) => {
+			let (craft_inv, inv) = invs.split_at_mut(1);
+			let recipe = get_matching_recipe(&craft_inv[0], *grid, params)
+				.ok_or("No recipe matches the crafting grid")?;
+			if !inv[0].put(recipe.output).is_empty() {
+				Err("The crafting output doesn't fit into the inventory")?;
+			}
+			recipe.consume_inputs(&mut craft_inv[0], *grid);
+		},
+		FillGrid(grid, idx) => {
+			let recipe = params.recipes.get(*idx)
+				.ok_or_else(|| format!("No recipe with index {}", idx))?;
+			let (craft_inv, inv) = invs.split_at_mut(1);
+			if !fill_grid(recipe, *grid, &mut craft_inv[0], &mut inv[0]) {
+				Err("The inputs of the recipe are missing")?;
+			}
+		},
+		ReturnCrafting(_grid) => {
+			let (craft_inv, inv) = invs.split_at_mut(1);
+			craft_inv[0].move_all(&mut inv[0]);
+		},
+		MoveAll(from, to) => {
+			if from == to {
+				Err("Can't move an inventory into itself")?;
+			}
+			let (from_inv, to_inv) = invs.split_at_mut(1);
+			from_inv[0].move_all(&mut to_inv[0]);
+		},
+		PickBlock(item, hud_slot_count) => {
+			if *item == params.block_roles.air {
+				Err("Can't pick air")?;
+			}
+			invs[0].pick_block(*item, *hud_slot_count);
+		},
+	}
+	Ok(locations.into_iter().zip(invs).collect())
+}
+
+#[cfg(test)]
+#[test]
+fn test_inventory_actions() {
+	use crate::game_params::{NameIdMap, load_params_failible};
+	use crate::inventory::Stack;
+
+	let params = load_params_failible(NameIdMap::builtin_name_list()).unwrap().p;
+	let tree = params.search_block_name("default:tree").unwrap();
+	let wood = params.search_block_name("default:wood").unwrap();
+	let grid = CraftingGrid::PERSONAL;
+	let chest_pos = Vector3::new(1, 2, 3);
+	let mut inv = SelectableInventory::new();
+	inv.put(Stack::with(tree, 2));
+	let mut craft_inv = SelectableInventory::crafting_inv(grid);
+	let mut chest_inv = SelectableInventory::empty_with_size(4);
+	let mut apply = |action :InventoryAction, chest_accessible :bool| {
+		let res = apply_action(&action, &params, |loc| match loc {
+			InvLocation::Player => Some(inv.clone()),
+			InvLocation::Craft(g) if g == grid => Some(craft_inv.clone()),
+			InvLocation::Craft(_) => None,
+			InvLocation::Chest(p) if p == chest_pos && chest_accessible => Some(chest_inv.clone()),
+			InvLocation::Chest(_) => None,
+		});
+		let changed = res.map_err(|e| e.0)?;
+		for (loc, new) in changed {
+			match loc {
+				InvLocation::Player => inv = new,
+				InvLocation::Craft(_) => craft_inv = new,
+				InvLocation::Chest(_) => chest_inv = new,
+			}
+		}
+		Ok::<_, String>((inv.clone(), craft_inv.clone(), chest_inv.clone()))
+	};
+
+	// Crafting needs the inputs in the grid
+	assert!(apply(InventoryAction::Craft(grid), true).is_err());
+	let (inv_after, craft_after, _) = apply(InventoryAction::FillGrid(grid, 0), true).unwrap();
+	assert_eq!(inv_after.stacks()[0], Stack::with(tree, 1));
+	assert_eq!(craft_after.stacks()[0], Stack::with(tree, 1));
+	let (inv_after, craft_after, _) = apply(InventoryAction::Craft(grid), true).unwrap();
+	assert!(craft_after.is_empty());
+	assert_eq!(inv_after.stacks()[1], Stack::with(wood, 4));
+
+	// Moves between the inventory and a chest
+	let to_chest = InventoryAction::MergeOrSwap((InvLocation::Player, 1), (InvLocation::Chest(chest_pos), 3));
+	assert!(apply(to_chest.clone(), false).is_err());
+	let (inv_after, _, chest_after) = apply(to_chest, true).unwrap();
+	assert!(inv_after.stacks()[1].is_empty());
+	assert_eq!(chest_after.stacks()[3], Stack::with(wood, 4));
+	let missing_slot = InventoryAction::MoveN((InvLocation::Chest(chest_pos), 4), (InvLocation::Player, 1), 1);
+	assert!(apply(missing_slot, true).is_err());
+	let spread = InventoryAction::Distribute((InvLocation::Chest(chest_pos), 3),
+		vec![(InvLocation::Chest(chest_pos), 0), (InvLocation::Player, 5)], None);
+	let (inv_after, _, chest_after) = apply(spread, true).unwrap();
+	assert_eq!(chest_after.stacks()[0], Stack::with(wood, 2));
+	assert_eq!(inv_after.stacks()[5], Stack::with(wood, 2));
+	let take_all = InventoryAction::MoveAll(InvLocation::Chest(chest_pos), InvLocation::Player);
+	let (inv_after, _, chest_after) = apply(take_all, true).unwrap();
+	assert!(chest_after.is_empty());
+	assert_eq!(inv_after.stacks()[5], Stack::with(wood, 4));
+
+	assert!(apply(InventoryAction::Select(16), true).is_err());
+	assert_eq!(apply(InventoryAction::Select(2), true).unwrap().0.selection(), Some(2));
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::str;
+use toml::{from_str, to_string};
+use crate::inventory::SelectableInventory;
+use crate::game_params::NameIdMap;
+use crate::whitelist::is_valid_nick;
+use super::StrErr;
+
+/// Named snapshots of the inventories of the players
+///
+/// Lets players switch between palettes when building. It is
+/// stored in the global kv of the map storage as TOML, with a
+/// table for each nick mapping the preset names to the base64
+/// encoded serialization of the inventory.
+#[derive(Clone, Default)]
+pub struct InventoryPresets {
+	presets :BTreeMap<String, BTreeMap<String, SelectableInventory>>,
+}
+
+impl InventoryPresets {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn get(&self, nick :&str, name :&str) -> Option<&SelectableInventory> {
+		self.presets.get(nick)?.get(name)
+	}
+	/// Saves the inventory as preset of the player, replacing any with the same name
+	///
+	/// Fails if the name is invalid, or if it's a new preset
+	/// and the player already has `max` presets.
+	pub fn save(&mut self, nick :&str, name :&str, inv :SelectableInventory,
+			max :usize) -> Result<(), StrErr> {
+		// Preset names follow the same rules as nicks
+		if !is_valid_nick(name) {
+			Err(format!("Invalid preset name '{}'", name))?;
+		}
+		let presets = self.presets.entry(nick.to_owned()).or_default();
+		if !presets.contains_key(name) && presets.len() >= max {
+			Err(format!("You can't have more than {} inventory presets", max))?;
+		}
+		presets.insert(name.to_owned(), inv);
+		Ok(())
+	}
+	/// The names of the presets of the player, in alphabetical order
+	pub fn names(&self, nick :&str) -> Vec<&str> {
+		self.presets.get(nick)
+			.map(|presets| presets.keys().map(|n| n.as_str()).collect())
+			.unwrap_or_default()
+	}
+	pub fn serialize(&self) -> Result<Vec<u8>, StrErr> {
+		let encoded = self.presets.iter()
+			.map(|(nick, presets)| {
+				let presets = presets.iter()
+					.map(|(name, inv)| (name.clone(), base64::encode(inv.serialize())))
+					.collect::<BTreeMap<_, _>>();
+				(nick.clone(), presets)
+			})
+			.collect::<BTreeMap<_, _>>();
+		Ok(to_string(&encoded)?.into_bytes())
+	}
+	pub fn deserialize(buf :&[u8], m :&NameIdMap) -> Result<Self, StrErr> {
+		let encoded :BTreeMap<String, BTreeMap<String, String>> = from_str(str::from_utf8(buf)?)?;
+		let mut presets = BTreeMap::new();
+		for (nick, encoded_presets) in encoded {
+			let mut player_presets = BTreeMap::new();
+			for (name, inv) in encoded_presets {
+				let inv = SelectableInventory::deserialize(&base64::decode(&inv)?, m)?;
+				player_presets.insert(name, inv);
+			}
+			presets.insert(nick, player_presets);
+		}
+		Ok(Self { presets })
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_inventory_presets() {
+	use crate::inventory::Stack;
+
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let wood = nm.get_id("default:wood").unwrap();
+	let mut stones = SelectableInventory::new();
+	stones.stacks_mut()[3] = Stack::with(stone, 20);
+	stones.select(3);
+	let mut woods = SelectableInventory::new();
+	woods.put(Stack::with(wood, 5));
+
+	let mut presets = InventoryPresets::new();
+	presets.save("alice", "stone", stones.clone(), 2).unwrap();
+	presets.save("alice", "wood", woods.clone(), 2).unwrap();
+	presets.save("bob", "wood", stones.clone(), 2).unwrap();
+	assert_eq!(presets.names("alice"), vec!["stone", "wood"]);
+	assert_eq!(presets.get("alice", "stone"), Some(&stones));
+	assert!(presets.get("alice", "glass").is_none());
+	assert!(presets.save("alice", "not valid", woods.clone(), 2).is_err());
+
+	// Overwriting a preset doesn't count towards the limit
+	assert!(presets.save("alice", "glass", woods.clone(), 2).is_err());
+	presets.save("alice", "wood", stones.clone(), 2).unwrap();
+	assert_eq!(presets.get("alice", "wood"), Some(&stones));
+
+	// The exact layout including the selection survives storage
+	let round_trip = InventoryPresets::deserialize(&presets.serialize().unwrap(), &nm).unwrap();
+	assert_eq!(round_trip.names("alice"), vec!["stone", "wood"]);
+	assert_eq!(round_trip.get("alice", "stone"), Some(&stones));
+	assert_eq!(round_trip.get("alice", "stone").unwrap().selection(), Some(3));
+	assert_eq!(round_trip.get("bob", "wood"), Some(&stones));
+	assert!(round_trip.names("carol").is_empty());
+}
@@ -27,6 +27,8 @@ extern crate libsqlite3_sys;
 extern crate byteorder;
 extern crate flate2;
 extern crate base64;
+#[macro_use]
+extern crate log;
 
 pub mod map;
 pub mod mapgen;
@@ -40,30 +42,87 @@ pub mod inventory;
 pub mod crafting;
 pub mod game_params;
 pub mod toml_util;
+pub mod tick_pacer;
+pub mod hunger;
+pub mod collide;
+pub mod entities;
+pub mod difficulty;
+pub mod clock;
+pub mod whitelist;
+pub mod game_mode;
+pub mod random_tick;
+pub mod falling;
+pub mod chat_log;
+pub mod error;
+pub mod homes;
+pub mod inventory_presets;
+pub mod teleport;
+pub mod fragment;
+pub mod edit_log;
+pub mod inventory_action;
+pub mod logging;
+pub mod server_list;
+pub mod explosion;
+pub mod playtime;
 
 use crate::map::{Map, ServerMap, MapBackend, MapChunkData, CHUNKSIZE, MapBlock,
-	MetadataEntry};
+	MetadataEntry, BlockBatch};
 use nalgebra::{Vector3};
-use std::time::{Instant, Duration};
+use std::time::Instant;
 use std::thread;
 use std::cell::RefCell;
 use std::collections::{HashSet, HashMap};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::mem::take;
+use std::path::{Path, PathBuf};
 use std::fmt::Display;
 use crate::generic_net::{NetworkServerSocket, NetworkServerConn, NetErr};
-use crate::config::Config;
-use crate::map_storage::{PlayerIdPair, PlayerPosition};
+use crate::config::{Config, load_config};
+use crate::tick_pacer::TickPacer;
+use crate::hunger::{Hunger, MovementTracker};
+use crate::entities::{Entities, EntityId, EntityKind};
+use crate::map_storage::{PlayerIdPair, PlayerPosition, NullStorageBackend,
+	DynStorageBackend};
+use crate::mapgen::{find_spawn, scatter_spawn, MapgenParams, MapgenMap};
+use crate::difficulty::Difficulty;
+use crate::game_mode::GameMode;
+use crate::clock::{WorldClock, TimeCommand};
+use crate::whitelist::{Whitelist, is_valid_nick, file_path_in};
+use crate::homes::{Homes, DEFAULT_HOME_NAME};
+use crate::playtime::{Playtime, format_ticks};
+use crate::inventory_presets::InventoryPresets;
+use crate::teleport::{TeleportTimers, TeleportEvent, TeleportTarget};
+use crate::chat_log::ChatLog;
+use crate::edit_log::{EditRecorder, EditReplay};
 use crate::inventory::{SelectableInventory, Stack};
+use crate::inventory_action::{InventoryAction, InvLocation, apply_action};
+use crate::logging::{TARGET_MAPGEN, TARGET_NET, TARGET_STORAGE, TARGET_SERVER};
+use crate::crafting::CraftingGrid;
 use crate::local_auth::{SqliteLocalAuth, AuthBackend, PlayerPwHash, HashParams};
-use crate::game_params::{GameParams, ServerGameParams, ServerGameParamsHdl};
+use crate::game_params::{GameParams, ServerGameParams, ServerGameParamsHdl, drops_for_roll};
 use srp::server::{SrpServer, UserRecord};
 use srp::client::SrpClient;
 use srp::groups::G_4096;
 use sha2::Sha256;
-use rand::RngCore;
+use rand::{Rng, RngCore};
+
+/// The version of the network protocol, to be bumped on incompatible changes
+pub const PROTOCOL_VERSION :u32 = 6;
+/// The oldest client protocol version the server still accepts
+pub const MIN_COMPATIBLE_PROTOCOL_VERSION :u32 = 6;
+
+/// Whether a client with the given protocol version can connect
+pub fn protocol_compatible(version :u32) -> bool {
+	(MIN_COMPATIBLE_PROTOCOL_VERSION ..= PROTOCOL_VERSION).contains(&version)
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientToServerMsg {
+	/// The protocol version of the client, sent first.
+	/// Keep this the first variant so that its encoding is stable.
+	Hello(u32),
 	LogIn(String, Vec<u8>),
 	SendHash(PlayerPwHash), // "Auth" for new users
 	SendM1(Vec<u8>), // Auth for existing users
@@ -73,34 +132,57 @@ pub enum ClientToServerMsg {
 	SetMetadata(Vector3<isize>, MetadataEntry),
 	PlaceTree(Vector3<isize>),
 	Dig(Vector3<isize>),
+	Eat,
 
 	SetPos(PlayerPosition),
-	SetInventory(SelectableInventory),
+	InventoryAction(InventoryAction),
+	/// Confirms that the client has the given version of the chunk
+	AckChunk(Vector3<isize>, u64),
 	Chat(String),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum ServerToClientMsg {
+	/// The client protocol version is rejected, with the
+	/// server protocol version and the oldest one it accepts.
+	/// Keep this the first variant so that its encoding is stable.
+	IncompatibleVersion(u32, u32),
 	HashEnrollment,
 	HashParamsBpub(HashParams, Vec<u8>),
 	LogInFail(String),
 	GameParams(GameParams),
 	HashedBlobs(Vec<(Vec<u8>, Vec<u8>)>),
 
-	PlayerPositions(PlayerIdPair, Vec<(PlayerIdPair, Vector3<f32>)>),
+	/// The id of the receiver and the nicks and positions of the players
+	PlayerPositions(PlayerIdPair, Vec<(PlayerIdPair, String, Vector3<f32>)>),
 
 	SetPos(PlayerPosition),
 	SetInventory(SelectableInventory),
-	ChunkUpdated(Vector3<isize>, MapChunkData),
+	SetCraftInventory(CraftingGrid, SelectableInventory),
+	SetHunger(Hunger),
+	SetTime(WorldClock),
+	SetGameMode(GameMode),
+	Entities(Vec<(EntityId, Vector3<f32>, EntityKind)>),
+	/// A chunk and its version, to be acknowledged by the client
+	ChunkUpdated(Vector3<isize>, MapChunkData, u64),
+	/// Block edits in chunks the client has loaded
+	BlockBatch(BlockBatch),
 	Chat(String),
+	/// The blocks around the spawn only admins can edit, with exclusive maximum
+	SetSpawnProtection(Option<(Vector3<isize>, Vector3<isize>)>),
 }
 
 enum AuthState {
+	AwaitingHello,
 	Unauthenticated,
 	NewUser(String),
 	WaitingForM1(String, PlayerIdPair, SrpServer<Sha256>),
 }
 
+/// An error only carrying a message
+///
+/// Anything implementing `Display` converts into it,
+/// including the typed `MimasError`.
 #[derive(Debug)]
 pub struct StrErr(String);
 
@@ -130,13 +212,24 @@ struct Player<C: NetworkServerConn> {
 	pos :PlayerPosition,
 	inventory :SelectableInventory,
 	inventory_last_ser :SelectableInventory,
+	/// The contents of the crafting grids of the player
+	craft_invs :HashMap<CraftingGrid, SelectableInventory>,
+	hunger :Hunger,
+	hunger_last_sent :Hunger,
+	movement :MovementTracker,
+	/// The chunks the player has loaded
 	sent_chunks :HashSet<Vector3<isize>>,
+	/// The chunk positions chunks are sent for, with exclusive maximum
+	sent_area :Option<(Vector3<isize>, Vector3<isize>)>,
+	/// The chunk versions the client confirmed to have
+	acked_chunks :HashMap<Vector3<isize>, u64>,
 	last_chunk_pos :Vector3<isize>,
+	game_mode :GameMode,
 }
 
 impl<C: NetworkServerConn> Player<C> {
 	pub fn from_stuff(conn :C, ids :PlayerIdPair,
-			nick :String, inventory :SelectableInventory) -> Self {
+			nick :String, inventory :SelectableInventory, hunger :Hunger) -> Self {
 		Player {
 			conn,
 			ids,
@@ -144,13 +237,189 @@ impl<C: NetworkServerConn> Player<C> {
 			pos : PlayerPosition::default(),
 			inventory,
 			inventory_last_ser : SelectableInventory::new(),
+			craft_invs : HashMap::new(),
+			hunger,
+			hunger_last_sent : hunger,
+			movement : MovementTracker::default(),
 			sent_chunks : HashSet::new(),
+			sent_area : None,
+			acked_chunks : HashMap::new(),
 			last_chunk_pos : Vector3::new(0, 0, 0),
+			game_mode : GameMode::default(),
 		}
 	}
 	fn pos(&self) -> Vector3<f32> {
 		self.pos.pos()
 	}
+	/// The inventory with the contents of the crafting grids moved into it
+	///
+	/// This is what gets stored, so that the items left in a
+	/// crafting grid aren't lost when the player leaves.
+	/// Items not fitting into the inventory are returned as well.
+	fn inventory_with_craft_grids(&self) -> (SelectableInventory, Vec<Stack>) {
+		let mut inv = self.inventory.clone();
+		let mut leftover = Vec::new();
+		for craft_inv in self.craft_invs.values() {
+			for stack in craft_inv.stacks().iter() {
+				if stack.is_empty() {
+					continue;
+				}
+				let rest = inv.put(*stack);
+				if !rest.is_empty() {
+					leftover.push(rest);
+				}
+			}
+		}
+		(inv, leftover)
+	}
+	/// Whether the player should receive updates of the given chunk
+	///
+	/// This is the case if the player has the chunk loaded already,
+	/// or if it lies in the area around the player that chunks are
+	/// sent for, e.g. because it has just been generated.
+	fn wants_chunk(&self, chunk_pos :Vector3<isize>) -> bool {
+		if self.sent_chunks.contains(&chunk_pos) {
+			return true;
+		}
+		self.sent_area
+			.map(|area| in_chunk_area(area, chunk_pos))
+			.unwrap_or(false)
+	}
+}
+
+fn in_chunk_area((min, max) :(Vector3<isize>, Vector3<isize>), chunk_pos :Vector3<isize>) -> bool {
+	(0 .. 3).all(|i| min[i] <= chunk_pos[i] && chunk_pos[i] < max[i])
+}
+
+/// Receives the messages of all players for this tick
+///
+/// This defines the order in which edits are applied:
+/// all messages are handled serially in the server tick,
+/// in the order the server received them. This makes the
+/// outcome of conflicting edits of multiple players deterministic.
+fn recv_player_msgs<C :NetworkServerConn>(players :&mut HashMap<PlayerIdPair, Player<C>>)
+		-> Vec<(PlayerIdPair, ClientToServerMsg)> {
+	let mut msgs = Vec::new();
+	let mut conns_to_close = Vec::new();
+	for (id, player) in players.iter_mut() {
+		loop {
+			let msg = player.conn.try_recv_seq();
+			match msg {
+				Ok(Some((_seq, ClientToServerMsg::SetPos(p)))) => {
+					player.pos = p;
+					let can_sprint = player.hunger.can_sprint();
+					if let Some(pos) = player.movement.update(p.pos(), Instant::now(), can_sprint) {
+						// Hold back players that sprint while being unable to
+						player.pos = PlayerPosition::from_pos_pitch_yaw(pos, p.pitch(), p.yaw());
+						if player.conn.send(ServerToClientMsg::SetPos(player.pos)).is_err() {
+							conns_to_close.push(*id);
+							break;
+						}
+					}
+				},
+				Ok(Some((_seq, ClientToServerMsg::AckChunk(p, version)))) => {
+					player.acked_chunks.insert(p, version);
+				},
+				Ok(Some((seq, msg))) => {
+					msgs.push((seq, *id, msg));
+				},
+				Ok(None) => break,
+				Err(NetErr::ConnectionClosed) => {
+					info!(target : TARGET_NET, "Client connection closed.");
+					conns_to_close.push(*id);
+					break;
+				},
+				Err(_) => {
+					warn!(target : TARGET_NET, "Client connection error.");
+					conns_to_close.push(*id);
+					break;
+				},
+			}
+		}
+	}
+	close_connections(&conns_to_close, players);
+	msgs.sort_by_key(|(seq, _id, _msg)| *seq);
+	msgs.into_iter()
+		.map(|(_seq, id, msg)| (id, msg))
+		.collect()
+}
+
+/// Sends a changed chunk to all players that want it
+///
+/// This includes the player whose edit caused the change,
+/// so that it can reconcile its local prediction.
+/// Players that have neither loaded the chunk nor are
+/// near it don't get the update.
+fn broadcast_chunk_update<C :NetworkServerConn>(players :&mut HashMap<PlayerIdPair, Player<C>>,
+		chunk_pos :Vector3<isize>, chunk :&MapChunkData) {
+	let msg = ServerToClientMsg::ChunkUpdated(chunk_pos, chunk.clone(), chunk.version());
+	let mut conns_to_close = Vec::new();
+	for (id, player) in players.iter_mut() {
+		if !player.wants_chunk(chunk_pos) {
+			continue;
+		}
+		player.sent_chunks.insert(chunk_pos);
+		match player.conn.send(msg.clone()) {
+			Ok(_) => (),
+			Err(_) => conns_to_close.push(*id),
+		}
+	}
+	close_connections(&conns_to_close, players);
+}
+
+/// Whether the block at the position holds items, like a filled chest
+fn holds_items<B :MapBackend>(map :&Map<B>, pos :Vector3<isize>) -> bool {
+	match map.get_blk_meta(pos) {
+		Some(Some(MetadataEntry::Inventory(inv))) => !inv.is_empty(),
+		_ => false,
+	}
+}
+
+/// Applies a block edit of a client
+///
+/// Edits to unloaded chunks are ignored. Callers check
+/// `Server::can_place_at` first, so that no items are lost.
+/// Sets the block, removing the metadata of the block it replaces
+fn apply_block_edit<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, b :MapBlock) {
+	if map.set_blocks(&[(pos, b)])[0].is_none() {
+		// TODO log something about an attempted action in an unloaded chunk
+	}
+}
+
+/// The commands only admins may use
+///
+/// The queries of `is_public_query` are available to everyone.
+const ADMIN_COMMANDS :&[&str] = &["gamemode", "gamerule", "reload", "whitelist",
+	"mapgen", "time", "difficulty"];
+
+/// Whether the admin command only prints the current state
+fn is_public_query(command :&str, params :&[&str]) -> bool {
+	matches!((command, params),
+		("mapgen", ["get"]) | ("time", ["query"]) | ("difficulty", []))
+}
+
+/// The maximum number of simulation steps per tick settable via `/gamerule tickspeed`
+const MAX_TICK_SPEED :u32 = 32;
+
+/// The maximum radius of the spawn protection, also for the setting
+pub const MAX_SPAWN_PROTECTION_RADIUS :isize = 256;
+
+/// Slack in blocks on top of the reach distance, as the
+/// position the server knows may lag behind the client's
+const REACH_TOLERANCE :f32 = 1.0;
+
+/// Lets other threads shut down a server running its `run_loop`
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+	/// Makes the server shut down after its current tick
+	pub fn request(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+	fn is_requested(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
 }
 
 pub struct Server<S :NetworkServerSocket> {
@@ -162,12 +431,33 @@ pub struct Server<S :NetworkServerSocket> {
 	unauthenticated_players :Vec<(S::Conn, AuthState)>,
 	players_waiting_for_kv :HashMap<PlayerIdPair,
 		(S::Conn, String,
-			Option<PlayerPosition>, Option<SelectableInventory>)>,
+			Option<PlayerPosition>, Option<SelectableInventory>, Option<Hunger>)>,
 	players :Rc<RefCell<HashMap<PlayerIdPair, Player<S::Conn>>>>,
+	/// The block edits of this tick, to be sent in batches
+	pending_edits :Rc<RefCell<BlockBatch>>,
 
-	last_frame_time :Instant,
+	tick_pacer :TickPacer,
 	last_pos_storage_time :Instant,
-	last_fps :f32,
+	spawn_pos :Vector3<f32>,
+	clock :WorldClock,
+	last_clock_sync_time :Instant,
+	/// The number of simulation steps per tick, for fast-forwarding time
+	tick_speed :u32,
+	whitelist :Whitelist,
+	homes :Homes,
+	inventory_presets :InventoryPresets,
+	playtime :Playtime,
+	teleports :TeleportTimers,
+	chat_log :ChatLog,
+	edit_recorder :EditRecorder,
+	/// The recording of block edits currently being replayed
+	edit_replay :Option<EditReplay>,
+	entities :Entities,
+	/// Whether the last entity list sent to the players was empty
+	entities_sent_empty :bool,
+	/// Whether `shutdown` has been called
+	shut_down :bool,
+	shutdown_handle :ShutdownHandle,
 
 	map :ServerMap,
 }
@@ -177,28 +467,52 @@ impl<S :NetworkServerSocket> Server<S> {
 			singleplayer :bool, mut config :Config) -> Self {
 		let backends = map_storage::backends_from_config(&mut config, !singleplayer);
 		let (mut storage_back, auth_back) = backends;
+		let fresh_world = storage_back.get_global_kv("name_id_map").unwrap().is_none();
 		let nm = map_storage::load_name_id_map(&mut storage_back).unwrap();
 		let params = ServerGameParams::load(nm);
 		map_storage::save_name_id_map(&mut storage_back, &params.p.name_id_map).unwrap();
+		let spawn_pos = spawn_pos(&config, &params);
+		// Generate the area around the spawn of new worlds right away,
+		// so that the first player doesn't have to wait for it
+		if fresh_world && config.map_storage_path.is_some() &&
+				config.generate_terrain && config.initial_gen_radius > 0 {
+			storage_back = pregen_storage(&config, &params, spawn_pos,
+				storage_back, config.initial_gen_radius).unwrap();
+		}
+		let clock = map_storage::load_world_clock(&mut storage_back).unwrap();
+		let whitelist = map_storage::load_whitelist(&mut storage_back).unwrap();
+		let homes = map_storage::load_homes(&mut storage_back).unwrap();
+		let playtime = map_storage::load_playtime(&mut storage_back).unwrap();
+		let inventory_presets = map_storage::load_inventory_presets(&mut storage_back,
+			&params.p.name_id_map).unwrap();
 		let mut map = ServerMap::new(config.mapgen_seed,
-			params.clone(), storage_back);
+			config.generate_terrain, params.clone(), storage_back);
+		map.backend_mut().set_mapgen_params(config.mapgen_params());
+		map.backend_mut().set_min_padding(config.mapgen_min_padding);
 
 		let unauthenticated_players = Vec::<_>::new();
 		let players = Rc::new(RefCell::new(HashMap::<_, Player<S::Conn>>::new()));
+		let pending_edits = Rc::new(RefCell::new(BlockBatch::new()));
 		let playersc = players.clone();
 		map.register_on_change(Box::new(move |chunk_pos, chunk| {
-			let mut players = playersc.borrow_mut();
-			let msg = ServerToClientMsg::ChunkUpdated(chunk_pos, chunk.clone());
-			let mut conns_to_close = Vec::new();
-			for (id, player) in players.iter_mut() {
-				player.sent_chunks.insert(chunk_pos);
-				match player.conn.send(msg.clone()) {
-					Ok(_) => (),
-					Err(_) => conns_to_close.push(*id),
-				}
-			}
-			close_connections(&conns_to_close, &mut *players);
+			broadcast_chunk_update(&mut playersc.borrow_mut(), chunk_pos, chunk);
 		}));
+		let pending_editsc = pending_edits.clone();
+		map.register_on_block_change(Box::new(move |pos, b, _| {
+			pending_editsc.borrow_mut().push(pos, b);
+		}));
+
+		let tick_pacer = TickPacer::new(config.tick_rate, Instant::now());
+		let chat_log = if let Some(path) = &config.chat_log_path {
+			ChatLog::open(path)
+		} else {
+			ChatLog::disabled()
+		};
+		let edit_recorder = if let Some(path) = &config.edit_recording_path {
+			EditRecorder::open(path, &params.p.name_id_map)
+		} else {
+			EditRecorder::disabled()
+		};
 
 		let srv = Server {
 			srv_socket,
@@ -209,50 +523,30 @@ impl<S :NetworkServerSocket> Server<S> {
 			unauthenticated_players,
 			players_waiting_for_kv : HashMap::new(),
 			players,
+			pending_edits,
 
-			last_frame_time : Instant::now(),
+			tick_pacer,
 			last_pos_storage_time : Instant::now(),
-			last_fps : 0.0,
+			spawn_pos,
+			clock,
+			last_clock_sync_time : Instant::now(),
+			tick_speed : 1,
+			whitelist,
+			homes,
+			inventory_presets,
+			playtime,
+			teleports : TeleportTimers::new(),
+			chat_log,
+			edit_recorder,
+			edit_replay : None,
+			entities : Entities::new(),
+			entities_sent_empty : true,
+			shut_down : false,
+			shutdown_handle : ShutdownHandle::default(),
 			map,
 		};
 		srv
 	}
-	/// Update the stored fps value and return the delta time
-	fn update_fps(&mut self) -> f32 {
-		let cur_time = Instant::now();
-		let float_delta = (cur_time - self.last_frame_time).as_secs_f32();
-		self.last_frame_time = cur_time;
-
-		const EPS :f32 = 0.1;
-		const FPS_TGT :f32 = 60.0;
-		let fps_cur_term = if float_delta > 0.0 {
-			1.0 / float_delta
-		} else {
-			// At the beginning float_delta can be zero
-			// and 1/0 would fuck up the last_fps value.
-			// Also, a value of 0.0 would mean that FPS
-			// limiting couldn't kick in. Thus, set
-			// it to something highly above the target.
-			FPS_TGT * 100.0
-		};
-		let fps = self.last_fps * (1.0 - EPS) + fps_cur_term * EPS;
-		self.last_fps = fps;
-
-		// If we exceed our target FPS by a too high
-		// amount, slow a little bit down
-		// to avoid 100% CPU
-		if fps > 1.5 * FPS_TGT {
-			let smooth_delta = 1.0 / fps;
-			let delta_tgt = 1.0 / FPS_TGT;
-			let time_too_fast = delta_tgt - smooth_delta;
-			// Don't slow down by the full time that we were too fast,
-			// because then we are guaranteed to undershoot
-			// the FPS target in this frame. That's not our goal!
-			let micros_to_wait = (time_too_fast * 0.7 * 1_000_000.0) as u64;
-			thread::sleep(Duration::from_micros(micros_to_wait));
-		}
-		float_delta
-	}
 	fn handle_auth_msgs(&mut self) {
 		// TODO do SRP based auth or spake2 or sth
 		let mut players_to_add = Vec::new();
@@ -260,6 +554,7 @@ impl<S :NetworkServerSocket> Server<S> {
 		enum Verdict {
 			AddAsPlayer(String, PlayerIdPair),
 			LogInFail(String),
+			IncompatibleVersion(u32),
 			Close,
 		}
 		for (idx, (conn, state)) in
@@ -273,19 +568,29 @@ impl<S :NetworkServerSocket> Server<S> {
 				}
 				let msg = conn.try_recv();
 				match msg {
+					Ok(Some(ClientToServerMsg::Hello(version))) => {
+						match state {
+							AuthState::AwaitingHello => (),
+							_ => {
+								verdict!(Verdict::LogInFail("Wrong auth state".to_string()));
+							},
+						}
+						if !protocol_compatible(version) {
+							verdict!(Verdict::IncompatibleVersion(version));
+						}
+						*state = AuthState::Unauthenticated;
+					},
 					Ok(Some(ClientToServerMsg::LogIn(nick, a_pub))) => {
+						if let AuthState::AwaitingHello = state {
+							verdict!(Verdict::LogInFail("No protocol version sent".to_string()));
+						}
 						// Check that the nick uses valid characters
-						let nick_has_valid_chars = nick
-							.bytes()
-							.all(|b|
-								(b'0' ..= b'9').contains(&b) ||
-								(b'a' ..= b'z').contains(&b) ||
-								(b'A' ..= b'Z').contains(&b) ||
-								(b == b'-' || b == b'_'));
-
-						if !nick_has_valid_chars {
+						if !is_valid_nick(&nick) {
 							verdict!(Verdict::LogInFail("Invalid characters in nick".to_string()));
 						}
+						if self.config.whitelist_enabled && !self.whitelist.contains(&nick) {
+							verdict!(Verdict::LogInFail("Not on the whitelist".to_string()));
+						}
 
 						let la = self.auth_back.as_mut().unwrap();
 						let id_opt = la.get_player_id(&nick, 1).unwrap();
@@ -406,18 +711,18 @@ impl<S :NetworkServerSocket> Server<S> {
 					},
 					Ok(None) => break,
 					Err(NetErr::ConnectionClosed) => {
-						println!("Client connection closed.");
+						info!(target : TARGET_NET, "Client connection closed.");
 						verdict!(Verdict::Close);
 					},
 					Err(_) => {
-						println!("Client connection error.");
+						warn!(target : TARGET_NET, "Client connection error.");
 						verdict!(Verdict::Close);
 					},
 				}
 			}
 		}
 		for (skew, (idx, verd)) in conns_to_remove.into_iter().enumerate() {
-			println!("closing connection");
+			debug!(target : TARGET_NET, "closing connection");
 			let (conn, _state) = self.unauthenticated_players.remove(idx - skew);
 			match verd {
 				Verdict::AddAsPlayer(nick, id) => {
@@ -426,6 +731,12 @@ impl<S :NetworkServerSocket> Server<S> {
 				Verdict::LogInFail(reason) => {
 					let _ = conn.send(ServerToClientMsg::LogInFail(reason));
 				},
+				Verdict::IncompatibleVersion(version) => {
+					warn!(target : TARGET_NET, "Rejected client with incompatible protocol version {}", version);
+					let msg = ServerToClientMsg::IncompatibleVersion(PROTOCOL_VERSION,
+						MIN_COMPATIBLE_PROTOCOL_VERSION);
+					let _ = conn.send(msg);
+				},
 				Verdict::Close => (),
 			}
 		}
@@ -437,22 +748,30 @@ impl<S :NetworkServerSocket> Server<S> {
 		let mut players_to_add = Vec::new();
 		let pwfk = &mut self.players_waiting_for_kv;
 		let nm = &self.params.p.name_id_map;
+		// Players joining in the same tick share the position,
+		// which avoids searching one if nobody is joining
+		let spawn_pos = if pwfk.is_empty() {
+			self.spawn_pos
+		} else {
+			scatter_spawn(&self.map, self.spawn_pos, self.config.spawn_radius,
+				&self.params, &mut rand::thread_rng())
+		};
 		self.map.run_for_kv_results(&mut |id, _payload, key, value| {
 			let mut ready = false;
 			if key == "position" {
-				if let Some((_conn, _nick, pos, inv)) = pwfk.get_mut(&id) {
+				if let Some((_conn, _nick, pos, inv, hunger)) = pwfk.get_mut(&id) {
 					*pos = Some(if let Some(buf) = value {
 						PlayerPosition::deserialize(&buf)
 							.ok()
-							.unwrap_or_else(PlayerPosition::default)
+							.unwrap_or_else(|| PlayerPosition::from_pos(spawn_pos))
 					} else {
 						// No value could be found
-						PlayerPosition::default()
+						PlayerPosition::from_pos(spawn_pos)
 					});
-					ready = inv.is_some();
+					ready = inv.is_some() && hunger.is_some();
 				}
 			} else if key == "inventory" {
-				if let Some((_conn, _nick, pos, inv)) = pwfk.get_mut(&id) {
+				if let Some((_conn, _nick, pos, inv, hunger)) = pwfk.get_mut(&id) {
 					*inv = Some(if let Some(buf) = value {
 						SelectableInventory::deserialize(&buf, nm)
 							.ok()
@@ -461,58 +780,44 @@ impl<S :NetworkServerSocket> Server<S> {
 						// No value could be found
 						SelectableInventory::new()
 					});
-					ready = pos.is_some();
+					ready = pos.is_some() && hunger.is_some();
+				}
+			} else if key == "hunger" {
+				if let Some((_conn, _nick, pos, inv, hunger)) = pwfk.get_mut(&id) {
+					*hunger = Some(if let Some(buf) = value {
+						Hunger::deserialize(&buf)
+							.ok()
+							.unwrap_or_default()
+					} else {
+						// No value could be found
+						Hunger::default()
+					});
+					ready = pos.is_some() && inv.is_some();
 				}
 			}
 			if ready {
-				if let Some((conn, nick, Some(pos), Some(inv)))
+				if let Some((conn, nick, Some(pos), Some(inv), Some(hunger)))
 						= pwfk.remove(&id) {
-					players_to_add.push((conn, id, nick, pos, inv));
+					players_to_add.push((conn, id, nick, pos, inv, hunger));
 				}
 			}
 		});
-		for (conn, id, nick, pos, inv) in players_to_add {
-			self.add_player(conn, id, nick, pos, inv);
+		for (conn, id, nick, pos, inv, hunger) in players_to_add {
+			self.add_player(conn, id, nick, pos, inv, hunger);
 		}
 	}
 	fn get_msgs(&mut self) -> Vec<(PlayerIdPair, ClientToServerMsg)> {
-		let mut msgs = Vec::new();
-		let mut players = self.players.borrow_mut();
-		let mut conns_to_close = Vec::new();
-		for (id, player) in players.iter_mut() {
-			loop {
-				let msg = player.conn.try_recv();
-				match msg {
-					Ok(Some(ClientToServerMsg::SetPos(p))) => {
-						player.pos = p;
-					},
-					Ok(Some(ClientToServerMsg::SetInventory(inv))) => {
-						player.inventory = inv;
-					},
-					Ok(Some(msg)) => {
-						msgs.push((*id, msg));
-					},
-					Ok(None) => break,
-					Err(NetErr::ConnectionClosed) => {
-						println!("Client connection closed.");
-						conns_to_close.push(*id);
-						break;
-					},
-					Err(_) => {
-						println!("Client connection error.");
-						conns_to_close.push(*id);
-						break;
-					},
-				}
-			}
-		}
-		close_connections(&conns_to_close, &mut *players);
-		msgs
+		recv_player_msgs(&mut self.players.borrow_mut())
 	}
 	fn send_chunks_to_player(&mut self, player :&mut Player<S::Conn>) -> Result<(), NetErr> {
 		let isize_pos = player.pos().map(|v| v as isize);
 		let (pmin, pmax) = chunk_positions_around(isize_pos,
 			self.config.sent_chunks_radius_xy, self.config.sent_chunks_radius_z);
+		player.sent_area = Some((pmin, pmax));
+		// Forget the chunks out of range so that the player doesn't get
+		// their updates. If they are unchanged when the player comes back,
+		// the acknowledgements prevent sending them again.
+		player.sent_chunks.retain(|p| in_chunk_area((pmin, pmax), *p));
 		let pmin = pmin / CHUNKSIZE;
 		let pmax = pmax / CHUNKSIZE;
 		for x in pmin.x .. pmax.x {
@@ -521,8 +826,10 @@ impl<S :NetworkServerSocket> Server<S> {
 					let p = Vector3::new(x, y, z) * CHUNKSIZE;
 					if let Some(c) = self.map.get_chunk(p) {
 						if !player.sent_chunks.contains(&p) {
-							let msg = ServerToClientMsg::ChunkUpdated(p, c.clone());
-							player.conn.send(msg)?;
+							if player.acked_chunks.get(&p) != Some(&c.version()) {
+								let msg = ServerToClientMsg::ChunkUpdated(p, c.clone(), c.version());
+								player.conn.send(msg)?;
+							}
 							player.sent_chunks.insert(p);
 						}
 					}
@@ -531,8 +838,8 @@ impl<S :NetworkServerSocket> Server<S> {
 		}
 		Ok(())
 	}
-	fn store_player_kvs(&mut self) -> Result<(), StrErr> {
-		self.store_player_positions()?;
+	fn store_player_kvs(&mut self, force :bool) -> Result<(), StrErr> {
+		self.store_player_positions(force)?;
 		self.store_player_inventories()?;
 		Ok(())
 	}
@@ -541,21 +848,26 @@ impl<S :NetworkServerSocket> Server<S> {
 		// intervals because in general, intervals don't change around.
 		let players = self.players.clone();
 		for (_, player) in players.borrow_mut().iter_mut() {
-			if player.inventory_last_ser != player.inventory {
-				let serialized_inv = player.inventory.serialize();
+			let (inventory, leftover) = player.inventory_with_craft_grids();
+			if player.inventory_last_ser != inventory {
+				if !leftover.is_empty() {
+					warn!(target : TARGET_SERVER, "The inventory of {} can't take \
+						the contents of the crafting grids, not storing {:?}", player.nick, leftover);
+				}
+				let serialized_inv = inventory.serialize();
 				self.map.set_player_kv(player.ids, "inventory", serialized_inv);
-				player.inventory_last_ser = player.inventory.clone();
+				player.inventory_last_ser = inventory;
 			}
 		}
 		Ok(())
 	}
-	fn store_player_positions(&mut self) -> Result<(), StrErr> {
+	fn store_player_positions(&mut self, force :bool) -> Result<(), StrErr> {
 		// This limiting makes sure we don't store the player positions
 		// too often as that would mean too much wear on the hdd
 		// and cause massive mapgen thread lag.
 		const INTERVAL_MILLIS :u128 = 1_500;
 		let now = Instant::now();
-		if (now - self.last_pos_storage_time).as_millis() < INTERVAL_MILLIS {
+		if !force && (now - self.last_pos_storage_time).as_millis() < INTERVAL_MILLIS {
 			return Ok(());
 		}
 		self.last_pos_storage_time = now;
@@ -563,9 +875,88 @@ impl<S :NetworkServerSocket> Server<S> {
 		for (_, player) in players.borrow().iter() {
 			let serialized_str = toml::to_string(&player.pos)?;
 			self.map.set_player_kv(player.ids, "position", serialized_str.into());
+			// Hunger changes continuously as well,
+			// so store it together with the position
+			self.map.set_player_kv(player.ids, "hunger", player.hunger.serialize()?);
 		}
+		// The same goes for the playtime
+		self.map.backend_mut().set_global_kv("playtime", self.playtime.serialize()?);
 		Ok(())
 	}
+	fn update_hunger(&mut self, dt :f32) {
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let (sprint_secs, jumps) = player.movement.take_activity();
+			player.hunger.tick(dt, sprint_secs, jumps, self.config.difficulty);
+			// Only send updates once the change is visible in the hunger bar
+			// or when the ability to sprint changes
+			let last = player.hunger_last_sent;
+			if (player.hunger.value() - last.value()).abs() >= 0.1 ||
+					player.hunger.can_sprint() != last.can_sprint() {
+				let msg = ServerToClientMsg::SetHunger(player.hunger);
+				if player.conn.send(msg).is_err() {
+					players_to_remove.push(*id);
+				}
+				player.hunger_last_sent = player.hunger;
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	fn update_clock(&mut self, dt :f32) {
+		self.clock.tick(dt);
+		// The clients advance their clocks on their own,
+		// so only resync them and store the clock every now and then
+		const INTERVAL_SECS :u64 = 10;
+		let now = Instant::now();
+		if (now - self.last_clock_sync_time).as_secs() >= INTERVAL_SECS {
+			self.last_clock_sync_time = now;
+			self.sync_clock();
+		}
+	}
+	/// Stores the clock and sends it to all players
+	fn sync_clock(&mut self) {
+		// Serialization of a plain float can't fail
+		let serialized = self.clock.serialize().unwrap();
+		self.map.backend_mut().set_global_kv("world_clock", serialized);
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let msg = ServerToClientMsg::SetTime(self.clock);
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	fn update_entities(&mut self, dt :f32) {
+		self.entities.tick(dt, &self.map, &self.params.p);
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let pos = player.pos();
+			if self.entities.pick_up(pos, &mut player.inventory) {
+				let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+				if player.conn.send(msg).is_err() {
+					players_to_remove.push(*id);
+				}
+			}
+		}
+		// Send the empty list once so that clients remove the last entities
+		if !self.entities.is_empty() || !self.entities_sent_empty {
+			let entities = self.entities.iter()
+				.map(|e| (e.id, e.pos, e.kind))
+				.collect::<Vec<_>>();
+			let msg = ServerToClientMsg::Entities(entities);
+			for (id, player) in players.borrow_mut().iter_mut() {
+				if player.conn.send(msg.clone()).is_err() {
+					players_to_remove.push(*id);
+				}
+			}
+			self.entities_sent_empty = self.entities.is_empty();
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
 	fn send_chunks_to_players(&mut self) {
 		let players = self.players.clone();
 		let mut players_to_remove = Vec::new();
@@ -582,14 +973,55 @@ impl<S :NetworkServerSocket> Server<S> {
 		}
 		close_connections(&players_to_remove, &mut *players.borrow_mut());
 	}
+	/// Sends the block edits of this tick to the players in one batch each
+	///
+	/// Players get the edits in the chunks they have loaded. Players
+	/// that want a chunk but don't have it yet get the entire chunk.
+	fn send_block_batches(&mut self) {
+		let edits = take(&mut *self.pending_edits.borrow_mut());
+		if edits.is_empty() {
+			return;
+		}
+		let mut players = self.players.borrow_mut();
+		let mut conns_to_close = Vec::new();
+		for (id, player) in players.iter_mut() {
+			let mut batch = BlockBatch::new();
+			let mut res = Ok(());
+			for (pos, b) in edits.edits.iter() {
+				let chunk_pos = btchn(*pos);
+				if player.sent_chunks.contains(&chunk_pos) {
+					batch.push(*pos, *b);
+				} else if player.wants_chunk(chunk_pos) {
+					if let Some(c) = self.map.get_chunk(chunk_pos) {
+						let msg = ServerToClientMsg::ChunkUpdated(chunk_pos, c.clone(), c.version());
+						res = res.and(player.conn.send(msg));
+						player.sent_chunks.insert(chunk_pos);
+					}
+				}
+			}
+			if !batch.is_empty() {
+				res = res.and(player.conn.send(ServerToClientMsg::BlockBatch(batch)));
+			}
+			if res.is_err() {
+				conns_to_close.push(*id);
+			}
+		}
+		close_connections(&conns_to_close, &mut *players);
+	}
 	fn send_positions_to_players(&mut self) {
 		let players = self.players.clone();
 		let mut players_to_remove = Vec::new();
 		let player_positions = players.borrow().iter()
-			.map(|(id, player)| (*id, player.pos()))
+			.map(|(id, player)| (*id, player.nick.clone(), player.pos(),
+				player.game_mode.is_visible()))
 			.collect::<Vec<_>>();
 		for (id, player) in players.borrow_mut().iter_mut() {
-			let msg = ServerToClientMsg::PlayerPositions(*id, player_positions.clone());
+			// Invisible players are only sent their own position
+			let positions = player_positions.iter()
+				.filter(|(pid, _nick, _pos, visible)| *visible || pid == id)
+				.map(|(pid, nick, pos, _visible)| (*pid, nick.clone(), *pos))
+				.collect::<Vec<_>>();
+			let msg = ServerToClientMsg::PlayerPositions(*id, positions);
 			if player.conn.send(msg).is_err() {
 				players_to_remove.push(*id);
 			}
@@ -600,10 +1032,12 @@ impl<S :NetworkServerSocket> Server<S> {
 		const PAYLOAD :u32 = 0;
 		self.map.get_player_kv(id, "position", PAYLOAD);
 		self.map.get_player_kv(id, "inventory", PAYLOAD);
-		self.players_waiting_for_kv.insert(id, (conn, nick, None, None));
+		self.map.get_player_kv(id, "hunger", PAYLOAD);
+		self.players_waiting_for_kv.insert(id, (conn, nick, None, None, None));
 	}
 	fn add_player(&mut self, conn :S::Conn, id :PlayerIdPair,
-			nick :String, pos :PlayerPosition, inv :SelectableInventory) {
+			nick :String, pos :PlayerPosition, inv :SelectableInventory,
+			hunger :Hunger) {
 		let player_count = {
 			let msg = ServerToClientMsg::GameParams(self.params.p.clone());
 			// TODO get rid of unwrap
@@ -617,8 +1051,26 @@ impl<S :NetworkServerSocket> Server<S> {
 			// TODO get rid of unwrap
 			conn.send(msg).unwrap();
 
+			let msg = ServerToClientMsg::SetHunger(hunger);
+			// TODO get rid of unwrap
+			conn.send(msg).unwrap();
+
+			let msg = ServerToClientMsg::SetTime(self.clock);
+			// TODO get rid of unwrap
+			conn.send(msg).unwrap();
+
+			let game_mode = self.config.game_mode;
+			let msg = ServerToClientMsg::SetGameMode(game_mode);
+			// TODO get rid of unwrap
+			conn.send(msg).unwrap();
+
+			let msg = ServerToClientMsg::SetSpawnProtection(self.spawn_protection_area());
+			// TODO get rid of unwrap
+			conn.send(msg).unwrap();
+
 			let mut players = self.players.borrow_mut();
-			let player = Player::from_stuff(conn, id, nick.clone(), inv);
+			let mut player = Player::from_stuff(conn, id, nick.clone(), inv, hunger);
+			player.game_mode = game_mode;
 			players.insert(id, player);
 			players.len()
 		};
@@ -630,10 +1082,19 @@ impl<S :NetworkServerSocket> Server<S> {
 		}
 	}
 	fn handle_command(&mut self, issuer_id :PlayerIdPair, msg :String) {
-		println!("Command: {}", msg);
+		info!(target : TARGET_SERVER, "Command: {}", msg);
 		let mut it = msg[1..].split(" ");
 		let command = it.next().unwrap();
 		let params = it.collect::<Vec<&str>>();
+		if ADMIN_COMMANDS.contains(&command) {
+			if self.is_admin(issuer_id) || is_public_query(command, &params) {
+				self.handle_admin_command(issuer_id, command, &params);
+			} else {
+				let msg = format!("Insufficient permission: /{} is for admins only", command);
+				self.chat_msg_for(issuer_id, msg);
+			}
+			return;
+		}
 		match command {
 			"info" => {
 				self.chat_msg_for(issuer_id, format!(
@@ -642,15 +1103,38 @@ impl<S :NetworkServerSocket> Server<S> {
 					env!("CARGO_PKG_VERSION")));
 			},
 			"spawn" => {
-				let players = self.players.clone();
-				let msg = ServerToClientMsg::SetPos(PlayerPosition::default());
-				let remove_player = {
-					let player = &players.borrow_mut()[&issuer_id];
-					player.conn.send(msg.clone()).is_err()
-				};
-				if remove_player {
-					close_connections(&[issuer_id], &mut *players.borrow_mut());
-				}
+				let target = PlayerPosition::from_pos(self.player_spawn_pos());
+				let target = TeleportTarget::Position(target);
+				self.request_teleport(issuer_id, target, "Teleported to the spawn".to_owned());
+			},
+			"sethome" | "home" | "delhome" | "listhomes" => {
+				self.handle_home_command(issuer_id, command, &params);
+			},
+			"inv" => {
+				self.handle_inv_command(issuer_id, &params);
+			},
+			"playtime" => {
+				let nick = self.players.borrow()[&issuer_id].nick.clone();
+				let ticks = self.playtime.player(&nick);
+				let msg = format!("You have played for {}",
+					format_ticks(ticks, self.config.tick_rate));
+				self.chat_msg_for(issuer_id, msg);
+			},
+			"worldage" => {
+				let ticks = self.playtime.world_age();
+				let msg = format!("The world is {} old",
+					format_ticks(ticks, self.config.tick_rate));
+				self.chat_msg_for(issuer_id, msg);
+			},
+			"kill" => {
+				// Respawning moves the player too, so it's subject
+				// to the same warmup and cooldown as teleports
+				let msg = "You died and respawned".to_owned();
+				self.request_teleport(issuer_id, TeleportTarget::Respawn, msg);
+			},
+			"gime" if self.players.borrow()[&issuer_id].game_mode != GameMode::Creative => {
+				// Giving creates the items out of thin air
+				self.chat_msg_for(issuer_id, "Items can only be given in creative mode");
 			},
 			"gime" => {
 				let content = params.get(0);
@@ -674,7 +1158,7 @@ impl<S :NetworkServerSocket> Server<S> {
 				let mut players = self.players.borrow_mut();
 				let remove_player = {
 					let player = players.get_mut(&issuer_id).unwrap();
-					player.inventory.put(Stack::with(content, count));
+					player.inventory.put(self.params.p.stack_with(content, count));
 					let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
 					player.conn.send(msg).is_err()
 				};
@@ -733,180 +1217,1070 @@ impl<S :NetworkServerSocket> Server<S> {
 			},
 		}
 	}
-	fn handle_chat_msg(&mut self, msg :String) {
-		println!("Chat: {}", msg);
-		let players = self.players.clone();
-		let mut players_to_remove = Vec::new();
-		for (id, player) in players.borrow_mut().iter_mut() {
-			let msg = ServerToClientMsg::Chat(msg.clone());
-			if player.conn.send(msg).is_err() {
-				players_to_remove.push(*id);
-			}
+	/// Handles the commands managing the homes of the issuer
+	fn handle_home_command(&mut self, issuer_id :PlayerIdPair, command :&str, params :&[&str]) {
+		let (nick, pos) = {
+			let players = self.players.borrow();
+			let player = &players[&issuer_id];
+			(player.nick.clone(), player.pos)
+		};
+		let name = params.first().copied().unwrap_or(DEFAULT_HOME_NAME);
+		let msg = match command {
+			"sethome" => {
+				let max = self.config.max_homes_per_player;
+				match self.homes.set(&nick, name, pos, max) {
+					Ok(()) => {
+						self.save_homes();
+						format!("Home {} set", name)
+					},
+					Err(e) => e.0,
+				}
+			},
+			"home" => if let Some(home) = self.homes.get(&nick, name) {
+				let target = TeleportTarget::Position(home);
+				self.request_teleport(issuer_id, target, format!("Teleported to home {}", name));
+				return;
+			} else {
+				format!("You have no home named {}", name)
+			},
+			"delhome" => if self.homes.remove(&nick, name) {
+				self.save_homes();
+				format!("Home {} deleted", name)
+			} else {
+				format!("You have no home named {}", name)
+			},
+			_ => {
+				let names = self.homes.names(&nick);
+				if names.is_empty() {
+					"You have no homes".to_owned()
+				} else {
+					format!("Your homes: {}", names.join(", "))
+				}
+			},
+		};
+		self.chat_msg_for(issuer_id, msg);
+	}
+	fn save_homes(&mut self) {
+		match self.homes.serialize() {
+			Ok(buf) => self.map.backend_mut().set_global_kv("homes", buf),
+			Err(e) => error!(target : TARGET_SERVER, "Couldn't serialize the homes: {}", e.0),
 		}
-		close_connections(&players_to_remove, &mut *players.borrow_mut());
 	}
-	fn chat_msg_for(&mut self, for_id :PlayerIdPair, msg :impl Into<String>) {
-		let players = self.players.clone();
-		let mut players_to_remove = Vec::new();
-		let msg = msg.into();
-		for (id, player) in players.borrow_mut().iter_mut() {
-			if *id != for_id {
-				continue;
-			}
-			let msg = ServerToClientMsg::Chat(msg.clone());
-			if player.conn.send(msg).is_err() {
-				players_to_remove.push(*id);
-			}
+	/// Handles the commands saving and loading inventory presets of the issuer
+	fn handle_inv_command(&mut self, issuer_id :PlayerIdPair, params :&[&str]) {
+		let nick = self.players.borrow()[&issuer_id].nick.clone();
+		let msg = match (params.first(), params.get(1)) {
+			(Some(&"save"), Some(name)) => {
+				let inv = self.players.borrow()[&issuer_id].inventory.clone();
+				let max = self.config.max_inventory_presets_per_player;
+				match self.inventory_presets.save(&nick, name, inv, max) {
+					Ok(()) => {
+						self.save_inventory_presets();
+						format!("Inventory preset {} saved", name)
+					},
+					Err(e) => e.0,
+				}
+			},
+			(Some(&"load"), Some(_)) if self.players.borrow()[&issuer_id].game_mode != GameMode::Creative => {
+				// Loading creates the items out of thin air
+				"Inventory presets can only be loaded in creative mode".to_owned()
+			},
+			(Some(&"load"), Some(name)) => if let Some(inv) = self.inventory_presets.get(&nick, name) {
+				let mut players = self.players.borrow_mut();
+				let remove_player = {
+					let player = players.get_mut(&issuer_id).unwrap();
+					player.inventory = inv.clone();
+					let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+					player.conn.send(msg).is_err()
+				};
+				if remove_player {
+					close_connections(&[issuer_id], &mut *players);
+					return;
+				}
+				format!("Inventory preset {} loaded", name)
+			} else {
+				format!("You have no inventory preset named {}", name)
+			},
+			(Some(&"list"), None) => {
+				let names = self.inventory_presets.names(&nick);
+				if names.is_empty() {
+					"You have no inventory presets".to_owned()
+				} else {
+					format!("Your inventory presets: {}", names.join(", "))
+				}
+			},
+			_ => "Usage: /inv {save <name>,load <name>,list}".to_owned(),
+		};
+		self.chat_msg_for(issuer_id, msg);
+	}
+	fn save_inventory_presets(&mut self) {
+		match self.inventory_presets.serialize() {
+			Ok(buf) => self.map.backend_mut().set_global_kv("inventory_presets", buf),
+			Err(e) => error!(target : TARGET_SERVER, "Couldn't serialize the inventory presets: {}", e.0),
 		}
-		close_connections(&players_to_remove, &mut *players.borrow_mut());
 	}
-	pub fn run_loop(&mut self) {
-		loop {
-			let positions = self.players.borrow().iter()
-				.map(|(_, player)| {
-					(btchn(player.pos.pos().map(|v| v as isize)), player.last_chunk_pos)
-				})
-				.filter(|(cp, lcp)| cp != lcp)
-				.map(|(cp, _lcp)| cp)
-				.collect::<Vec<_>>();
-			for pos in positions {
-				gen_chunks_around(&mut self.map,
-					pos.map(|v| v as isize),
-					self.config.mapgen_radius_xy,
-					self.config.mapgen_radius_z);
-			}
-			self.send_chunks_to_players();
-			self.send_positions_to_players();
-			self.map.tick();
-			let _float_delta = self.update_fps();
-			let exit = false;
-			while let Some(conn) = self.srv_socket.try_open_conn() {
-				if self.is_singleplayer {
-					let id = PlayerIdPair::singleplayer();
-					self.add_player_waiting(conn, id, "singleplayer".to_owned());
+	/// Handles the commands in `ADMIN_COMMANDS`
+	///
+	/// The permission of the issuer has been checked already.
+	fn handle_admin_command(&mut self, issuer_id :PlayerIdPair, command :&str, params :&[&str]) {
+		match command {
+			"gamemode" => {
+				let mode = if let Some(mode) = params.first().and_then(|n| GameMode::from_name(n)) {
+					mode
+				} else {
+					self.chat_msg_for(issuer_id, "Usage: /gamemode {survival,creative,spectator} [<nick>]");
+					return;
+				};
+				let target_id = if let Some(nick) = params.get(1) {
+					let id = self.players.borrow().iter()
+						.find(|(_, p)| p.nick == *nick)
+						.map(|(id, _)| *id);
+					if let Some(id) = id {
+						id
+					} else {
+						self.chat_msg_for(issuer_id, format!("No player named {} is online", nick));
+						return;
+					}
 				} else {
-					self.unauthenticated_players.push((conn, AuthState::Unauthenticated));
+					issuer_id
+				};
+				self.set_game_mode(target_id, mode);
+				self.chat_msg_for(issuer_id, format!("Game mode set to {}", mode.name()));
+			},
+			"reload" => {
+				let new_config = match load_config() {
+					Ok(c) => c,
+					Err(e) => {
+						self.chat_msg_for(issuer_id, format!("Couldn't load the config: {}", e.0));
+						return;
+					},
+				};
+				let report = self.config.apply_reload(&new_config);
+				if report.changed.contains(&"mapgen_min_padding") {
+					let min_padding = self.config.mapgen_min_padding;
+					self.map.backend_mut().set_min_padding(min_padding);
 				}
-			}
-			self.handle_auth_msgs();
-			self.handle_players_waiting_for_kv();
-			self.store_player_kvs().unwrap();
-
-			let msgs = self.get_msgs();
-
-			for (id, msg) in msgs {
-				use crate::ClientToServerMsg::*;
-				match msg {
-					LogIn(..) |
-					SendHash(_) |
-					SendM1(..) => {
-						// Invalid at this state. Ignore.
-						// TODO maybe issue a warning in the log? idk
+				let mut msg = if report.changed.is_empty() {
+					"Reloaded the config, no settings changed".to_owned()
+				} else {
+					format!("Reloaded the config, changed: {}", report.changed.join(", "))
+				};
+				if !report.needs_restart.is_empty() {
+					msg += &format!("\nOnly applied after a restart: {}",
+						report.needs_restart.join(", "));
+				}
+				if !report.kept.is_empty() {
+					msg += &format!("\nKept the values set by commands: {}",
+						report.kept.join(", "));
+				}
+				info!(target : TARGET_SERVER, "{}", msg);
+				self.chat_msg_for(issuer_id, msg);
+			},
+			"gamerule" => {
+				let msg = match params {
+					["tickspeed"] => format!("Tick speed is {}", self.tick_speed),
+					["tickspeed", n] => match n.parse::<u32>() {
+						Ok(n) if (1 ..= MAX_TICK_SPEED).contains(&n) => {
+							self.tick_speed = n;
+							format!("Tick speed set to {}", n)
+						},
+						_ => format!("Invalid tick speed {}, must be between 1 and {}",
+							n, MAX_TICK_SPEED),
 					},
-					GetHashedBlobs(blob_list) => {
-						let hashed_blobs = blob_list.iter()
-							.filter_map(|h| self.params.textures.get(h)
-								.map(|b| (h.clone(), b.clone())))
-							.collect::<Vec<_>>();
-						let msg = ServerToClientMsg::HashedBlobs(hashed_blobs);
-						let remove_player = {
-							let player = &self.players.borrow_mut()[&id];
-							player.conn.send(msg.clone()).is_err()
-						};
-						if remove_player {
-							close_connections(&[id], &mut *self.players.borrow_mut());
-						}
+					["spawnprotection"] => format!("Spawn protection radius is {}",
+						self.config.spawn_protection_radius),
+					["spawnprotection", n] => match n.parse::<isize>() {
+						Ok(n) if (0 ..= MAX_SPAWN_PROTECTION_RADIUS).contains(&n) => {
+							self.set_spawn_protection_radius(n);
+							format!("Spawn protection radius set to {}", n)
+						},
+						_ => format!("Invalid radius {}, must be between 0 and {}",
+							n, MAX_SPAWN_PROTECTION_RADIUS),
 					},
-					SetBlock(p, b) => {
-						if let Some(mut hdl) = self.map.get_blk_mut(p) {
-							hdl.set(b);
-						} else {
-							// TODO log something about an attempted action in an unloaded chunk
-						}
+					["keepinventory"] => format!("keepinventory is {}", self.config.keep_inventory),
+					["keepinventory", v] => match v.parse::<bool>() {
+						Ok(v) => {
+							self.set_keep_inventory(v);
+							format!("keepinventory set to {}", v)
+						},
+						_ => format!("Invalid value {}, must be true or false", v),
 					},
-					SetMetadata(p, ne) => {
-						if let Some(mut hdl) = self.map.get_blk_meta_mut(p) {
-							hdl.set(ne);
+					_ => "Usage: /gamerule tickspeed [<n>]|keepinventory [<bool>]|\
+						spawnprotection [<radius>]".to_owned(),
+				};
+				self.chat_msg_for(issuer_id, msg);
+			},
+			"whitelist" => {
+				let whitelist_before = self.whitelist.clone();
+				let msg = match params {
+					["list"] if self.whitelist.is_empty() => {
+						"The whitelist is empty".to_owned()
+					},
+					["list"] => {
+						self.whitelist.nicks().collect::<Vec<_>>().join(", ")
+					},
+					["add", nick] => match self.whitelist.add(nick) {
+						Ok(_) => format!("Added {} to the whitelist", nick),
+						Err(e) => e.0,
+					},
+					["remove", nick] => if self.whitelist.remove(nick) {
+						format!("Removed {} from the whitelist", nick)
+					} else {
+						format!("{} is not on the whitelist", nick)
+					},
+					["export", name] => match file_path_in(&self.world_dir(), name)
+							.and_then(|path| self.whitelist.export(&path)) {
+						Ok(()) => format!("Exported {} nicks to {}", self.whitelist.len(), name),
+						Err(e) => format!("Couldn't export the whitelist: {}", e.0),
+					},
+					["import", name] => match file_path_in(&self.world_dir(), name)
+							.and_then(|path| self.whitelist.import(&path)) {
+						Ok(added) => format!("Imported {} new nicks from {}", added, name),
+						Err(e) => format!("Couldn't import the whitelist: {}", e.0),
+					},
+					_ => "Usage: /whitelist list|add <nick>|remove <nick>|\
+						export <file>|import <file>".to_owned(),
+				};
+				if self.whitelist != whitelist_before {
+					let whitelist_buf = self.whitelist.serialize();
+					self.map.backend_mut().set_global_kv("whitelist", whitelist_buf);
+				}
+				self.chat_msg_for(issuer_id, msg);
+			},
+			"mapgen" => {
+				match params.first() {
+					Some(&"get") => {
+						let mp = &self.config.mapgen;
+						let list = MapgenParams::NAMES.iter()
+							.map(|name| format!("{} = {}", name, mp.get(name).unwrap()))
+							.collect::<Vec<_>>()
+							.join("\n");
+						self.chat_msg_for(issuer_id, list);
+					},
+					Some(&"set") => {
+						let name = params.get(1);
+						let value = params.get(2).and_then(|v| v.parse::<f64>().ok());
+						let (name, value) = if let (Some(name), Some(value)) = (name, value) {
+							(*name, value)
 						} else {
-							// TODO log something about an attempted action in an unloaded chunk
+							self.chat_msg_for(issuer_id, "Usage: /mapgen set <param> <value>");
+							return;
+						};
+						if !self.config.mapgen.set(name, value) {
+							self.chat_msg_for(issuer_id, format!("Unknown mapgen param {}", name));
+							return;
 						}
+						let mp = self.config.mapgen_params();
+						self.map.backend_mut().set_mapgen_params(mp);
+						self.chat_msg_for(issuer_id, format!("Set {} to {}. \
+							Only newly generated chunks are affected.", name, value));
 					},
-					PlaceTree(p) => {
-						map::spawn_tree(&mut self.map, p, &self.params);
+					_ => {
+						self.chat_msg_for(issuer_id, "Invalid mapgen command.");
 					},
-					Dig(p) => {
-						let mut remove = true;
-						if let Some(chest_meta) = self.map.get_blk_meta(p) {
-							if let Some(MetadataEntry::Inventory(inv)) = chest_meta {
-								if !inv.is_empty() {
-									remove = false;
-								}
-							}
-						} else {
-							// TODO log something about an attempted action in an unloaded chunk
-							remove = false;
+				}
+			},
+			"time" => {
+				match TimeCommand::parse(params) {
+					Ok(TimeCommand::Query) => {
+						let msg = format!("Time is {}", self.clock.display());
+						self.chat_msg_for(issuer_id, msg);
+					},
+					Ok(TimeCommand::Set(time)) => {
+						// The parser only returns valid times
+						self.clock.set_time(time).unwrap();
+						self.sync_clock();
+						self.handle_chat_msg(format!("Time set to {}", self.clock.display()));
+					},
+					Err(e) => {
+						self.chat_msg_for(issuer_id, e);
+					},
+				}
+			},
+			"difficulty" => {
+				let difficulty = if let Some(name) = params.first() {
+					if let Some(d) = Difficulty::from_name(name) {
+						d
+					} else {
+						self.chat_msg_for(issuer_id, format!("Invalid difficulty {}", name));
+						return;
+					}
+				} else {
+					let msg = format!("Difficulty is {}", self.config.difficulty.name());
+					self.chat_msg_for(issuer_id, msg);
+					return;
+				};
+				self.config.difficulty = difficulty;
+				self.map.backend_mut().set_global_kv("difficulty", difficulty.serialize());
+				self.handle_chat_msg(format!("Difficulty set to {}", difficulty.name()));
+			},
+			_ => unreachable!("/{} is no admin command", command),
+		}
+	}
+	fn set_game_mode(&mut self, id :PlayerIdPair, mode :GameMode) {
+		let players = self.players.clone();
+		let remove_player = {
+			let mut players = players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			player.game_mode = mode;
+			player.conn.send(ServerToClientMsg::SetGameMode(mode)).is_err()
+		};
+		if remove_player {
+			close_connections(&[id], &mut *players.borrow_mut());
+		}
+	}
+	/// A position to spawn a player at, scattered within `spawn_radius`
+	fn player_spawn_pos(&self) -> Vector3<f32> {
+		scatter_spawn(&self.map, self.spawn_pos, self.config.spawn_radius,
+			&self.params, &mut rand::thread_rng())
+	}
+	/// Moves the player to the given position
+	fn teleport(&mut self, id :PlayerIdPair, pos :PlayerPosition) {
+		let players = self.players.clone();
+		let remove_player = {
+			let mut players = players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			player.pos = pos;
+			player.movement.reset();
+			player.conn.send(ServerToClientMsg::SetPos(pos)).is_err()
+		};
+		if remove_player {
+			close_connections(&[id], &mut *players.borrow_mut());
+		}
+	}
+	/// Teleports the player, subject to the configured warmup and cooldown
+	///
+	/// The message is sent to the player once it has been teleported.
+	fn request_teleport(&mut self, id :PlayerIdPair, target :TeleportTarget, msg :String) {
+		if let Some(left) = self.teleports.cooldown_left(id) {
+			let msg = format!("You can teleport again in {} seconds", left.ceil());
+			self.chat_msg_for(id, msg);
+			return;
+		}
+		let warmup = self.config.teleport_warmup;
+		if warmup > 0.0 {
+			let start = self.players.borrow()[&id].pos();
+			self.teleports.schedule(id, target, start, warmup, msg);
+			self.chat_msg_for(id, format!("Teleporting in {} seconds, don't move", warmup));
+		} else {
+			self.teleport_to_target(id, target);
+			self.teleports.teleported(id, self.config.teleport_cooldown);
+			self.chat_msg_for(id, msg);
+		}
+	}
+	fn teleport_to_target(&mut self, id :PlayerIdPair, target :TeleportTarget) {
+		match target {
+			TeleportTarget::Position(pos) => self.teleport(id, pos),
+			TeleportTarget::Respawn => self.respawn(id),
+		}
+	}
+	/// Executes the teleports whose warmup has passed
+	fn update_teleports(&mut self, dt :f32) {
+		let events = {
+			let players = self.players.borrow();
+			self.teleports.update(dt, |id| players.get(&id).map(|p| p.pos()))
+		};
+		for event in events {
+			match event {
+				TeleportEvent::Ready(id, target, msg) => {
+					self.teleport_to_target(id, target);
+					self.teleports.teleported(id, self.config.teleport_cooldown);
+					self.chat_msg_for(id, msg);
+				},
+				TeleportEvent::Cancelled(id) => {
+					self.chat_msg_for(id, "Teleport cancelled because you moved");
+				},
+			}
+		}
+	}
+	/// Moves the player back to the spawn with its hunger reset
+	///
+	/// Unless `keep_inventory` is set, the inventory is emptied,
+	/// dropping the items where the player died if
+	/// `drop_item_entities` is set.
+	fn respawn(&mut self, id :PlayerIdPair) {
+		let players = self.players.clone();
+		let spawn_pos = self.player_spawn_pos();
+		let remove_player = {
+			let mut players = players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			let mut inv_err = false;
+			if !self.config.keep_inventory {
+				let death_pos = player.pos().map(|v| v.floor() as isize);
+				// Items left in the crafting grids are lost as well
+				let invs = std::iter::once(&mut player.inventory)
+					.chain(player.craft_invs.values_mut());
+				for inv in invs {
+					for st in inv.stacks_mut().iter_mut() {
+						let stack = *st;
+						*st = Stack::Empty;
+						if self.config.drop_item_entities {
+							self.entities.spawn_dropped_item(death_pos, stack);
 						}
-						let mut drops = None;
+					}
+				}
+				let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+				inv_err = player.conn.send(msg).is_err();
+				for (grid, inv) in player.craft_invs.iter() {
+					let msg = ServerToClientMsg::SetCraftInventory(*grid, inv.clone());
+					inv_err |= player.conn.send(msg).is_err();
+				}
+			}
+			player.pos = PlayerPosition::from_pos(spawn_pos);
+			player.movement.reset();
+			player.hunger = Hunger::default();
+			player.hunger_last_sent = player.hunger;
+			let msg = ServerToClientMsg::SetPos(player.pos);
+			let pos_err = player.conn.send(msg).is_err();
+			let msg = ServerToClientMsg::SetHunger(player.hunger);
+			inv_err || pos_err || player.conn.send(msg).is_err()
+		};
+		if remove_player {
+			close_connections(&[id], &mut *players.borrow_mut());
+		}
+	}
+	fn spawn_protection_area(&self) -> Option<(Vector3<isize>, Vector3<isize>)> {
+		spawn_protection_area(self.spawn_pos, self.config.spawn_protection_radius)
+	}
+	/// Whether the block is protected from edits of the player
+	fn is_protected(&self, id :PlayerIdPair, pos :Vector3<isize>) -> bool {
+		self.spawn_protection_area()
+			.map(|area| in_chunk_area(area, pos))
+			.unwrap_or(false) && !self.is_admin(id)
+	}
+	/// Changes the spawn protection radius, persisting and broadcasting it
+	fn set_spawn_protection_radius(&mut self, radius :isize) {
+		self.config.spawn_protection_radius = radius;
+		let buf = radius.to_string().into_bytes();
+		self.map.backend_mut().set_global_kv("spawn_protection", buf);
+		let area = self.spawn_protection_area();
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let msg = ServerToClientMsg::SetSpawnProtection(area);
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	/// The directory of the world, or the current one if there is no storage
+	fn world_dir(&self) -> PathBuf {
+		self.config.map_storage_path.as_ref()
+			.and_then(|p| Path::new(p).parent())
+			.map(|dir| dir.to_owned())
+			.unwrap_or_else(|| PathBuf::from("."))
+	}
+	/// Changes whether players keep their inventory on death, persisting it
+	fn set_keep_inventory(&mut self, keep_inventory :bool) {
+		self.config.keep_inventory = keep_inventory;
+		let buf = keep_inventory.to_string().into_bytes();
+		self.map.backend_mut().set_global_kv("keep_inventory", buf);
+	}
+	/// Whether the block is too far away from the player to edit it
+	fn is_out_of_reach(&self, id :PlayerIdPair, pos :Vector3<isize>) -> bool {
+		let player_pos = if let Some(player) = self.players.borrow().get(&id) {
+			player.pos.pos()
+		} else {
+			return true;
+		};
+		// The point of the block closest to the player
+		let block_min = pos.map(|v| v as f32);
+		let closest = player_pos.zip_map(&block_min, |p, min| p.max(min).min(min + 1.0));
+		(closest - player_pos).norm() > self.config.reach_distance + REACH_TOLERANCE
+	}
+	/// Blows up the sphere around the center, dropping items into the world
+	///
+	/// The changed blocks reach clients with the next block batch.
+	pub fn explode(&mut self, center :Vector3<isize>, radius :isize) {
+		let explosion = explosion::explode(&mut self.map, center, radius,
+			&self.params.p, &mut rand::thread_rng());
+		for (pos, stack) in explosion.drops {
+			self.entities.spawn_dropped_item(pos, stack);
+		}
+	}
+	/// Whether a client may place a block at the position
+	///
+	/// Clients place blocks into air and other blocks they can't point
+	/// at. Blocks holding items are never replaced, as their items
+	/// only change through inventory actions.
+	fn can_place_at(&self, pos :Vector3<isize>) -> bool {
+		let replaceable = self.map.get_blk(pos)
+			.map(|b| !self.params.p.get_pointability_for_blk(&b))
+			.unwrap_or(false);
+		replaceable && !holds_items(&self.map, pos)
+	}
+	fn can_edit(&self, id :PlayerIdPair) -> bool {
+		self.players.borrow().get(&id)
+			.map(|p| p.game_mode.can_edit())
+			.unwrap_or(false)
+	}
+	/// Whether the player may use admin commands
+	fn is_admin(&self, id :PlayerIdPair) -> bool {
+		if self.is_singleplayer {
+			return true;
+		}
+		self.players.borrow().get(&id)
+			.map(|p| self.config.is_admin(&p.nick))
+			.unwrap_or(false)
+	}
+	fn handle_chat_msg(&mut self, msg :String) {
+		info!(target : TARGET_SERVER, "Chat: {}", msg);
+		self.chat_log.log(&msg);
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			let msg = ServerToClientMsg::Chat(msg.clone());
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	fn chat_msg_for(&mut self, for_id :PlayerIdPair, msg :impl Into<String>) {
+		let players = self.players.clone();
+		let mut players_to_remove = Vec::new();
+		let msg = msg.into();
+		for (id, player) in players.borrow_mut().iter_mut() {
+			if *id != for_id {
+				continue;
+			}
+			let msg = ServerToClientMsg::Chat(msg.clone());
+			if player.conn.send(msg).is_err() {
+				players_to_remove.push(*id);
+			}
+		}
+		close_connections(&players_to_remove, &mut *players.borrow_mut());
+	}
+	/// Takes the block from the selection of the player, for it to be placed
+	///
+	/// Returns whether the player had the block selected.
+	fn take_placed_block(&mut self, id :PlayerIdPair, b :MapBlock) -> bool {
+		let placeable = self.params.p.get_block_params(b)
+			.map(|bp| bp.placeable)
+			.unwrap_or(false);
+		let mut players = self.players.borrow_mut();
+		let player = if let Some(player) = players.get_mut(&id) {
+			player
+		} else {
+			return false;
+		};
+		if !placeable || player.inventory.get_selected() != Some(b) {
+			return false;
+		}
+		player.inventory.take_selected();
+		true
+	}
+	/// Sends the player its inventory and crafting grids
+	fn send_inventories(&mut self, id :PlayerIdPair) {
+		let mut players = self.players.borrow_mut();
+		let remove_player = if let Some(player) = players.get(&id) {
+			let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+			let mut err = player.conn.send(msg).is_err();
+			for (grid, inv) in player.craft_invs.iter() {
+				let msg = ServerToClientMsg::SetCraftInventory(*grid, inv.clone());
+				err |= player.conn.send(msg).is_err();
+			}
+			err
+		} else {
+			return;
+		};
+		if remove_player {
+			close_connections(&[id], &mut *players);
+		}
+	}
+	/// Applies an inventory action of the player to the inventories on the server
+	///
+	/// If the action is invalid, nothing changes, and the player
+	/// gets sent the inventories to override its local prediction.
+	fn handle_inventory_action(&mut self, id :PlayerIdPair, action :InventoryAction) {
+		let res = {
+			let players = self.players.borrow();
+			let player = if let Some(player) = players.get(&id) {
+				player
+			} else {
+				return;
+			};
+			let map = &self.map;
+			let params = &self.params.p;
+			let picking = matches!(action, InventoryAction::PickBlock(..));
+			if picking && !player.game_mode.can_pick_blocks() {
+				Err(StrErr::from("Picking blocks needs the creative mode"))
+			} else {
+				apply_action(&action, params, |loc| match loc {
+					InvLocation::Player => Some(player.inventory.clone()),
+					InvLocation::Craft(grid) => {
+						if grid != CraftingGrid::PERSONAL && grid != CraftingGrid::TABLE {
+							return None;
+						}
+						if grid == CraftingGrid::TABLE &&
+								!crafting_grid_near(map, params, grid, player.pos()) {
+							return None;
+						}
+						Some(player.craft_invs.get(&grid).cloned()
+							.unwrap_or_else(|| SelectableInventory::crafting_inv(grid)))
+					},
+					InvLocation::Chest(pos) => chest_inventory(map, params, pos, player.pos()),
+				})
+			}
+		};
+		let changed = match res {
+			Ok(changed) => changed,
+			Err(e) => {
+				warn!(target : TARGET_SERVER, "Rejected inventory action {:?}: {}", action, e.0);
+				for loc in action.locations() {
+					if let InvLocation::Chest(pos) = loc {
+						if let Some(mut hdl) = self.map.get_blk_mut(pos) {
+							hdl.fake_change();
+						}
+					}
+				}
+				self.send_inventories(id);
+				return;
+			},
+		};
+		let mut chests = Vec::new();
+		{
+			let mut players = self.players.borrow_mut();
+			let player = players.get_mut(&id).unwrap();
+			for (loc, inv) in changed {
+				match loc {
+					InvLocation::Player => player.inventory = inv,
+					InvLocation::Craft(grid) => {
+						player.craft_invs.insert(grid, inv);
+					},
+					InvLocation::Chest(pos) => chests.push((pos, inv)),
+				}
+			}
+		}
+		for (pos, inv) in chests {
+			let unchanged = match self.map.get_blk_meta(pos) {
+				Some(Some(MetadataEntry::Inventory(old))) => *old == inv,
+				_ => false,
+			};
+			if unchanged {
+				continue;
+			}
+			if let Some(mut hdl) = self.map.get_blk_meta_mut(pos) {
+				hdl.set(MetadataEntry::Inventory(inv));
+			}
+		}
+	}
+	/// Runs the server until it is shut down
+	///
+	/// Other threads can shut it down through `shutdown_handle`.
+	pub fn run_loop(&mut self) {
+		while !self.shut_down {
+			if self.shutdown_handle.is_requested() {
+				self.shutdown();
+				break;
+			}
+			self.step().unwrap();
+			let to_sleep = self.tick_pacer.end_tick(Instant::now());
+			thread::sleep(to_sleep);
+		}
+	}
+	/// Runs the simulation steps of one tick
+	///
+	/// Runs `tick_speed` steps, returning the number
+	/// of blocks updated by random ticks.
+	fn simulate(&mut self, dt :f32) -> usize {
+		let mut random_ticks = 0;
+		// Teleport timers run in real time, regardless of the tick speed
+		self.update_teleports(dt);
+		for _ in 0 .. self.tick_speed {
+			self.update_hunger(dt);
+			self.update_entities(dt);
+			random_ticks += random_tick::random_tick(&mut self.map, &self.params.p,
+				self.config.random_tick_speed, &mut rand::thread_rng());
+			self.update_clock(dt);
+		}
+		random_ticks
+	}
+	/// Runs exactly one tick of the server
+	///
+	/// Unlike `run_loop`, this doesn't do any pacing, so embedders
+	/// can drive the server at their own speed and inspect its
+	/// state between the ticks.
+	pub fn step(&mut self) -> Result<(), StrErr> {
+		if self.shut_down {
+			Err("The server has been shut down")?;
+		}
+		let positions = self.players.borrow().values()
+			.map(|player| {
+				(btchn(player.pos.pos().map(|v| v as isize)), player.last_chunk_pos)
+			})
+			.filter(|(cp, lcp)| cp != lcp)
+			.map(|(cp, _lcp)| cp)
+			.collect::<Vec<_>>();
+		for pos in positions {
+			gen_chunks_around(&mut self.map,
+				pos,
+				self.config.mapgen_radius_xy,
+				self.config.mapgen_radius_z);
+		}
+		self.send_chunks_to_players();
+		self.send_positions_to_players();
+		self.map.tick();
+		// Simulation steps should use this as their dt
+		let dt = self.tick_pacer.tick_duration().as_secs_f32();
+		self.simulate(dt);
+		self.playtime.tick(self.players.borrow().values().map(|p| p.nick.as_str()));
+		while let Some(conn) = self.srv_socket.try_open_conn() {
+			if self.is_singleplayer {
+				let id = PlayerIdPair::singleplayer();
+				self.add_player_waiting(conn, id, "singleplayer".to_owned());
+			} else {
+				self.unauthenticated_players.push((conn, AuthState::AwaitingHello));
+			}
+		}
+		self.handle_auth_msgs();
+		self.handle_players_waiting_for_kv();
+		self.store_player_kvs(false)?;
+
+		let msgs = self.get_msgs();
+
+		for (id, msg) in msgs {
+			use crate::ClientToServerMsg::*;
+			match msg {
+				Hello(_) |
+				LogIn(..) |
+				SendHash(_) |
+				SendM1(..) => {
+					// Invalid at this state. Ignore.
+					// TODO maybe issue a warning in the log? idk
+				},
+				GetHashedBlobs(blob_list) => {
+					let hashed_blobs = blob_list.iter()
+						.filter_map(|h| self.params.textures.get(h)
+							.map(|b| (h.clone(), b.clone())))
+						.collect::<Vec<_>>();
+					let msg = ServerToClientMsg::HashedBlobs(hashed_blobs);
+					let remove_player = {
+						let player = &self.players.borrow_mut()[&id];
+						player.conn.send(msg.clone()).is_err()
+					};
+					if remove_player {
+						close_connections(&[id], &mut *self.players.borrow_mut());
+					}
+				},
+				PlaceTree(p) if self.players.borrow()[&id].game_mode != GameMode::Creative => {
+					// Trees are created out of thin air,
+					// so only creative mode players can place them
+					if let Some(mut hdl) = self.map.get_blk_mut(p) {
+						hdl.fake_change();
+					}
+				},
+				SetBlock(p, _) |
+				SetMetadata(p, _) |
+				PlaceTree(p) |
+				Dig(p) if !self.can_edit(id) || self.is_protected(id, p) ||
+						self.is_out_of_reach(id, p) => {
+					// Send the unchanged block to the client
+					// to override its local prediction
+					if let Some(mut hdl) = self.map.get_blk_mut(p) {
+						hdl.fake_change();
+					}
+				},
+				SetBlock(p, b) => {
+					if !self.can_place_at(p) {
+						// The position is taken, override the local
+						// prediction of the block and the inventory
+						if let Some(mut hdl) = self.map.get_blk_mut(p) {
+							hdl.fake_change();
+						}
+						self.send_inventories(id);
+					} else if self.take_placed_block(id, b) {
+						apply_block_edit(&mut self.map, p, b);
+						falling::remove_unsupported(&mut self.map, p, &self.params.p);
+						falling::settle_falling(&mut self.map, p, &self.params.p);
+					} else {
+						// The player doesn't have the block selected,
+						// override its local prediction
+						if let Some(mut hdl) = self.map.get_blk_mut(p) {
+							hdl.fake_change();
+						}
+						self.send_inventories(id);
+					}
+				},
+				SetMetadata(p, MetadataEntry::Inventory(_)) => {
+					// Chest inventories only change through inventory actions
+					if let Some(mut hdl) = self.map.get_blk_mut(p) {
+						hdl.fake_change();
+					}
+				},
+				SetMetadata(p, MetadataEntry::Facing(facing)) => {
+					let directional = self.map.get_blk(p)
+						.and_then(|b| self.params.p.get_block_params(b))
+						.map(|bp| bp.directional)
+						.unwrap_or(false);
+					if directional {
+						let mut hdl = self.map.get_blk_meta_mut(p).unwrap();
+						hdl.set(MetadataEntry::Facing(facing));
+					} else if let Some(mut hdl) = self.map.get_blk_mut(p) {
+						// Only directional blocks have a facing
+						hdl.fake_change();
+					} else {
+						// TODO log something about an attempted action in an unloaded chunk
+					}
+				},
+				PlaceTree(p) => {
+					map::spawn_tree(&mut self.map, p, &self.params);
+				},
+				Dig(p) => {
+					let remove = if self.map.get_blk(p).is_some() {
+						// Chests holding items can't be dug
+						!holds_items(&self.map, p)
+					} else {
+						// TODO log something about an attempted action in an unloaded chunk
+						false
+					};
+					let mut drops = None;
+					if remove {
+						{
+							// We can unwrap here as above we set remove to false if
+							// the result is None
+							let mut hdl = self.map.get_blk_mut(p).unwrap();
+							let roll = rand::thread_rng().gen::<f64>();
+							drops = drops_for_roll(hdl.get(), &self.params.p, roll);
+							let air_bl = self.params.p.block_roles.air;
+							hdl.set(air_bl);
+						}
+						falling::remove_unsupported(&mut self.map, p, &self.params.p);
+						falling::settle_falling(&mut self.map, p, &self.params.p);
+					} else {
+						// Send the unchanged block to the client
+						if let Some(mut hdl) = self.map.get_blk_mut(p) {
+							hdl.fake_change();
+						}
+					}
+					let remove_player = {
+						let mut players = self.players.borrow_mut();
+						let player = &mut players.get_mut(&id).unwrap();
 						if remove {
-							{
-								// We can unwrap here as above we set remove to false if
-								// the result is None
-								let mut hdl = self.map.get_blk_mut(p).unwrap();
-								drops = Some(self.params.p.get_block_params(hdl.get()).unwrap().drops);
-								let air_bl = self.params.p.block_roles.air;
-								hdl.set(air_bl);
+							// If we remove the block, put the dropped item
+							// into the inventory or into the world.
+							// Send the new inventory to the client in any
+							// case to override any possibly mistaken
+							// local prediction.
+							if let Some(drops) = drops {
+								if self.config.drop_item_entities {
+									self.entities.spawn_dropped_item(p, drops);
+								} else {
+									player.inventory.put(drops);
+								}
 							}
-							let mut hdl = self.map.get_blk_meta_mut(p).unwrap();
-							hdl.clear();
-						} else {
-							// Send the unchanged block to the client
-							if let Some(mut hdl) = self.map.get_blk_mut(p) {
-								hdl.fake_change();
+							if !player.game_mode.digs_instantly() {
+								player.inventory.wear_selected();
 							}
 						}
-						let remove_player = {
-							let mut players = self.players.borrow_mut();
-							let player = &mut players.get_mut(&id).unwrap();
-							if remove {
-								// If we remove the block, put the dropped item
-								// into the inventory. Send the new inventory to
-								// the client in any case to override any
-								// possibly mistaken local prediction.
-								player.inventory.put(drops.unwrap());
-							}
+						let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
+						player.conn.send(msg).is_err()
+					};
+					if remove_player {
+						close_connections(&[id], &mut *self.players.borrow_mut());
+					}
+				},
+				Eat => {
+					let remove_player = {
+						let mut players = self.players.borrow_mut();
+						let player = &mut players.get_mut(&id).unwrap();
+						if player.hunger.eat_selected(&mut player.inventory, &self.params.p) {
 							let msg = ServerToClientMsg::SetInventory(player.inventory.clone());
-							player.conn.send(msg).is_err()
-						};
-						if remove_player {
-							close_connections(&[id], &mut *self.players.borrow_mut());
-						}
-					},
-					SetPos(_p) => unreachable!(),
-					SetInventory(_inv) => unreachable!(),
-					Chat(m) => {
-						if m.starts_with('/') {
-							self.handle_command(id, m);
+							let inv_err = player.conn.send(msg).is_err();
+							let msg = ServerToClientMsg::SetHunger(player.hunger);
+							player.hunger_last_sent = player.hunger;
+							inv_err || player.conn.send(msg).is_err()
 						} else {
-							let m = {
-								let nick = &self.players.borrow()[&id].nick;
-								format!("<{}> {}", nick, m)
-							};
-							self.handle_chat_msg(m);
+							false
 						}
-					},
-				}
+					};
+					if remove_player {
+						close_connections(&[id], &mut *self.players.borrow_mut());
+					}
+				},
+				SetPos(_p) => unreachable!(),
+				InventoryAction(action) => {
+					self.handle_inventory_action(id, action);
+				},
+				AckChunk(..) => unreachable!(),
+				Chat(m) => {
+					if m.starts_with('/') {
+						if self.config.chat_log_commands {
+							let nick = &self.players.borrow()[&id].nick;
+							self.chat_log.log(&format!("<{}> {}", nick, m));
+						}
+						self.handle_command(id, m);
+					} else {
+						let m = {
+							let nick = &self.players.borrow()[&id].nick;
+							format!("<{}> {}", nick, m)
+						};
+						self.handle_chat_msg(m);
+					}
+				},
 			}
+		}
+		if let Some(replay) = &mut self.edit_replay {
+			replay.step(&mut self.map);
+			if replay.is_finished() {
+				info!(target : TARGET_SERVER, "Replay of the edit recording finished");
+				self.edit_replay = None;
+			}
+		}
+		self.edit_recorder.record(&self.pending_edits.borrow());
+		self.send_block_batches();
+		Ok(())
+	}
+	/// Replays the block edits recorded at the path, `speed` times as fast
+	pub fn replay_edits(&mut self, path :impl AsRef<std::path::Path>,
+			speed :f64) -> Result<(), StrErr> {
+		let replay = EditReplay::open(path, &self.params.p.name_id_map, speed)?;
+		self.edit_replay = Some(replay);
+		Ok(())
+	}
+	/// Stores the state of the world and disconnects all players
+	///
+	/// Afterwards, `run_loop` returns and `step` fails.
+	pub fn shutdown(&mut self) {
+		if self.shut_down {
+			return;
+		}
+		self.shut_down = true;
+		if let Err(e) = self.store_player_kvs(true) {
+			error!(target : TARGET_STORAGE, "Error while storing the players: {}", e.0);
+		}
+		self.sync_clock();
+		self.map.tick();
+		self.unauthenticated_players.clear();
+		self.players_waiting_for_kv.clear();
+		self.players.borrow_mut().clear();
+	}
+	/// A handle to shut down the server while it runs its `run_loop`
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		self.shutdown_handle.clone()
+	}
+	pub fn map(&self) -> &ServerMap {
+		&self.map
+	}
+	pub fn spawn_pos(&self) -> Vector3<f32> {
+		self.spawn_pos
+	}
+}
 
-			if exit {
-				break;
+/// The blocks within the radius around the spawn, with exclusive maximum
+///
+/// A radius of 0 disables the spawn protection.
+fn spawn_protection_area(spawn_pos :Vector3<f32>, radius :isize)
+		-> Option<(Vector3<isize>, Vector3<isize>)> {
+	if radius <= 0 {
+		return None;
+	}
+	let center = spawn_pos.map(|v| v.floor() as isize);
+	let r = Vector3::new(radius, radius, radius);
+	Some((center - r, center + r + Vector3::new(1, 1, 1)))
+}
+
+/// The position new players spawn at
+fn spawn_pos(config :&Config, params :&ServerGameParamsHdl) -> Vector3<f32> {
+	let default_pos = PlayerPosition::default().pos();
+	if config.generate_terrain {
+		// Search on a throwaway map so that the search
+		// doesn't write any chunks into the storage
+		let mut spawn_map = Map::new_sync(config.mapgen_seed,
+			params.clone(), Box::new(NullStorageBackend));
+		spawn_map.backend_mut().set_mapgen_params(config.mapgen_params());
+		spawn_map.backend_mut().set_min_padding(config.mapgen_min_padding);
+		find_spawn(&mut spawn_map, default_pos.map(|v| v as isize))
+	} else {
+		default_pos
+	}
+}
+
+/// Generates and stores the chunks up to `radius` chunks around the spawn
+///
+/// Vertically, the chunks up to `mapgen_radius_z` chunks
+/// above and below the spawn are generated.
+pub fn pregen_world(mut config :Config, radius :isize) -> Result<(), StrErr> {
+	if config.map_storage_path.is_none() {
+		Err("Pre-generation needs map_storage_path to be set")?;
+	}
+	let (mut storage_back, _) = map_storage::backends_from_config(&mut config, false);
+	let nm = map_storage::load_name_id_map(&mut storage_back)?;
+	let params = ServerGameParams::load(nm);
+	map_storage::save_name_id_map(&mut storage_back, &params.p.name_id_map)?;
+	let spawn_pos = spawn_pos(&config, &params);
+	pregen_storage(&config, &params, spawn_pos, storage_back, radius)?;
+	Ok(())
+}
+
+/// Generates the chunks around the spawn into the given storage
+///
+/// Returns the storage so that the map can use it afterwards.
+fn pregen_storage(config :&Config, params :&ServerGameParamsHdl, spawn_pos :Vector3<f32>,
+		storage_back :DynStorageBackend, radius :isize) -> Result<DynStorageBackend, StrErr> {
+	let mut mapgen_map = MapgenMap::new(config.mapgen_seed,
+		config.generate_terrain, params.clone(), storage_back);
+	mapgen_map.set_mapgen_params(config.mapgen_params());
+	mapgen_map.set_min_padding(config.mapgen_min_padding);
+	let mut last_percent = None;
+	mapgen_map.pregen(spawn_pos.map(|v| v as isize), radius,
+			config.mapgen_radius_z, |done, total| {
+		let percent = done * 100 / total;
+		if last_percent != Some(percent) {
+			info!(target : TARGET_MAPGEN, "Pre-generating: {}% ({}/{} chunk columns)", percent, done, total);
+			last_percent = Some(percent);
+		}
+	})?;
+	Ok(mapgen_map.into_storage())
+}
+
+/// How far away from a chest players can access its inventory
+const MAX_CHEST_DISTANCE :f32 = 8.0;
+
+/// The inventory of the chest at the position, if the player can access it
+fn chest_inventory(map :&ServerMap, params :&GameParams, pos :Vector3<isize>,
+		player_pos :Vector3<f32>) -> Option<SelectableInventory> {
+	let center = pos.map(|v| v as f32 + 0.5);
+	if (center - player_pos).norm() > MAX_CHEST_DISTANCE {
+		return None;
+	}
+	let stack_num = params.get_block_params(map.get_blk(pos)?)?.inventory?;
+	match map.get_blk_meta(pos)? {
+		Some(MetadataEntry::Inventory(inv)) => Some(inv.clone()),
+		_ => Some(SelectableInventory::empty_with_size(stack_num as usize)),
+	}
+}
+
+/// Whether a block providing the crafting grid is close enough to the player to use it
+///
+/// Crafting tables are in reach at the same distance as chests.
+fn crafting_grid_near(map :&ServerMap, params :&GameParams, grid :CraftingGrid,
+		player_pos :Vector3<f32>) -> bool {
+	let center = player_pos.map(|v| v.floor() as isize);
+	let r = MAX_CHEST_DISTANCE as isize;
+	for x in -r ..= r {
+		for y in -r ..= r {
+			for z in -r ..= r {
+				let pos = center + Vector3::new(x, y, z);
+				if (pos.map(|v| v as f32 + 0.5) - player_pos).norm() > MAX_CHEST_DISTANCE {
+					continue;
+				}
+				let provides_grid = map.get_blk(pos)
+					.and_then(|b| params.get_block_params(b))
+					.and_then(|bp| bp.crafting_grid) == Some(grid);
+				if provides_grid {
+					return true;
+				}
 			}
 		}
 	}
+	false
 }
 
 fn close_connections(conns_to_close :&[PlayerIdPair], connections :&mut HashMap<PlayerIdPair, impl Sized>) {
 	for id in conns_to_close.iter() {
-		println!("closing connection");
+		debug!(target : TARGET_NET, "closing connection");
 		connections.remove(&id);
 	}
 }
@@ -924,3 +2298,1315 @@ pub fn btchn(v :Vector3<isize>) -> Vector3<isize> {
 fn btpic(v :Vector3<isize>) -> Vector3<isize> {
 	v.map(|v| (v as f32).rem_euclid(CHUNKSIZE as f32) as isize)
 }
+
+#[cfg(test)]
+#[test]
+fn test_concurrent_block_edits() {
+	use crate::generic_net::{MpscServerConn, NetworkClientConn};
+	use crate::map::ClientBackend;
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let players = Rc::new(RefCell::new(HashMap::new()));
+	let mut clients = Vec::new();
+	for i in 1 ..= 2 {
+		let (conn, client) = MpscServerConn::new();
+		let id = PlayerIdPair::from_components(0, i);
+		let player = Player::from_stuff(conn, id, format!("player{}", i),
+			SelectableInventory::new(), Hunger::default());
+		players.borrow_mut().insert(id, player);
+		clients.push(client);
+	}
+	for player in players.borrow_mut().values_mut() {
+		player.sent_chunks.insert(Vector3::new(0, 0, 0));
+	}
+	let mut map = Map::from_backend(ClientBackend);
+	let playersc = players.clone();
+	map.register_on_change(Box::new(move |chunk_pos, chunk| {
+		broadcast_chunk_update(&mut playersc.borrow_mut(), chunk_pos, chunk);
+	}));
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.air));
+	for client in clients.iter_mut() {
+		while let Ok(Some(_)) = client.try_recv() {}
+	}
+
+	// Both players edit the same block in the same tick,
+	// the second player sending its edit first, and
+	// another one after the edits of the first player.
+	let pos = Vector3::new(1, 2, 3);
+	clients[1].send(ClientToServerMsg::SetBlock(pos, roles.wood)).unwrap();
+	clients[0].send(ClientToServerMsg::SetBlock(pos, roles.stone)).unwrap();
+	clients[0].send(ClientToServerMsg::SetBlock(pos, roles.sand)).unwrap();
+	clients[1].send(ClientToServerMsg::SetBlock(pos, roles.ground)).unwrap();
+
+	let msgs = recv_player_msgs(&mut players.borrow_mut());
+	let edit_order = msgs.iter()
+		.map(|(id, msg)| match msg {
+			ClientToServerMsg::SetBlock(p, b) => {
+				apply_block_edit(&mut map, *p, *b);
+				(id.id_u64(), *b)
+			},
+			_ => panic!("unexpected message"),
+		})
+		.collect::<Vec<_>>();
+	// Edits are applied in the order the server received them
+	assert_eq!(edit_order, vec![(2, roles.wood), (1, roles.stone), (1, roles.sand), (2, roles.ground)]);
+	assert_eq!(map.get_blk(pos), Some(roles.ground));
+
+	// Every client, including the originators, received every edit
+	for client in clients.iter_mut() {
+		let mut updates = Vec::new();
+		while let Ok(Some(msg)) = client.try_recv() {
+			if let ServerToClientMsg::ChunkUpdated(p, chunk, _) = msg {
+				assert_eq!(p, Vector3::new(0, 0, 0));
+				updates.push(*chunk.get_blk(pos));
+			}
+		}
+		assert_eq!(updates, vec![roles.wood, roles.stone, roles.sand, roles.ground]);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_loaded_chunks() {
+	use crate::map::ClientBackend;
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let mut map = Map::from_backend(ClientBackend);
+	assert_eq!(map.loaded_chunks().count(), 0);
+	let positions = [
+		Vector3::new(0, 0, 0),
+		Vector3::new(-1, 2, 0) * CHUNKSIZE,
+		Vector3::new(5, 0, -3) * CHUNKSIZE,
+	];
+	for p in positions.iter() {
+		map.set_chunk(*p, MapChunkData::filled_with(air));
+	}
+	// Setting a chunk again doesn't add it twice
+	map.set_chunk(positions[0], MapChunkData::filled_with(air));
+
+	let mut loaded = map.loaded_chunks().collect::<Vec<_>>();
+	loaded.sort_by_key(|p| (p.x, p.y, p.z));
+	let mut expected = positions.to_vec();
+	expected.sort_by_key(|p| (p.x, p.y, p.z));
+	assert_eq!(loaded, expected);
+	assert_eq!(map.loaded_chunks_count(), 3);
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_updates_in_range() {
+	use crate::generic_net::{MpscServerConn, MpscClientConn, NetworkClientConn};
+	use crate::map::ClientBackend;
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let chunk = |x| Vector3::new(x * CHUNKSIZE, 0, 0);
+	let players = Rc::new(RefCell::new(HashMap::new()));
+	let mut clients = Vec::new();
+	// The first player has chunks 0 and 1 loaded,
+	// the second one chunks 1 and 2.
+	for i in 1 ..= 2 {
+		let (conn, client) = MpscServerConn::new();
+		let id = PlayerIdPair::from_components(0, i);
+		let mut player = Player::from_stuff(conn, id, format!("player{}", i),
+			SelectableInventory::new(), Hunger::default());
+		player.sent_chunks.insert(chunk(i as isize - 1));
+		player.sent_chunks.insert(chunk(i as isize));
+		players.borrow_mut().insert(id, player);
+		clients.push(client);
+	}
+	let mut map = Map::from_backend(ClientBackend);
+	let playersc = players.clone();
+	map.register_on_change(Box::new(move |chunk_pos, chunk| {
+		broadcast_chunk_update(&mut playersc.borrow_mut(), chunk_pos, chunk);
+	}));
+	let received = |clients :&mut Vec<MpscClientConn>| {
+		clients.iter_mut()
+			.map(|client| {
+				let mut chunks = Vec::new();
+				while let Ok(Some(msg)) = client.try_recv() {
+					if let ServerToClientMsg::ChunkUpdated(p, _, _) = msg {
+						chunks.push(p);
+					}
+				}
+				chunks
+			})
+			.collect::<Vec<_>>()
+	};
+	for x in 0 ..= 3 {
+		map.set_chunk(chunk(x), MapChunkData::filled_with(roles.air));
+	}
+	assert_eq!(received(&mut clients), vec![
+		vec![chunk(0), chunk(1)],
+		vec![chunk(1), chunk(2)],
+	]);
+
+	// Edits only reach the players that have the chunk loaded
+	for x in 0 ..= 3 {
+		apply_block_edit(&mut map, chunk(x) + Vector3::new(1, 2, 3), roles.stone);
+	}
+	assert_eq!(received(&mut clients), vec![
+		vec![chunk(0), chunk(1)],
+		vec![chunk(1), chunk(2)],
+	]);
+
+	// Chunks in the area around the player are sent,
+	// and from then on count as loaded
+	let id = PlayerIdPair::from_components(0, 2);
+	players.borrow_mut().get_mut(&id).unwrap().sent_area = Some((chunk(3), chunk(3) + Vector3::new(1, 1, 1) * CHUNKSIZE));
+	apply_block_edit(&mut map, chunk(3), roles.wood);
+	assert_eq!(received(&mut clients), vec![vec![], vec![chunk(3)]]);
+	assert!(players.borrow()[&id].sent_chunks.contains(&chunk(3)));
+}
+
+/// A multiplayer server without terrain, with alice as first player
+#[cfg(test)]
+fn test_server_with_player(mut config :Config)
+		-> (Server<crate::generic_net::MpscServerSocket>, crate::generic_net::MpscClientConn, PlayerIdPair) {
+	config.generate_terrain = false;
+	let (socket, _client) = crate::generic_net::MpscServerSocket::new();
+	let mut server = Server::new(socket, false, config);
+	let (alice, client) = join_test_player(&mut server, 1, "alice");
+	(server, client, alice)
+}
+
+/// Lets a player with the given id and nick join the server
+#[cfg(test)]
+fn join_test_player(server :&mut Server<crate::generic_net::MpscServerSocket>, id :u64, nick :&str)
+		-> (PlayerIdPair, crate::generic_net::MpscClientConn) {
+	let (conn, client) = crate::generic_net::MpscServerConn::new();
+	let id = PlayerIdPair::from_components(0, id);
+	server.add_player(conn, id, nick.to_owned(), PlayerPosition::default(),
+		SelectableInventory::new(), Hunger::default());
+	(id, client)
+}
+
+/// Receives all messages the client got so far
+#[cfg(test)]
+fn drain_msgs(client :&mut crate::generic_net::MpscClientConn) -> Vec<ServerToClientMsg> {
+	use crate::generic_net::NetworkClientConn;
+
+	let mut msgs = Vec::new();
+	while let Ok(Some(msg)) = client.try_recv() {
+		msgs.push(msg);
+	}
+	msgs
+}
+
+/// The last chat message among the messages
+#[cfg(test)]
+fn last_chat(msgs :&[ServerToClientMsg]) -> Option<String> {
+	msgs.iter()
+		.filter_map(|msg| match msg {
+			ServerToClientMsg::Chat(m) => Some(m.clone()),
+			_ => None,
+		})
+		.last()
+}
+
+/// The last position the player got teleported to among the messages
+#[cfg(test)]
+fn last_pos(msgs :&[ServerToClientMsg]) -> Option<Vector3<f32>> {
+	msgs.iter()
+		.filter_map(|msg| match msg {
+			ServerToClientMsg::SetPos(p) => Some(p.pos()),
+			_ => None,
+		})
+		.last()
+}
+
+/// Receives all messages the client got so far, returning the last chat message
+#[cfg(test)]
+fn drain_chat(client :&mut crate::generic_net::MpscClientConn) -> Option<String> {
+	last_chat(&drain_msgs(client))
+}
+
+#[cfg(test)]
+fn step_until<S :NetworkServerSocket>(server :&mut Server<S>, f :impl Fn(&Server<S>) -> bool) {
+	use std::time::Duration;
+
+	let mut steps = 0;
+	while !f(server) {
+		assert!(steps < 1000, "condition not reached");
+		server.step().unwrap();
+		thread::sleep(Duration::from_millis(5));
+		steps += 1;
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_server_step() {
+	use crate::generic_net::MpscServerSocket;
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, _client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let spawn_chunk = btchn(server.spawn_pos().map(|v| v as isize));
+	assert!(server.map().get_chunk(spawn_chunk).is_none());
+
+	// The player joins and the chunks around it get generated
+	// on the mapgen thread, so step until they arrive
+	step_until(&mut server, |srv| srv.map().get_chunk(spawn_chunk).is_some());
+	assert_eq!(server.players.borrow().len(), 1);
+
+	server.shutdown();
+	assert!(server.players.borrow().is_empty());
+	assert!(server.step().is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_shutdown_handle() {
+	use std::sync::mpsc::channel;
+	use crate::generic_net::MpscServerSocket;
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, _client) = MpscServerSocket::new();
+	let (handle_s, handle_r) = channel();
+	let server_thread = thread::spawn(move || {
+		let mut server = Server::new(socket, true, config);
+		handle_s.send(server.shutdown_handle()).unwrap();
+		server.run_loop();
+	});
+	let handle = handle_r.recv().unwrap();
+	handle.request();
+	// run_loop returns once the handle requested the shutdown
+	server_thread.join().unwrap();
+}
+
+/// Runs the singleplayer startup like the client does in its main.rs
+#[cfg(test)]
+#[test]
+fn test_singleplayer_startup() {
+	use std::time::Duration;
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	// Without map_storage_path, the null storage is used
+	let config = Config::default();
+	assert!(config.map_storage_path.is_none() && config.generate_terrain);
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	client.send(ClientToServerMsg::Hello(PROTOCOL_VERSION)).unwrap();
+
+	// The chunk with the block the player stands on
+	let spawn_pos = server.spawn_pos();
+	let surface = Vector3::new(spawn_pos.x.floor() as isize, spawn_pos.y.floor() as isize,
+		spawn_pos.z.floor() as isize - 2);
+	let surface_chunk = btchn(surface);
+
+	let mut params = None;
+	let mut pos = None;
+	let mut chunk = None;
+	let mut steps = 0;
+	while chunk.is_none() {
+		assert!(steps < 1000, "the client didn't receive the spawn chunk");
+		server.step().unwrap();
+		while let Ok(Some(msg)) = client.try_recv() {
+			match msg {
+				ServerToClientMsg::GameParams(p) => params = Some(p),
+				ServerToClientMsg::SetPos(p) => pos = Some(p.pos()),
+				ServerToClientMsg::ChunkUpdated(p, c, _) if p == surface_chunk => chunk = Some(c),
+				_ => (),
+			}
+		}
+		thread::sleep(Duration::from_millis(5));
+		steps += 1;
+	}
+	let params = params.expect("no game params received before the chunk");
+	assert_eq!(pos, Some(spawn_pos));
+
+	// The received chunk holds generated terrain the player can stand on
+	let chunk = chunk.unwrap();
+	let blk_params = |pos :Vector3<isize>| {
+		let blk = *chunk.get_blk(pos - surface_chunk);
+		params.get_block_params(blk).unwrap()
+	};
+	assert!(blk_params(surface).solid);
+	if btchn(surface + Vector3::new(0, 0, 1)) == surface_chunk {
+		assert!(!blk_params(surface + Vector3::new(0, 0, 1)).solid);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_initial_gen_radius() {
+	use crate::generic_net::MpscServerSocket;
+	use crate::map_storage::{SqliteStorageBackend, StorageBackend, TestDir};
+
+	let dir = TestDir::new("initial-gen");
+	let path = dir.join("map.sqlite");
+	let mut config = Config::default();
+	config.map_storage_path = Some(path.to_str().unwrap().to_owned());
+	config.initial_gen_radius = 1;
+	let (socket, _client) = MpscServerSocket::new();
+	let server = Server::new(socket, true, config);
+	let spawn_chunk = btchn(server.spawn_pos().map(|v| v as isize));
+
+	// Before anyone joined, exactly the chunk columns
+	// within the radius are in the storage
+	let m = &server.params.p.name_id_map;
+	let mut storage = SqliteStorageBackend::open_or_create(&path).unwrap();
+	for x in -2 ..= 2 {
+		for y in -2 ..= 2 {
+			let pos = spawn_chunk + Vector3::new(x, y, 0) * CHUNKSIZE;
+			let within = x.abs() <= 1 && y.abs() <= 1;
+			let chunk = storage.load_chunk(pos, m).unwrap();
+			assert_eq!(chunk.is_some(), within, "{:?}", pos);
+		}
+	}
+	drop(server);
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_acks() {
+	use crate::generic_net::{MpscServerSocket, MpscClientConn, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	let spawn_pos = server.spawn_pos();
+	let spawn_chunk = btchn(spawn_pos.map(|v| v as isize));
+	step_until(&mut server, |srv| {
+		srv.players.borrow().get(&id)
+			.map(|p| p.sent_chunks.contains(&spawn_chunk))
+			.unwrap_or(false)
+	});
+	// Returns the versions of the spawn chunk the client received
+	let received = |client :&mut MpscClientConn| {
+		let mut versions = Vec::new();
+		while let Ok(Some(msg)) = client.try_recv() {
+			if let ServerToClientMsg::ChunkUpdated(p, _, version) = msg {
+				if p == spawn_chunk {
+					versions.push(version);
+				}
+			}
+		}
+		versions
+	};
+	let move_to = |server :&mut Server<_>, client :&mut MpscClientConn, pos| {
+		client.send(ClientToServerMsg::SetPos(PlayerPosition::from_pos(pos))).unwrap();
+		server.step().unwrap();
+		server.step().unwrap();
+	};
+	let version = server.map.chunk_version(spawn_chunk).unwrap();
+	let versions = received(&mut client);
+	assert_eq!(versions, vec![version]);
+	client.send(ClientToServerMsg::AckChunk(spawn_chunk, version)).unwrap();
+
+	// Leaving and coming back doesn't resend the acknowledged chunk
+	let far_away = spawn_pos + Vector3::new(1000.0, 0.0, 0.0);
+	move_to(&mut server, &mut client, far_away);
+	assert!(!server.players.borrow()[&id].sent_chunks.contains(&spawn_chunk));
+	move_to(&mut server, &mut client, spawn_pos);
+	assert!(server.players.borrow()[&id].sent_chunks.contains(&spawn_chunk));
+	assert_eq!(received(&mut client), Vec::<u64>::new());
+
+	// An edit while away invalidates the acknowledgement
+	move_to(&mut server, &mut client, far_away);
+	let stone = server.params.p.block_roles.stone;
+	server.map.get_blk_mut(spawn_chunk).unwrap().set(stone);
+	assert_eq!(received(&mut client), Vec::<u64>::new());
+	move_to(&mut server, &mut client, spawn_pos);
+	assert_eq!(received(&mut client), vec![version + 1]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_block_batches() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	// Encoding roundtrip
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let mut batch = BlockBatch::new();
+	batch.push(Vector3::new(1, -2, 3), roles.stone);
+	batch.push(Vector3::new(-40, 5, 17), roles.wood);
+	let encoded = bincode::serialize(&batch).unwrap();
+	let decoded :BlockBatch = bincode::deserialize(&encoded).unwrap();
+	assert_eq!(decoded, batch);
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	let spawn_chunk = btchn(server.spawn_pos().map(|v| v as isize));
+	step_until(&mut server, |srv| {
+		srv.players.borrow().get(&id)
+			.map(|p| p.sent_chunks.contains(&spawn_chunk))
+			.unwrap_or(false)
+	});
+	while let Ok(Some(_)) = client.try_recv() {}
+	// Placing blocks takes them from the inventory
+	{
+		let mut players = server.players.borrow_mut();
+		let player = players.get_mut(&id).unwrap();
+		player.inventory.put(Stack::with(roles.stone, 4));
+		// Stay within reach of the edits
+		player.pos = PlayerPosition::from_pos(spawn_chunk.map(|v| v as f32));
+	}
+
+	// Several edits in one tick get coalesced into one batch
+	let edits = (0 .. 4)
+		.map(|i| (spawn_chunk + Vector3::new(i, 1, 2), roles.stone))
+		.collect::<Vec<_>>();
+	for (pos, b) in edits.iter() {
+		client.send(ClientToServerMsg::SetBlock(*pos, *b)).unwrap();
+	}
+	server.step().unwrap();
+	let mut batches = Vec::new();
+	while let Ok(Some(msg)) = client.try_recv() {
+		match msg {
+			ServerToClientMsg::BlockBatch(b) => batches.push(b),
+			// Chunks further away may still arrive from the mapgen meanwhile
+			ServerToClientMsg::ChunkUpdated(p, ..) if p == spawn_chunk => {
+				panic!("unexpected chunk update")
+			},
+			_ => (),
+		}
+	}
+	assert_eq!(batches, vec![BlockBatch { edits }]);
+
+	// The receiver applies the batch
+	let mut map = Map::from_backend(crate::map::ClientBackend);
+	map.set_chunk(spawn_chunk, server.map().get_chunk(spawn_chunk).unwrap().clone());
+	for (pos, _) in batches[0].edits.iter() {
+		map.get_blk_mut(*pos).unwrap().set(roles.air);
+	}
+	map.apply_block_batch(&batches[0]);
+	for (pos, b) in batches[0].edits.iter() {
+		assert_eq!(map.get_blk(*pos), Some(*b));
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_kill_respawns() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	step_until(&mut server, |srv| srv.players.borrow().contains_key(&id));
+
+	// The player got stuck somewhere far away and hungry
+	let stuck_pos = server.spawn_pos() + Vector3::new(100.0, 0.0, -30.0);
+	client.send(ClientToServerMsg::SetPos(PlayerPosition::from_pos(stuck_pos))).unwrap();
+	server.step().unwrap();
+	server.players.borrow_mut().get_mut(&id).unwrap().hunger.tick(600.0, 600.0, 0, Difficulty::Normal);
+	assert_ne!(server.players.borrow()[&id].hunger, Hunger::default());
+	while let Ok(Some(_)) = client.try_recv() {}
+
+	client.send(ClientToServerMsg::Chat("/kill".to_owned())).unwrap();
+	server.step().unwrap();
+	let (mut pos, mut hunger) = (None, None);
+	while let Ok(Some(msg)) = client.try_recv() {
+		match msg {
+			ServerToClientMsg::SetPos(p) => pos = Some(p.pos()),
+			ServerToClientMsg::SetHunger(h) => hunger = Some(h),
+			_ => (),
+		}
+	}
+	assert_eq!(pos, Some(server.spawn_pos()));
+	assert_eq!(hunger, Some(Hunger::default()));
+	let player = &server.players.borrow()[&id];
+	assert_eq!(player.pos(), server.spawn_pos());
+	assert_eq!(player.hunger, Hunger::default());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sprint_lockout() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+	use std::time::Duration;
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	step_until(&mut server, |srv| srv.players.borrow().contains_key(&id));
+
+	// Sprints 40 blocks per second along x, returns the corrected position
+	let mut sprint = |server :&mut Server<MpscServerSocket>, start :Vector3<f32>| {
+		server.teleport(id, PlayerPosition::from_pos(start));
+		while let Ok(Some(_)) = client.try_recv() {}
+		for i in 0 .. 3 {
+			let pos = start + Vector3::new(i as f32 * 8.0, 0.0, 0.0);
+			client.send(ClientToServerMsg::SetPos(PlayerPosition::from_pos(pos))).unwrap();
+			server.step().unwrap();
+			thread::sleep(Duration::from_millis(200));
+		}
+		let mut corrected = None;
+		while let Ok(Some(msg)) = client.try_recv() {
+			if let ServerToClientMsg::SetPos(p) = msg {
+				corrected = Some(p.pos());
+			}
+		}
+		corrected
+	};
+
+	// Sprinting is fine while the player isn't starving
+	let start = server.spawn_pos();
+	assert_eq!(sprint(&mut server, start), None);
+	assert_eq!(server.players.borrow()[&id].pos(), start + Vector3::new(16.0, 0.0, 0.0));
+
+	// Starving players get held back
+	server.players.borrow_mut().get_mut(&id).unwrap().hunger.tick(100_000.0, 0.0, 0, Difficulty::Normal);
+	let start = start + Vector3::new(16.0, 0.0, 0.0);
+	let corrected = sprint(&mut server, start).unwrap();
+	assert!(corrected.x - start.x < 16.0, "{:?}", corrected);
+	assert_eq!(server.players.borrow()[&id].pos(), corrected);
+}
+
+#[cfg(test)]
+#[test]
+fn test_keep_inventory() {
+	for &(keep_inventory, drop_item_entities) in &[(true, false), (false, false), (false, true)] {
+		let mut config = Config::default();
+		config.keep_inventory = keep_inventory;
+		config.drop_item_entities = drop_item_entities;
+		config.admins = vec!["alice".to_owned()];
+		let (mut server, _client, alice) = test_server_with_player(config);
+		let stack = server.params.p.stack_with(server.params.p.block_roles.stone, 7);
+		let wood = server.params.p.name_id_map.get_id("default:wood").unwrap();
+		let craft_stack = server.params.p.stack_with(wood, 3);
+		{
+			let mut players = server.players.borrow_mut();
+			let player = players.get_mut(&alice).unwrap();
+			player.inventory.put(stack);
+			let mut craft_inv = SelectableInventory::crafting_inv(CraftingGrid::PERSONAL);
+			craft_inv.put(craft_stack);
+			player.craft_invs.insert(CraftingGrid::PERSONAL, craft_inv);
+		}
+
+		server.handle_command(alice, "/kill".to_owned());
+		{
+			let players = server.players.borrow();
+			let has_stone = players[&alice].inventory.stacks().contains(&stack);
+			assert_eq!(has_stone, keep_inventory);
+			let has_wood = players[&alice].craft_invs[&CraftingGrid::PERSONAL]
+				.stacks().contains(&craft_stack);
+			assert_eq!(has_wood, keep_inventory);
+		}
+		let dropped = server.entities.iter()
+			.map(|e| e.kind)
+			.collect::<Vec<_>>();
+		if drop_item_entities {
+			assert_eq!(dropped.len(), 2);
+			assert!(dropped.contains(&EntityKind::DroppedItem(stack)));
+			assert!(dropped.contains(&EntityKind::DroppedItem(craft_stack)));
+		} else {
+			assert!(dropped.is_empty());
+		}
+
+		// The gamerule toggles the setting at runtime
+		server.handle_command(alice, "/gamerule keepinventory true".to_owned());
+		let mut inventory = SelectableInventory::new();
+		inventory.put(stack);
+		server.players.borrow_mut().get_mut(&alice).unwrap().inventory = inventory;
+		server.handle_command(alice, "/kill".to_owned());
+		assert!(server.players.borrow()[&alice].inventory.stacks().contains(&stack));
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_spawn_protection() {
+	use crate::generic_net::MpscClientConn;
+
+	let mut config = Config::default();
+	config.spawn_protection_radius = 5;
+	config.admins = vec!["alice".to_owned()];
+	let (mut server, mut alice_client, alice) = test_server_with_player(config);
+	let (bob, mut bob_client) = join_test_player(&mut server, 2, "bob");
+	let last_area = |client :&mut MpscClientConn| {
+		drain_msgs(client).into_iter()
+			.filter_map(|msg| match msg {
+				ServerToClientMsg::SetSpawnProtection(area) => Some(area),
+				_ => None,
+			})
+			.last()
+	};
+
+	// Joining players get the configured area
+	let center = server.spawn_pos().map(|v| v.floor() as isize);
+	let r = Vector3::new(5, 5, 5);
+	let area = Some((center - r, center + r + Vector3::new(1, 1, 1)));
+	assert_eq!(last_area(&mut bob_client), Some(area));
+	assert_eq!(spawn_protection_area(server.spawn_pos(), 0), None);
+
+	// Only admins may edit inside of it
+	assert!(server.is_protected(bob, center + Vector3::new(5, -5, 0)));
+	assert!(!server.is_protected(bob, center + Vector3::new(6, 0, 0)));
+	assert!(!server.is_protected(alice, center));
+
+	// Changes are sent to everyone
+	server.handle_command(alice, "/gamerule spawnprotection 2".to_owned());
+	let r = Vector3::new(2, 2, 2);
+	let area = Some((center - r, center + r + Vector3::new(1, 1, 1)));
+	assert_eq!(last_area(&mut alice_client), Some(area));
+	assert_eq!(last_area(&mut bob_client), Some(area));
+	assert!(!server.is_protected(bob, center + Vector3::new(3, 0, 0)));
+	server.handle_command(alice, "/gamerule spawnprotection 0".to_owned());
+	assert_eq!(last_area(&mut bob_client), Some(None));
+	assert!(!server.is_protected(bob, center));
+}
+
+#[cfg(test)]
+#[test]
+fn test_admin_commands() {
+	let mut config = Config::default();
+	config.admins = vec!["alice".to_owned()];
+	let (mut server, mut alice_client, alice) = test_server_with_player(config);
+	let (bob, mut bob_client) = join_test_player(&mut server, 2, "bob");
+
+	// The non-admin is rejected
+	server.handle_command(bob, "/gamemode spectator".to_owned());
+	assert_eq!(drain_chat(&mut bob_client).as_deref(),
+		Some("Insufficient permission: /gamemode is for admins only"));
+	assert_eq!(server.players.borrow()[&bob].game_mode, GameMode::Survival);
+	// Public commands still work for non-admins
+	server.handle_command(bob, "/info".to_owned());
+	assert!(drain_chat(&mut bob_client).unwrap().starts_with(env!("CARGO_PKG_NAME")));
+	// Changing the time is for admins only, querying it isn't
+	let time_before = server.clock.time();
+	server.handle_command(bob, "/time set night".to_owned());
+	assert_eq!(drain_chat(&mut bob_client).as_deref(),
+		Some("Insufficient permission: /time is for admins only"));
+	assert_eq!(server.clock.time(), time_before);
+	server.handle_command(bob, "/time query".to_owned());
+	assert!(drain_chat(&mut bob_client).unwrap().starts_with("Time is"));
+	server.handle_command(bob, "/difficulty peaceful".to_owned());
+	assert_eq!(drain_chat(&mut bob_client).as_deref(),
+		Some("Insufficient permission: /difficulty is for admins only"));
+
+	// The admin may use the command, also on others
+	server.handle_command(alice, "/gamemode spectator bob".to_owned());
+	assert_eq!(drain_chat(&mut alice_client).as_deref(), Some("Game mode set to Spectator"));
+	assert_eq!(server.players.borrow()[&bob].game_mode, GameMode::Spectator);
+}
+
+#[cfg(test)]
+#[test]
+fn test_home_commands() {
+	let mut config = Config::default();
+	config.max_homes_per_player = 2;
+	let (mut server, mut client, alice) = test_server_with_player(config);
+	let mut command = |server :&mut Server<_>, cmd :&str| {
+		server.handle_command(alice, cmd.to_owned());
+		let msgs = drain_msgs(&mut client);
+		(last_chat(&msgs).unwrap(), last_pos(&msgs))
+	};
+	let move_to = |server :&mut Server<_>, pos| {
+		server.players.borrow_mut().get_mut(&alice).unwrap().pos = PlayerPosition::from_pos(pos);
+	};
+	let base = Vector3::new(10.0, 20.0, 30.0);
+	let tower = Vector3::new(-5.0, 8.0, 50.0);
+
+	assert_eq!(command(&mut server, "/listhomes").0, "You have no homes");
+	move_to(&mut server, base);
+	assert_eq!(command(&mut server, "/sethome").0, "Home home set");
+	move_to(&mut server, tower);
+	assert_eq!(command(&mut server, "/sethome tower").0, "Home tower set");
+	assert_eq!(command(&mut server, "/listhomes").0, "Your homes: home, tower");
+
+	// The limit applies to new homes only
+	assert_eq!(command(&mut server, "/sethome cave").0, "You can't have more than 2 homes");
+	assert_eq!(command(&mut server, "/sethome tower").0, "Home tower set");
+
+	move_to(&mut server, Vector3::new(0.0, 0.0, 0.0));
+	assert_eq!(command(&mut server, "/home"), ("Teleported to home home".to_owned(), Some(base)));
+	assert_eq!(server.players.borrow()[&alice].pos.pos(), base);
+	assert_eq!(command(&mut server, "/home tower").1, Some(tower));
+	assert_eq!(command(&mut server, "/home cave"), ("You have no home named cave".to_owned(), None));
+
+	assert_eq!(command(&mut server, "/delhome tower").0, "Home tower deleted");
+	assert_eq!(command(&mut server, "/delhome tower").0, "You have no home named tower");
+	assert_eq!(command(&mut server, "/sethome cave").0, "Home cave set");
+	assert_eq!(command(&mut server, "/listhomes").0, "Your homes: cave, home");
+}
+
+#[cfg(test)]
+#[test]
+fn test_playtime_commands() {
+	let mut config = Config::default();
+	config.tick_rate = 1;
+	let (mut server, mut client, alice) = test_server_with_player(config);
+	let mut command = |server :&mut Server<_>, cmd :&str| {
+		server.handle_command(alice, cmd.to_owned());
+		drain_chat(&mut client).unwrap()
+	};
+	for _ in 0 .. 3 {
+		server.step().unwrap();
+	}
+	assert_eq!(command(&mut server, "/playtime"), "You have played for 3s");
+	assert_eq!(command(&mut server, "/worldage"), "The world is 3s old");
+
+	// The playtime stops while disconnected
+	close_connections(&[alice], &mut *server.players.borrow_mut());
+	server.step().unwrap();
+	assert_eq!(server.playtime.world_age(), 4);
+	assert_eq!(server.playtime.player("alice"), 3);
+}
+
+#[cfg(test)]
+#[test]
+fn test_inventory_preset_commands() {
+	let mut config = Config::default();
+	config.max_inventory_presets_per_player = 2;
+	let (mut server, mut client, alice) = test_server_with_player(config);
+	let mut command = |server :&mut Server<_>, cmd :&str| {
+		server.handle_command(alice, cmd.to_owned());
+		let msgs = drain_msgs(&mut client);
+		let inv = msgs.iter()
+			.filter_map(|msg| match msg {
+				ServerToClientMsg::SetInventory(inv) => Some(inv.clone()),
+				_ => None,
+			})
+			.last();
+		(last_chat(&msgs).unwrap(), inv)
+	};
+	let set_inv = |server :&mut Server<_>, inv :&SelectableInventory| {
+		server.players.borrow_mut().get_mut(&alice).unwrap().inventory = inv.clone();
+	};
+	let nm = &server.params.p.name_id_map;
+	let stone = nm.get_id("default:stone").unwrap();
+	let wood = nm.get_id("default:wood").unwrap();
+	let mut building = SelectableInventory::new();
+	building.stacks_mut()[2] = Stack::with(stone, 30);
+	building.stacks_mut()[5] = Stack::with(wood, 12);
+	building.select(5);
+	let mut digging = SelectableInventory::new();
+	digging.stacks_mut()[0] = Stack::with(wood, 1);
+
+	assert_eq!(command(&mut server, "/inv list").0, "You have no inventory presets");
+	set_inv(&mut server, &building);
+	assert_eq!(command(&mut server, "/inv save build").0, "Inventory preset build saved");
+	set_inv(&mut server, &digging);
+	assert_eq!(command(&mut server, "/inv save dig").0, "Inventory preset dig saved");
+	assert_eq!(command(&mut server, "/inv list").0, "Your inventory presets: build, dig");
+
+	// Loading would duplicate items in survival
+	assert_eq!(command(&mut server, "/inv load build"),
+		("Inventory presets can only be loaded in creative mode".to_owned(), None));
+	assert_eq!(server.players.borrow()[&alice].inventory, digging);
+	server.players.borrow_mut().get_mut(&alice).unwrap().game_mode = GameMode::Creative;
+
+	// Loading restores the exact layout, including the selection
+	assert_eq!(command(&mut server, "/inv load build"),
+		("Inventory preset build loaded".to_owned(), Some(building.clone())));
+	assert_eq!(server.players.borrow()[&alice].inventory, building);
+	assert_eq!(command(&mut server, "/inv load cave"),
+		("You have no inventory preset named cave".to_owned(), None));
+
+	// The limit applies to new presets only
+	assert_eq!(command(&mut server, "/inv save cave").0, "You can't have more than 2 inventory presets");
+	set_inv(&mut server, &SelectableInventory::new());
+	assert_eq!(command(&mut server, "/inv save dig").0, "Inventory preset dig saved");
+	set_inv(&mut server, &building);
+	assert_eq!(command(&mut server, "/inv load dig").1, Some(SelectableInventory::new()));
+	assert_eq!(command(&mut server, "/inv save").0, "Usage: /inv {save <name>,load <name>,list}");
+}
+
+#[cfg(test)]
+#[test]
+fn test_gime_needs_creative() {
+	let (mut server, mut client, alice) = test_server_with_player(Config::default());
+	let mut command = |server :&mut Server<_>, cmd :&str| {
+		server.handle_command(alice, cmd.to_owned());
+		drain_chat(&mut client).unwrap()
+	};
+	let stone = server.params.p.name_id_map.get_id("default:stone").unwrap();
+
+	// A survival player that isn't an admin gets nothing
+	assert!(!server.is_admin(alice));
+	assert_eq!(command(&mut server, "/gime default:stone 5"),
+		"Items can only be given in creative mode");
+	assert!(server.players.borrow()[&alice].inventory.is_empty());
+
+	server.players.borrow_mut().get_mut(&alice).unwrap().game_mode = GameMode::Creative;
+	command(&mut server, "/gime default:stone 5");
+	assert!(server.players.borrow()[&alice].inventory.stacks()
+		.contains(&Stack::with(stone, 5)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_teleport_warmup_cooldown() {
+	let mut config = Config::default();
+	config.teleport_warmup = 1.0;
+	config.teleport_cooldown = 5.0;
+	let (mut server, mut client, alice) = test_server_with_player(config);
+	let mut received = || {
+		let msgs = drain_msgs(&mut client);
+		(last_chat(&msgs), last_pos(&msgs))
+	};
+	let move_to = |server :&mut Server<_>, pos| {
+		server.players.borrow_mut().get_mut(&alice).unwrap().pos = PlayerPosition::from_pos(pos);
+	};
+	let base = Vector3::new(10.0, 20.0, 30.0);
+	let start = Vector3::new(0.0, 0.0, 0.0);
+	move_to(&mut server, base);
+	server.handle_command(alice, "/sethome".to_owned());
+	move_to(&mut server, start);
+	received();
+
+	// Moving during the warmup cancels the teleport
+	server.handle_command(alice, "/home".to_owned());
+	assert_eq!(received(),
+		(Some("Teleporting in 1 seconds, don't move".to_owned()), None));
+	server.simulate(0.5);
+	move_to(&mut server, Vector3::new(2.0, 0.0, 0.0));
+	server.simulate(0.5);
+	assert_eq!(received(),
+		(Some("Teleport cancelled because you moved".to_owned()), None));
+	server.simulate(1.0);
+	assert_eq!(received(), (None, None));
+
+	// Standing still, the teleport happens after the warmup
+	server.handle_command(alice, "/home".to_owned());
+	server.simulate(0.5);
+	assert_eq!(received().1, None);
+	server.simulate(0.5);
+	assert_eq!(received(),
+		(Some("Teleported to home home".to_owned()), Some(base)));
+	assert_eq!(server.players.borrow()[&alice].pos.pos(), base);
+
+	// A second teleport right away is rejected by the cooldown
+	server.simulate(1.0);
+	server.handle_command(alice, "/spawn".to_owned());
+	assert_eq!(received(),
+		(Some("You can teleport again in 4 seconds".to_owned()), None));
+	server.simulate(4.0);
+	server.handle_command(alice, "/spawn".to_owned());
+	assert_eq!(received().0.as_deref(), Some("Teleporting in 1 seconds, don't move"));
+
+	// Respawning with /kill is a teleport as well
+	server.simulate(1.0);
+	received();
+	server.handle_command(alice, "/kill".to_owned());
+	assert_eq!(received(),
+		(Some("You can teleport again in 5 seconds".to_owned()), None));
+	server.simulate(5.0);
+	move_to(&mut server, base);
+	server.handle_command(alice, "/kill".to_owned());
+	assert_eq!(received(),
+		(Some("Teleporting in 1 seconds, don't move".to_owned()), None));
+	server.simulate(1.0);
+	let spawn_pos = server.player_spawn_pos();
+	assert_eq!(received(),
+		(Some("You died and respawned".to_owned()), Some(spawn_pos)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_tick_speed() {
+	let mut config = Config::default();
+	config.random_tick_speed = 5;
+	config.admins = vec!["alice".to_owned()];
+	let (mut server, mut client, alice) = test_server_with_player(config);
+	let air = server.params.p.block_roles.air;
+	for x in 0 .. 2 {
+		server.map.set_chunk(Vector3::new(x * CHUNKSIZE, 0, 0), MapChunkData::filled_with(air));
+	}
+
+	let dt = 0.05;
+	let time_before = server.clock.time();
+	assert_eq!(server.simulate(dt), 2 * 5);
+	let advance = server.clock.time() - time_before;
+
+	server.handle_command(alice, "/gamerule tickspeed 4".to_owned());
+	assert_eq!(drain_chat(&mut client).as_deref(), Some("Tick speed set to 4"));
+	let time_before = server.clock.time();
+	assert_eq!(server.simulate(dt), 4 * 2 * 5);
+	let advance_fast = server.clock.time() - time_before;
+	assert!((advance_fast - 4.0 * advance).abs() < 1e-4, "{} {}", advance, advance_fast);
+
+	// The tick speed is bounded
+	server.handle_command(alice, "/gamerule tickspeed 100000".to_owned());
+	assert!(drain_chat(&mut client).unwrap().starts_with("Invalid tick speed"));
+	server.handle_command(alice, "/gamerule tickspeed 0".to_owned());
+	assert!(drain_chat(&mut client).unwrap().starts_with("Invalid tick speed"));
+	assert_eq!(server.tick_speed, 4);
+}
+
+#[cfg(test)]
+#[test]
+fn test_protocol_version_check() {
+	use crate::generic_net::{MpscServerSocket, MpscServerConn, NetworkClientConn};
+
+	assert!(protocol_compatible(PROTOCOL_VERSION));
+	assert!(protocol_compatible(MIN_COMPATIBLE_PROTOCOL_VERSION));
+	assert!(!protocol_compatible(PROTOCOL_VERSION + 1));
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, _client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, false, config);
+	let connect = |server :&mut Server<MpscServerSocket>, version| {
+		let (conn, client) = MpscServerConn::new();
+		server.unauthenticated_players.push((conn, AuthState::AwaitingHello));
+		client.send(ClientToServerMsg::Hello(version)).unwrap();
+		server.handle_auth_msgs();
+		client
+	};
+
+	// Matching versions are accepted, and login can continue
+	let mut client = connect(&mut server, PROTOCOL_VERSION);
+	assert_eq!(server.unauthenticated_players.len(), 1);
+	match server.unauthenticated_players[0].1 {
+		AuthState::Unauthenticated => (),
+		_ => panic!("expected to wait for the login"),
+	}
+	assert!(client.try_recv().unwrap().is_none());
+	server.unauthenticated_players.clear();
+
+	// Mismatching versions are rejected before auth
+	let mut client = connect(&mut server, PROTOCOL_VERSION + 1);
+	assert!(server.unauthenticated_players.is_empty());
+	match client.try_recv().unwrap() {
+		Some(ServerToClientMsg::IncompatibleVersion(srv, min)) => {
+			assert_eq!(srv, PROTOCOL_VERSION);
+			assert_eq!(min, MIN_COMPATIBLE_PROTOCOL_VERSION);
+		},
+		_ => panic!("expected an incompatible version reply"),
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_spectator_edits_rejected() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	config.game_mode = GameMode::Spectator;
+	let (socket, client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let pos = server.spawn_pos().map(|v| v as isize);
+	step_until(&mut server, |srv| srv.map().get_blk(pos).is_some());
+	let id = PlayerIdPair::singleplayer();
+	assert_eq!(server.players.borrow()[&id].game_mode, GameMode::Spectator);
+
+	let before = server.map().get_blk(pos).unwrap();
+	let stone = server.params.p.block_roles.stone;
+	assert_ne!(before, stone);
+	client.send(ClientToServerMsg::SetBlock(pos, stone)).unwrap();
+	client.send(ClientToServerMsg::PlaceTree(pos)).unwrap();
+	for _ in 0 .. 3 {
+		server.step().unwrap();
+	}
+	assert_eq!(server.map().get_blk(pos), Some(before));
+
+	// Back in survival mode, the edits are applied again
+	client.send(ClientToServerMsg::Chat("/gamemode survival".to_owned())).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.players.borrow()[&id].game_mode, GameMode::Survival);
+	server.players.borrow_mut().get_mut(&id).unwrap()
+		.inventory.put(Stack::with(stone, 1));
+	client.send(ClientToServerMsg::SetBlock(pos, stone)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(stone));
+}
+
+#[cfg(test)]
+#[test]
+fn test_place_tree_creative_only() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let pos = server.spawn_pos().map(|v| v as isize);
+	step_until(&mut server, |srv| srv.map().get_blk(pos).is_some());
+	let id = PlayerIdPair::singleplayer();
+	assert_eq!(server.players.borrow()[&id].game_mode, GameMode::Survival);
+
+	// Survival players can't grow trees out of thin air
+	let before = server.map().get_blk(pos).unwrap();
+	client.send(ClientToServerMsg::PlaceTree(pos)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(before));
+
+	client.send(ClientToServerMsg::Chat("/gamemode creative".to_owned())).unwrap();
+	server.step().unwrap();
+	client.send(ClientToServerMsg::PlaceTree(pos)).unwrap();
+	server.step().unwrap();
+	assert_ne!(server.map().get_blk(pos), Some(before));
+}
+
+#[cfg(test)]
+#[test]
+fn test_facing_metadata() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+	use crate::map::Dir6;
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let pos = server.spawn_pos().map(|v| v as isize);
+	step_until(&mut server, |srv| srv.map().get_blk(pos).is_some());
+	let id = PlayerIdPair::singleplayer();
+	let stone = server.params.p.block_roles.stone;
+	let tree = server.params.p.block_roles.tree;
+	server.players.borrow_mut().get_mut(&id).unwrap()
+		.inventory.put(Stack::with(tree, 2));
+	let facing = |server :&Server<MpscServerSocket>| {
+		match server.map().get_blk_meta(pos) {
+			Some(Some(MetadataEntry::Facing(f))) => Some(*f),
+			_ => None,
+		}
+	};
+
+	// Directional blocks get their facing
+	client.send(ClientToServerMsg::SetBlock(pos, tree)).unwrap();
+	client.send(ClientToServerMsg::SetMetadata(pos, MetadataEntry::Facing(Dir6::XPos))).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(tree));
+	assert_eq!(facing(&server), Some(Dir6::XPos));
+
+	// Digging removes the facing
+	client.send(ClientToServerMsg::Dig(pos)).unwrap();
+	server.step().unwrap();
+	assert_eq!(facing(&server), None);
+
+	// Blocks can't be placed onto it, so it keeps its facing
+	client.send(ClientToServerMsg::SetBlock(pos, tree)).unwrap();
+	client.send(ClientToServerMsg::SetMetadata(pos, MetadataEntry::Facing(Dir6::YNeg))).unwrap();
+	server.step().unwrap();
+	assert_eq!(facing(&server), Some(Dir6::YNeg));
+	let mut inventory = SelectableInventory::new();
+	inventory.put(Stack::with(stone, 1));
+	server.players.borrow_mut().get_mut(&id).unwrap().inventory = inventory;
+	client.send(ClientToServerMsg::SetBlock(pos, stone)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(tree));
+	assert_eq!(facing(&server), Some(Dir6::YNeg));
+	client.send(ClientToServerMsg::Dig(pos)).unwrap();
+	client.send(ClientToServerMsg::SetBlock(pos, stone)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(stone));
+	assert_eq!(facing(&server), None);
+
+	// Non-directional blocks can't get a facing
+	client.send(ClientToServerMsg::SetMetadata(pos, MetadataEntry::Facing(Dir6::XNeg))).unwrap();
+	server.step().unwrap();
+	assert_eq!(facing(&server), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_edits_beyond_reach_rejected() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	config.reach_distance = 5.0;
+	let (socket, client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	let pos = server.spawn_pos().map(|v| v as isize);
+	let near = pos + Vector3::new(5, 0, 0);
+	let far = pos + Vector3::new(8, 0, 0);
+	step_until(&mut server, |srv| srv.map().get_blk(near).is_some() &&
+		srv.map().get_blk(far).is_some());
+	let stone = server.params.p.block_roles.stone;
+	{
+		let mut players = server.players.borrow_mut();
+		let player = players.get_mut(&id).unwrap();
+		player.pos = PlayerPosition::from_pos(pos.map(|v| v as f32 + 0.5));
+		player.inventory.put(Stack::with(stone, 2));
+	}
+	let before = server.map().get_blk(far).unwrap();
+	assert_ne!(before, stone);
+	let inv_before = server.players.borrow()[&id].inventory.clone();
+
+	// The far block is more than the reach plus the tolerance away
+	client.send(ClientToServerMsg::SetBlock(far, stone)).unwrap();
+	client.send(ClientToServerMsg::Dig(far)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(far), Some(before));
+	assert_eq!(server.players.borrow()[&id].inventory, inv_before);
+
+	// The near one is within reach
+	client.send(ClientToServerMsg::SetBlock(near, stone)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(near), Some(stone));
+}
+
+#[cfg(test)]
+#[test]
+fn test_place_onto_chest_rejected() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	let pos = server.spawn_pos().map(|v| v as isize);
+	step_until(&mut server, |srv| srv.map().get_blk(pos).is_some());
+	let chest = server.params.p.search_block_name("default:chest").unwrap();
+	let stone = server.params.p.block_roles.stone;
+	let mut chest_inv = SelectableInventory::new();
+	chest_inv.put(Stack::with(stone, 5));
+	server.map.get_blk_mut(pos).unwrap().set(chest);
+	server.map.get_blk_meta_mut(pos).unwrap().set(MetadataEntry::Inventory(chest_inv.clone()));
+	server.players.borrow_mut().get_mut(&id).unwrap()
+		.inventory.put(Stack::with(stone, 1));
+	let inv_before = server.players.borrow()[&id].inventory.clone();
+	while let Ok(Some(_)) = client.try_recv() {}
+
+	// The chest and its items stay, and the client
+	// gets both the block and its inventory back
+	client.send(ClientToServerMsg::SetBlock(pos, stone)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(chest));
+	match server.map().get_blk_meta(pos) {
+		Some(Some(MetadataEntry::Inventory(inv))) => assert_eq!(*inv, chest_inv),
+		_ => panic!("the chest inventory is gone"),
+	}
+	assert_eq!(server.players.borrow()[&id].inventory, inv_before);
+	let (mut chunk_updated, mut inv_sent) = (false, false);
+	while let Ok(Some(msg)) = client.try_recv() {
+		match msg {
+			ServerToClientMsg::ChunkUpdated(..) => chunk_updated = true,
+			ServerToClientMsg::SetInventory(inv) => {
+				assert_eq!(inv, inv_before);
+				inv_sent = true;
+			},
+			_ => (),
+		}
+	}
+	assert!(chunk_updated && inv_sent);
+
+	// Neither digging it nor growing a tree there destroys it
+	client.send(ClientToServerMsg::Chat("/gamemode creative".to_owned())).unwrap();
+	client.send(ClientToServerMsg::Dig(pos)).unwrap();
+	client.send(ClientToServerMsg::PlaceTree(pos)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.map().get_blk(pos), Some(chest));
+	match server.map().get_blk_meta(pos) {
+		Some(Some(MetadataEntry::Inventory(inv))) => assert_eq!(*inv, chest_inv),
+		_ => panic!("the chest inventory is gone"),
+	}
+	let leaves = server.params.p.block_roles.leaves;
+	assert_eq!(server.map().get_blk(pos + Vector3::new(0, 0, 4)), Some(leaves));
+}
+
+#[cfg(test)]
+#[test]
+fn test_invalid_inventory_actions_rejected() {
+	use crate::generic_net::{MpscServerSocket, NetworkClientConn};
+	use crate::inventory::HUD_SLOT_COUNT;
+
+	let mut config = Config::default();
+	config.generate_terrain = false;
+	let (socket, mut client) = MpscServerSocket::new();
+	let mut server = Server::new(socket, true, config);
+	let id = PlayerIdPair::singleplayer();
+	step_until(&mut server, |srv| srv.players.borrow().contains_key(&id));
+	let params = server.params.clone();
+	let p = &params.p;
+	let tree = p.search_block_name("default:tree").unwrap();
+	let wood = p.search_block_name("default:wood").unwrap();
+	let table = p.search_block_name("default:crafting_table").unwrap();
+	let recipe_for = |item| p.recipes.iter()
+		.position(|r| r.output.content().map(|(it, _)| it) == Some(item))
+		.unwrap();
+	{
+		let mut players = server.players.borrow_mut();
+		let inv = &mut players.get_mut(&id).unwrap().inventory;
+		inv.put(Stack::with(wood, 3));
+		inv.put(Stack::with(tree, 1));
+	}
+	let inv_before = server.players.borrow()[&id].inventory.clone();
+	while let Ok(Some(_)) = client.try_recv() {}
+
+	// Crafting without the ingredients in the grid,
+	// and filling the grid with missing ingredients
+	let grid = CraftingGrid::PERSONAL;
+	let actions = [
+		InventoryAction::Craft(grid),
+		InventoryAction::FillGrid(grid, recipe_for(table)),
+	];
+	for action in actions.iter() {
+		client.send(ClientToServerMsg::InventoryAction(action.clone())).unwrap();
+	}
+	server.step().unwrap();
+	assert_eq!(server.players.borrow()[&id].inventory, inv_before);
+	assert!(server.players.borrow()[&id].craft_invs.values().all(|inv| inv.is_empty()));
+	// The client gets the authoritative inventory to undo its prediction
+	let mut inv_msgs = 0;
+	while let Ok(Some(msg)) = client.try_recv() {
+		if let ServerToClientMsg::SetInventory(inv) = msg {
+			assert_eq!(inv, inv_before);
+			inv_msgs += 1;
+		}
+	}
+	assert_eq!(inv_msgs, 2);
+
+	// With the ingredients, crafting works
+	let actions = [
+		InventoryAction::FillGrid(grid, recipe_for(wood)),
+		InventoryAction::Craft(grid),
+	];
+	for action in actions.iter() {
+		client.send(ClientToServerMsg::InventoryAction(action.clone())).unwrap();
+	}
+	server.step().unwrap();
+	let players = server.players.borrow();
+	let count_of = |item| players[&id].inventory.stacks().iter()
+		.filter_map(|st| st.content())
+		.filter(|(it, _)| *it == item)
+		.map(|(_, n)| n)
+		.sum::<u16>();
+	assert_eq!(count_of(wood), 7);
+	assert_eq!(count_of(tree), 0);
+	assert!(players[&id].craft_invs[&grid].is_empty());
+	drop(players);
+
+	// Picking blocks only works in creative mode
+	let stone = p.search_block_name("default:stone").unwrap();
+	let pick = InventoryAction::PickBlock(stone, HUD_SLOT_COUNT);
+	client.send(ClientToServerMsg::InventoryAction(pick.clone())).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.players.borrow()[&id].inventory.get_selected(), Some(wood));
+	server.players.borrow_mut().get_mut(&id).unwrap().game_mode = GameMode::Creative;
+	client.send(ClientToServerMsg::InventoryAction(pick)).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.players.borrow()[&id].inventory.get_selected(), Some(stone));
+
+	// The table grid needs a crafting table nearby
+	let table_pos = server.players.borrow()[&id].pos().map(|v| v.floor() as isize) +
+		Vector3::new(2, 0, 0);
+	step_until(&mut server, |srv| srv.map().get_blk(table_pos).is_some());
+	server.players.borrow_mut().get_mut(&id).unwrap().inventory.put(Stack::with(tree, 1));
+	let inv_before = server.players.borrow()[&id].inventory.clone();
+	let fill = InventoryAction::FillGrid(CraftingGrid::TABLE, recipe_for(wood));
+	client.send(ClientToServerMsg::InventoryAction(fill.clone())).unwrap();
+	server.step().unwrap();
+	assert_eq!(server.players.borrow()[&id].inventory, inv_before);
+	server.map.get_blk_mut(table_pos).unwrap().set(table);
+	client.send(ClientToServerMsg::InventoryAction(fill)).unwrap();
+	server.step().unwrap();
+	let players = server.players.borrow();
+	assert!(!players[&id].craft_invs[&CraftingGrid::TABLE].is_empty());
+	// The items in the grid are stored as part of the inventory
+	let (stored, leftover) = players[&id].inventory_with_craft_grids();
+	assert!(leftover.is_empty());
+	assert_eq!(stored, inv_before);
+}
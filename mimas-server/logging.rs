@@ -0,0 +1,174 @@
+use std::env;
+use std::str::FromStr;
+use log::{Log, Record, Metadata, Level, LevelFilter};
+use crate::StrErr;
+
+/// The log targets of the subsystems
+///
+/// Every module logs under one of these instead of
+/// its module path, so that filters stay stable.
+pub const TARGET_MAPGEN :&str = "mimas::mapgen";
+pub const TARGET_NET :&str = "mimas::net";
+pub const TARGET_STORAGE :&str = "mimas::storage";
+pub const TARGET_CONFIG :&str = "mimas::config";
+pub const TARGET_SERVER :&str = "mimas::server";
+pub const TARGET_RENDER :&str = "mimas::render";
+pub const TARGET_CLIENT :&str = "mimas::client";
+
+/// The environment variable holding the filter spec
+const FILTER_ENV :&str = "RUST_LOG";
+
+/// The verbosity of log targets
+///
+/// Parsed from a comma separated spec like
+/// `mimas::mapgen=warn,mimas::net=debug`. An entry without
+/// target sets the level of all other targets. A target also
+/// applies to the targets nested below it, the longest
+/// matching one wins.
+#[derive(Clone, Debug)]
+pub struct TargetFilter {
+	default :LevelFilter,
+	directives :Vec<(String, LevelFilter)>,
+}
+
+impl TargetFilter {
+	pub fn new(default :LevelFilter) -> Self {
+		Self {
+			default,
+			directives : Vec::new(),
+		}
+	}
+	pub fn parse(spec :&str) -> Result<Self, StrErr> {
+		let mut res = Self::new(LevelFilter::Info);
+		for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+			let mut parts = entry.splitn(2, '=');
+			let first = parts.next().unwrap();
+			match parts.next() {
+				Some(level) => {
+					let level = LevelFilter::from_str(level.trim())
+						.map_err(|_| format!("Invalid log level '{}'", level))?;
+					res.directives.push((first.trim().to_owned(), level));
+				},
+				None => match LevelFilter::from_str(first) {
+					Ok(level) => res.default = level,
+					// A target on its own enables everything of it
+					Err(_) => res.directives.push((first.to_owned(), LevelFilter::Trace)),
+				},
+			}
+		}
+		Ok(res)
+	}
+	/// The filter from the `RUST_LOG` environment variable, or `info` for everything
+	pub fn from_env() -> Self {
+		let spec = if let Ok(spec) = env::var(FILTER_ENV) {
+			spec
+		} else {
+			return Self::new(LevelFilter::Info);
+		};
+		Self::parse(&spec).unwrap_or_else(|e| {
+			eprintln!("Ignoring the {} setting: {}", FILTER_ENV, e.0);
+			Self::new(LevelFilter::Info)
+		})
+	}
+	pub fn level_for(&self, target :&str) -> LevelFilter {
+		self.directives.iter()
+			.filter(|(t, _)| target == t ||
+				(target.starts_with(t.as_str()) && target[t.len() ..].starts_with("::")))
+			.max_by_key(|(t, _)| t.len())
+			.map(|(_, level)| *level)
+			.unwrap_or(self.default)
+	}
+	pub fn enabled(&self, metadata :&Metadata) -> bool {
+		metadata.level() <= self.level_for(metadata.target())
+	}
+	/// The most verbose level any target is enabled for
+	pub fn max_level(&self) -> LevelFilter {
+		self.directives.iter()
+			.map(|(_, level)| *level)
+			.fold(self.default, |a, b| a.max(b))
+	}
+}
+
+/// Prints the records passing the filter, errors to stderr
+struct PrintLogger {
+	filter :TargetFilter,
+}
+
+impl Log for PrintLogger {
+	fn enabled(&self, metadata :&Metadata) -> bool {
+		self.filter.enabled(metadata)
+	}
+	fn log(&self, record :&Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		if record.level() == Level::Error {
+			eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+		} else {
+			println!("[{} {}] {}", record.level(), record.target(), record.args());
+		}
+	}
+	fn flush(&self) {}
+}
+
+/// Sets up logging with the filter from the environment
+///
+/// Does nothing if a logger has been set up already.
+pub fn init() {
+	let filter = TargetFilter::from_env();
+	let max_level = filter.max_level();
+	let logger = Box::leak(Box::new(PrintLogger { filter }));
+	if log::set_logger(logger).is_ok() {
+		log::set_max_level(max_level);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_target_filter() {
+	use std::sync::Mutex;
+
+	struct CapturingLogger {
+		filter :TargetFilter,
+		captured :Mutex<Vec<String>>,
+	}
+	impl Log for CapturingLogger {
+		fn enabled(&self, metadata :&Metadata) -> bool {
+			self.filter.enabled(metadata)
+		}
+		fn log(&self, record :&Record) {
+			if self.enabled(record.metadata()) {
+				self.captured.lock().unwrap().push(format!("{}", record.args()));
+			}
+		}
+		fn flush(&self) {}
+	}
+
+	let filter = TargetFilter::parse("mimas::mapgen=warn,mimas::net=debug").unwrap();
+	assert_eq!(filter.max_level(), LevelFilter::Debug);
+	let logger = CapturingLogger {
+		filter,
+		captured : Mutex::new(Vec::new()),
+	};
+	let log = |target :&str, level :Level, msg :&str| logger.log(&Record::builder()
+		.target(target)
+		.level(level)
+		.args(format_args!("{}", msg))
+		.build());
+	log(TARGET_MAPGEN, Level::Debug, "generated chunk");
+	log(TARGET_MAPGEN, Level::Warn, "mapgen warning");
+	log(TARGET_NET, Level::Debug, "received message");
+	log(TARGET_NET, Level::Trace, "received bytes");
+	// Nested targets inherit the level, others use the default
+	log("mimas::mapgen::caves", Level::Info, "carved cave");
+	log("mimas::network", Level::Debug, "not below mimas::net");
+	log(TARGET_SERVER, Level::Info, "server started");
+	log(TARGET_SERVER, Level::Debug, "tick took long");
+	assert_eq!(*logger.captured.lock().unwrap(),
+		vec!["mapgen warning", "received message", "server started"]);
+
+	let filter = TargetFilter::parse("debug, mimas::storage").unwrap();
+	assert_eq!(filter.level_for(TARGET_CONFIG), LevelFilter::Debug);
+	assert_eq!(filter.level_for(TARGET_STORAGE), LevelFilter::Trace);
+	assert!(TargetFilter::parse("mimas::net=loud").is_err());
+}
@@ -3,12 +3,16 @@
 extern crate mimas_server;
 extern crate structopt;
 
-use mimas_server::{Server, StrErr};
+use mimas_server::{Server, StrErr, pregen_world};
 //use mimas_server::generic_net::TcpServerSocket;
 use mimas_server::quic_net::QuicServerSocket;
 use mimas_server::config::load_config;
+use mimas_server::mapgen::seed_from_str;
+use mimas_server::logging;
 
 use structopt::StructOpt;
+use std::io::BufRead;
+use std::thread;
 
 /// Mimas server
 #[derive(StructOpt, Debug)]
@@ -17,19 +21,62 @@ struct Options {
 	/// Set the listen address
 	#[structopt(long = "listen")]
 	listen_addr :Option<String>,
+	/// Generate the chunks up to the given radius (in chunks)
+	/// around the spawn into the map storage, then exit
+	#[structopt(long = "pregen")]
+	pregen_radius :Option<isize>,
+	/// Use the given map seed instead of the configured one.
+	/// Text that isn't a number is hashed into a seed.
+	#[structopt(long = "seed")]
+	seed :Option<String>,
+	/// Replay the block edits of the given recording
+	/// into the world. Best used with a fresh world.
+	#[structopt(long = "replay-edits")]
+	replay_edits :Option<String>,
+	/// How many times as fast as recorded to replay the edits
+	#[structopt(long = "replay-speed", default_value = "1.0")]
+	replay_speed :f64,
 }
 
 fn main() -> Result<(), StrErr> {
 	let options = Options::from_args();
+	logging::init();
+
+	let mut config = load_config()?;
+	if let Some(seed) = &options.seed {
+		config.mapgen_seed = seed_from_str(seed);
+	}
+
+	if let Some(radius) = options.pregen_radius {
+		pregen_world(config, radius)?;
+		return Ok(());
+	}
 
 	let server_socket = if let Some(addr) = options.listen_addr {
 		let addr = addr.parse().expect("couldn't parse address");
-		QuicServerSocket::with_socket_addr(&addr)?
+		QuicServerSocket::with_socket_addr(&addr, config.net_fragment_size)?
 	} else {
-		QuicServerSocket::new()?
+		QuicServerSocket::new(config.net_fragment_size)?
 	};
-	let config = load_config();
 	let mut server = Server::new(server_socket, false, config);
+	if let Some(path) = &options.replay_edits {
+		server.replay_edits(path, options.replay_speed)?;
+	}
+	// Typing stop into the console shuts the server down
+	let shutdown_handle = server.shutdown_handle();
+	thread::spawn(move || {
+		let stdin = std::io::stdin();
+		for line in stdin.lock().lines() {
+			match line {
+				Ok(line) if line.trim() == "stop" => {
+					shutdown_handle.request();
+					break;
+				},
+				Ok(_) => (),
+				Err(_) => break,
+			}
+		}
+	});
 	server.run_loop();
 
 	Ok(())
@@ -1,5 +1,6 @@
-use nalgebra::Vector3;
+use nalgebra::{Vector2, Vector3};
 use std::collections::hash_map::{HashMap, Entry};
+use std::collections::BTreeSet;
 use crate::{btchn, btpic};
 use crate::map_storage::PlayerIdPair;
 use crate::game_params::{ServerGameParamsHdl, Id, UncheckedId};
@@ -32,11 +33,69 @@ impl Id for MapBlock {
 	}
 }
 
+/// One of the six axis aligned directions
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Dir6 {
+	XPos,
+	XNeg,
+	YPos,
+	YNeg,
+	ZPos,
+	ZNeg,
+}
+
+impl Default for Dir6 {
+	fn default() -> Self {
+		Dir6::ZPos
+	}
+}
+
+impl Dir6 {
+	pub const ALL :[Dir6; 6] = [
+		Dir6::XPos, Dir6::XNeg,
+		Dir6::YPos, Dir6::YNeg,
+		Dir6::ZPos, Dir6::ZNeg,
+	];
+	pub fn opposite(self) -> Self {
+		match self {
+			Dir6::XPos => Dir6::XNeg,
+			Dir6::XNeg => Dir6::XPos,
+			Dir6::YPos => Dir6::YNeg,
+			Dir6::YNeg => Dir6::YPos,
+			Dir6::ZPos => Dir6::ZNeg,
+			Dir6::ZNeg => Dir6::ZPos,
+		}
+	}
+	pub fn id(self) -> u8 {
+		Self::ALL.iter().position(|d| *d == self).unwrap() as u8
+	}
+	pub fn from_id(id :u8) -> Option<Self> {
+		Self::ALL.get(id as usize).copied()
+	}
+	/// The facing of a directional block placed by a player
+	/// looking into the given direction
+	///
+	/// The block faces back towards the player, along the
+	/// axis that the player is looking along the most.
+	pub fn placement_facing(look :Vector3<f32>) -> Self {
+		let abs = look.map(|v| v.abs());
+		if abs.x >= abs.y && abs.x >= abs.z {
+			if look.x > 0.0 { Dir6::XNeg } else { Dir6::XPos }
+		} else if abs.y >= abs.z {
+			if look.y > 0.0 { Dir6::YNeg } else { Dir6::YPos }
+		} else if look.z > 0.0 {
+			Dir6::ZNeg
+		} else {
+			Dir6::ZPos
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub enum MetadataEntry {
 	Inventory(SelectableInventory),
+	Facing(Dir6),
 	//Text(String), // TODO
-	//Orientation, // TODO
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -59,12 +118,96 @@ pub struct MapChunkData(
 	#[serde(with = "BigArray")]
 	pub(in super) [MapBlock; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize],
 	pub MapChunkMetadata,
+	/// Increased on every change of the blocks or the metadata
+	pub(in super) u64,
 );
 
+/// A run of block edits, to be applied together
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct BlockBatch {
+	pub edits :Vec<(Vector3<isize>, MapBlock)>,
+}
+
+impl BlockBatch {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn push(&mut self, pos :Vector3<isize>, b :MapBlock) {
+		self.edits.push((pos, b));
+	}
+	pub fn is_empty(&self) -> bool {
+		self.edits.is_empty()
+	}
+}
+
+/// Called with the position and the new content of each changed block,
+/// as well as with the chunk containing it
+type BlockChangeCallback = Box<dyn Fn(Vector3<isize>, MapBlock, &MapChunkData)>;
+
 pub struct Map<B :MapBackend> {
 	backend :B,
 	chunks :HashMap<Vector3<isize>, MapChunkData>,
+	columns :HashMap<Vector2<isize>, ChunkColumn>,
 	on_change :Box<dyn Fn(Vector3<isize>, &MapChunkData)>,
+	on_block_change :Option<BlockChangeCallback>,
+}
+
+/// The loaded chunks of a chunk column and its heightmap
+#[derive(Default)]
+struct ChunkColumn {
+	/// The z positions of the loaded chunks
+	chunk_zs :BTreeSet<isize>,
+	/// The surface height for each x, y in the column, if computed
+	heights :Option<Box<[Option<isize>]>>,
+}
+
+impl ChunkColumn {
+	fn insert_chunk(&mut self, z :isize) {
+		self.chunk_zs.insert(z);
+		self.heights = None;
+	}
+	/// Updates the heightmap for a block set at the given position
+	fn block_set(&mut self, pos :Vector3<isize>, b :MapBlock) {
+		let heights = if let Some(heights) = &mut self.heights {
+			heights
+		} else {
+			return;
+		};
+		let h = &mut heights[column_idx(pos.x, pos.y)];
+		if b != map_block::AIR {
+			if h.map(|h| h < pos.z).unwrap_or(true) {
+				*h = Some(pos.z);
+			}
+		} else if *h == Some(pos.z) {
+			// The surface block got removed. Finding the
+			// new one needs a scan, so recompute lazily.
+			self.heights = None;
+		}
+	}
+}
+
+fn column_idx(x :isize, y :isize) -> usize {
+	let p = btpic(Vector3::new(x, y, 0));
+	(p.x + p.y * CHUNKSIZE) as usize
+}
+
+/// Scans the loaded chunks of the column for the topmost non-air blocks
+fn compute_heights(chunks :&HashMap<Vector3<isize>, MapChunkData>,
+		column_pos :Vector2<isize>, chunk_zs :&BTreeSet<isize>) -> Box<[Option<isize>]> {
+	let mut heights = vec![None; (CHUNKSIZE * CHUNKSIZE) as usize];
+	for x in 0 .. CHUNKSIZE {
+		for y in 0 .. CHUNKSIZE {
+			heights[(x + y * CHUNKSIZE) as usize] = chunk_zs.iter().rev()
+				.filter_map(|z| {
+					let chunk = &chunks[&Vector3::new(column_pos.x, column_pos.y, *z)];
+					(0 .. CHUNKSIZE).rev()
+						.find(|pz| *chunk.get_blk(Vector3::new(x, y, *pz)) != map_block::AIR)
+						.map(|pz| z + pz)
+				})
+				.next();
+		}
+	}
+	heights.into_boxed_slice()
 }
 
 pub type ServerMap = Map<MapgenThread>;
@@ -76,7 +219,17 @@ impl MapChunkData {
 	}
 	pub fn filled_with(m :MapBlock) -> Self {
 		Self([m; (CHUNKSIZE * CHUNKSIZE * CHUNKSIZE) as usize],
-			MapChunkMetadata::empty())
+			MapChunkMetadata::empty(), 0)
+	}
+	/// The version of the chunk, increased with each change
+	///
+	/// Lets users of the chunk find out whether their copy
+	/// or anything derived from it is out of date.
+	pub fn version(&self) -> u64 {
+		self.2
+	}
+	fn bump_version(&mut self) {
+		self.2 += 1;
 	}
 	pub fn get_blk_mut(&mut self, pos :Vector3<isize>) -> &mut MapBlock {
 		let (x, y, z) = (pos.x, pos.y, pos.z);
@@ -86,18 +239,48 @@ impl MapChunkData {
 		let (x, y, z) = (pos.x, pos.y, pos.z);
 		&self.0[(x * CHUNKSIZE * CHUNKSIZE + y * CHUNKSIZE + z) as usize]
 	}
+	/// Sets the block, returning the one it replaced and
+	/// whether that one's metadata got removed
+	///
+	/// Metadata belongs to the block it was set for, so
+	/// replacing the block by a different one removes it.
+	fn replace_blk(&mut self, pos :Vector3<isize>, b :MapBlock) -> (MapBlock, bool) {
+		let prev = std::mem::replace(self.get_blk_mut(pos), b);
+		let mut metadata_removed = false;
+		if prev != b {
+			if let Entry::Occupied(e) = self.get_blk_meta_entry(pos) {
+				e.remove_entry();
+				metadata_removed = true;
+			}
+		}
+		(prev, metadata_removed)
+	}
 	pub fn get_blk_meta_entry(&mut self, pos :Vector3<isize>) -> Entry<'_, Vector3<u8>, MetadataEntry> {
 		self.1.metadata.entry(pos.map(|v| v as u8))
 	}
 	pub fn get_blk_meta(&self, pos :Vector3<isize>) -> Option<&MetadataEntry> {
 		self.1.metadata.get(&pos.map(|v| v as u8))
 	}
+	/// The facing of the block, defaulting to upwards
+	/// if none has been recorded
+	pub fn get_blk_facing(&self, pos :Vector3<isize>) -> Dir6 {
+		match self.get_blk_meta(pos) {
+			Some(MetadataEntry::Facing(facing)) => *facing,
+			_ => Dir6::default(),
+		}
+	}
 }
 
 fn spawn_schematic<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, schematic :&Schematic) {
 	for (bpos, mb) in schematic.items.iter() {
-		let blk = map.get_blk_mut_no_upd(pos + bpos).unwrap();
-		*blk = *mb;
+		// Keep blocks with an inventory, so that their items don't get lost
+		if let Some(Some(MetadataEntry::Inventory(_))) = map.get_blk_meta(pos + bpos) {
+			continue;
+		}
+		// Skip the parts in unloaded chunks
+		if let Some(blk) = map.get_blk_mut_no_upd(pos + bpos) {
+			*blk = *mb;
+		}
 	}
 	let pos_min = btchn(pos + schematic.aabb_min);
 	let pos_max = btchn(pos + schematic.aabb_max);
@@ -122,17 +305,28 @@ pub fn spawn_tree<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>,
 pub struct MapBlockHandle<'a, B :MapBackend> {
 	pos :Vector3<isize>,
 	chk :&'a mut MapChunkData,
+	column :&'a mut ChunkColumn,
 	backend :&'a mut B,
 	on_change :&'a Box<dyn Fn(Vector3<isize>, &MapChunkData)>,
+	on_block_change :&'a Option<BlockChangeCallback>,
 }
 
 impl<'a, B :MapBackend> MapBlockHandle<'a, B> {
+	/// Sets the block, removing the metadata of the block it replaces
 	pub fn set(&mut self, b :MapBlock) {
 		let chunk_pos = btchn(self.pos);
 		let pos_in_chunk = btpic(self.pos);
-		*self.chk.get_blk_mut(pos_in_chunk) = b;
+		let (_, metadata_removed) = self.chk.replace_blk(pos_in_chunk, b);
+		self.chk.bump_version();
+		self.column.block_set(self.pos, b);
 		self.backend.chunk_changed(chunk_pos, self.chk.clone());
-		(*self.on_change)(chunk_pos, &self.chk);
+		// The block change callback doesn't cover metadata
+		if self.on_block_change.is_none() || metadata_removed {
+			(*self.on_change)(chunk_pos, self.chk);
+		}
+		if let Some(on_block_change) = self.on_block_change {
+			on_block_change(self.pos, b, self.chk);
+		}
 	}
 	pub fn fake_change(&mut self) {
 		let chunk_pos = btchn(self.pos);
@@ -164,6 +358,7 @@ impl<'a, B :MapBackend> MetadataHandle<'a, B> {
 				e.insert(b);
 			},
 		}
+		self.chk.bump_version();
 		self.backend.chunk_changed(chunk_pos, self.chk.clone());
 		(*self.on_change)(chunk_pos, &self.chk);
 	}
@@ -180,6 +375,7 @@ impl<'a, B :MapBackend> MetadataHandle<'a, B> {
 			},
 			Entry::Vacant(_e) => (),
 		}
+		self.chk.bump_version();
 		self.backend.chunk_changed(chunk_pos, self.chk.clone());
 		(*self.on_change)(chunk_pos, &self.chk);
 	}
@@ -211,6 +407,17 @@ impl MapBackend for ClientBackend {
 	}
 }
 
+/// The positions between the corners, both inclusive, at the given spacing
+fn positions_in_box(min :Vector3<isize>, max :Vector3<isize>,
+		step :isize) -> impl Iterator<Item = Vector3<isize>> {
+	let step = step as usize;
+	(min.z ..= max.z).step_by(step).flat_map(move |z| {
+		(min.y ..= max.y).step_by(step).flat_map(move |y| {
+			(min.x ..= max.x).step_by(step).map(move |x| Vector3::new(x, y, z))
+		})
+	})
+}
+
 pub trait MapBackend {
 	fn gen_chunks_in_area(&mut self, pos_min :Vector3<isize>,
 			pos_max :Vector3<isize>);
@@ -234,20 +441,48 @@ impl<B :MapBackend> Map<B> {
 		Map {
 			backend,
 			chunks : HashMap::new(),
+			columns : HashMap::new(),
 			on_change : Box::new(|_, _| {}),
+			on_block_change : None,
 		}
 	}
+	pub fn backend(&self) -> &B {
+		&self.backend
+	}
+	pub fn backend_mut(&mut self) -> &mut B {
+		&mut self.backend
+	}
 	pub fn register_on_change(&mut self, f :Box<dyn Fn(Vector3<isize>, &MapChunkData)>) {
 		self.on_change = f;
 	}
+	/// Registers a callback for single block edits
+	///
+	/// Edits through `MapBlockHandle::set` get reported to it
+	/// instead of to the `on_change` callback, so that they
+	/// can be batched.
+	pub fn register_on_block_change(&mut self, f :BlockChangeCallback) {
+		self.on_block_change = Some(f);
+	}
 	pub fn get_chunk(&self, pos :Vector3<isize>) -> Option<&MapChunkData> {
 		self.chunks.get(&pos)
 	}
+	/// The version of the chunk at the given chunk position, if it's loaded
+	pub fn chunk_version(&self, pos :Vector3<isize>) -> Option<u64> {
+		self.get_chunk(pos).map(|chn| chn.version())
+	}
+	/// The positions of the loaded chunks, in no particular order
+	pub fn loaded_chunks(&self) -> impl Iterator<Item = Vector3<isize>> + '_ {
+		self.chunks.keys().copied()
+	}
+	pub fn loaded_chunks_count(&self) -> usize {
+		self.chunks.len()
+	}
 	fn get_chunk_mut(&mut self, pos :Vector3<isize>) -> Option<&mut MapChunkData> {
 		self.chunks.get_mut(&pos)
 	}
 	pub fn set_chunk(&mut self, pos :Vector3<isize>, data :MapChunkData) {
 		self.chunks.insert(pos, data.clone());
+		self.columns.entry(pos.xy()).or_default().insert_chunk(pos.z);
 		self.backend.chunk_changed(pos, data.clone());
 		(self.on_change)(pos, &data);
 	}
@@ -258,9 +493,15 @@ impl<B :MapBackend> Map<B> {
 	pub fn tick(&mut self) {
 		let on_change = &self.on_change;
 		let chunks = &mut self.chunks;
+		let columns = &mut self.columns;
 		self.backend.run_for_generated_chunks(&mut |pos, chn :&MapChunkData| {
-			chunks.insert(pos, chn.clone());
-			on_change(pos, chn);
+			// Never overwrite chunks that are already present,
+			// as they might contain edits the backend doesn't know about yet.
+			if let Entry::Vacant(v) = chunks.entry(pos) {
+				v.insert(chn.clone());
+				columns.entry(pos.xy()).or_default().insert_chunk(pos.z);
+				on_change(pos, chn);
+			}
 		});
 	}
 	pub fn get_blk(&self, pos :Vector3<isize>) -> Option<MapBlock> {
@@ -269,24 +510,132 @@ impl<B :MapBackend> Map<B> {
 		self.get_chunk(chunk_pos)
 			.map(|blk| *blk.get_blk(pos_in_chunk))
 	}
+	/// Reads the block at the given position, generating its chunk if needed
+	///
+	/// Backends that generate chunks synchronously return the block right
+	/// away. With asynchronous ones, None is returned until the chunk arrives.
+	pub fn get_block(&mut self, pos :Vector3<isize>) -> Option<MapBlock> {
+		if let Some(blk) = self.get_blk(pos) {
+			return Some(blk);
+		}
+		let chunk_pos = btchn(pos);
+		self.gen_chunks_in_area(chunk_pos, chunk_pos);
+		self.tick();
+		self.get_blk(pos)
+	}
+	/// The blocks in the region between the two corners, both inclusive
+	///
+	/// Missing chunks of the region get generated first. As with
+	/// `get_block`, asynchronous backends may not deliver them right
+	/// away, and the blocks of chunks still missing are skipped.
+	pub fn blocks_in_region(&mut self, min :Vector3<isize>, max :Vector3<isize>)
+			-> impl Iterator<Item = (Vector3<isize>, MapBlock)> + '_ {
+		let (min, max) = (min.zip_map(&max, |a, b| a.min(b)), min.zip_map(&max, |a, b| a.max(b)));
+		let (chunk_min, chunk_max) = (btchn(min), btchn(max));
+		let chunks_missing = positions_in_box(chunk_min, chunk_max, CHUNKSIZE)
+			.any(|chunk_pos| self.get_chunk(chunk_pos).is_none());
+		if chunks_missing {
+			self.gen_chunks_in_area(chunk_min, chunk_max);
+			self.tick();
+		}
+		let chunks = &self.chunks;
+		positions_in_box(chunk_min, chunk_max, CHUNKSIZE)
+			.filter_map(move |chunk_pos| chunks.get(&chunk_pos).map(|chn| (chunk_pos, chn)))
+			.flat_map(move |(chunk_pos, chn)| {
+				// Only the part of the chunk inside the region
+				let chunk_end = chunk_pos.map(|v| v + CHUNKSIZE - 1);
+				let lo = min.zip_map(&chunk_pos, |a, b| a.max(b));
+				let hi = max.zip_map(&chunk_end, |a, b| a.min(b));
+				positions_in_box(lo, hi, 1)
+					.map(move |pos| (pos, *chn.get_blk(pos - chunk_pos)))
+			})
+	}
 	pub fn get_blk_mut_no_upd(&mut self, pos :Vector3<isize>) -> Option<&mut MapBlock> {
 		let chunk_pos = btchn(pos);
 		let pos_in_chunk = btpic(pos);
+		// The block might change in any way
+		if let Some(column) = self.columns.get_mut(&chunk_pos.xy()) {
+			column.heights = None;
+		}
 		self.get_chunk_mut(chunk_pos)
-			.map(|blk| blk.get_blk_mut(pos_in_chunk))
+			.map(|blk| {
+				blk.bump_version();
+				blk.get_blk_mut(pos_in_chunk)
+			})
 	}
 	pub fn get_blk_mut<'s>(&'s mut self, pos :Vector3<isize>) -> Option<MapBlockHandle<'s, B>> {
 		let chunk_pos = btchn(pos);
 		let on_change = &self.on_change;
+		let on_block_change = &self.on_block_change;
 		let backend = &mut self.backend;
+		let columns = &mut self.columns;
 		self.chunks.get_mut(&chunk_pos)
 			.map(move |chk| MapBlockHandle {
 				pos,
 				chk,
+				column : columns.entry(chunk_pos.xy()).or_default(),
 				backend,
 				on_change,
+				on_block_change,
 			})
 	}
+	/// Sets the blocks at the positions, returning the blocks they replaced
+	///
+	/// The edits are grouped by chunk, so that the backend learns about
+	/// each changed chunk only once. The block change callback, if
+	/// registered, gets every edit, otherwise the change callback gets
+	/// every changed chunk once. Edits in chunks that aren't loaded are
+	/// skipped, and None is returned for them.
+	///
+	/// Like `MapBlockHandle::set`, the metadata of replaced blocks is
+	/// removed. As the block change callback doesn't cover metadata,
+	/// chunks where this happened are always reported to the change callback.
+	pub fn set_blocks(&mut self, edits :&[(Vector3<isize>, MapBlock)]) -> Vec<Option<MapBlock>> {
+		let mut previous = Vec::with_capacity(edits.len());
+		let mut changed = Vec::new();
+		let mut metadata_cleared = Vec::new();
+		for (pos, b) in edits.iter() {
+			let chunk_pos = btchn(*pos);
+			let chk = if let Some(chk) = self.chunks.get_mut(&chunk_pos) {
+				chk
+			} else {
+				previous.push(None);
+				continue;
+			};
+			let (prev, metadata_removed) = chk.replace_blk(btpic(*pos), *b);
+			previous.push(Some(prev));
+			if metadata_removed && !metadata_cleared.contains(&chunk_pos) {
+				metadata_cleared.push(chunk_pos);
+			}
+			self.columns.entry(chunk_pos.xy()).or_default().block_set(*pos, *b);
+			if !changed.contains(&chunk_pos) {
+				changed.push(chunk_pos);
+			}
+		}
+		for chunk_pos in changed {
+			let chk = self.chunks.get_mut(&chunk_pos).unwrap();
+			chk.bump_version();
+			self.backend.chunk_changed(chunk_pos, chk.clone());
+			if self.on_block_change.is_none() || metadata_cleared.contains(&chunk_pos) {
+				(self.on_change)(chunk_pos, chk);
+			}
+		}
+		if let Some(on_block_change) = &self.on_block_change {
+			for ((pos, b), prev) in edits.iter().zip(previous.iter()) {
+				if prev.is_some() {
+					on_block_change(*pos, *b, &self.chunks[&btchn(*pos)]);
+				}
+			}
+		}
+		previous
+	}
+	/// Applies all edits of the batch before notifying about the changed chunks
+	///
+	/// Edits in chunks that aren't loaded are skipped.
+	/// Returns the previous blocks, like `set_blocks`.
+	pub fn apply_block_batch(&mut self, batch :&BlockBatch) -> Vec<Option<MapBlock>> {
+		self.set_blocks(&batch.edits)
+	}
 	pub fn get_blk_meta_mut(&mut self, pos :Vector3<isize>) -> Option<MetadataHandle<'_, B>> {
 		let chunk_pos = btchn(pos);
 		let on_change = &self.on_change;
@@ -299,6 +648,20 @@ impl<B :MapBackend> Map<B> {
 				on_change,
 			})
 	}
+	/// The z of the topmost non-air block at the given x, y
+	///
+	/// Only the loaded chunks are considered. The heightmap of each
+	/// chunk column is cached until a chunk gets added to the column
+	/// or its surface block gets removed.
+	pub fn surface_height(&mut self, x :isize, y :isize) -> Option<isize> {
+		let column_pos = btchn(Vector3::new(x, y, 0)).xy();
+		let ChunkColumn { chunk_zs, heights } = self.columns.get_mut(&column_pos)?;
+		let chunks = &self.chunks;
+		let heights = heights.get_or_insert_with(|| {
+			compute_heights(chunks, column_pos, chunk_zs)
+		});
+		heights[column_idx(x, y)]
+	}
 	pub fn get_blk_meta(&self, pos :Vector3<isize>) -> Option<Option<&MetadataEntry>> {
 		let chunk_pos = btchn(pos);
 		let pos_in_chunk = btpic(pos);
@@ -1,15 +1,23 @@
 use rusqlite::{Connection, NO_PARAMS, OptionalExtension};
 use rusqlite::types::{Value, ToSql};
-use crate::map::{MapChunkData, MetadataEntry, CHUNKSIZE};
-use crate::StrErr;
+use crate::map::{MapChunkData, MetadataEntry, Dir6, CHUNKSIZE};
+use crate::error::{MimasError, StorageError};
 use nalgebra::Vector3;
 use std::{str, io, path::Path};
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 use flate2::{Compression, GzBuilder, read::GzDecoder};
 use crate::config::Config;
+use crate::difficulty::Difficulty;
+use crate::clock::WorldClock;
+use crate::whitelist::Whitelist;
+use crate::homes::Homes;
+use crate::playtime::Playtime;
+use crate::inventory_presets::InventoryPresets;
+use crate::logging::TARGET_STORAGE;
 use toml::{from_str, to_string};
 use crate::sqlite_generic::{get_user_version, set_user_version,
-	get_app_id, set_app_id, open_or_create_db};
+	get_app_id, set_app_id, open_or_create_db,
+	set_cache_size_kb, set_synchronous, SqliteSynchronous};
 use crate::local_auth::SqliteLocalAuth;
 use std::num::NonZeroU64;
 use crate::game_params::{NameIdMap, parse_block_name, Id};
@@ -18,6 +26,45 @@ use crate::inventory::SelectableInventory;
 pub struct SqliteStorageBackend {
 	conn :Connection,
 	ctr :u32,
+	compression :CompressionKind,
+}
+
+/// The codec used to compress chunks in the map storage
+///
+/// Each stored chunk records the codec it was written with,
+/// so changing the setting keeps existing chunks readable.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionKind {
+	Gzip,
+	None,
+	Zstd,
+}
+
+impl Default for CompressionKind {
+	fn default() -> Self {
+		CompressionKind::Gzip
+	}
+}
+
+/// The compression level used for zstd
+const ZSTD_LEVEL :i32 = 3;
+
+impl CompressionKind {
+	fn codec_id(&self) -> u8 {
+		match self {
+			CompressionKind::None => 0,
+			CompressionKind::Gzip => 1,
+			CompressionKind::Zstd => 2,
+		}
+	}
+	fn from_codec_id(id :u8) -> Result<Self, MimasError> {
+		Ok(match id {
+			0 => CompressionKind::None,
+			1 => CompressionKind::Gzip,
+			2 => CompressionKind::Zstd,
+			_ => Err(StorageError::Corrupted(format!("Unsupported map chunk codec {}", id)))?,
+		})
+	}
 }
 
 /// Magic used to identify the mimas application.
@@ -33,7 +80,7 @@ const USER_VERSION :u16 = 2;
 /// per write really slow.
 const WRITES_PER_TRANSACTION :u32 = 50;
 
-fn init_db(conn :&mut Connection) -> Result<(), StrErr> {
+fn init_db(conn :&mut Connection) -> Result<(), MimasError> {
 	set_app_id(conn, MEHLON_SQLITE_APP_ID)?;
 	set_user_version(conn, USER_VERSION)?;
 	conn.execute(
@@ -57,7 +104,7 @@ fn init_db(conn :&mut Connection) -> Result<(), StrErr> {
 	Ok(())
 }
 
-fn migrate_v2(conn :&mut Connection) -> Result<(), StrErr> {
+fn migrate_v2(conn :&mut Connection) -> Result<(), MimasError> {
 	conn.execute(
 		"CREATE TABLE IF NOT EXISTS player_kvstore (
 			id_src INTEGER,
@@ -71,16 +118,20 @@ fn migrate_v2(conn :&mut Connection) -> Result<(), StrErr> {
 	Ok(())
 }
 
-fn expect_user_ver(conn :&mut Connection) -> Result<(), StrErr> {
+fn expect_user_ver(conn :&mut Connection) -> Result<(), MimasError> {
 	let app_id = get_app_id(conn)?;
 	let user_version = get_user_version(conn)?;
 	if app_id != MEHLON_SQLITE_APP_ID {
-		Err(format!("expected app id {} but was {}",
-			MEHLON_SQLITE_APP_ID, app_id))?;
+		Err(StorageError::AppIdMismatch {
+			found : app_id,
+			expected : MEHLON_SQLITE_APP_ID,
+		})?;
 	}
 	if user_version > USER_VERSION {
-		Err(format!("user_version of database {} newer than maximum supported {}",
-			user_version, USER_VERSION))?;
+		Err(StorageError::VersionMismatch {
+			found : user_version,
+			supported : USER_VERSION,
+		})?;
 	} else if user_version < USER_VERSION {
 		migrate_v2(conn)?;
 		set_user_version(conn, USER_VERSION)?;
@@ -89,7 +140,7 @@ fn expect_user_ver(conn :&mut Connection) -> Result<(), StrErr> {
 }
 
 impl SqliteStorageBackend {
-	pub fn from_conn(mut conn :Connection, freshly_created :bool) -> Result<Self, StrErr> {
+	pub fn from_conn(mut conn :Connection, freshly_created :bool) -> Result<Self, MimasError> {
 		if freshly_created {
 			init_db(&mut conn)?;
 		} else {
@@ -99,13 +150,25 @@ impl SqliteStorageBackend {
 		Ok(Self {
 			conn,
 			ctr : 0,
+			compression : CompressionKind::default(),
 		})
 	}
-	pub fn open_or_create(path :impl AsRef<Path> + Clone) -> Result<Self, StrErr> {
+	pub fn open_or_create(path :impl AsRef<Path> + Clone) -> Result<Self, MimasError> {
 		let (conn, freshly_created) = open_or_create_db(path)?;
 		Ok(Self::from_conn(conn, freshly_created)?)
 	}
-	fn maybe_begin_commit(&mut self) -> Result<(), StrErr> {
+	pub fn set_compression(&mut self, compression :CompressionKind) {
+		self.compression = compression;
+	}
+	/// Sets the size of sqlite's page cache in KiB
+	pub fn set_cache_size_kb(&mut self, kb :u32) -> Result<(), MimasError> {
+		set_cache_size_kb(&mut self.conn, kb)
+	}
+	/// Sets sqlite's synchronous mode, see `SqliteSynchronous`
+	pub fn set_synchronous(&mut self, synchronous :SqliteSynchronous) -> Result<(), MimasError> {
+		set_synchronous(&mut self.conn, synchronous)
+	}
+	fn maybe_begin_commit(&mut self) -> Result<(), MimasError> {
 		if self.ctr == 0 {
 			self.ctr = WRITES_PER_TRANSACTION;
 			if !self.conn.is_autocommit() {
@@ -123,10 +186,90 @@ impl SqliteStorageBackend {
 	}
 }
 
-fn serialize_mapchunk_data(data :&MapChunkData) -> Vec<u8> {
+/// The encoding of the block numbers of a serialized chunk
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum BlockEncoding {
+	/// One byte per block, used up to version 2
+	Raw,
+	/// Runs of identical blocks, used since version 3
+	///
+	/// Since version 4, the version of the chunk follows the metadata.
+	///
+	/// The blocks are stored in their in-memory order, where
+	/// z is the innermost coordinate. Vertical columns of
+	/// identical blocks, like air or stone, become single runs,
+	/// and runs continue into the next column.
+	ZRle,
+}
+
+impl BlockEncoding {
+	fn version(&self) -> u8 {
+		match self {
+			BlockEncoding::Raw => 2,
+			BlockEncoding::ZRle => 4,
+		}
+	}
+	fn for_version(version :u8) -> Self {
+		if version >= 3 {
+			BlockEncoding::ZRle
+		} else {
+			BlockEncoding::Raw
+		}
+	}
+}
+
+fn write_blocks_zrle(data :&MapChunkData, w :&mut Vec<u8>) {
+	let mut blocks = data.0.iter().map(|b| b.id());
+	let mut current = if let Some(id) = blocks.next() {
+		(id, 1u16)
+	} else {
+		return;
+	};
+	for id in blocks {
+		if id == current.0 {
+			current.1 += 1;
+		} else {
+			w.write_u16::<BigEndian>(current.1).unwrap();
+			w.write_u8(current.0).unwrap();
+			current = (id, 1);
+		}
+	}
+	w.write_u16::<BigEndian>(current.1).unwrap();
+	w.write_u8(current.0).unwrap();
+}
+
+fn read_blocks_zrle(rdr :&mut &[u8], r :&mut MapChunkData, m :&NameIdMap) -> Result<(), MimasError> {
+	let len = r.0.len();
+	let mut i = 0;
+	while i < len {
+		let run = rdr.read_u16::<BigEndian>()? as usize;
+		let mb = m.mb_from_id(rdr.read_u8()?)
+			.ok_or_else(|| StorageError::corrupted("invalid block number"))?;
+		if run == 0 || i + run > len {
+			Err(StorageError::Corrupted(format!("Invalid run of length {} at block {}", run, i)))?;
+		}
+		for v in r.0[i .. i + run].iter_mut() {
+			*v = mb;
+		}
+		i += run;
+	}
+	Ok(())
+}
+
+fn serialize_mapchunk_data(data :&MapChunkData, compression :CompressionKind) -> Vec<u8> {
+	serialize_mapchunk_data_enc(data, compression, BlockEncoding::ZRle)
+}
+
+fn serialize_mapchunk_data_enc(data :&MapChunkData, compression :CompressionKind,
+		encoding :BlockEncoding) -> Vec<u8> {
 	let mut blocks = Vec::new();
-	for b in data.0.iter() {
-		blocks.write_u8(b.id()).unwrap();
+	match encoding {
+		BlockEncoding::Raw => {
+			for b in data.0.iter() {
+				blocks.write_u8(b.id()).unwrap();
+			}
+		},
+		BlockEncoding::ZRle => write_blocks_zrle(data, &mut blocks),
 	}
 	// TODO maybe create an error if the number doesn't fit
 	blocks.write_u16::<BigEndian>(data.1.metadata.len() as u16).unwrap();
@@ -144,33 +287,74 @@ fn serialize_mapchunk_data(data :&MapChunkData) -> Vec<u8> {
 				blocks.write_u8(0).unwrap();
 				inv.serialize_to(&mut blocks);
 			},
+			MetadataEntry::Facing(facing) => {
+				// Kind 1 stands for facings
+				blocks.write_u8(1).unwrap();
+				blocks.write_u8(facing.id()).unwrap();
+			},
 		}
 	}
-	let rdr :&[u8] = &blocks;
-	let mut gz_enc = GzBuilder::new().read(rdr, Compression::fast());
+	if encoding.version() >= 4 {
+		blocks.write_u64::<BigEndian>(data.version()).unwrap();
+	}
+	let mut rdr :&[u8] = &blocks;
 	let mut r = Vec::<u8>::new();
 
 	// Version
-	r.write_u8(1).unwrap();
-	io::copy(&mut gz_enc, &mut r).unwrap();
+	r.write_u8(encoding.version()).unwrap();
+	r.write_u8(compression.codec_id()).unwrap();
+	match compression {
+		CompressionKind::Gzip => {
+			let mut gz_enc = GzBuilder::new().read(rdr, Compression::fast());
+			io::copy(&mut gz_enc, &mut r).unwrap();
+		},
+		CompressionKind::Zstd => {
+			zstd::stream::copy_encode(rdr, &mut r, ZSTD_LEVEL).unwrap();
+		},
+		CompressionKind::None => {
+			io::copy(&mut rdr, &mut r).unwrap();
+		},
+	}
 	r
 }
 
-fn deserialize_mapchunk_data(data :&[u8], m :&NameIdMap) -> Result<MapChunkData, StrErr> {
+fn deserialize_mapchunk_data(data :&[u8], m :&NameIdMap) -> Result<MapChunkData, MimasError> {
 	let mut rdr = data;
 	let version = rdr.read_u8()?;
-	if version > 1 {
+	if version > 4 {
 		// The version is too recent
-		Err(format!("Unsupported map chunk version {}", version))?;
+		Err(StorageError::Corrupted(format!("Unsupported map chunk version {}", version)))?;
 	}
-	let mut gz_dec = GzDecoder::new(rdr);
+	// Before version 2, chunks were always gzip compressed
+	let compression = if version >= 2 {
+		CompressionKind::from_codec_id(rdr.read_u8()?)?
+	} else {
+		CompressionKind::Gzip
+	};
 	let mut buffer = Vec::<u8>::new();
-	io::copy(&mut gz_dec, &mut buffer)?;
+	match compression {
+		CompressionKind::Gzip => {
+			let mut gz_dec = GzDecoder::new(rdr);
+			io::copy(&mut gz_dec, &mut buffer)?;
+		},
+		CompressionKind::Zstd => {
+			zstd::stream::copy_decode(rdr, &mut buffer)?;
+		},
+		CompressionKind::None => {
+			io::copy(&mut rdr, &mut buffer)?;
+		},
+	}
 	let mut rdr :&[u8] = &buffer;
 	let mut r = MapChunkData::uninitialized();
-	for v in r.0.iter_mut() {
-		let n = rdr.read_u8()?;
-		*v = m.mb_from_id(n).ok_or("invalid block number")?;
+	match BlockEncoding::for_version(version) {
+		BlockEncoding::Raw => {
+			for v in r.0.iter_mut() {
+				let n = rdr.read_u8()?;
+				*v = m.mb_from_id(n)
+					.ok_or_else(|| StorageError::corrupted("invalid block number"))?;
+			}
+		},
+		BlockEncoding::ZRle => read_blocks_zrle(&mut rdr, &mut r, m)?,
 	}
 	if version > 0 {
 		let count = rdr.read_u16::<BigEndian>()?;
@@ -181,25 +365,198 @@ fn deserialize_mapchunk_data(data :&[u8], m :&NameIdMap) -> Result<MapChunkData,
 			let entries_count = rdr.read_u8()?;
 			if entries_count > 1 {
 				// For now, we only support 1 entry at most
-				Err(format!("Too many metadata entries: {}", entries_count))?;
+				Err(StorageError::Corrupted(format!("Too many metadata entries: {}", entries_count)))?;
 			} else if entries_count == 1 {
 				let kind = rdr.read_u8()?;
 				let entry = match kind {
 					// 0 is for inventories
 					0 => {
-						let inv = SelectableInventory::deserialize_rdr(&mut rdr, m)?;
+						let inv = SelectableInventory::deserialize_rdr(&mut rdr, m)
+							.map_err(StorageError::from)?;
 						MetadataEntry::Inventory(inv)
 					},
-					_ => Err(format!("Unsupported entry kind"))?,
+					// 1 is for facings
+					1 => {
+						let facing = Dir6::from_id(rdr.read_u8()?)
+							.ok_or_else(|| StorageError::corrupted("invalid facing"))?;
+						MetadataEntry::Facing(facing)
+					},
+					_ => Err(StorageError::corrupted("Unsupported entry kind"))?,
 				};
 				r.1.metadata.insert(pos, entry);
 			}
 		}
 	}
+	if version >= 4 {
+		r.2 = rdr.read_u64::<BigEndian>()?;
+	}
 	Ok(r)
 }
 
-fn serialize_name_id_map<T :Id>(m :&NameIdMap<T>) -> Vec<u8> {
+#[cfg(test)]
+#[test]
+fn test_mapchunk_compression_roundtrip() {
+	use crate::inventory::Stack;
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let m = &params.p.name_id_map;
+
+	let mut chunk = MapChunkData::filled_with(roles.air);
+	*chunk.get_blk_mut(Vector3::new(1, 2, 3)) = roles.stone;
+	*chunk.get_blk_mut(Vector3::new(4, 5, 6)) = roles.wood;
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.put(Stack::with(roles.stone, 3));
+	chunk.get_blk_meta_entry(Vector3::new(4, 5, 6))
+		.or_insert(MetadataEntry::Inventory(inv.clone()));
+	chunk.get_blk_meta_entry(Vector3::new(7, 8, 9))
+		.or_insert(MetadataEntry::Facing(Dir6::YNeg));
+
+	for &compression in &[CompressionKind::Gzip, CompressionKind::None, CompressionKind::Zstd] {
+		let ser = serialize_mapchunk_data(&chunk, compression);
+		assert_eq!(ser[0], 4);
+		assert_eq!(CompressionKind::from_codec_id(ser[1]).unwrap(), compression);
+		let deser = deserialize_mapchunk_data(&ser, m).unwrap();
+		assert_eq!(deser.0[..], chunk.0[..]);
+		assert_eq!(deser.1.metadata.len(), 2);
+		match deser.get_blk_meta(Vector3::new(4, 5, 6)) {
+			Some(MetadataEntry::Inventory(i)) => assert_eq!(i, &inv),
+			_ => panic!("metadata missing"),
+		}
+		assert_eq!(deser.get_blk_facing(Vector3::new(7, 8, 9)), Dir6::YNeg);
+	}
+	let gzip = serialize_mapchunk_data(&chunk, CompressionKind::Gzip);
+	let none = serialize_mapchunk_data(&chunk, CompressionKind::None);
+	let zstd = serialize_mapchunk_data(&chunk, CompressionKind::Zstd);
+	assert!(gzip.len() < none.len());
+	assert!(zstd.len() < none.len());
+
+	// Chunks from before the codec id was added are gzip compressed
+	let raw_gzip = serialize_mapchunk_data_enc(&chunk, CompressionKind::Gzip, BlockEncoding::Raw);
+	assert_eq!(raw_gzip[0], 2);
+	let mut legacy = vec![1];
+	legacy.extend_from_slice(&raw_gzip[2..]);
+	let deser = deserialize_mapchunk_data(&legacy, m).unwrap();
+	assert_eq!(deser.0[..], chunk.0[..]);
+
+	// Unknown codecs are rejected
+	let mut unknown = none.clone();
+	unknown[1] = 200;
+	assert!(deserialize_mapchunk_data(&unknown, m).is_err());
+
+	// The codec is chosen in the settings
+	let config :Config = toml::from_str(r#"chunk_compression = "Zstd""#).unwrap();
+	assert_eq!(config.chunk_compression, CompressionKind::Zstd);
+	assert_eq!(Config::default().chunk_compression, CompressionKind::Gzip);
+}
+
+#[cfg(test)]
+#[test]
+fn test_mapchunk_zrle() {
+	use crate::map::Map;
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let m = &params.p.name_id_map;
+
+	// Chunks in version 2 are still readable
+	let mut chunk = MapChunkData::filled_with(roles.stone);
+	*chunk.get_blk_mut(Vector3::new(0, 0, CHUNKSIZE - 1)) = roles.air;
+	let raw = serialize_mapchunk_data_enc(&chunk, CompressionKind::None, BlockEncoding::Raw);
+	let rle = serialize_mapchunk_data(&chunk, CompressionKind::None);
+	assert_eq!(deserialize_mapchunk_data(&raw, m).unwrap().0[..], chunk.0[..]);
+	assert_eq!(deserialize_mapchunk_data(&rle, m).unwrap().0[..], chunk.0[..]);
+	// Three runs with three bytes each, the metadata count and the chunk version
+	assert_eq!(rle.len(), 2 + 3 * 3 + 2 + 8);
+
+	// Runs exceeding the chunk are rejected
+	let mut overlong = rle.clone();
+	overlong[2 + 3 * 2] = 0xff;
+	assert!(deserialize_mapchunk_data(&overlong, m).is_err());
+
+	// Generated terrain round trips and is smaller than before
+	let mut map = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	let pos_min = Vector3::new(0, 0, -2 * CHUNKSIZE);
+	let pos_max = Vector3::new(2 * CHUNKSIZE, 2 * CHUNKSIZE, 2 * CHUNKSIZE);
+	map.gen_chunks_in_area(pos_min, pos_max);
+	map.tick();
+	let mut sizes = [0; 6];
+	let mut chunk_count = 0;
+	for x in 0 .. 2 {
+		for y in 0 .. 2 {
+			for z in -2 .. 2 {
+				let pos = Vector3::new(x, y, z) * CHUNKSIZE;
+				let chunk = if let Some(chunk) = map.get_chunk(pos) {
+					chunk
+				} else {
+					continue;
+				};
+				chunk_count += 1;
+				let mut i = 0;
+				for &compression in &[CompressionKind::None, CompressionKind::Gzip, CompressionKind::Zstd] {
+					for &encoding in &[BlockEncoding::Raw, BlockEncoding::ZRle] {
+						let ser = serialize_mapchunk_data_enc(chunk, compression, encoding);
+						let deser = deserialize_mapchunk_data(&ser, m).unwrap();
+						assert_eq!(deser.0[..], chunk.0[..]);
+						sizes[i] += ser.len();
+						i += 1;
+					}
+				}
+			}
+		}
+	}
+	assert!(chunk_count > 0);
+	let [raw_none, rle_none, raw_gzip, rle_gzip, raw_zstd, rle_zstd] = sizes;
+	assert!(rle_none < raw_none);
+	assert!(rle_gzip < raw_gzip);
+	assert!(raw_gzip < raw_none && raw_zstd < raw_none);
+	assert!(rle_zstd < rle_none);
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_version_persistence() {
+	use crate::map::{Map, ClientBackend};
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let m = &params.p.name_id_map;
+
+	let mut map = Map::from_backend(ClientBackend);
+	let chunk_pos = Vector3::new(0, 0, CHUNKSIZE);
+	map.set_chunk(chunk_pos, MapChunkData::filled_with(roles.air));
+	assert_eq!(map.chunk_version(chunk_pos), Some(0));
+	assert_eq!(map.chunk_version(Vector3::new(0, 0, 0)), None);
+
+	// Block and metadata edits bump the version
+	let pos = chunk_pos + Vector3::new(1, 2, 3);
+	map.get_blk_mut(pos).unwrap().set(roles.stone);
+	assert_eq!(map.chunk_version(chunk_pos), Some(1));
+	map.get_blk_mut(pos).unwrap().set(roles.wood);
+	map.get_blk_meta_mut(pos).unwrap().set(MetadataEntry::Facing(Dir6::XPos));
+	assert_eq!(map.chunk_version(chunk_pos), Some(3));
+
+	// The version survives storage
+	let conn = Connection::open_in_memory().unwrap();
+	let mut backend = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	backend.store_chunk(chunk_pos, map.get_chunk(chunk_pos).unwrap()).unwrap();
+	backend.tick().unwrap();
+	let loaded = backend.load_chunk(chunk_pos, m).unwrap().unwrap();
+	assert_eq!(loaded.version(), 3);
+	assert_eq!(*loaded.get_blk(Vector3::new(1, 2, 3)), roles.wood);
+
+	// Edits of the reloaded chunk keep counting up
+	let mut map = Map::from_backend(ClientBackend);
+	map.set_chunk(chunk_pos, loaded);
+	map.get_blk_mut(pos).unwrap().set(roles.air);
+	assert_eq!(map.chunk_version(chunk_pos), Some(4));
+
+	// Chunks from before the version was stored start at zero
+	let chunk = map.get_chunk(chunk_pos).unwrap();
+	let ser = serialize_mapchunk_data_enc(chunk, CompressionKind::None, BlockEncoding::Raw);
+	assert_eq!(deserialize_mapchunk_data(&ser, m).unwrap().version(), 0);
+}
+
+pub(crate) fn serialize_name_id_map<T :Id>(m :&NameIdMap<T>) -> Vec<u8> {
 	use std::io::Write;
 	let names = m.names();
 	let mut r = Vec::new();
@@ -215,32 +572,33 @@ fn serialize_name_id_map<T :Id>(m :&NameIdMap<T>) -> Vec<u8> {
 	r
 }
 
-fn deserialize_name_id_map<T :Id>(data :&[u8]) -> Result<NameIdMap<T>, StrErr> {
+pub(crate) fn deserialize_name_id_map<T :Id>(data :&[u8]) -> Result<NameIdMap<T>, MimasError> {
 	use std::io::Read;
 	let mut rdr = data;
 	let version = rdr.read_u8()?;
 	if version != 0 {
 		// The version is too recent
-		Err(format!("Unsupported name id map version {}", version))?;
+		Err(StorageError::Corrupted(format!("Unsupported name id map version {}", version)))?;
 	}
 	let count = rdr.read_u16::<BigEndian>()?;
 	if count >= u8::max_value() as u16 {
 		// We use u8 as storage for now so we don't support
 		// any counts above 255. 255 is reserved.
-		Err(format!("Too many id's stored in name id map {}", count))?;
+		Err(StorageError::Corrupted(format!("Too many id's stored in name id map {}", count)))?;
 	}
 	let mut res = Vec::with_capacity(count as usize);
 	for _ in 0 .. count {
 		let len = rdr.read_u8()? as usize;
 		let mut s = vec![0; len];
 		rdr.read_exact(&mut s)?;
-		let name = String::from_utf8(s)?;
+		let name = String::from_utf8(s)
+			.map_err(|e| StorageError::corrupted(e.to_string()))?;
 		// For backwards compatibility with a few
 		// (unreleased) git versions that used ::
 		// instead of :
 		let name = name.replace("::", ":");
 		// To ensure the block is correctly named
-		let _components = parse_block_name(&name)?;
+		let _components = parse_block_name(&name).map_err(StorageError::from)?;
 		res.push(name);
 	}
 	Ok(NameIdMap::from_name_list(res))
@@ -257,16 +615,16 @@ fn ensure_name_id_roundtrip() {
 
 impl StorageBackend for SqliteStorageBackend {
 	fn store_chunk(&mut self, pos :Vector3<isize>,
-			data :&MapChunkData) -> Result<(), StrErr> {
+			data :&MapChunkData) -> Result<(), MimasError> {
 		let pos = pos / CHUNKSIZE;
-		let data = serialize_mapchunk_data(&data);
+		let data = serialize_mapchunk_data(data, self.compression);
 		self.maybe_begin_commit()?;
 		let mut stmt = self.conn.prepare_cached("INSERT OR REPLACE INTO chunks (x, y, z, content) \
 			VALUES (?, ?, ?, ?);")?;
 		stmt.execute(&[&pos.x as &dyn ToSql, &pos.y, &pos.z, &data])?;
 		Ok(())
 	}
-	fn tick(&mut self) -> Result<(), StrErr> {
+	fn tick(&mut self) -> Result<(), MimasError> {
 		if !self.conn.is_autocommit() {
 			self.ctr = WRITES_PER_TRANSACTION;
 			let mut stmt = self.conn.prepare_cached("COMMIT;")?;
@@ -274,7 +632,7 @@ impl StorageBackend for SqliteStorageBackend {
 		}
 		Ok(())
 	}
-	fn load_chunk(&mut self, pos :Vector3<isize>, m :&NameIdMap) -> Result<Option<MapChunkData>, StrErr> {
+	fn load_chunk(&mut self, pos :Vector3<isize>, m :&NameIdMap) -> Result<Option<MapChunkData>, MimasError> {
 		let pos = pos / CHUNKSIZE;
 		let mut stmt = self.conn.prepare_cached("SELECT content FROM chunks WHERE x=? AND y=? AND z=?")?;
 		let data :Option<Vec<u8>> = stmt.query_row(
@@ -288,31 +646,41 @@ impl StorageBackend for SqliteStorageBackend {
 			Ok(None)
 		}
 	}
-	fn get_global_kv(&mut self, key :&str) -> Result<Option<Vec<u8>>, StrErr> {
+	fn get_global_kv(&mut self, key :&str) -> Result<Option<Vec<u8>>, MimasError> {
 		let mut stmt = self.conn.prepare_cached("SELECT content FROM kvstore WHERE kkey=?")?;
 		let data :Option<Value> = stmt.query_row(
 			&[&key],
 			|row| row.get(0)
 		).optional()?;
-		Ok(value_to_vec(data)?)
+		value_to_vec(data)?
+			.map(|v| decode_kv_value(&v)
+				.map_err(|e| StorageError::Corrupted(
+					format!("Invalid value for key '{}': {}", key, e)).into()))
+			.transpose()
 	}
-	fn set_global_kv(&mut self, key :&str, content :&[u8]) -> Result<(), StrErr> {
+	fn set_global_kv(&mut self, key :&str, content :&[u8]) -> Result<(), MimasError> {
 		self.maybe_begin_commit()?;
+		let content = encode_kv_value(content, self.compression);
 		let mut stmt = self.conn.prepare_cached("INSERT OR REPLACE INTO kvstore (kkey, content) \
 			VALUES (?, ?);")?;
 		stmt.execute(&[&key as &dyn ToSql, &content])?;
 		Ok(())
 	}
-	fn get_player_kv(&mut self, id_pair :PlayerIdPair, key :&str) -> Result<Option<Vec<u8>>, StrErr> {
+	fn get_player_kv(&mut self, id_pair :PlayerIdPair, key :&str) -> Result<Option<Vec<u8>>, MimasError> {
 		let mut stmt = self.conn.prepare_cached("SELECT content FROM player_kvstore WHERE id_src=? AND id=? AND kkey=?")?;
 		let data :Option<Value> = stmt.query_row(
 			&[&(id_pair.id_src()) as &dyn ToSql, &(id_pair.id_i64()), &key],
 			|row| row.get(0)
 		).optional()?;
-		Ok(value_to_vec(data)?)
+		value_to_vec(data)?
+			.map(|v| decode_kv_value(&v)
+				.map_err(|e| StorageError::Corrupted(
+					format!("Invalid value for player key '{}': {}", key, e)).into()))
+			.transpose()
 	}
-	fn set_player_kv(&mut self, id_pair :PlayerIdPair, key :&str, content :&[u8]) -> Result<(), StrErr> {
+	fn set_player_kv(&mut self, id_pair :PlayerIdPair, key :&str, content :&[u8]) -> Result<(), MimasError> {
 		self.maybe_begin_commit()?;
+		let content = encode_kv_value(content, self.compression);
 		let mut stmt = self.conn.prepare_cached("INSERT OR REPLACE INTO player_kvstore (id_src, id, kkey, content) \
 			VALUES (?, ?, ?, ?);")?;
 		stmt.execute(&[&(id_pair.id_src()) as &dyn ToSql,
@@ -330,43 +698,165 @@ impl StorageBackend for SqliteStorageBackend {
 // we'll have to support reading in the Text format,
 // while also supporting the Blob format for possibly binary data.
 // [1]: https://www.sqlite.org/datatype3.html
-fn value_to_vec(v :Option<Value>) -> Result<Option<Vec<u8>>, StrErr> {
+fn value_to_vec(v :Option<Value>) -> Result<Option<Vec<u8>>, MimasError> {
 	Ok(match v {
 		Some(Value::Text(s)) => Some(s.into_bytes()),
 		Some(Value::Blob(b)) => Some(b),
-		Some(_) => return Err("SQL column entry type mismatch: Blob or String required.".into()),
+		Some(_) => Err(StorageError::corrupted("SQL column entry type mismatch: Blob or String required."))?,
 		None => None,
 	})
 }
 
+/// Header byte of kv values stored uncompressed with a checksum
+const KV_HEADER_PLAIN :u8 = 0xfe;
+/// Header byte of kv values stored gzip compressed with a checksum
+const KV_HEADER_GZIP :u8 = 0xff;
+
+/// Values at least this large get compressed
+const KV_COMPRESSION_THRESHOLD :usize = 256;
+
+/// Encodes a kv value for storage
+///
+/// The value is prefixed by a header byte flagging the compression
+/// and followed by the big endian CRC32 of the header and the payload.
+/// The header bytes never start valid UTF-8, so values without them,
+/// written by older versions or edited by hand, can still be read.
+/// Compressed values always use gzip.
+fn encode_kv_value(content :&[u8], compression :CompressionKind) -> Vec<u8> {
+	let mut r = Vec::with_capacity(content.len() + 5);
+	if compression != CompressionKind::None && content.len() >= KV_COMPRESSION_THRESHOLD {
+		r.write_u8(KV_HEADER_GZIP).unwrap();
+		let mut gz_enc = GzBuilder::new().read(content, Compression::fast());
+		io::copy(&mut gz_enc, &mut r).unwrap();
+	} else {
+		r.write_u8(KV_HEADER_PLAIN).unwrap();
+		r.extend_from_slice(content);
+	}
+	let checksum = crc32(&r);
+	r.write_u32::<BigEndian>(checksum).unwrap();
+	r
+}
+
+fn crc32(data :&[u8]) -> u32 {
+	let mut hasher = crc32fast::Hasher::new();
+	hasher.update(data);
+	hasher.finalize()
+}
+
+fn decode_kv_value(data :&[u8]) -> Result<Vec<u8>, MimasError> {
+	let header = match data.first() {
+		Some(&h) if h == KV_HEADER_PLAIN || h == KV_HEADER_GZIP => h,
+		// Legacy value without header and checksum
+		_ => return Ok(data.to_vec()),
+	};
+	if data.len() < 5 {
+		Err(StorageError::corrupted("kv value too short for its checksum"))?;
+	}
+	let (checked, mut checksum) = data.split_at(data.len() - 4);
+	let checksum = checksum.read_u32::<BigEndian>()?;
+	if crc32(checked) != checksum {
+		Err(StorageError::corrupted("kv value checksum mismatch, the value is corrupted"))?;
+	}
+	let payload = &checked[1..];
+	if header == KV_HEADER_GZIP {
+		let mut buffer = Vec::new();
+		io::copy(&mut GzDecoder::new(payload), &mut buffer)?;
+		Ok(buffer)
+	} else {
+		Ok(payload.to_vec())
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_kv_value_encoding() {
+	let small = b"time = 8.0\n".to_vec();
+	let large = "alice\n".repeat(200).into_bytes();
+	for &compression in &[CompressionKind::Gzip, CompressionKind::None] {
+		for content in &[&small, &large, &Vec::new()] {
+			let encoded = encode_kv_value(content, compression);
+			assert_eq!(&decode_kv_value(&encoded).unwrap(), *content);
+		}
+	}
+	// Only large values get compressed
+	assert_eq!(encode_kv_value(&small, CompressionKind::Gzip)[0], KV_HEADER_PLAIN);
+	let compressed = encode_kv_value(&large, CompressionKind::Gzip);
+	assert_eq!(compressed[0], KV_HEADER_GZIP);
+	assert!(compressed.len() < large.len());
+
+	// Legacy values are read as they are
+	assert_eq!(decode_kv_value(&small).unwrap(), small);
+	assert_eq!(decode_kv_value(&[1, 0, 1]).unwrap(), vec![1, 0, 1]);
+
+	// Going through the sqlite backend
+	let conn = Connection::open_in_memory().unwrap();
+	let mut backend = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	backend.set_global_kv("whitelist", &large).unwrap();
+	assert_eq!(backend.get_global_kv("whitelist").unwrap(), Some(large.clone()));
+	let id = PlayerIdPair::singleplayer();
+	backend.set_player_kv(id, "inventory", &small).unwrap();
+	assert_eq!(backend.get_player_kv(id, "inventory").unwrap(), Some(small.clone()));
+}
+
+#[cfg(test)]
+#[test]
+fn test_kv_value_corruption() {
+	let large = "alice\n".repeat(200).into_bytes();
+	for &compression in &[CompressionKind::Gzip, CompressionKind::None] {
+		let encoded = encode_kv_value(&large, compression);
+		for idx in &[1, encoded.len() / 2, encoded.len() - 1] {
+			let mut corrupted = encoded.clone();
+			corrupted[*idx] ^= 0x10;
+			let err = decode_kv_value(&corrupted).unwrap_err();
+			assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+		}
+		// Truncated values are detected as well
+		assert!(decode_kv_value(&encoded[.. encoded.len() - 1]).is_err());
+	}
+	assert!(decode_kv_value(&[KV_HEADER_PLAIN, 0]).is_err());
+
+	// The backend reports the key of the corrupted value
+	let conn = Connection::open_in_memory().unwrap();
+	let mut backend = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	backend.set_global_kv("mapgen_meta", &large).unwrap();
+	let mut stored :Vec<u8> = backend.conn.query_row(
+		"SELECT content FROM kvstore WHERE kkey='mapgen_meta'",
+		NO_PARAMS, |row| row.get(0)).unwrap();
+	stored[20] ^= 0x10;
+	backend.conn.execute("UPDATE kvstore SET content=? WHERE kkey='mapgen_meta'",
+		&[&stored]).unwrap();
+	let err = backend.get_global_kv("mapgen_meta").unwrap_err();
+	assert!(err.to_string().contains("'mapgen_meta'"), "{}", err);
+}
+
 pub struct NullStorageBackend;
 
 impl StorageBackend for NullStorageBackend {
 	fn store_chunk(&mut self, _pos :Vector3<isize>,
-			_data :&MapChunkData) -> Result<(), StrErr> {
+			_data :&MapChunkData) -> Result<(), MimasError> {
 		Ok(())
 	}
-	fn tick(&mut self) -> Result<(), StrErr> {
+	fn tick(&mut self) -> Result<(), MimasError> {
 		Ok(())
 	}
-	fn load_chunk(&mut self, _pos :Vector3<isize>, _m :&NameIdMap) -> Result<Option<MapChunkData>, StrErr> {
+	fn load_chunk(&mut self, _pos :Vector3<isize>, _m :&NameIdMap) -> Result<Option<MapChunkData>, MimasError> {
 		Ok(None)
 	}
-	fn get_global_kv(&mut self, _key :&str) -> Result<Option<Vec<u8>>, StrErr> {
+	fn get_global_kv(&mut self, _key :&str) -> Result<Option<Vec<u8>>, MimasError> {
 		Ok(None)
 	}
-	fn set_global_kv(&mut self, _key :&str, _content :&[u8]) -> Result<(), StrErr> {
+	fn set_global_kv(&mut self, _key :&str, _content :&[u8]) -> Result<(), MimasError> {
 		Ok(())
 	}
-	fn get_player_kv(&mut self, _id_pair :PlayerIdPair, _key :&str) -> Result<Option<Vec<u8>>, StrErr> {
+	fn get_player_kv(&mut self, _id_pair :PlayerIdPair, _key :&str) -> Result<Option<Vec<u8>>, MimasError> {
 		Ok(None)
 	}
-	fn set_player_kv(&mut self, _id_pair :PlayerIdPair, _key :&str, _content :&[u8]) -> Result<(), StrErr> {
+	fn set_player_kv(&mut self, _id_pair :PlayerIdPair, _key :&str, _content :&[u8]) -> Result<(), MimasError> {
 		Ok(())
 	}
 }
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Serialize, Deserialize)]
 pub struct PlayerIdPair(NonZeroU64);
 
 impl PlayerIdPair {
@@ -414,7 +904,7 @@ fn test_player_id_pair() {
 
 // This function is not generic on the backend because of a limitation of the language:
 // Box<dyn Trait> does not impl Trait.
-pub(crate) fn load_name_id_map(backend :&mut DynStorageBackend) -> Result<NameIdMap, StrErr> {
+pub(crate) fn load_name_id_map(backend :&mut DynStorageBackend) -> Result<NameIdMap, MimasError> {
 	let buf = if let Some(v) = backend.get_global_kv("name_id_map")? {
 		v
 	} else {
@@ -426,36 +916,225 @@ pub(crate) fn load_name_id_map(backend :&mut DynStorageBackend) -> Result<NameId
 
 // This function is not generic on the backend because of a limitation of the language:
 // Box<dyn Trait> does not impl Trait.
-pub(crate) fn save_name_id_map(backend :&mut DynStorageBackend, nm :&NameIdMap) -> Result<(), StrErr> {
+pub(crate) fn save_name_id_map(backend :&mut DynStorageBackend, nm :&NameIdMap) -> Result<(), MimasError> {
 	let buf = serialize_name_id_map(nm);
 	backend.set_global_kv("name_id_map", &buf)?;
 	Ok(())
 }
 
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub(crate) fn load_world_clock(backend :&mut DynStorageBackend) -> Result<WorldClock, MimasError> {
+	if let Some(buf) = backend.get_global_kv("world_clock")? {
+		Ok(WorldClock::deserialize(&buf).map_err(StorageError::from)?)
+	} else {
+		Ok(WorldClock::default())
+	}
+}
+
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub(crate) fn load_whitelist(backend :&mut DynStorageBackend) -> Result<Whitelist, MimasError> {
+	if let Some(buf) = backend.get_global_kv("whitelist")? {
+		Ok(Whitelist::deserialize(&buf).map_err(StorageError::from)?)
+	} else {
+		Ok(Whitelist::new())
+	}
+}
+
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub(crate) fn load_homes(backend :&mut DynStorageBackend) -> Result<Homes, MimasError> {
+	if let Some(buf) = backend.get_global_kv("homes")? {
+		Ok(Homes::deserialize(&buf).map_err(StorageError::from)?)
+	} else {
+		Ok(Homes::new())
+	}
+}
+
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub(crate) fn load_playtime(backend :&mut DynStorageBackend) -> Result<Playtime, MimasError> {
+	if let Some(buf) = backend.get_global_kv("playtime")? {
+		Ok(Playtime::deserialize(&buf).map_err(StorageError::from)?)
+	} else {
+		Ok(Playtime::new())
+	}
+}
+
+// This function is not generic on the backend because of a limitation of the language:
+// Box<dyn Trait> does not impl Trait.
+pub(crate) fn load_inventory_presets(backend :&mut DynStorageBackend,
+		m :&NameIdMap) -> Result<InventoryPresets, MimasError> {
+	if let Some(buf) = backend.get_global_kv("inventory_presets")? {
+		Ok(InventoryPresets::deserialize(&buf, m).map_err(StorageError::from)?)
+	} else {
+		Ok(InventoryPresets::new())
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_world_clock_persistence() {
+	use crate::clock::TimeCommand;
+
+	let conn = Connection::open_in_memory().unwrap();
+	let mut backend :DynStorageBackend = Box::new(SqliteStorageBackend::from_conn(conn, true).unwrap());
+	assert_eq!(load_world_clock(&mut backend).unwrap(), WorldClock::default());
+
+	let mut clock = WorldClock::default();
+	if let Ok(TimeCommand::Set(time)) = TimeCommand::parse(&["set", "night"]) {
+		clock.set_time(time).unwrap();
+	} else {
+		panic!("couldn't parse time command");
+	}
+	backend.set_global_kv("world_clock", &clock.serialize().unwrap()).unwrap();
+	let loaded = load_world_clock(&mut backend).unwrap();
+	assert_eq!(loaded, clock);
+	assert_ne!(loaded, WorldClock::default());
+}
+
+/// A directory for the databases of a test, removed with everything in it on drop
+///
+/// This also cleans up the `-wal` and `-shm` files next to the databases.
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+	pub fn new(name :&str) -> Self {
+		let path = std::env::temp_dir()
+			.join(format!("mimas-test-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&path);
+		std::fs::create_dir_all(&path).unwrap();
+		TestDir(path)
+	}
+	pub fn join(&self, file_name :&str) -> std::path::PathBuf {
+		self.0.join(file_name)
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_homes_persistence() {
+	let dir = TestDir::new("homes");
+	let path = dir.join("map.sqlite");
+	let pos = PlayerPosition::from_pos_pitch_yaw(Vector3::new(1.0, 2.0, 3.0), 10.0, 20.0);
+	{
+		let mut backend :DynStorageBackend = Box::new(SqliteStorageBackend::open_or_create(&path).unwrap());
+		let mut homes = load_homes(&mut backend).unwrap();
+		assert!(homes.names("alice").is_empty());
+		homes.set("alice", "tower", pos, 1).unwrap();
+		backend.set_global_kv("homes", &homes.serialize().unwrap()).unwrap();
+		backend.tick().unwrap();
+	}
+	let mut backend :DynStorageBackend = Box::new(SqliteStorageBackend::open_or_create(&path).unwrap());
+	let homes = load_homes(&mut backend).unwrap();
+	let loaded = homes.get("alice", "tower").unwrap();
+	assert_eq!(loaded.pos(), pos.pos());
+	assert_eq!((loaded.pitch(), loaded.yaw()), (10.0, 20.0));
+}
+
+#[cfg(test)]
+#[test]
+fn test_playtime_persistence() {
+	let dir = TestDir::new("playtime");
+	let path = dir.join("map.sqlite");
+	{
+		let mut backend :DynStorageBackend = Box::new(SqliteStorageBackend::open_or_create(&path).unwrap());
+		let mut playtime = load_playtime(&mut backend).unwrap();
+		assert_eq!(playtime, Playtime::new());
+		playtime.tick(vec!["alice"]);
+		playtime.tick(None);
+		backend.set_global_kv("playtime", &playtime.serialize().unwrap()).unwrap();
+		backend.tick().unwrap();
+	}
+	let mut backend :DynStorageBackend = Box::new(SqliteStorageBackend::open_or_create(&path).unwrap());
+	let playtime = load_playtime(&mut backend).unwrap();
+	assert_eq!(playtime.world_age(), 2);
+	assert_eq!(playtime.player("alice"), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_version_mismatch_error() {
+	let conn = Connection::open_in_memory().unwrap();
+	let SqliteStorageBackend { mut conn, .. } = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	set_user_version(&mut conn, USER_VERSION + 1).unwrap();
+	match SqliteStorageBackend::from_conn(conn, false) {
+		Err(MimasError::Storage(StorageError::VersionMismatch { found, supported })) => {
+			assert_eq!(found, USER_VERSION + 1);
+			assert_eq!(supported, USER_VERSION);
+		},
+		Err(e) => panic!("unexpected error: {:?}", e),
+		Ok(_) => panic!("newer database was accepted"),
+	}
+
+	// Databases of other applications are rejected as well
+	let mut conn = Connection::open_in_memory().unwrap();
+	set_app_id(&mut conn, 42).unwrap();
+	match SqliteStorageBackend::from_conn(conn, false) {
+		Err(MimasError::Storage(StorageError::AppIdMismatch { found : 42, .. })) => (),
+		Err(e) => panic!("unexpected error: {:?}", e),
+		Ok(_) => panic!("foreign database was accepted"),
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_sqlite_tuning() {
+	use crate::sqlite_generic::{get_cache_size_kb, get_synchronous};
+
+	let conn = Connection::open_in_memory().unwrap();
+	let mut backend = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	assert_eq!(get_synchronous(&mut backend.conn).unwrap(), SqliteSynchronous::Full);
+
+	let config = Config::default();
+	backend.set_cache_size_kb(config.sqlite_cache_kb).unwrap();
+	assert_eq!(get_cache_size_kb(&mut backend.conn).unwrap(), 2000);
+
+	backend.set_cache_size_kb(65536).unwrap();
+	backend.set_synchronous(SqliteSynchronous::Normal).unwrap();
+	assert_eq!(get_cache_size_kb(&mut backend.conn).unwrap(), 65536);
+	assert_eq!(get_synchronous(&mut backend.conn).unwrap(), SqliteSynchronous::Normal);
+	backend.set_synchronous(SqliteSynchronous::Off).unwrap();
+	assert_eq!(get_synchronous(&mut backend.conn).unwrap(), SqliteSynchronous::Off);
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MapgenMetaToml {
 	seed :u64,
 	mapgen_name :String,
 }
 
-fn load_mapgen_meta_toml<B :StorageBackend>(backend :&mut B) -> Result<Option<MapgenMetaToml>, StrErr> {
+fn load_mapgen_meta_toml<B :StorageBackend>(backend :&mut B) -> Result<Option<MapgenMetaToml>, MimasError> {
 	let mapgen_meta_arr = if let Some(v) = backend.get_global_kv("mapgen_meta")? {
 		v
 	} else {
 		return Ok(None);
 	};
-	let mapgen_meta_str = str::from_utf8(&mapgen_meta_arr)?;
-	let mapgen_meta = from_str(mapgen_meta_str)?;
+	let mapgen_meta_str = str::from_utf8(&mapgen_meta_arr)
+		.map_err(|e| StorageError::corrupted(e.to_string()))?;
+	let mapgen_meta = from_str(mapgen_meta_str)
+		.map_err(|e| StorageError::corrupted(e.to_string()))?;
 	Ok(Some(mapgen_meta))
 }
 
-fn save_mapgen_meta_toml<B :StorageBackend>(backend :&mut B, m :&MapgenMetaToml) -> Result<(), StrErr> {
-	let mapgen_meta_str = to_string(m)?;
+fn save_mapgen_meta_toml<B :StorageBackend>(backend :&mut B, m :&MapgenMetaToml) -> Result<(), MimasError> {
+	let mapgen_meta_str = to_string(m)
+		.map_err(|e| StorageError::corrupted(e.to_string()))?;
 	backend.set_global_kv("mapgen_meta", mapgen_meta_str.as_bytes())?;
 	Ok(())
 }
 
-fn manage_mapgen_meta_toml<B :StorageBackend>(backend :&mut B, config :&mut Config) -> Result<(), StrErr> {
+fn manage_mapgen_meta_toml<B :StorageBackend>(backend :&mut B, config :&mut Config) -> Result<(), MimasError> {
 	if let Some(mapgen_meta) = load_mapgen_meta_toml(backend)? {
 		// If a seed already exists, use it
 		config.mapgen_seed = mapgen_meta.seed;
@@ -470,6 +1149,62 @@ fn manage_mapgen_meta_toml<B :StorageBackend>(backend :&mut B, config :&mut Conf
 	Ok(())
 }
 
+fn manage_difficulty<B :StorageBackend>(backend :&mut B, config :&mut Config) -> Result<(), MimasError> {
+	if let Some(buf) = backend.get_global_kv("difficulty")? {
+		// The difficulty stored with the world takes precedence,
+		// as it might have been changed at runtime
+		config.difficulty = Difficulty::deserialize(&buf).map_err(StorageError::from)?;
+	} else {
+		backend.set_global_kv("difficulty", &config.difficulty.serialize())?;
+	}
+	Ok(())
+}
+
+fn manage_spawn_protection<B :StorageBackend>(backend :&mut B, config :&mut Config) -> Result<(), MimasError> {
+	// Only changes at runtime are stored, and they take precedence
+	if let Some(buf) = backend.get_global_kv("spawn_protection")? {
+		config.spawn_protection_radius = str::from_utf8(&buf).ok()
+			.and_then(|s| s.trim().parse().ok())
+			.ok_or_else(|| StorageError::corrupted("Invalid spawn protection radius"))?;
+	}
+	Ok(())
+}
+
+fn manage_keep_inventory<B :StorageBackend>(backend :&mut B, config :&mut Config) -> Result<(), MimasError> {
+	// Only changes at runtime are stored, and they take precedence
+	if let Some(buf) = backend.get_global_kv("keep_inventory")? {
+		config.keep_inventory = str::from_utf8(&buf).ok()
+			.and_then(|s| s.trim().parse().ok())
+			.ok_or_else(|| StorageError::corrupted("Invalid keep_inventory value"))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_spawn_protection_persistence() {
+	let dir = TestDir::new("spawn-protection");
+	let path = dir.join("map.sqlite");
+	let mut config = Config::default();
+	config.spawn_protection_radius = 8;
+	{
+		let mut backend = SqliteStorageBackend::open_or_create(&path).unwrap();
+		// Without a change at runtime, the config is used
+		manage_spawn_protection(&mut backend, &mut config).unwrap();
+		manage_keep_inventory(&mut backend, &mut config).unwrap();
+		assert_eq!(config.spawn_protection_radius, 8);
+		assert!(!config.keep_inventory);
+		backend.set_global_kv("spawn_protection", b"20").unwrap();
+		backend.set_global_kv("keep_inventory", b"true").unwrap();
+		backend.tick().unwrap();
+	}
+	let mut backend = SqliteStorageBackend::open_or_create(&path).unwrap();
+	manage_spawn_protection(&mut backend, &mut config).unwrap();
+	manage_keep_inventory(&mut backend, &mut config).unwrap();
+	assert_eq!(config.spawn_protection_radius, 20);
+	assert!(config.keep_inventory);
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct PlayerPosition {
 	x :f32,
@@ -513,9 +1248,11 @@ impl PlayerPosition {
 	pub fn yaw(&self) -> f32 {
 		self.yaw
 	}
-	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
-		let serialized_str = str::from_utf8(buf)?;
-		let deserialized = from_str(serialized_str)?;
+	pub fn deserialize(buf :&[u8]) -> Result<Self, MimasError> {
+		let serialized_str = str::from_utf8(buf)
+			.map_err(|e| StorageError::corrupted(e.to_string()))?;
+		let deserialized = from_str(serialized_str)
+			.map_err(|e| StorageError::corrupted(e.to_string()))?;
 		Ok(deserialized)
 	}
 }
@@ -533,11 +1270,19 @@ fn sqlite_backend_from_config(config :&mut Config, auth_needed :bool)
 
 	let sqlite_backend = match SqliteStorageBackend::open_or_create(&p) {
 		Ok(mut b) => {
+			// The pragmas can't be changed once the writes below
+			// have opened a transaction
+			b.set_cache_size_kb(config.sqlite_cache_kb).unwrap();
+			b.set_synchronous(config.sqlite_synchronous).unwrap();
 			manage_mapgen_meta_toml(&mut b, config).unwrap();
+			manage_difficulty(&mut b, config).unwrap();
+			manage_spawn_protection(&mut b, config).unwrap();
+			manage_keep_inventory(&mut b, config).unwrap();
+			b.set_compression(config.chunk_compression);
 			b
 		},
 		Err(e) => {
-			println!("Error while opening database: {:?}", e);
+			error!(target : TARGET_STORAGE, "Error while opening database: {:?}", e);
 			return None;
 		},
 	};
@@ -567,11 +1312,11 @@ pub fn backends_from_config(config :&mut Config, auth_needed :bool)
 
 pub trait StorageBackend {
 	fn store_chunk(&mut self, pos :Vector3<isize>,
-			data :&MapChunkData) -> Result<(), StrErr>;
-	fn tick(&mut self) -> Result<(), StrErr>;
-	fn load_chunk(&mut self, pos :Vector3<isize>, m :&NameIdMap) -> Result<Option<MapChunkData>, StrErr>;
-	fn get_global_kv(&mut self, key :&str) -> Result<Option<Vec<u8>>, StrErr>;
-	fn set_global_kv(&mut self, key :&str, content :&[u8]) -> Result<(), StrErr>;
-	fn get_player_kv(&mut self, id_pair :PlayerIdPair, key :&str) -> Result<Option<Vec<u8>>, StrErr>;
-	fn set_player_kv(&mut self, id_pair :PlayerIdPair, key :&str, content :&[u8]) -> Result<(), StrErr>;
+			data :&MapChunkData) -> Result<(), MimasError>;
+	fn tick(&mut self) -> Result<(), MimasError>;
+	fn load_chunk(&mut self, pos :Vector3<isize>, m :&NameIdMap) -> Result<Option<MapChunkData>, MimasError>;
+	fn get_global_kv(&mut self, key :&str) -> Result<Option<Vec<u8>>, MimasError>;
+	fn set_global_kv(&mut self, key :&str, content :&[u8]) -> Result<(), MimasError>;
+	fn get_player_kv(&mut self, id_pair :PlayerIdPair, key :&str) -> Result<Option<Vec<u8>>, MimasError>;
+	fn set_player_kv(&mut self, id_pair :PlayerIdPair, key :&str, content :&[u8]) -> Result<(), MimasError>;
 }
@@ -1,16 +1,20 @@
 use std::thread;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use nalgebra::Vector3;
+use nalgebra::{Vector2, Vector3};
 use noise::{Perlin, NoiseFn, Seedable};
 use std::collections::{HashMap, hash_map::Entry};
-use std::mem::replace;
+use std::mem::take;
 use std::hash::Hasher;
 use crate::{btchn, btpic};
 use rand_pcg::Pcg32;
 use rand::Rng;
+use rand::distributions::{Distribution, Standard};
 use twox_hash::XxHash64;
+use sha2::{Sha256, Digest};
 use crate::map_storage::PlayerIdPair;
-use crate::game_params::{ServerGameParamsHdl, BlockRoles};
+use crate::StrErr;
+use crate::logging::TARGET_MAPGEN;
+use crate::game_params::{ServerGameParamsHdl, BlockRoles, Biome, is_solid};
 
 use super::map::{Map, MapChunkData, MapBlock, MapBackend, CHUNKSIZE};
 use crate::map_storage::DynStorageBackend;
@@ -30,13 +34,140 @@ pub struct MapChunk {
 	pub data :MapChunkData,
 	generation_phase :GenerationPhase,
 	tree_spawn_points :Vec<(Vector3<isize>, bool)>,
+	/// Surface positions that may get a decoration in phase two
+	decoration_points :Vec<(Vector3<isize>, Biome)>,
+}
+
+/// How the terrain generator decides which blocks are solid
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapgenMode {
+	/// Everything below a two dimensional elevation noise is solid
+	Heightmap,
+	/// A three dimensional density noise decides, allowing
+	/// overhangs and arches, at a higher cost
+	Density,
+}
+
+impl Default for MapgenMode {
+	fn default() -> Self {
+		MapgenMode::Heightmap
+	}
+}
+
+/// Tunable parameters of the terrain generator
+///
+/// Changes only affect chunks generated afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MapgenParams {
+	/// Frequency of the basic elevation noise
+	pub base_freq :f64,
+	/// Magnitude of the basic elevation noise
+	pub base_mag :f64,
+	/// Frequency of the macro elevation noise
+	pub macro_freq :f64,
+	/// Magnitude of the macro elevation noise
+	pub macro_mag :f64,
+	/// Frequency of the super macro elevation noise
+	pub super_macro_freq :f64,
+	/// Magnitude of the super macro elevation noise
+	pub super_macro_mag :f64,
+	/// Factor the elevation of the terrain is multiplied with
+	pub elevation_weight :f64,
+	/// Density of trees outside of deserts
+	pub tree_density :f64,
+	/// Density of cacti in deserts
+	pub desert_tree_density :f64,
+	/// Height up to which water fills the space above the terrain
+	pub sea_level :isize,
+	/// Set from the `mapgen_mode` setting
+	#[serde(skip)]
+	pub mode :MapgenMode,
+}
+
+impl Default for MapgenParams {
+	fn default() -> Self {
+		Self {
+			base_freq : 0.02356,
+			base_mag : 8.3,
+			macro_freq : 0.0018671,
+			macro_mag : 23.27713,
+			super_macro_freq : 0.00043571,
+			super_macro_mag : 137.479131,
+			elevation_weight : 1.0,
+			tree_density : 0.4,
+			desert_tree_density : 0.1,
+			sea_level : 0,
+			mode : MapgenMode::Heightmap,
+		}
+	}
+}
+
+impl MapgenParams {
+	/// The names of the parameters, in the order they are listed
+	pub const NAMES :&'static [&'static str] = &[
+		"base_freq",
+		"base_mag",
+		"macro_freq",
+		"macro_mag",
+		"super_macro_freq",
+		"super_macro_mag",
+		"elevation_weight",
+		"tree_density",
+		"desert_tree_density",
+		"sea_level",
+	];
+	fn param_mut(&mut self, name :&str) -> Option<&mut f64> {
+		Some(match name {
+			"base_freq" => &mut self.base_freq,
+			"base_mag" => &mut self.base_mag,
+			"macro_freq" => &mut self.macro_freq,
+			"macro_mag" => &mut self.macro_mag,
+			"super_macro_freq" => &mut self.super_macro_freq,
+			"super_macro_mag" => &mut self.super_macro_mag,
+			"elevation_weight" => &mut self.elevation_weight,
+			"tree_density" => &mut self.tree_density,
+			"desert_tree_density" => &mut self.desert_tree_density,
+			_ => return None,
+		})
+	}
+	pub fn get(&self, name :&str) -> Option<f64> {
+		if name == "sea_level" {
+			return Some(self.sea_level as f64);
+		}
+		self.clone().param_mut(name).map(|v| *v)
+	}
+	/// Sets the parameter with the given name
+	///
+	/// Returns false if there is no such parameter.
+	pub fn set(&mut self, name :&str, value :f64) -> bool {
+		if name == "sea_level" {
+			self.sea_level = value.round() as isize;
+			return true;
+		}
+		if let Some(v) = self.param_mut(name) {
+			*v = value;
+			true
+		} else {
+			false
+		}
+	}
 }
 
 pub struct MapgenMap {
 	seed :u64,
+	generate_terrain :bool,
+	mapgen_params :MapgenParams,
+	/// Minimum padding in chunks around generated areas
+	min_padding :isize,
 	params :ServerGameParamsHdl,
 	chunks :HashMap<Vector3<isize>, MapChunk>,
+	/// Noise planes of recently generated chunk columns
+	noise_planes :HashMap<Vector2<isize>, NoisePlane>,
 	storage :DynStorageBackend,
+	/// Results for when the map is used as a synchronous backend
+	generated :Vec<(Vector3<isize>, MapChunkData)>,
+	kv_results :Vec<(PlayerIdPair, u32, String, Option<Vec<u8>>)>,
 }
 
 impl MapChunk {
@@ -58,46 +189,199 @@ fn pos_hash(pos :Vector3<isize>) -> u64 {
 	mh.finish()
 }
 
+/// Derives the seed of a noise or random number generator from the map seed
+fn derive_seed<T>(seed :u64, name :&[u8; 8]) -> T
+		where Standard :Distribution<T> {
+	let mut seeder = Pcg32::new(seed, u64::from_be_bytes(*name));
+	seeder.gen::<T>()
+}
+
+/// The noises that only depend on the x and y coordinates
+struct PlaneNoises {
+	elevation_weight :f64,
+	/// Basic chunk noise
+	noise :NoiseMag,
+	/// Macro noise
+	mnoise :NoiseMag,
+	/// Super macro noise
+	smnoise :NoiseMag,
+	/// Amplifier noise
+	ampnoise :Noise,
+	/// Amplifier noise 2
+	ampnoise2 :Noise,
+	/// Tree noise
+	tnoise :Noise,
+	/// Macro tree noise
+	mtnoise :Noise,
+	/// Biome noise
+	binoise :NoiseMag,
+	/// Macro biome noise
+	mbinoise :NoiseMag,
+}
+
+impl PlaneNoises {
+	fn new(seed :u64, mp :&MapgenParams) -> Self {
+		let s = |name| derive_seed::<u32>(seed, name);
+		let smf = 0.0023473;
+		let tf = 0.0088971;
+		let mtf = 0.00093952;
+		let bf = 0.0023881;
+		let mbf = 0.00113881;
+		Self {
+			elevation_weight : mp.elevation_weight,
+			noise : NoiseMag::new(s(b"chn-base"), mp.base_freq, mp.base_mag),
+			mnoise : NoiseMag::new(s(b"chn-mcro"), mp.macro_freq, mp.macro_mag),
+			smnoise : NoiseMag::new(s(b"chn-smcr"), mp.super_macro_freq, mp.super_macro_mag),
+			ampnoise : Noise::new(s(b"chn-ampl"), smf),
+			ampnoise2 : Noise::new(s(b"chn-ampt"), smf),
+			tnoise : Noise::new(s(b"trenoise"), tf),
+			mtnoise : Noise::new(s(b"mtrnoise"), mtf),
+			binoise : NoiseMag::new(s(b"biom-bas"), bf, 0.4),
+			mbinoise : NoiseMag::new(s(b"biom-mac"), mbf, 0.6),
+		}
+	}
+	fn elevation(&self, p :[f64; 2]) -> f64 {
+		let sm_elev = self.smnoise.get(p);
+		let amp = 1.0 + self.ampnoise.get(p) * 0.9;
+		let amp2 = 0.6 + self.ampnoise2.get(p) * 0.5;
+		let base_noise = amp * self.noise.get(p) + amp2 * self.mnoise.get(p);
+		(base_noise + sm_elev) * self.elevation_weight
+	}
+	fn biome(&self, p :[f64; 2]) -> f64 {
+		self.binoise.get(p) + self.mbinoise.get(p)
+	}
+	fn tree_density(&self, p :[f64; 2]) -> f64 {
+		let macro_density = self.mtnoise.get(p).max(0.0);
+		self.tnoise.get(p) + macro_density
+	}
+}
+
+/// The values of the two dimensional noises for a chunk column
+///
+/// They are the same for all chunks stacked on top of each other,
+/// so they only need to be sampled once per column instead of
+/// once per chunk.
+struct NoisePlane {
+	elevation :Vec<f64>,
+	biome :Vec<f64>,
+	tree_density :Vec<f64>,
+}
+
+impl NoisePlane {
+	fn sample(seed :u64, pos :Vector2<isize>, mp :&MapgenParams) -> Self {
+		let noises = PlaneNoises::new(seed, mp);
+		let len = (CHUNKSIZE * CHUNKSIZE) as usize;
+		let mut res = Self {
+			elevation : Vec::with_capacity(len),
+			biome : Vec::with_capacity(len),
+			tree_density : Vec::with_capacity(len),
+		};
+		for x in 0 .. CHUNKSIZE {
+			for y in 0 .. CHUNKSIZE {
+				let p = [(pos.x + x) as f64, (pos.y + y) as f64];
+				res.elevation.push(noises.elevation(p));
+				res.biome.push(noises.biome(p));
+				res.tree_density.push(noises.tree_density(p));
+			}
+		}
+		res
+	}
+	fn idx(x :isize, y :isize) -> usize {
+		(x * CHUNKSIZE + y) as usize
+	}
+}
+
+#[cfg(test)]
 fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
-		params :&ServerGameParamsHdl) -> MapChunk {
+		params :&ServerGameParamsHdl, mp :&MapgenParams) -> MapChunk {
+	let plane = NoisePlane::sample(seed, pos.xy(), mp);
+	gen_chunk_phase_one_plane(seed, pos, params, mp, &plane)
+}
+
+/// Frequency of the density noise
+const DENSITY_FREQ :f64 = 0.04;
+/// Frequency of the noise for the amount of decorations
+const DECORATION_FREQ :f64 = 0.03;
+/// Blocks over which the density falls off from fully solid to air
+const DENSITY_FALLOFF :f64 = 24.0;
+/// Thickness of the ground layer in the density mode
+const DENSITY_GROUND_DEPTH :isize = 4;
+
+/// Generates the chunk from a three dimensional density field
+///
+/// Cells with a positive density are solid. The density decreases
+/// with the height above the elevation of the plane noises, so the
+/// terrain is solid deep down and air high up, while the 3D noise
+/// carves overhangs and arches around the surface.
+fn gen_chunk_phase_one_density(seed :u64, pos :Vector3<isize>,
+		params :&ServerGameParamsHdl, mp :&MapgenParams,
+		plane :&NoisePlane) -> MapChunk {
+	let role = &params.p.block_roles;
+	let dnoise = Noise::new(derive_seed(seed, b"dens-3d "), DENSITY_FREQ);
+	let is_solid = |x :isize, y :isize, z :isize, elev :f64| {
+		let p3 = [(pos.x + x) as f64, (pos.y + y) as f64, (pos.z + z) as f64];
+		(elev - p3[2]) / DENSITY_FALLOFF + dnoise.get_3d(p3) > 0.0
+	};
+	let mut res = MapChunk {
+		data : MapChunkData::filled_with(role.air),
+		generation_phase : GenerationPhase::PhaseOne,
+		tree_spawn_points : Vec::new(),
+		decoration_points : Vec::new(),
+	};
+	for x in 0 .. CHUNKSIZE {
+		for y in 0 .. CHUNKSIZE {
+			let pidx = NoisePlane::idx(x, y);
+			let elev = plane.elevation[pidx];
+			let (ground_bl, ground_top, biome) = if plane.biome[pidx] < 0.3 {
+				(role.ground, role.ground_top, Biome::Grassland)
+			} else {
+				(role.sand, role.sand, Biome::Desert)
+			};
+			// The number of solid cells directly above, starting
+			// above the chunk so that the layers continue into it
+			let mut depth = 0;
+			for z in (0 .. CHUNKSIZE + DENSITY_GROUND_DEPTH).rev() {
+				if !is_solid(x, y, z, elev) {
+					depth = 0;
+					if z < CHUNKSIZE && pos.z + z <= mp.sea_level {
+						*res.get_blk_mut(Vector3::new(x, y, z)) = role.water;
+					}
+					continue;
+				}
+				if depth == 0 && z + 1 < CHUNKSIZE && pos.z + z >= mp.sea_level {
+					res.decoration_points.push((pos + Vector3::new(x, y, z + 1), biome));
+				}
+				if z < CHUNKSIZE {
+					*res.get_blk_mut(Vector3::new(x, y, z)) = if depth == 0 {
+						ground_top
+					} else if depth < DENSITY_GROUND_DEPTH {
+						ground_bl
+					} else {
+						role.stone
+					};
+				}
+				depth += 1;
+			}
+		}
+	}
+	res
+}
+
+fn gen_chunk_phase_one_plane(seed :u64, pos :Vector3<isize>,
+		params :&ServerGameParamsHdl, mp :&MapgenParams,
+		plane :&NoisePlane) -> MapChunk {
+	if mp.mode == MapgenMode::Density {
+		return gen_chunk_phase_one_density(seed, pos, params, mp, plane);
+	}
 	let role = &params.p.block_roles;
 	macro_rules! s {
 		($e:expr) => {
 			s!($e, u32)
 		};
-		($e:expr, $t:ident) => {{
-			let mut seeder = Pcg32::new(seed, u64::from_be_bytes(*$e));
-			let seed :$t= seeder.gen::<$t>();
-			seed
-		}};
-	}
-	// Basic chunk noise
-	let f = 0.02356;
-	let noise = NoiseMag::new(s!(b"chn-base"), f, 8.3);
-	// Macro noise
-	let mf = 0.0018671;
-	let mnoise = NoiseMag::new(s!(b"chn-mcro"), mf, 23.27713);
-	// Super macro noise
-	let smf = 0.00043571;
-	let smnoise = NoiseMag::new(s!(b"chn-smcr"), smf, 137.479131);
-	// Amplifier noise
-	let smf = 0.0023473;
-	let ampnoise = Noise::new(s!(b"chn-ampl"), smf);
-	// Amplifier noise 2
-	let smf = 0.0023473;
-	let ampnoise2 = Noise::new(s!(b"chn-ampt"), smf);
-	// Tree noise
-	let tf = 0.0088971;
-	let tnoise = Noise::new(s!(b"trenoise"), tf);
-	// Macro tree noise
-	let mtf = 0.00093952;
-	let mtnoise = Noise::new(s!(b"mtrnoise"), mtf);
-	// Biome noise
-	let bf = 0.0023881;
-	let binoise = NoiseMag::new(s!(b"biom-bas"), bf, 0.4);
-	// Macro biome noise
-	let mbf = 0.00113881;
-	let mbinoise = NoiseMag::new(s!(b"biom-mac"), mbf, 0.6);
+		($e:expr, $t:ident) => {
+			derive_seed::<$t>(seed, $e)
+		};
+	}
 	// Tree pcg
 	let mut tpcg = Pcg32::new(s!(b"pcg-tree", u64), pos_hash(pos));
 
@@ -109,13 +393,6 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 		})
 		.collect::<Vec<_>>();
 
-	let mut plant_rngs = params.mapgen_params.plants.iter()
-		.map(|plant| {
-			let pcg = Pcg32::new(s!(&plant.pcg_seed, u64), pos_hash(pos));
-			(plant, pcg)
-		})
-		.collect::<Vec<_>>();
-
 	// Cave noise
 	let ca_f = 0.052951;
 	let ca_noise = Noise::new(s!(b"nois-cav"), ca_f);
@@ -127,15 +404,12 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 		data : MapChunkData::filled_with(role.air),
 		generation_phase : GenerationPhase::PhaseOne,
 		tree_spawn_points : Vec::new(),
+		decoration_points : Vec::new(),
 	};
 	for x in 0 .. CHUNKSIZE {
 		for y in 0 .. CHUNKSIZE {
-			let p = [(pos.x + x) as f64, (pos.y + y) as f64];
-			let sm_elev = smnoise.get(p);
-			let amp = 1.0 + ampnoise.get(p) * 0.9;
-			let amp2 = 0.6 + ampnoise2.get(p) * 0.5;
-			let base_noise = amp * noise.get(p) + amp2 * mnoise.get(p);
-			let elev = base_noise + sm_elev;
+			let pidx = NoisePlane::idx(x, y);
+			let elev = plane.elevation[pidx];
 			let elev_blocks = elev as isize;
 			if let Some(elev_blocks) = elev_blocks.checked_sub(pos.z) {
 				let els = elev_blocks - 4;
@@ -152,10 +426,8 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 						} else {
 							ore.limit_a
 						};
-						if noise.get_3d(p3) > limit {
-							if pcg.gen::<f64>() > ore.pcg_chance {
-								*res.get_blk_mut(Vector3::new(x, y, z)) = ore.block;
-							}
+						if noise.get_3d(p3) > limit && pcg.gen::<f64>() > ore.pcg_chance {
+							*res.get_blk_mut(Vector3::new(x, y, z)) = ore.block;
 						}
 					}
 
@@ -175,12 +447,15 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 						*res.get_blk_mut(Vector3::new(x, y, z)) = role.air;
 					}
 				}
-				if pos.z < 0 {
-					for z in  els .. CHUNKSIZE {
+				if elev_blocks + pos.z <= mp.sea_level {
+					// The surface is at or below the sea level:
+					// fill up with water instead of placing ground
+					let water_top = (mp.sea_level + 1 - pos.z).min(CHUNKSIZE);
+					for z in els .. water_top {
 						*res.get_blk_mut(Vector3::new(x, y, z)) = role.water;
 					}
 				} else {
-					let (ground_bl, ground_top) = if binoise.get(p) + mbinoise.get(p) < 0.3 {
+					let (ground_bl, ground_top) = if plane.biome[pidx] < 0.3 {
 						(role.ground, role.ground_top)
 					} else {
 						(role.sand, role.sand)
@@ -193,24 +468,20 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 							*res.get_blk_mut(Vector3::new(x, y, z)) = ground_top;
 						}
 					}
-					if pos.z == 0 && elg <= 0 {
-						*res.get_blk_mut(Vector3::new(x, y, 0)) = role.water;
+					// Sand rests on a layer of gravel
+					let gravel_z = elev_blocks - 4;
+					if ground_bl == role.sand && (0 .. CHUNKSIZE).contains(&gravel_z) {
+						*res.get_blk_mut(Vector3::new(x, y, gravel_z)) = role.gravel;
 					}
 					if elg > 0 && elg < CHUNKSIZE {
 						let in_desert = ground_bl == role.sand;
 						// Tree spawning
 						let tree_density = if in_desert {
-							0.1
+							mp.desert_tree_density
 						} else {
-							0.4
+							mp.tree_density
 						};
-						let macro_density = mtnoise.get(p);
-						let macro_density = if macro_density < 0.0 {
-							0.0
-						} else {
-							macro_density
-						};
-						let local_density = tnoise.get(p) + macro_density;
+						let local_density = plane.tree_density[pidx];
 
 						let mut spawning_tree = false;
 						if local_density > 1.0 - tree_density {
@@ -226,12 +497,13 @@ fn gen_chunk_phase_one(seed :u64, pos :Vector3<isize>,
 							}
 						}
 
-						if !spawning_tree && !in_desert {
-							for (plant, pcg) in plant_rngs.iter_mut() {
-								if pcg.gen::<f64>() > plant.pcg_limit {
-									*res.get_blk_mut(Vector3::new(x, y, elg)) = plant.block;
-								}
-							}
+						if !spawning_tree {
+							let biome = if in_desert {
+								Biome::Desert
+							} else {
+								Biome::Grassland
+							};
+							res.decoration_points.push((pos + Vector3::new(x, y, elg), biome));
 						}
 					}
 				}
@@ -303,6 +575,10 @@ impl Schematic {
 			aabb_max,
 		}
 	}
+	/// How far the schematic extends from its origin, per axis
+	pub fn extent(&self) -> Vector3<isize> {
+		self.aabb_min.zip_map(&self.aabb_max, |a, b| a.abs().max(b.abs()))
+	}
 }
 
 fn aabb_min_max(items :&[(Vector3<isize>, MapBlock)]) -> (Vector3<isize>, Vector3<isize>) {
@@ -315,6 +591,33 @@ fn aabb_min_max(items :&[(Vector3<isize>, MapBlock)]) -> (Vector3<isize>, Vector
 	(Vector3::new(min_x, min_y, min_z), Vector3::new(max_x, max_y, max_z))
 }
 
+/// Turns a seed given as text into a numeric seed
+///
+/// Numbers are used directly, other text is hashed.
+pub fn seed_from_str(s :&str) -> u64 {
+	if let Ok(v) = s.trim().parse() {
+		return v;
+	}
+	let hash = Sha256::digest(s.as_bytes());
+	let mut buf = [0; 8];
+	buf.copy_from_slice(&hash[.. 8]);
+	u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+#[test]
+fn test_seed_from_str() {
+	// Text seeds are stable across runs and versions
+	assert_eq!(seed_from_str("mimas"), 5122336817328764519);
+	assert_eq!(seed_from_str("mimas"), seed_from_str("mimas"));
+	assert_ne!(seed_from_str("mimas"), seed_from_str("Mimas"));
+	// Numbers are used directly
+	assert_eq!(seed_from_str("78"), 78);
+	assert_eq!(seed_from_str(" 18446744073709551615 "), std::u64::MAX);
+	// Not a valid u64, so it gets hashed
+	assert_ne!(seed_from_str("-5"), 5);
+}
+
 pub(super) fn tree_schematic(roles :&BlockRoles) -> Schematic {
 	let mut items = Vec::new();
 	for x in -1 ..= 1 {
@@ -338,24 +641,59 @@ pub(super) fn cactus_schematic(roles :&BlockRoles) -> Schematic {
 	Schematic::from_items(items)
 }
 
+/// Spawns the schematic into the chunks present in the generator
+///
+/// Blocks in absent chunks are skipped. Generating these chunks
+/// here instead could shadow chunks that are in the storage.
 fn spawn_schematic_mapgen(map :&mut MapgenMap, pos :Vector3<isize>,
 		schematic :&Schematic) {
 	for (bpos, mb) in schematic.items.iter() {
-		let blk = map.get_blk_p1_mut(pos + bpos).unwrap();
-		*blk = *mb;
+		if let Some(blk) = map.get_blk_p1_mut(pos + bpos) {
+			*blk = *mb;
+		}
 	}
 }
 
+/// Maximum number of noise planes kept by the generator
+///
+/// One plane takes about 6 KiB.
+const MAX_CACHED_NOISE_PLANES :usize = 1024;
+
 impl MapgenMap {
-	pub fn new(seed :u64, params :ServerGameParamsHdl,
+	pub fn new(seed :u64, generate_terrain :bool, params :ServerGameParamsHdl,
 			storage :DynStorageBackend) -> Self {
 		MapgenMap {
 			seed,
+			generate_terrain,
+			mapgen_params : MapgenParams::default(),
+			min_padding : 1,
 			params,
 			chunks : HashMap::new(),
+			noise_planes : HashMap::new(),
 			storage,
+			generated : Vec::new(),
+			kv_results : Vec::new(),
 		}
 	}
+	pub fn set_mapgen_params(&mut self, mapgen_params :MapgenParams) {
+		self.mapgen_params = mapgen_params;
+		// The cached noise was sampled with the old params
+		self.noise_planes.clear();
+	}
+	pub fn set_min_padding(&mut self, min_padding :isize) {
+		self.min_padding = min_padding;
+	}
+	pub fn into_storage(self) -> DynStorageBackend {
+		self.storage
+	}
+	/// How many chunks around an area need to reach phase two for the area to be done
+	///
+	/// Schematics spawned in phase two reach into the neighbouring chunks,
+	/// so the padding has to cover the extent of the largest schematic.
+	fn chunk_padding(&self) -> Vector3<isize> {
+		let extent = self.params.p.schematics.max_extent();
+		extent.map(|e| ((e + CHUNKSIZE - 1) / CHUNKSIZE).max(self.min_padding))
+	}
 	pub fn get_chunk_p1(&self, pos :Vector3<isize>) -> Option<&MapChunk> {
 		self.chunks.get(&pos)
 	}
@@ -364,17 +702,31 @@ impl MapgenMap {
 	}
 	fn gen_chunk_phase_one(&mut self, pos :Vector3<isize>) {
 		if let Entry::Vacant(v) = self.chunks.entry(pos) {
-			v.insert(gen_chunk_phase_one(self.seed, pos, &self.params));
+			let (seed, mp) = (self.seed, &self.mapgen_params);
+			let plane_pos = pos.xy();
+			if self.noise_planes.len() >= MAX_CACHED_NOISE_PLANES &&
+					!self.noise_planes.contains_key(&plane_pos) {
+				self.noise_planes.clear();
+			}
+			let plane = self.noise_planes.entry(plane_pos)
+				.or_insert_with(|| NoisePlane::sample(seed, plane_pos, mp));
+			v.insert(gen_chunk_phase_one_plane(seed, pos, &self.params,
+				mp, plane));
 		}
 	}
 	fn gen_chunk_phase_two(&mut self, pos :Vector3<isize>) {
-		let tree_spawn_points = {
-			let chnk = self.chunks.get_mut(&pos).unwrap();
+		let (tree_spawn_points, decoration_points) = {
+			let chnk = if let Some(chnk) = self.chunks.get_mut(&pos) {
+				chnk
+			} else {
+				return;
+			};
 			if chnk.generation_phase >= GenerationPhase::PhaseTwo {
 				return;
 			}
 			chnk.generation_phase = GenerationPhase::PhaseTwo;
-			replace(&mut chnk.tree_spawn_points, Vec::new())
+			(take(&mut chnk.tree_spawn_points),
+				take(&mut chnk.decoration_points))
 		};
 		// We clone the RC because of Rust's aliasing rules
 		let schematics = &self.params.clone().p.schematics;
@@ -385,9 +737,61 @@ impl MapgenMap {
 				spawn_schematic_mapgen(self, p, &schematics.tree_schematic);
 			}
 		}
+		self.decorate(pos, decoration_points);
+	}
+	/// Scatters plants onto the surface positions of the chunk
+	///
+	/// How many plants a position gets depends on a noise of its biome.
+	/// Positions without a solid block below, for example
+	/// because a tree has been placed there, are skipped.
+	fn decorate(&mut self, pos :Vector3<isize>, points :Vec<(Vector3<isize>, Biome)>) {
+		let params = self.params.clone();
+		let seed = self.seed;
+		let density_noises = Biome::ALL.iter()
+			.map(|biome| {
+				let name = match biome {
+					Biome::Grassland => b"deco-grs",
+					Biome::Desert => b"deco-dsr",
+				};
+				(*biome, Noise::new(derive_seed(seed, name), DECORATION_FREQ))
+			})
+			.collect::<Vec<_>>();
+		let mut plant_rngs = params.mapgen_params.plants.iter()
+			.map(|plant| {
+				let pcg = Pcg32::new(derive_seed(seed, &plant.pcg_seed), pos_hash(pos));
+				(plant, pcg)
+			})
+			.collect::<Vec<_>>();
+		for (p, biome) in points {
+			let noise = &density_noises.iter()
+				.find(|(b, _noise)| *b == biome)
+				.unwrap().1;
+			// Between none and twice the usual amount of plants
+			let density = (1.0 + noise.get([p.x as f64, p.y as f64])).max(0.0);
+			let mut decoration = None;
+			for (plant, pcg) in plant_rngs.iter_mut() {
+				if plant.biome != biome {
+					continue;
+				}
+				if pcg.gen::<f64>() > 1.0 - (1.0 - plant.pcg_limit) * density {
+					decoration = Some(plant.block);
+				}
+			}
+			let decoration = if let Some(decoration) = decoration {
+				decoration
+			} else {
+				continue;
+			};
+			let below = p - Vector3::new(0, 0, 1);
+			let supported = self.get_blk_p1(below)
+				.map(|b| is_solid(b, &params.p))
+				.unwrap_or(false);
+			if supported && self.get_blk_p1(p) == Some(params.p.block_roles.air) {
+				*self.get_blk_p1_mut(p).unwrap() = decoration;
+			}
+		}
 	}
 
-	#[allow(unused)]
 	fn get_blk_p1(&self, pos :Vector3<isize>) -> Option<MapBlock> {
 		let chunk_pos = btchn(pos);
 		let pos_in_chunk = btpic(pos);
@@ -400,6 +804,32 @@ impl MapgenMap {
 		self.get_chunk_p1_mut(chunk_pos)
 			.map(|blk| blk.get_blk_mut(pos_in_chunk))
 	}
+	/// Generates and stores all chunks around the given position
+	///
+	/// Covers the chunk columns up to `radius` chunks away horizontally,
+	/// and `zradius` chunks up and down. Chunks already present in the
+	/// storage are kept. After each chunk column, `progress` is called
+	/// with the number of finished columns and the total number of columns.
+	pub fn pregen(&mut self, center :Vector3<isize>, radius :isize, zradius :isize,
+			mut progress :impl FnMut(usize, usize)) -> Result<(), StrErr> {
+		let center = btchn(center);
+		let side = (2 * radius + 1) as usize;
+		let total = side * side;
+		let mut done = 0;
+		for cx in -radius ..= radius {
+			for cy in -radius ..= radius {
+				let pos = center + Vector3::new(cx, cy, 0) * CHUNKSIZE;
+				let pos_min = pos - Vector3::new(0, 0, zradius * CHUNKSIZE);
+				let pos_max = pos + Vector3::new(0, 0, zradius * CHUNKSIZE);
+				self.gen_chunks_in_area(pos_min, pos_max, &mut |_, _| {});
+				// Commit the stored chunks
+				self.storage.tick()?;
+				done += 1;
+				progress(done, total);
+			}
+		}
+		Ok(())
+	}
 
 
 	fn gen_chunks_in_area<F :FnMut(Vector3<isize>, &MapChunkData)>(&mut self,
@@ -409,19 +839,24 @@ impl MapgenMap {
 		let pos_max = pos_max.map(|v| v / CHUNKSIZE);
 
 		let mut sth_to_generate = false;
+		// The area is inclusive of pos_max, like the loop storing the chunks below
+		let in_area = |x, y, z| {
+			(pos_min.x ..= pos_max.x).contains(&x) &&
+				(pos_min.y ..= pos_max.y).contains(&y) &&
+				(pos_min.z ..= pos_max.z).contains(&z)
+		};
 
-		let ex = 2;
-		for x in pos_min.x - ex ..= pos_max.x + ex {
-			for y in pos_min.y - ex ..= pos_max.y + ex {
-				for z in pos_min.z - ex ..= pos_max.z + ex {
+		// Phase two needs phase one to be done in the chunks
+		// around the ones it spawns schematics in
+		let t = self.chunk_padding();
+		let s = t * 2;
+		for x in pos_min.x - s.x ..= pos_max.x + s.x {
+			for y in pos_min.y - s.y ..= pos_max.y + s.y {
+				for z in pos_min.z - s.z ..= pos_max.z + s.z {
 					let pos = Vector3::new(x, y, z) * CHUNKSIZE;
 					if let Some(c) = self.chunks.get(&pos) {
-						if (pos_min.x .. pos_max.x).contains(&x) &&
-								(pos_min.y .. pos_max.y).contains(&y) &&
-								(pos_min.z .. pos_max.z).contains(&z) {
-							if c.generation_phase != GenerationPhase::Done {
-								sth_to_generate = true;
-							}
+						if in_area(x, y, z) && c.generation_phase != GenerationPhase::Done {
+							sth_to_generate = true;
 						}
 					} else {
 						let m = &self.params.p.name_id_map;
@@ -430,14 +865,25 @@ impl MapgenMap {
 								data,
 								generation_phase : GenerationPhase::Done,
 								tree_spawn_points : Vec::new(),
+								decoration_points : Vec::new(),
 							};
 							f(pos, &chn.data);
 							self.chunks.insert(pos, chn);
-						} else {
-							if (pos_min.x .. pos_max.x).contains(&x) &&
-									(pos_min.y .. pos_max.y).contains(&y) &&
-									(pos_min.z .. pos_max.z).contains(&z) {
+						} else if in_area(x, y, z) {
+							if self.generate_terrain {
 								sth_to_generate = true;
+							} else {
+								// Terrain generation is disabled,
+								// serve an empty chunk instead.
+								let air = self.params.p.block_roles.air;
+								let chn = MapChunk {
+									data : MapChunkData::filled_with(air),
+									generation_phase : GenerationPhase::Done,
+									tree_spawn_points : Vec::new(),
+									decoration_points : Vec::new(),
+								};
+								f(pos, &chn.data);
+								self.chunks.insert(pos, chn);
 							}
 						}
 					}
@@ -448,19 +894,17 @@ impl MapgenMap {
 			return;
 		}
 
-		let s = 2;
-		for x in pos_min.x - s ..= pos_max.x + s {
-			for y in pos_min.y - s ..= pos_max.y + s {
-				for z in pos_min.z - s ..= pos_max.z + s {
+		for x in pos_min.x - s.x ..= pos_max.x + s.x {
+			for y in pos_min.y - s.y ..= pos_max.y + s.y {
+				for z in pos_min.z - s.z ..= pos_max.z + s.z {
 					let pos = Vector3::new(x, y, z) * CHUNKSIZE;
 					self.gen_chunk_phase_one(pos);
 				}
 			}
 		}
-		let t = 1;
-		for x in pos_min.x - t ..= pos_max.x + t {
-			for y in pos_min.y - t ..= pos_max.y + t {
-				for z in pos_min.z - t ..= pos_max.z + t {
+		for x in pos_min.x - t.x ..= pos_max.x + t.x {
+			for y in pos_min.y - t.y ..= pos_max.y + t.y {
+				for z in pos_min.z - t.z ..= pos_max.z + t.z {
 					let pos = Vector3::new(x, y, z) * CHUNKSIZE;
 					self.gen_chunk_phase_two(pos);
 				}
@@ -482,12 +926,1024 @@ impl MapgenMap {
 	}
 }
 
+#[cfg(test)]
+#[test]
+fn test_mapgen_params() {
+	let config :crate::config::Config = toml::from_str(r#"
+		[mapgen]
+		elevation_weight = 2.0
+		tree_density = 0.0
+	"#).unwrap();
+	let mp = config.mapgen;
+	assert_eq!(mp.get("elevation_weight"), Some(2.0));
+	assert_eq!(mp.get("tree_density"), Some(0.0));
+	// Unspecified values keep their defaults
+	assert_eq!(mp.base_freq, MapgenParams::default().base_freq);
+	assert_eq!(mp.get("nonexistent"), None);
+
+	let mut set = MapgenParams::default();
+	assert!(set.set("elevation_weight", 2.0));
+	assert!(set.set("tree_density", 0.0));
+	assert!(!set.set("nonexistent", 1.0));
+	assert_eq!(set, mp);
+
+	let params = crate::game_params::test_params();
+	// Counts the solid blocks above the sea level
+	let solid_count = |mp :&MapgenParams| {
+		let mut count = 0;
+		let mut tree_count = 0;
+		for z in 0 .. 4 {
+			let pos = Vector3::new(-128, 0, z * CHUNKSIZE);
+			let chunk = gen_chunk_phase_one(78, pos, &params, mp);
+			count += chunk.data.0.iter()
+				.filter(|b| is_solid(**b, &params.p))
+				.count();
+			tree_count += chunk.tree_spawn_points.len();
+		}
+		(count, tree_count)
+	};
+	let (default_count, default_trees) = solid_count(&MapgenParams::default());
+	let (count, trees) = solid_count(&mp);
+	assert!(count > default_count, "{} {}", count, default_count);
+	assert!(default_trees > 0);
+	assert_eq!(trees, 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_tree_spawn_points_reproducible() {
+	let params = crate::game_params::test_params();
+	let mp = MapgenParams::default();
+	// The tree spawn points of a chunk column in a forest, in generation order
+	let spawn_points = |seed :u64| {
+		let mut points = Vec::new();
+		for z in 0 .. 4 {
+			let pos = Vector3::new(-9 * CHUNKSIZE, CHUNKSIZE, z * CHUNKSIZE);
+			let chunk = gen_chunk_phase_one(seed, pos, &params, &mp);
+			points.extend_from_slice(&chunk.tree_spawn_points);
+		}
+		points
+	};
+	let expected :&[(isize, isize, isize)] = &[
+		(-144, 28, 31), (-143, 31, 31), (-142, 20, 30), (-142, 31, 31),
+		(-141, 23, 30), (-141, 31, 31), (-139, 20, 29), (-139, 21, 29),
+		(-138, 16, 29), (-138, 23, 28), (-136, 28, 28), (-135, 16, 28),
+		(-135, 19, 27), (-135, 28, 28), (-134, 22, 27), (-134, 24, 27),
+		(-133, 21, 26), (-133, 22, 26), (-133, 25, 26), (-132, 16, 26),
+		(-132, 21, 26), (-131, 27, 26), (-131, 30, 26), (-130, 16, 26),
+		(-130, 19, 25), (-130, 29, 26), (-130, 30, 26), (-129, 31, 25),
+	];
+	let expected = expected.iter()
+		.map(|&(x, y, z)| (Vector3::new(x, y, z), false))
+		.collect::<Vec<_>>();
+	assert_eq!(spawn_points(78), expected);
+	// Another seed gives other points
+	assert_ne!(spawn_points(79), expected);
+}
+
+#[cfg(test)]
+#[test]
+fn test_gravel_generation() {
+	use crate::game_params::{NameIdMap, load_params_failible};
+
+	let params = std::sync::Arc::new(load_params_failible(NameIdMap::builtin_name_list()).unwrap());
+	let roles = &params.p.block_roles;
+	let mp = MapgenParams::default();
+	// A chunk column with some desert above the sea level
+	let desert_column = (-40 .. 40).step_by(4)
+		.flat_map(|cx| (-40 .. 40).step_by(4).map(move |cy| Vector2::new(cx, cy) * CHUNKSIZE))
+		.find(|pos| {
+			let plane = NoisePlane::sample(78, *pos, &mp);
+			plane.biome.iter().zip(plane.elevation.iter())
+				.any(|(b, e)| *b >= 0.3 && *e as isize > mp.sea_level)
+		})
+		.expect("no desert found");
+	let mut stone_count = 0;
+	let mut layer_count = 0;
+	for column_pos in [Vector2::new(0, 0), desert_column].iter() {
+		let plane = NoisePlane::sample(78, *column_pos, &mp);
+		let elevs = plane.elevation.iter()
+			.map(|e| *e as isize)
+			.collect::<Vec<_>>();
+		let z_min = btchn(Vector3::new(0, 0, elevs.iter().min().unwrap() - 48)).z;
+		let z_max = btchn(Vector3::new(0, 0, *elevs.iter().max().unwrap())).z;
+		for chunk_z in (z_min ..= z_max).step_by(CHUNKSIZE as usize) {
+			let pos = Vector3::new(column_pos.x, column_pos.y, chunk_z);
+			let chunk = gen_chunk_phase_one(78, pos, &params, &mp);
+			for x in 0 .. CHUNKSIZE {
+				for y in 0 .. CHUNKSIZE {
+					let elev = elevs[NoisePlane::idx(x, y)];
+					for z in 0 .. CHUNKSIZE {
+						let p = Vector3::new(x, y, z);
+						if *chunk.data.get_blk(p) != roles.gravel {
+							continue;
+						}
+						let z_abs = pos.z + z;
+						if z_abs == elev - 4 {
+							// The layer below the sand
+							let above = p + Vector3::new(0, 0, 1);
+							if above.z < CHUNKSIZE {
+								assert_eq!(*chunk.data.get_blk(above), roles.sand);
+							}
+							layer_count += 1;
+						} else {
+							// Patches only occur inside the stone
+							assert!(z_abs < elev - 4, "{:?} {}", pos + p, elev);
+							stone_count += 1;
+						}
+					}
+				}
+			}
+		}
+	}
+	assert!(stone_count > 0);
+	assert!(layer_count > 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_surface_decorations() {
+	use crate::game_params::{NameIdMap, load_params_failible, is_attached};
+	use crate::map_storage::NullStorageBackend;
+
+	let params = std::sync::Arc::new(load_params_failible(NameIdMap::builtin_name_list()).unwrap());
+	let roles = &params.p.block_roles;
+	let mp = MapgenParams::default();
+	// Columns with land above the sea level in the respective biome
+	let find_column = |desert :bool| (-40 .. 40).step_by(4)
+		.flat_map(|cx| (-40 .. 40).step_by(4).map(move |cy| Vector2::new(cx, cy) * CHUNKSIZE))
+		.find(|pos| {
+			let plane = NoisePlane::sample(78, *pos, &mp);
+			plane.biome.iter().zip(plane.elevation.iter())
+				.all(|(b, e)| (*b >= 0.3) == desert && *e as isize > mp.sea_level + 1)
+		});
+	let grassland_column = find_column(false).expect("no grassland found");
+	let desert_column = find_column(true).expect("no desert found");
+	let mut decorations = 0;
+	let mut sand_surfaces = 0;
+	for column_pos in [grassland_column, desert_column].iter() {
+		let plane = NoisePlane::sample(78, *column_pos, &mp);
+		let elevs = plane.elevation.iter()
+			.map(|e| *e as isize)
+			.collect::<Vec<_>>();
+		let z_min = btchn(Vector3::new(0, 0, elevs.iter().min().unwrap() - 1)).z;
+		let z_max = btchn(Vector3::new(0, 0, elevs.iter().max().unwrap() + 1)).z;
+		let mut map = MapgenMap::new(78, true, params.clone(), Box::new(NullStorageBackend));
+		let mut chunks = HashMap::new();
+		map.gen_chunks_in_area(Vector3::new(column_pos.x, column_pos.y, z_min),
+			Vector3::new(column_pos.x, column_pos.y, z_max),
+			&mut |pos, chk| {
+				chunks.insert(pos, chk.clone());
+			});
+		let get_blk = |pos :Vector3<isize>| *chunks[&btchn(pos)].get_blk(btpic(pos));
+		for x in 0 .. CHUNKSIZE {
+			for y in 0 .. CHUNKSIZE {
+				for z in z_min + 1 .. z_max + CHUNKSIZE {
+					let pos = Vector3::new(column_pos.x + x, column_pos.y + y, z);
+					let below = get_blk(pos - Vector3::new(0, 0, 1));
+					if below == roles.sand && get_blk(pos) == roles.air {
+						sand_surfaces += 1;
+					}
+					if !is_attached(get_blk(pos), &params.p) {
+						continue;
+					}
+					// Decorations only grow on the ground of grassland
+					assert_eq!(below, roles.ground_top, "{:?}", pos);
+					decorations += 1;
+				}
+			}
+		}
+	}
+	assert!(decorations > 0);
+	assert!(sand_surfaces > 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_density_mode() {
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let mp = MapgenParams { mode : MapgenMode::Density, .. MapgenParams::default() };
+	let solid = |b :MapBlock| b != roles.air && b != roles.water;
+	let mut overhangs = 0;
+	for cx in 0 .. 4 {
+		let column_pos = Vector3::new(cx * CHUNKSIZE, 0, 0);
+		let plane = NoisePlane::sample(78, column_pos.xy(), &mp);
+		let elev = plane.elevation[NoisePlane::idx(0, 0)] as isize;
+		let chunk_z = btchn(Vector3::new(0, 0, elev)).z;
+
+		// Deep down it's solid, high up it's air
+		let deep = gen_chunk_phase_one(78, column_pos + Vector3::new(0, 0, chunk_z - 4 * CHUNKSIZE), &params, &mp);
+		assert!(deep.data.0.iter().all(|b| solid(*b)));
+		let high = gen_chunk_phase_one(78, column_pos + Vector3::new(0, 0, chunk_z + 4 * CHUNKSIZE), &params, &mp);
+		assert!(high.data.0.iter().all(|b| *b == roles.air));
+
+		for dz in -1 ..= 1 {
+			let pos = column_pos + Vector3::new(0, 0, chunk_z + dz * CHUNKSIZE);
+			let chunk = gen_chunk_phase_one(78, pos, &params, &mp);
+			for x in 0 .. CHUNKSIZE {
+				for y in 0 .. CHUNKSIZE {
+					for z in 1 .. CHUNKSIZE - 1 {
+						let blk = |z| *chunk.data.get_blk(Vector3::new(x, y, z));
+						// A solid cell with air below and solid above
+						if solid(blk(z)) && blk(z - 1) == roles.air && solid(blk(z + 1)) {
+							overhangs += 1;
+						}
+					}
+				}
+			}
+		}
+	}
+	assert!(overhangs > 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_sea_level() {
+	let params = crate::game_params::test_params();
+	let water = params.p.block_roles.water;
+	let column_pos = Vector3::new(-128, 0, 0);
+	let plane = NoisePlane::sample(78, column_pos.xy(), &MapgenParams::default());
+	let elevs = plane.elevation.iter()
+		.map(|e| *e as isize)
+		.collect::<Vec<_>>();
+	let min = *elevs.iter().min().unwrap();
+	let max = *elevs.iter().max().unwrap();
+
+	// For each column, the number of water blocks and the topmost one
+	let water_columns = |sea_level :isize| {
+		let mp = MapgenParams { sea_level, .. MapgenParams::default() };
+		let mut columns = HashMap::new();
+		let z_min = btchn(Vector3::new(0, 0, min - 16)).z;
+		let z_max = btchn(Vector3::new(0, 0, max + 16)).z;
+		for chunk_z in (z_min ..= z_max).step_by(CHUNKSIZE as usize) {
+			let pos = Vector3::new(column_pos.x, column_pos.y, chunk_z);
+			let chunk = gen_chunk_phase_one(78, pos, &params, &mp);
+			for x in 0 .. CHUNKSIZE {
+				for y in 0 .. CHUNKSIZE {
+					for z in 0 .. CHUNKSIZE {
+						if *chunk.data.get_blk(Vector3::new(x, y, z)) != water {
+							continue;
+						}
+						let e = columns.entry((x, y)).or_insert((0, chunk_z + z));
+						e.0 += 1;
+						e.1 = e.1.max(chunk_z + z);
+					}
+				}
+			}
+		}
+		columns
+	};
+
+	// Below the whole terrain, there is no water at all
+	assert!(water_columns(min - 8).is_empty());
+
+	for &sea_level in [(min + max) / 2, max + 4].iter() {
+		let columns = water_columns(sea_level);
+		assert!(!columns.is_empty());
+		for x in 0 .. CHUNKSIZE {
+			for y in 0 .. CHUNKSIZE {
+				let elev = elevs[NoisePlane::idx(x, y)];
+				if elev > sea_level {
+					assert_eq!(columns.get(&(x, y)), None, "{} {}", x, y);
+					continue;
+				}
+				// Water reaches from the stone up to the sea level
+				let (count, top) = columns[&(x, y)];
+				assert_eq!(top, sea_level, "{} {}", x, y);
+				assert_eq!(count, sea_level - (elev - 4) + 1, "{} {}", x, y);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_map_get_block() {
+	use crate::map::ClientBackend;
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let stone = params.p.block_roles.stone;
+
+	// Chunks that are present are read without involving the backend
+	let mut map = Map::<ClientBackend>::new();
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(air));
+	map.set_chunk(Vector3::new(-CHUNKSIZE, 0, 0), MapChunkData::filled_with(stone));
+	map.set_chunk(Vector3::new(0, -CHUNKSIZE, -CHUNKSIZE), MapChunkData::filled_with(stone));
+	assert_eq!(map.get_block(Vector3::new(0, 0, 0)), Some(air));
+	assert_eq!(map.get_block(Vector3::new(CHUNKSIZE - 1, 5, 5)), Some(air));
+	assert_eq!(map.get_block(Vector3::new(-1, 5, 5)), Some(stone));
+	assert_eq!(map.get_block(Vector3::new(-CHUNKSIZE, 0, 0)), Some(stone));
+	assert_eq!(map.get_block(Vector3::new(3, -1, -1)), Some(stone));
+	assert_eq!(map.get_block(Vector3::new(3, -1, 0)), None);
+	assert_eq!(map.get_block(Vector3::new(CHUNKSIZE, 0, 0)), None);
+	// The client backend can't generate anything
+	assert_eq!(map.loaded_chunks_count(), 3);
+
+	// Absent chunks get generated on access
+	let mapgen = MapgenMap::new(78, false, params.clone(), Box::new(NullStorageBackend));
+	let mut map = Map::from_backend(mapgen);
+	assert_eq!(map.get_blk(Vector3::new(-1, -1, -1)), None);
+	assert_eq!(map.get_block(Vector3::new(-1, -1, -1)), Some(air));
+	assert_eq!(map.get_blk(Vector3::new(-CHUNKSIZE, -CHUNKSIZE, -CHUNKSIZE)), Some(air));
+	assert_eq!(map.get_blk(Vector3::new(0, 0, 0)), None);
+	assert_eq!(map.get_block(Vector3::new(CHUNKSIZE, 0, 0)), Some(air));
+	assert_eq!(map.loaded_chunks_count(), 2);
+}
+
+#[cfg(test)]
+#[test]
+fn test_map_blocks_in_region() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let stone = params.p.block_roles.stone;
+	let mapgen = MapgenMap::new(78, false, params.clone(), Box::new(NullStorageBackend));
+	let mut map = Map::from_backend(mapgen);
+
+	// The region covers parts of chunks on both sides of zero
+	let min = Vector3::new(-3, -CHUNKSIZE - 2, -5);
+	let max = Vector3::new(CHUNKSIZE + 1, 4, 2);
+	let volume = (max - min).map(|v| v + 1).iter().product::<isize>();
+	assert_eq!(map.blocks_in_region(min, max).count(), volume as usize);
+	assert_eq!(map.loaded_chunks_count(), 3 * 3 * 2);
+	// The corners can be given in any order
+	let pos = Vector3::new(-1, 3, -4);
+	map.get_blk_mut(pos).unwrap().set(stone);
+	let blocks = map.blocks_in_region(max, min).collect::<Vec<_>>();
+	assert_eq!(blocks.len(), volume as usize);
+	assert!(blocks.iter().all(|(p, _)| (0 .. 3).all(|i| min[i] <= p[i] && p[i] <= max[i])));
+	let non_air = blocks.iter()
+		.filter(|(_, b)| *b != air)
+		.map(|(p, _)| *p)
+		.collect::<Vec<_>>();
+	assert_eq!(non_air, vec![pos]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_map_set_blocks() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use crate::map::MetadataEntry;
+
+	/// Counts how often each chunk got reported as changed
+	#[derive(Default)]
+	struct CountingBackend(HashMap<Vector3<isize>, usize>);
+
+	impl MapBackend for CountingBackend {
+		fn gen_chunks_in_area(&mut self, _pos_min :Vector3<isize>, _pos_max :Vector3<isize>) {}
+		fn run_for_generated_chunks<F :FnMut(Vector3<isize>, &MapChunkData)>(&mut self,
+				_f :&mut F) {}
+		fn chunk_changed(&mut self, pos :Vector3<isize>, _data :MapChunkData) {
+			*self.0.entry(pos).or_default() += 1;
+		}
+		fn set_player_kv(&mut self, _id :PlayerIdPair, _key :&str, _value :Vec<u8>) {}
+		fn get_player_kv(&mut self, _id: PlayerIdPair, _key :&str, _data :u32) {}
+		fn run_for_kv_results<F :FnMut(PlayerIdPair, u32, String, Option<Vec<u8>>)>(
+				&mut self, _f :&mut F) {}
+	}
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let stone = params.p.block_roles.stone;
+	let wood = params.p.block_roles.wood;
+	let mut map = Map::from_backend(CountingBackend::default());
+	let chunks = [Vector3::new(0, 0, 0), Vector3::new(CHUNKSIZE, 0, 0), Vector3::new(0, 0, CHUNKSIZE)];
+	for chunk_pos in chunks.iter() {
+		map.set_chunk(*chunk_pos, MapChunkData::filled_with(air));
+	}
+	map.get_blk_mut(Vector3::new(1, 1, 1)).unwrap().set(wood);
+	let inv = crate::inventory::SelectableInventory::new();
+	map.get_blk_meta_mut(Vector3::new(1, 1, 1)).unwrap().set(MetadataEntry::Inventory(inv.clone()));
+	map.backend_mut().0.clear();
+	let notified = Rc::new(RefCell::new(Vec::new()));
+	let notified_cl = notified.clone();
+	map.register_on_change(Box::new(move |chunk_pos, _chunk| {
+		notified_cl.borrow_mut().push(chunk_pos);
+	}));
+
+	let edits = vec![
+		(Vector3::new(1, 1, 1), stone),
+		(Vector3::new(CHUNKSIZE + 2, 3, 4), stone),
+		(Vector3::new(2, 2, 2), stone),
+		(Vector3::new(5, 5, CHUNKSIZE + 5), stone),
+		(Vector3::new(CHUNKSIZE + 5, 0, 0), stone),
+		// Not loaded
+		(Vector3::new(-1, 0, 0), stone),
+	];
+	let previous = map.set_blocks(&edits);
+	assert_eq!(previous, vec![Some(wood), Some(air), Some(air), Some(air), Some(air), None]);
+	for (pos, b) in edits[.. 5].iter() {
+		assert_eq!(map.get_blk(*pos), Some(*b));
+	}
+	assert_eq!(map.get_blk(Vector3::new(-1, 0, 0)), None);
+	// The metadata of the replaced block is gone
+	assert_eq!(map.get_blk_meta(Vector3::new(1, 1, 1)).map(|m| m.is_none()), Some(true));
+	// Each changed chunk is reported once
+	for chunk_pos in chunks.iter() {
+		assert_eq!(map.backend().0[chunk_pos], 1);
+		assert_eq!(map.chunk_version(*chunk_pos), Some(if *chunk_pos == chunks[0] { 3 } else { 1 }));
+	}
+	assert_eq!(map.backend().0.len(), 3);
+	assert_eq!(*notified.borrow(), chunks.to_vec());
+	assert_eq!(map.surface_height(5, 5), Some(CHUNKSIZE + 5));
+
+	// With a block change callback, it gets every single edit instead
+	let block_edits = Rc::new(RefCell::new(Vec::new()));
+	let block_edits_cl = block_edits.clone();
+	map.register_on_block_change(Box::new(move |pos, b, _| {
+		block_edits_cl.borrow_mut().push((pos, b));
+	}));
+	notified.borrow_mut().clear();
+	let edits = vec![
+		(Vector3::new(1, 1, 1), air),
+		(Vector3::new(-1, 0, 0), air),
+		(Vector3::new(2, 2, 2), air),
+	];
+	assert_eq!(map.set_blocks(&edits), vec![Some(stone), None, Some(stone)]);
+	assert_eq!(*block_edits.borrow(), vec![edits[0], edits[2]]);
+	assert!(notified.borrow().is_empty());
+	assert_eq!(map.backend().0[&chunks[0]], 2);
+
+	// Setting a single block removes the metadata the same way
+	let pos = Vector3::new(3, 3, 3);
+	map.get_blk_mut(pos).unwrap().set(wood);
+	map.get_blk_meta_mut(pos).unwrap().set(MetadataEntry::Inventory(inv));
+	notified.borrow_mut().clear();
+	map.get_blk_mut(pos).unwrap().set(wood);
+	assert!(map.get_blk_meta(pos).unwrap().is_some());
+	assert!(notified.borrow().is_empty());
+	map.get_blk_mut(pos).unwrap().set(stone);
+	assert_eq!(map.get_blk_meta(pos).map(|m| m.is_none()), Some(true));
+	assert_eq!(*notified.borrow(), vec![chunks[0]]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_schematic_into_absent_chunk() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let schematic = &params.p.schematics.tree_schematic;
+	let mut map = MapgenMap::new(78, true, params.clone(), Box::new(NullStorageBackend));
+	let chunk_pos = Vector3::new(0, 0, 0);
+	map.chunks.insert(chunk_pos, MapChunk {
+		data : MapChunkData::filled_with(air),
+		generation_phase : GenerationPhase::PhaseOne,
+		tree_spawn_points : Vec::new(),
+		decoration_points : Vec::new(),
+	});
+
+	// The tree sticks out of the top of the only chunk present
+	let pos = Vector3::new(8, 8, CHUNKSIZE - 2);
+	assert!(schematic.items.iter().any(|(bpos, _)| btchn(pos + bpos) != chunk_pos));
+	spawn_schematic_mapgen(&mut map, pos, schematic);
+	for (bpos, mb) in schematic.items.iter() {
+		let p = pos + bpos;
+		if btchn(p) == chunk_pos {
+			assert_eq!(map.get_blk_p1(p), Some(*mb), "{:?}", p);
+		} else {
+			assert_eq!(map.get_blk_p1(p), None, "{:?}", p);
+		}
+	}
+	assert_eq!(map.chunks.len(), 1);
+
+	// Phase two of absent chunks is skipped, too
+	map.gen_chunk_phase_two(Vector3::new(0, 0, CHUNKSIZE));
+	assert_eq!(map.chunks.len(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_large_schematic_padding() {
+	use crate::map_storage::NullStorageBackend;
+	use std::sync::Arc;
+
+	let mut params = Arc::try_unwrap(crate::game_params::test_params()).ok().unwrap();
+	let (tree, air) = (params.p.block_roles.tree, params.p.block_roles.air);
+	// Trees become columns higher than two chunks
+	let height = 2 * CHUNKSIZE + 8;
+	let items = (0 .. height).map(|z| (Vector3::new(0, 0, z), tree)).collect();
+	params.p.schematics.tree_schematic = Schematic::from_items(items);
+	let params = Arc::new(params);
+	let mut map = MapgenMap::new(78, true, params, Box::new(NullStorageBackend));
+
+	// The padding covers the largest schematic, but isn't below the minimum
+	assert_eq!(map.chunk_padding(), Vector3::new(1, 1, 3));
+	map.set_min_padding(2);
+	assert_eq!(map.chunk_padding(), Vector3::new(2, 2, 3));
+	map.set_min_padding(1);
+
+	// A single tree spawns in a chunk high above the terrain
+	let spawn_chunk = Vector3::new(0, 0, 8 * CHUNKSIZE);
+	let spawn = spawn_chunk + Vector3::new(5, 6, 10);
+	map.chunks.insert(spawn_chunk, MapChunk {
+		data : MapChunkData::filled_with(air),
+		generation_phase : GenerationPhase::PhaseOne,
+		tree_spawn_points : vec![(spawn, false)],
+		decoration_points : Vec::new(),
+	});
+	// Only request the chunk the top of the tree reaches into,
+	// three chunks above the one it spawns in
+	let top_chunk = btchn(spawn + Vector3::new(0, 0, height - 1));
+	assert_eq!(top_chunk, spawn_chunk + Vector3::new(0, 0, 3 * CHUNKSIZE));
+	map.gen_chunks_in_area(top_chunk, top_chunk, &mut |_, _| {});
+	for z in 0 .. height {
+		let pos = spawn + Vector3::new(0, 0, z);
+		assert_eq!(map.get_blk_p1(pos), Some(tree), "{:?}", pos);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_noise_plane_cache() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let mp = MapgenParams::default();
+	let seed = 78;
+
+	// The plane holds the same values as sampling each column
+	let plane_pos = Vector2::new(-128, 32);
+	let plane = NoisePlane::sample(seed, plane_pos, &mp);
+	let noises = PlaneNoises::new(seed, &mp);
+	for x in 0 .. CHUNKSIZE {
+		for y in 0 .. CHUNKSIZE {
+			let p = [(plane_pos.x + x) as f64, (plane_pos.y + y) as f64];
+			let idx = NoisePlane::idx(x, y);
+			assert_eq!(plane.elevation[idx], noises.elevation(p));
+			assert_eq!(plane.biome[idx], noises.biome(p));
+			assert_eq!(plane.tree_density[idx], noises.tree_density(p));
+		}
+	}
+
+	// Chunks generated from cached planes are identical
+	// to chunks generated with freshly sampled noise
+	let mut map = MapgenMap::new(seed, true, params.clone(), Box::new(NullStorageBackend));
+	let positions = (-2 .. 4)
+		.map(|z| Vector3::new(-128, 32, z * CHUNKSIZE))
+		.collect::<Vec<_>>();
+	for pos in positions.iter() {
+		map.gen_chunk_phase_one(*pos);
+	}
+	assert_eq!(map.noise_planes.len(), 1);
+	for pos in positions.iter() {
+		let cached = map.get_chunk_p1(*pos).unwrap();
+		let naive = gen_chunk_phase_one(seed, *pos, &params, &mp);
+		assert_eq!(cached.data.0[..], naive.data.0[..]);
+		assert_eq!(cached.tree_spawn_points, naive.tree_spawn_points);
+	}
+
+	// Changing the params invalidates the cache
+	map.set_mapgen_params(MapgenParams::default());
+	assert!(map.noise_planes.is_empty());
+}
+
+/// Compares generating chunk stacks with and without reusing the noise planes
+///
+/// Only checks that both give the same chunks, the timings are printed.
+/// Run with `cargo test --release -- --ignored --nocapture bench_noise_sampling`.
+#[cfg(test)]
+#[test]
+#[ignore]
+fn bench_noise_sampling() {
+	use std::time::Instant;
+
+	let params = crate::game_params::test_params();
+	let mp = MapgenParams::default();
+	let seed = 78;
+	let columns = 16;
+	let stack = 8;
+	let iterations = 5;
+
+	let bench = |f :&dyn Fn(Vector2<isize>) -> Vec<MapChunk>| {
+		let mut chunks = Vec::new();
+		let mut samples = (0 .. iterations)
+			.map(|_| {
+				chunks.clear();
+				let start = Instant::now();
+				for c in 0 .. columns {
+					chunks.extend(f(Vector2::new(c * CHUNKSIZE, 0)));
+				}
+				start.elapsed()
+			})
+			.collect::<Vec<_>>();
+		samples.sort();
+		(samples[samples.len() / 2], chunks)
+	};
+	let (naive, naive_chunks) = bench(&|plane_pos| {
+		(0 .. stack)
+			.map(|z| {
+				let pos = Vector3::new(plane_pos.x, plane_pos.y, (z - stack / 2) * CHUNKSIZE);
+				gen_chunk_phase_one(seed, pos, &params, &mp)
+			})
+			.collect()
+	});
+	let (cached, cached_chunks) = bench(&|plane_pos| {
+		let plane = NoisePlane::sample(seed, plane_pos, &mp);
+		(0 .. stack)
+			.map(|z| {
+				let pos = Vector3::new(plane_pos.x, plane_pos.y, (z - stack / 2) * CHUNKSIZE);
+				gen_chunk_phase_one_plane(seed, pos, &params, &mp, &plane)
+			})
+			.collect()
+	});
+	assert_eq!(cached_chunks.len(), naive_chunks.len());
+	for (cached, naive) in cached_chunks.iter().zip(naive_chunks.iter()) {
+		assert_eq!(cached.data.0[..], naive.data.0[..]);
+		assert_eq!(cached.tree_spawn_points, naive.tree_spawn_points);
+	}
+	println!("median of {:?} with cached planes, {:?} without, per {} chunks",
+		cached, naive, columns * stack);
+}
+
+#[cfg(test)]
+#[test]
+fn test_generation_disabled() {
+	use crate::map_storage::{SqliteStorageBackend, StorageBackend};
+	use rusqlite::Connection;
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let stored_pos = Vector3::new(0, 0, 0);
+	let absent_pos = Vector3::new(CHUNKSIZE, 0, 0);
+
+	let conn = Connection::open_in_memory().unwrap();
+	let mut storage = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	storage.store_chunk(stored_pos, &MapChunkData::filled_with(roles.stone)).unwrap();
+
+	let mut map = MapgenMap::new(78, false, params.clone(), Box::new(storage));
+	let mut emitted = HashMap::new();
+	map.gen_chunks_in_area(Vector3::new(0, 0, 0), Vector3::new(2, 2, 2) * CHUNKSIZE,
+		&mut |pos, chk| {
+			emitted.insert(pos, chk.clone());
+		});
+
+	let stored = &emitted[&stored_pos];
+	assert!(stored.0.iter().all(|b| *b == roles.stone));
+	let absent = &emitted[&absent_pos];
+	assert!(absent.0.iter().all(|b| *b == roles.air));
+}
+
+#[cfg(test)]
+#[test]
+fn test_pregen() {
+	use crate::map_storage::SqliteStorageBackend;
+	use rusqlite::Connection;
+
+	let params = crate::game_params::test_params();
+	let m = &params.p.name_id_map;
+	let conn = Connection::open_in_memory().unwrap();
+	let storage = SqliteStorageBackend::from_conn(conn, true).unwrap();
+	let mut map = MapgenMap::new(78, true, params.clone(), Box::new(storage));
+
+	let center = Vector3::new(40, -20, 5);
+	let (radius, zradius) = (1, 1);
+	let mut progress = Vec::new();
+	map.pregen(center, radius, zradius, |done, total| progress.push((done, total))).unwrap();
+	assert_eq!(progress.len(), 9);
+	assert_eq!(progress.last(), Some(&(9, 9)));
+
+	// Exactly the chunks within the radius are stored
+	let center_chunk = btchn(center);
+	let mut stored = 0;
+	for x in -2 ..= 2 {
+		for y in -2 ..= 2 {
+			for z in -2 ..= 2 {
+				let pos = center_chunk + Vector3::new(x, y, z) * CHUNKSIZE;
+				let within = x.abs() <= radius && y.abs() <= radius && z.abs() <= zradius;
+				let chunk = map.storage.load_chunk(pos, m).unwrap();
+				assert_eq!(chunk.is_some(), within, "{:?}", pos);
+				if chunk.is_some() {
+					stored += 1;
+				}
+			}
+		}
+	}
+	assert_eq!(stored, 27);
+}
+
+#[cfg(test)]
+#[test]
+fn test_container_persistence() {
+	use crate::map_storage::SqliteStorageBackend;
+	use crate::map::MetadataEntry;
+	use crate::inventory::{SelectableInventory, Stack};
+	use crate::game_params::{NameIdMap, load_params_failible};
+
+	let params = std::sync::Arc::new(load_params_failible(NameIdMap::builtin_name_list()).unwrap());
+	let roles = &params.p.block_roles;
+	let chest = params.p.name_id_map.get_id("default:chest").unwrap();
+	let dir = crate::map_storage::TestDir::new("containers");
+	let path = dir.join("map.sqlite");
+
+	let chunk_pos = Vector3::new(CHUNKSIZE, 2 * CHUNKSIZE, 0);
+	let pos = chunk_pos + Vector3::new(3, 4, 5);
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.put(Stack::with(roles.stone, 12));
+	inv.put(Stack::with(roles.wood, 3));
+
+	let open_map = || {
+		let storage = SqliteStorageBackend::open_or_create(path.clone()).unwrap();
+		let mut map = Map::new_sync(78, params.clone(), Box::new(storage));
+		map.gen_chunks_in_area(chunk_pos, chunk_pos);
+		map.tick();
+		map
+	};
+	{
+		let mut map = open_map();
+		map.get_blk_mut(pos).unwrap().set(chest);
+		map.get_blk_meta_mut(pos).unwrap().set(MetadataEntry::Inventory(inv.clone()));
+		// Commit the stored chunks
+		map.tick();
+	}
+
+	// After reopening, the contents are back at the same position
+	let map = open_map();
+	assert_eq!(map.get_blk(pos), Some(chest));
+	match map.get_blk_meta(pos) {
+		Some(Some(MetadataEntry::Inventory(i))) => assert_eq!(i, &inv),
+		_ => panic!("container inventory missing"),
+	}
+	let neighbour = pos + Vector3::new(1, 0, 0);
+	assert_eq!(map.get_blk_meta(neighbour).map(|m| m.is_none()), Some(true));
+}
+
+/// Synchronous backend, mainly useful for tests and tools
+impl MapBackend for MapgenMap {
+	fn gen_chunks_in_area(&mut self, pos_min :Vector3<isize>,
+			pos_max :Vector3<isize>) {
+		let mut generated = take(&mut self.generated);
+		MapgenMap::gen_chunks_in_area(self, pos_min, pos_max, &mut |pos, chk| {
+			generated.push((pos, chk.clone()));
+		});
+		self.generated = generated;
+	}
+	fn run_for_generated_chunks<F :FnMut(Vector3<isize>, &MapChunkData)>(&mut self,
+			f :&mut F) {
+		for (pos, chk) in self.generated.drain(..) {
+			f(pos, &chk);
+		}
+		self.storage.tick().unwrap();
+	}
+	fn chunk_changed(&mut self, pos :Vector3<isize>, data :MapChunkData) {
+		self.storage.store_chunk(pos, &data).unwrap();
+	}
+	fn set_player_kv(&mut self, id :PlayerIdPair, key :&str, value :Vec<u8>) {
+		self.storage.set_player_kv(id, key, &value).unwrap();
+	}
+	fn get_player_kv(&mut self, id: PlayerIdPair, key :&str, payload :u32) {
+		let res = self.storage.get_player_kv(id, key).unwrap();
+		self.kv_results.push((id, payload, key.to_owned(), res));
+	}
+	fn run_for_kv_results<F :FnMut(PlayerIdPair, u32, String, Option<Vec<u8>>)>(&mut self, f :&mut F) {
+		for (id, payload, key, value) in self.kv_results.drain(..) {
+			f(id, payload, key, value);
+		}
+	}
+}
+
+impl Map<MapgenMap> {
+	pub fn new_sync(seed :u64, params :ServerGameParamsHdl,
+			storage :DynStorageBackend) -> Self {
+		Map::from_backend(MapgenMap::new(seed, true, params, storage))
+	}
+}
+
+/// Highest z coordinate considered when searching for a spawn position
+const SPAWN_SEARCH_TOP :isize = 6 * CHUNKSIZE;
+/// Lowest z coordinate considered when searching for a spawn position
+const SPAWN_SEARCH_BOTTOM :isize = -CHUNKSIZE;
+/// How many chunk columns away from the start to search at most
+const SPAWN_SEARCH_RADIUS :isize = 8;
+/// Distance from the feet to the eyes of a player
+const PLAYER_EYE_HEIGHT :f32 = 1.6;
+
+/// Finds the topmost surface of the given column that a player can stand on
+///
+/// Returns the z coordinate of the surface block.
+fn find_spawn_in_column<B :MapBackend>(map :&Map<B>, x :isize, y :isize,
+		params :&ServerGameParamsHdl) -> Option<isize> {
+	let water = params.p.block_roles.water;
+	let mut free_above = 0;
+	for z in (SPAWN_SEARCH_BOTTOM .. SPAWN_SEARCH_TOP).rev() {
+		let blk = map.get_blk(Vector3::new(x, y, z))?;
+		if blk == water {
+			// Spawning underwater is not allowed
+			return None;
+		}
+		if !is_solid(blk, &params.p) {
+			free_above += 1;
+			continue;
+		}
+		// The player is two blocks tall
+		if free_above >= 2 {
+			return Some(z);
+		}
+		return None;
+	}
+	None
+}
+
+/// Finds a position for new players to spawn at
+///
+/// Generates the area around the given position and returns
+/// the position (of the eyes) of a player standing on the topmost
+/// solid, non-water surface. If there is no such surface, e.g.
+/// because everything is covered by water, the search continues
+/// outwards, chunk column by chunk column.
+pub fn find_spawn(map :&mut Map<MapgenMap>, around :Vector3<isize>) -> Vector3<f32> {
+	let params = map.backend().params.clone();
+	let center = btchn(around);
+	for ring in 0 ..= SPAWN_SEARCH_RADIUS {
+		let mut best :Option<(isize, Vector3<isize>)> = None;
+		for cx in -ring ..= ring {
+			for cy in -ring ..= ring {
+				if cx.abs() != ring && cy.abs() != ring {
+					// Only the chunk columns at the border of the ring
+					continue;
+				}
+				let chunk_pos = center + Vector3::new(cx, cy, 0) * CHUNKSIZE;
+				let pos_min = Vector3::new(chunk_pos.x, chunk_pos.y, SPAWN_SEARCH_BOTTOM);
+				let pos_max = Vector3::new(chunk_pos.x + CHUNKSIZE,
+					chunk_pos.y + CHUNKSIZE, SPAWN_SEARCH_TOP);
+				map.gen_chunks_in_area(pos_min, pos_max);
+				map.tick();
+				for x in pos_min.x .. pos_max.x {
+					for y in pos_min.y .. pos_max.y {
+						let z = if let Some(z) = find_spawn_in_column(map, x, y, &params) {
+							z
+						} else {
+							continue;
+						};
+						let pos = Vector3::new(x, y, z);
+						let d = (pos.xy() - around.xy()).abs().sum();
+						if best.map(|(bd, _)| d < bd).unwrap_or(true) {
+							best = Some((d, pos));
+						}
+					}
+				}
+			}
+		}
+		if let Some((_, pos)) = best {
+			return standing_pos(pos);
+		}
+	}
+	// Nothing found, fall back to a position high above the start
+	Vector3::new(around.x as f32 + 0.5, around.y as f32 + 0.5,
+		SPAWN_SEARCH_TOP as f32)
+}
+
+/// The eye position of a player standing on the given surface block
+fn standing_pos(surface :Vector3<isize>) -> Vector3<f32> {
+	Vector3::new(surface.x as f32 + 0.5, surface.y as f32 + 0.5,
+		(surface.z + 1) as f32 + PLAYER_EYE_HEIGHT)
+}
+
+/// How many random columns to try when scattering a spawn position
+const SPAWN_SCATTER_TRIES :usize = 16;
+
+/// Picks a random position within `radius` blocks horizontally of the spawn
+///
+/// Only columns that are loaded and have a surface to stand on
+/// are considered. If none is found, the spawn itself is returned.
+pub fn scatter_spawn<B :MapBackend>(map :&Map<B>, spawn :Vector3<f32>, radius :isize,
+		params :&ServerGameParamsHdl, rng :&mut impl Rng) -> Vector3<f32> {
+	if radius <= 0 {
+		return spawn;
+	}
+	let center = spawn.map(|v| v.floor() as isize);
+	for _ in 0 .. SPAWN_SCATTER_TRIES {
+		let dx = rng.gen_range(-radius, radius + 1);
+		let dy = rng.gen_range(-radius, radius + 1);
+		if dx * dx + dy * dy > radius * radius {
+			continue;
+		}
+		let (x, y) = (center.x + dx, center.y + dy);
+		if let Some(z) = find_spawn_in_column(map, x, y, params) {
+			return standing_pos(Vector3::new(x, y, z));
+		}
+	}
+	spawn
+}
+
+#[cfg(test)]
+#[test]
+fn test_scatter_spawn() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let mut map = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	let spawn = find_spawn(&mut map, Vector3::new(60, 40, 0));
+	let radius = 12;
+	let center = spawn.map(|v| v.floor() as isize);
+	map.gen_chunks_in_area(
+		Vector3::new(center.x - radius, center.y - radius, SPAWN_SEARCH_BOTTOM),
+		Vector3::new(center.x + radius, center.y + radius, SPAWN_SEARCH_TOP));
+	map.tick();
+
+	let mut rng = Pcg32::new(42, 7);
+	assert_eq!(scatter_spawn(&map, spawn, 0, &params, &mut rng), spawn);
+	let mut scattered = false;
+	for _ in 0 .. 100 {
+		let pos = scatter_spawn(&map, spawn, radius, &params, &mut rng);
+		let d = (pos - spawn).xy();
+		assert!(d.norm() <= radius as f32 + 0.01, "{:?} too far from {:?}", pos, spawn);
+		scattered |= pos != spawn;
+
+		let below = Vector3::new(pos.x.floor() as isize, pos.y.floor() as isize,
+			(pos.z - PLAYER_EYE_HEIGHT).round() as isize - 1);
+		let blk_below = map.get_blk(below).unwrap();
+		assert!(blk_below != params.p.block_roles.water);
+		assert!(params.p.get_block_params(blk_below).unwrap().solid);
+		for dz in 1 ..= 2 {
+			let blk = map.get_blk(below + Vector3::new(0, 0, dz)).unwrap();
+			assert!(!params.p.get_block_params(blk).unwrap().solid);
+		}
+	}
+	assert!(scattered);
+
+	// Without any loaded columns, the spawn itself is used
+	let map = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	assert_eq!(scatter_spawn(&map, spawn, radius, &params, &mut rng), spawn);
+}
+
+#[cfg(test)]
+#[test]
+fn test_find_spawn() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let mut map = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	let around = Vector3::new(60, 40, 0);
+	let spawn = find_spawn(&mut map, around);
+
+	// Deterministic
+	let mut map2 = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	assert_eq!(find_spawn(&mut map2, around), spawn);
+
+	let below = Vector3::new(spawn.x.floor() as isize, spawn.y.floor() as isize,
+		(spawn.z - PLAYER_EYE_HEIGHT).round() as isize - 1);
+	let blk_below = map.get_blk(below).unwrap();
+	let roles = &params.p.block_roles;
+	assert!(blk_below != roles.water);
+	assert!(params.p.get_block_params(blk_below).unwrap().solid);
+	for dz in 1 ..= 2 {
+		let blk = map.get_blk(below + Vector3::new(0, 0, dz)).unwrap();
+		assert!(!params.p.get_block_params(blk).unwrap().solid);
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_surface_height() {
+	use crate::map_storage::NullStorageBackend;
+
+	let params = crate::game_params::test_params();
+	let air = params.p.block_roles.air;
+	let mut map = Map::new_sync(78, params.clone(), Box::new(NullStorageBackend));
+	map.gen_chunks_in_area(Vector3::new(0, 0, -2 * CHUNKSIZE),
+		Vector3::new(CHUNKSIZE, CHUNKSIZE, 2 * CHUNKSIZE));
+	map.tick();
+
+	let scan = |map :&Map<MapgenMap>, x, y| {
+		(-8 * CHUNKSIZE .. 8 * CHUNKSIZE).rev()
+			.find(|z| map.get_blk(Vector3::new(x, y, *z)).map(|b| b != air).unwrap_or(false))
+	};
+	for x in (0 .. 2 * CHUNKSIZE).step_by(3) {
+		for y in (0 .. 2 * CHUNKSIZE).step_by(5) {
+			assert_eq!(map.surface_height(x, y), scan(&map, x, y), "{} {}", x, y);
+		}
+	}
+	assert_eq!(map.surface_height(-100 * CHUNKSIZE, 0), None);
+
+	// Removing the top block updates the height
+	let (x, y) = (7, 9);
+	let top = map.surface_height(x, y).unwrap();
+	map.get_blk_mut(Vector3::new(x, y, top)).unwrap().set(air);
+	assert_eq!(map.surface_height(x, y), scan(&map, x, y));
+	assert!(map.surface_height(x, y) < Some(top));
+
+	// Placing a block above raises it again
+	let stone = params.p.block_roles.stone;
+	map.get_blk_mut(Vector3::new(x, y, top + 1)).unwrap().set(stone);
+	assert_eq!(map.surface_height(x, y), Some(top + 1));
+}
+
 pub enum MapgenMsg {
-	ChunkChanged(Vector3<isize>, MapChunkData),
+	ChunkChanged(Vector3<isize>, Box<MapChunkData>),
 	Tick,
 	GenArea(Vector3<isize>, Vector3<isize>),
 	SetPlayerKv(PlayerIdPair, String, Vec<u8>),
 	GetPlayerKv(PlayerIdPair, String, u32),
+	SetMapgenParams(MapgenParams),
+	SetMinPadding(isize),
+	SetGlobalKv(String, Vec<u8>),
 }
 
 pub struct MapgenThread {
@@ -497,9 +1953,9 @@ pub struct MapgenThread {
 }
 
 impl MapgenThread {
-	pub fn new(seed :u64, params :ServerGameParamsHdl,
+	pub fn new(seed :u64, generate_terrain :bool, params :ServerGameParamsHdl,
 			storage :DynStorageBackend) -> Self {
-		let mut mapgen_map = MapgenMap::new(seed, params, storage);
+		let mut mapgen_map = MapgenMap::new(seed, generate_terrain, params, storage);
 		let (area_s, area_r) = channel();
 		let (result_s, result_r) = channel();
 		let (result_kv_s, result_kv_r) = channel();
@@ -513,6 +1969,7 @@ impl MapgenThread {
 						mapgen_map.storage.tick().unwrap();
 					},
 					MapgenMsg::GenArea(pos_min, pos_max) => {
+						debug!(target : TARGET_MAPGEN, "Generating the area {:?} to {:?}", pos_min, pos_max);
 						mapgen_map.gen_chunks_in_area(pos_min, pos_max, &mut |pos, chk|{
 							result_s.send((pos, chk.clone())).unwrap();
 						})
@@ -524,6 +1981,15 @@ impl MapgenThread {
 						let res = mapgen_map.storage.get_player_kv(id, &key).unwrap();
 						result_kv_s.send((id, payload, key, res)).unwrap();
 					},
+					MapgenMsg::SetMapgenParams(mapgen_params) => {
+						mapgen_map.set_mapgen_params(mapgen_params);
+					},
+					MapgenMsg::SetMinPadding(min_padding) => {
+						mapgen_map.set_min_padding(min_padding);
+					},
+					MapgenMsg::SetGlobalKv(key, content) => {
+						mapgen_map.storage.set_global_kv(&key, &content).unwrap();
+					},
 				}
 			}
 		});
@@ -533,6 +1999,15 @@ impl MapgenThread {
 			result_kv_r,
 		}
 	}
+	pub fn set_mapgen_params(&mut self, mapgen_params :MapgenParams) {
+		self.area_s.send(MapgenMsg::SetMapgenParams(mapgen_params)).unwrap();
+	}
+	pub fn set_min_padding(&mut self, min_padding :isize) {
+		self.area_s.send(MapgenMsg::SetMinPadding(min_padding)).unwrap();
+	}
+	pub fn set_global_kv(&mut self, key :&str, value :Vec<u8>) {
+		self.area_s.send(MapgenMsg::SetGlobalKv(key.to_owned(), value)).unwrap();
+	}
 }
 
 impl MapBackend for MapgenThread {
@@ -549,7 +2024,7 @@ impl MapBackend for MapgenThread {
 		self.area_s.send(MapgenMsg::Tick).unwrap();
 	}
 	fn chunk_changed(&mut self, pos :Vector3<isize>, data :MapChunkData) {
-		self.area_s.send(MapgenMsg::ChunkChanged(pos, data)).unwrap();
+		self.area_s.send(MapgenMsg::ChunkChanged(pos, Box::new(data))).unwrap();
 	}
 	fn set_player_kv(&mut self, id :PlayerIdPair, key :&str, value :Vec<u8>) {
 		self.area_s.send(MapgenMsg::SetPlayerKv(id, key.to_owned(), value)).unwrap();
@@ -565,8 +2040,8 @@ impl MapBackend for MapgenThread {
 }
 
 impl Map<MapgenThread> {
-	pub fn new(seed :u64, params :ServerGameParamsHdl,
+	pub fn new(seed :u64, generate_terrain :bool, params :ServerGameParamsHdl,
 			storage :DynStorageBackend) -> Self {
-		Map::from_backend(MapgenThread::new(seed, params, storage))
+		Map::from_backend(MapgenThread::new(seed, generate_terrain, params, storage))
 	}
 }
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::str;
+use toml::{from_str, to_string};
+use super::StrErr;
+
+/// The age of the world and the playtime of each player, in ticks
+///
+/// It is stored in the global kv of the map storage as TOML,
+/// with the playtime of each nick.
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Playtime {
+	world_age :u64,
+	players :BTreeMap<String, u64>,
+}
+
+impl Playtime {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// The number of ticks the world has been running for
+	pub fn world_age(&self) -> u64 {
+		self.world_age
+	}
+	/// The number of ticks the player has been connected for
+	pub fn player(&self, nick :&str) -> u64 {
+		self.players.get(nick).copied().unwrap_or(0)
+	}
+	/// Advances the world age and the playtime of the connected players by a tick
+	pub fn tick<'a>(&mut self, connected :impl IntoIterator<Item = &'a str>) {
+		self.world_age += 1;
+		for nick in connected {
+			*self.players.entry(nick.to_owned()).or_default() += 1;
+		}
+	}
+	pub fn serialize(&self) -> Result<Vec<u8>, StrErr> {
+		Ok(to_string(self)?.into_bytes())
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		Ok(from_str(str::from_utf8(buf)?)?)
+	}
+}
+
+/// Formats a number of ticks as duration, like `1d 2h 3m 4s`
+///
+/// Leading zero units are left out.
+pub fn format_ticks(ticks :u64, tick_rate :u32) -> String {
+	let secs = ticks / tick_rate.max(1) as u64;
+	let units = [
+		(secs / 86400, "d"),
+		(secs / 3600 % 24, "h"),
+		(secs / 60 % 60, "m"),
+		(secs % 60, "s"),
+	];
+	let first = units.iter()
+		.position(|(v, _)| *v > 0)
+		.unwrap_or(units.len() - 1);
+	units[first..].iter()
+		.map(|(v, unit)| format!("{}{}", v, unit))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_ticks() {
+	assert_eq!(format_ticks(0, 60), "0s");
+	assert_eq!(format_ticks(59, 60), "0s");
+	assert_eq!(format_ticks(60 * 45, 60), "45s");
+	assert_eq!(format_ticks(20 * 61, 20), "1m 1s");
+	assert_eq!(format_ticks(3600, 1), "1h 0m 0s");
+	assert_eq!(format_ticks((86400 + 2 * 3600 + 3 * 60 + 4) * 10, 10), "1d 2h 3m 4s");
+}
+
+#[cfg(test)]
+#[test]
+fn test_playtime() {
+	let mut playtime = Playtime::new();
+	playtime.tick(vec!["alice"]);
+	playtime.tick(vec!["alice", "bob"]);
+	// Playtime only accumulates while connected
+	playtime.tick(vec!["bob"]);
+	playtime.tick(None);
+	assert_eq!(playtime.world_age(), 4);
+	assert_eq!(playtime.player("alice"), 2);
+	assert_eq!(playtime.player("bob"), 2);
+	assert_eq!(playtime.player("carol"), 0);
+	// Reconnecting continues from the previous playtime
+	playtime.tick(vec!["alice"]);
+	assert_eq!(playtime.player("alice"), 3);
+
+	let round_trip = Playtime::deserialize(&playtime.serialize().unwrap()).unwrap();
+	assert_eq!(round_trip, playtime);
+}
@@ -1,12 +1,17 @@
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
 use crate::StrErr;
-use quinn::RecvStream;
+use crate::logging::TARGET_NET;
+use crate::fragment::{Fragmenter, Reassembler, REASSEMBLY_TIMEOUT};
+use quinn::{RecvStream, SendStream};
 use quinn::generic::EndpointBuilder;
 use quinn::crypto::rustls::TlsSession;
 use crate::generic_net::{MsgStream, NetErr, MsgStreamClientConn,
-	MsgStreamServerConn, NetworkServerSocket};
-use std::sync::Arc;
+	MsgStreamServerConn, NetworkServerSocket, next_recv_seq};
+use std::sync::{Arc, Mutex};
 
 use std::thread;
 
@@ -27,7 +32,7 @@ macro_rules! ltry {
 		match $f {
 			Ok(v) => v,
 			Err(e) => {
-				eprintln!("Net Error: {:?}", e);
+				error!(target : TARGET_NET, "{:?}", e);
 				$e
 			}
 		}
@@ -48,7 +53,136 @@ impl rustls::ServerCertVerifier for NullVerifier {
 	}
 }
 
-fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Result<(), StrErr> {
+/// How long a cached session ticket is considered usable.
+///
+/// Servers only keep their session state for a limited time,
+/// so there is no point in trying to resume with tickets that
+/// are older than this.
+const TICKET_LIFETIME :Duration = Duration::from_secs(60 * 60);
+
+/// Cache for TLS session tickets, keyed by server address
+///
+/// All servers present themselves with the same "mimas-host"
+/// name, so we can't rely on the name based lookup of rustls
+/// and instead key the tickets by the address of the server.
+pub struct TicketCache {
+	lifetime :Duration,
+	tickets :Mutex<TicketMap>,
+}
+
+type TicketMap = HashMap<(SocketAddr, Vec<u8>), (Vec<u8>, Instant)>;
+
+impl Default for TicketCache {
+	fn default() -> Self {
+		Self::with_lifetime(TICKET_LIFETIME)
+	}
+}
+
+impl TicketCache {
+	pub fn with_lifetime(lifetime :Duration) -> Self {
+		Self {
+			lifetime,
+			tickets : Mutex::new(HashMap::new()),
+		}
+	}
+	pub fn put(&self, addr :SocketAddr, key :Vec<u8>, value :Vec<u8>, now :Instant) {
+		let mut tickets = self.tickets.lock().unwrap();
+		tickets.insert((addr, key), (value, now));
+	}
+	/// Looks up a ticket, removing it if it has expired
+	pub fn get(&self, addr :SocketAddr, key :&[u8], now :Instant) -> Option<Vec<u8>> {
+		let mut tickets = self.tickets.lock().unwrap();
+		let k = (addr, key.to_vec());
+		let expired = match tickets.get(&k) {
+			Some((_value, stored)) => now.duration_since(*stored) >= self.lifetime,
+			None => return None,
+		};
+		if expired {
+			tickets.remove(&k);
+			return None;
+		}
+		tickets.get(&k).map(|(value, _stored)| value.clone())
+	}
+	/// Removes all tickets stored for the given server
+	///
+	/// Used when the server rejected our resumption attempt,
+	/// so that the next attempt does a full handshake.
+	pub fn forget(&self, addr :SocketAddr) {
+		let mut tickets = self.tickets.lock().unwrap();
+		tickets.retain(|(a, _key), _| *a != addr);
+	}
+	pub fn has_tickets_for(&self, addr :SocketAddr, now :Instant) -> bool {
+		let tickets = self.tickets.lock().unwrap();
+		tickets.iter()
+			.any(|((a, _key), (_value, stored))| {
+				*a == addr && now.duration_since(*stored) < self.lifetime
+			})
+	}
+}
+
+/// Adapter handing a [`TicketCache`] to rustls for one server address
+struct AddrSessionStore {
+	addr :SocketAddr,
+	cache :Arc<TicketCache>,
+}
+
+impl rustls::StoresClientSessions for AddrSessionStore {
+	fn put(&self, key :Vec<u8>, value :Vec<u8>) -> bool {
+		self.cache.put(self.addr, key, value, Instant::now());
+		true
+	}
+	fn get(&self, key :&[u8]) -> Option<Vec<u8>> {
+		self.cache.get(self.addr, key, Instant::now())
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_ticket_cache() {
+	let cache = TicketCache::with_lifetime(Duration::from_secs(10));
+	let addr_a :SocketAddr = "127.0.0.1:7700".parse().unwrap();
+	let addr_b :SocketAddr = "127.0.0.1:7701".parse().unwrap();
+	let start = Instant::now();
+
+	// Store and lookup
+	cache.put(addr_a, b"key".to_vec(), b"ticket-a".to_vec(), start);
+	assert_eq!(cache.get(addr_a, b"key", start), Some(b"ticket-a".to_vec()));
+	// Tickets are keyed by address
+	assert_eq!(cache.get(addr_b, b"key", start), None);
+	assert!(cache.has_tickets_for(addr_a, start));
+	assert!(!cache.has_tickets_for(addr_b, start));
+
+	// Still valid shortly before expiry
+	let later = start + Duration::from_secs(9);
+	assert_eq!(cache.get(addr_a, b"key", later), Some(b"ticket-a".to_vec()));
+
+	// Expired tickets are not returned and get removed
+	let expired = start + Duration::from_secs(10);
+	assert!(!cache.has_tickets_for(addr_a, expired));
+	assert_eq!(cache.get(addr_a, b"key", expired), None);
+	assert_eq!(cache.get(addr_a, b"key", start), None);
+
+	// Rejection: forgetting only affects the given server
+	cache.put(addr_a, b"key".to_vec(), b"ticket-a".to_vec(), start);
+	cache.put(addr_b, b"key".to_vec(), b"ticket-b".to_vec(), start);
+	cache.forget(addr_a);
+	assert_eq!(cache.get(addr_a, b"key", start), None);
+	assert_eq!(cache.get(addr_b, b"key", start), Some(b"ticket-b".to_vec()));
+
+	// The adapter for rustls stores tickets under its server's address
+	use rustls::StoresClientSessions;
+	let store = AddrSessionStore {
+		addr : addr_a,
+		cache : Arc::new(TicketCache::default()),
+	};
+	assert!(store.put(b"key".to_vec(), b"ticket-a".to_vec()));
+	assert_eq!(store.get(b"key"), Some(b"ticket-a".to_vec()));
+	assert!(store.cache.has_tickets_for(addr_a, Instant::now()));
+	assert!(!store.cache.has_tickets_for(addr_b, Instant::now()));
+}
+
+fn run_quinn_server(addr :&SocketAddr, fragment_size :usize,
+		conn_send :Sender<QuicServerConn>) -> Result<(), StrErr> {
 
 	let mut server_config = quinn::generic::ServerConfigBuilder::default();
 	let cert = rcgen::generate_simple_self_signed(vec!["mimas-host".into()])?;
@@ -95,7 +229,7 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 				} else {
 					break;
 				};
-				let (msg_stream, mut rcv, snd) = QuicMsgStream::new();
+				let (msg_stream, mut rcv, snd) = QuicMsgStream::new(fragment_size);
 
 				let conn = QuicServerConn {
 					stream : msg_stream,
@@ -112,7 +246,7 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 				}
 				// Gracefully terminate the stream
 				if let Err(e) = wtr.shutdown().await {
-					eprintln!("failed to shutdown stream: {}", e);
+					warn!(target : TARGET_NET, "failed to shutdown stream: {}", e);
 				}
 				break;
 			} });
@@ -121,12 +255,15 @@ fn run_quinn_server(addr :&SocketAddr, conn_send :Sender<QuicServerConn>) -> Res
 	Ok(())
 }
 
-async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
+/// A received message along with its sequence number
+type SeqMsg = (u64, Vec<u8>);
+
+async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<SeqMsg>) {
 	loop {
 		let mut len_buf = [0; 8];
 		if let Err(e) = rdr.read_exact(&mut len_buf).await {
 			if quinn::ReadExactError::FinishedEarly != e {
-				eprintln!("Net error: {:?}", e);
+				error!(target : TARGET_NET, "{:?}", e);
 			} else {
 				// Graceful termination of the stream,
 				// don't print an error.
@@ -137,22 +274,23 @@ async fn msg_rcv_task(mut rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
 		let len = u64::from_be_bytes(len_buf) as usize;
 		let mut buf = vec![0; len];
 		ltry!(rdr.read_exact(&mut buf).await; break);
-		ltry!(to_receive.send(buf); break);
+		ltry!(to_receive.send((next_recv_seq(), buf)); break);
 	}
 }
 
-fn spawn_msg_rcv_task(rdr :RecvStream, to_receive :Sender<Vec<u8>>) {
+fn spawn_msg_rcv_task(rdr :RecvStream, to_receive :Sender<SeqMsg>) {
 	tokio::spawn(msg_rcv_task(rdr, to_receive));
 }
 
-fn run_quinn_client(url :impl ToSocketAddrs,
-		mut to_send :UnboundedReceiver<Vec<u8>>, to_receive :Sender<Vec<u8>>) -> Result<(), StrErr> {
+fn run_quinn_client(url :impl ToSocketAddrs, tickets :Arc<TicketCache>,
+		mut to_send :UnboundedReceiver<Vec<u8>>, to_receive :Sender<SeqMsg>) -> Result<(), StrErr> {
 	let url = url.to_socket_addrs()?.next().expect("socket addr expected");
 
 	let mut endpoint = EndpointBuilder::default();
 	let mut client_config = quinn::generic::ClientConfigBuilder::<TlsSession>::default();
 
 	client_config.protocols(&[b"mimas"]);
+	client_config.enable_0rtt();
 
 	let mut client_config = client_config.build();
 
@@ -166,6 +304,13 @@ fn run_quinn_client(url :impl ToSocketAddrs,
 	Arc::get_mut(&mut client_config.crypto).unwrap().dangerous()
 		.set_certificate_verifier(Arc::new(NullVerifier));
 
+	// Resume earlier sessions with the same server if possible
+	Arc::get_mut(&mut client_config.crypto).unwrap()
+		.set_persistence(Arc::new(AddrSessionStore {
+			addr : url,
+			cache : tickets.clone(),
+		}));
+
 	endpoint.default_client_config(client_config);
 
 	let mut runtime = runtime::Builder::new()
@@ -175,55 +320,74 @@ fn run_quinn_client(url :impl ToSocketAddrs,
 
 	let listen_addr = "[::]:0".parse().unwrap();
 
-	runtime.block_on(async { loop {
+	runtime.block_on(async {
 		let (endpoint, _incoming) = endpoint.bind(&listen_addr)?;
-		let endpoint_future = endpoint.connect(
+		let connecting = endpoint.connect(
 			&url,
 			"mimas-host"
 		)?;
-		let new_conn = match endpoint_future.await {
-			Ok(new_conn) => new_conn,
-			Err(e) => {
-				eprintln!("Net Error: {:?}", e);
-				break Ok(());
+		// If we have a ticket for this server, the session gets resumed.
+		// We never send 0-RTT data though: an attacker could replay it,
+		// and none of our messages, starting with the login, are safe
+		// to be applied twice. Everything waits until the handshake
+		// is confirmed.
+		let new_conn = match connecting.into_0rtt() {
+			Ok((new_conn, accepted)) => {
+				if !accepted.await {
+					// Do a full handshake next time
+					info!(target : TARGET_NET, "session resumption rejected.");
+					tickets.forget(url);
+				}
+				new_conn
 			},
-		};
-		println!("connected to server.");
-		let stream = new_conn.connection.open_bi();
-		let (mut wtr, rdr) = match stream.await {
-			Ok(stream) => stream,
-			Err(e) => {
-				eprintln!("Net Error: {:?}", e);
-				break Ok(());
+			Err(connecting) => match connecting.await {
+				Ok(new_conn) => new_conn,
+				Err(e) => {
+					error!(target : TARGET_NET, "{:?}", e);
+					return Ok(());
+				},
 			},
 		};
+		info!(target : TARGET_NET, "connected to server.");
+		let connection = new_conn.connection;
+		let (mut wtr, rdr) = ltry!(connection.open_bi().await; return Ok(()));
 		spawn_msg_rcv_task(rdr, to_receive);
 		while let Some(msg) = to_send.next().await {
-			let len_buf = (msg.len() as u64).to_be_bytes();
-			ltry!(wtr.write_all(&len_buf).await; break);
-			ltry!(wtr.write_all(&msg).await; break);
+			ltry!(write_msg(&mut wtr, &msg).await; break);
 		}
 		// Gracefully terminate the stream
 		if let Err(e) = wtr.shutdown().await {
-			println!("failed to shutdown stream: {}", e)
+			warn!(target : TARGET_NET, "failed to shutdown stream: {}", e);
 		}
-		break Ok(());
-	}}).map_err(|e :StrErr| e)?;
+		Ok(())
+	}).map_err(|e :StrErr| e)?;
+	Ok(())
+}
+
+async fn write_msg(wtr :&mut SendStream, msg :&[u8]) -> Result<(), quinn::WriteError> {
+	let len_buf = (msg.len() as u64).to_be_bytes();
+	wtr.write_all(&len_buf).await?;
+	wtr.write_all(msg).await?;
 	Ok(())
 }
 
+/// A stream of messages, sent as fragments of at most `fragment_size` bytes
 pub struct QuicMsgStream {
 	sender :UnboundedSender<Vec<u8>>,
-	receiver :Receiver<Vec<u8>>,
+	receiver :Receiver<SeqMsg>,
+	fragmenter :RefCell<Fragmenter>,
+	reassembler :Reassembler,
 }
 
 impl QuicMsgStream {
-	pub fn new() -> (Self, UnboundedReceiver<Vec<u8>>, Sender<Vec<u8>>) {
+	pub fn new(fragment_size :usize) -> (Self, UnboundedReceiver<Vec<u8>>, Sender<SeqMsg>) {
 		let (u_s, u_rx) = unbounded();
 		let (c_s, c_rx) = channel();
 		let slf = Self {
 			sender : u_s,
 			receiver : c_rx,
+			fragmenter : RefCell::new(Fragmenter::new(fragment_size)),
+			reassembler : Reassembler::new(REASSEMBLY_TIMEOUT),
 		};
 		(slf, u_rx, c_s)
 	}
@@ -231,14 +395,32 @@ impl QuicMsgStream {
 
 impl MsgStream for QuicMsgStream {
 	fn send_msg(&self, buf :&[u8]) -> Result<(), NetErr> {
-		self.sender.unbounded_send(buf.into())
-			.map_err(|_| NetErr::ConnectionClosed)
+		for frag in self.fragmenter.borrow_mut().fragment(buf) {
+			self.sender.unbounded_send(frag)
+				.map_err(|_| NetErr::ConnectionClosed)?;
+		}
+		Ok(())
 	}
-	fn try_recv_msg(&mut self) -> Result<Option<Vec<u8>>, NetErr> {
-		match self.receiver.try_recv() {
-			Ok(v) => Ok(Some(v)),
-			Err(TryRecvError::Empty) => Ok(None),
-			Err(TryRecvError::Disconnected) => Err(NetErr::ConnectionClosed),
+	fn try_recv_msg(&mut self) -> Result<Option<SeqMsg>, NetErr> {
+		let dropped = self.reassembler.drop_expired(Instant::now());
+		if dropped > 0 {
+			warn!(target : TARGET_NET, "dropped {} incomplete messages", dropped);
+		}
+		loop {
+			let (seq, frag) = match self.receiver.try_recv() {
+				Ok(v) => v,
+				Err(TryRecvError::Empty) => return Ok(None),
+				Err(TryRecvError::Disconnected) => return Err(NetErr::ConnectionClosed),
+			};
+			match self.reassembler.add(&frag, Instant::now()) {
+				// A message counts as received with its last fragment
+				Ok(Some(msg)) => return Ok(Some((seq, msg))),
+				Ok(None) => (),
+				Err(e) => {
+					error!(target : TARGET_NET, "{}", e.0);
+					return Err(NetErr::Other);
+				},
+			}
 		}
 	}
 }
@@ -247,11 +429,18 @@ pub type QuicClientConn = MsgStreamClientConn<QuicMsgStream>;
 pub type QuicServerConn = MsgStreamServerConn<QuicMsgStream>;
 
 impl QuicClientConn {
-	pub fn from_socket_addr(addr :&SocketAddr) -> Result<Self, StrErr> {
-		let (stream, rcv, snd) = QuicMsgStream::new();
+	/// Connects to the given address without resuming earlier sessions
+	pub fn from_socket_addr(addr :&SocketAddr, fragment_size :usize) -> Result<Self, StrErr> {
+		Self::with_ticket_cache(addr, fragment_size, Arc::new(TicketCache::default()))
+	}
+	/// Connects to the given address, resuming an earlier session
+	/// if the cache contains a ticket for it
+	pub fn with_ticket_cache(addr :&SocketAddr, fragment_size :usize,
+			tickets :Arc<TicketCache>) -> Result<Self, StrErr> {
+		let (stream, rcv, snd) = QuicMsgStream::new(fragment_size);
 		let addr = addr.clone();
 		thread::spawn(move || {
-			run_quinn_client(&addr, rcv, snd).expect("errors in quic client");
+			run_quinn_client(addr, tickets, rcv, snd).expect("errors in quic client");
 		});
 		Ok(Self {
 			stream,
@@ -264,16 +453,16 @@ pub struct QuicServerSocket {
 }
 
 impl QuicServerSocket {
-	pub fn new() -> Result<Self, StrErr> {
+	pub fn new(fragment_size :usize) -> Result<Self, StrErr> {
 		let addr = "127.0.0.1:7700".parse().unwrap();
-		Self::with_socket_addr(&addr)
+		Self::with_socket_addr(&addr, fragment_size)
 	}
-	pub fn with_socket_addr(addr :&SocketAddr) -> Result<Self, StrErr> {
+	pub fn with_socket_addr(addr :&SocketAddr, fragment_size :usize) -> Result<Self, StrErr> {
 		let addr = addr.clone();
 		let (conn_send, conn_recv) = channel();
 
 		thread::spawn(move || {
-			run_quinn_server(&addr, conn_send).expect("errors in quic server");
+			run_quinn_server(&addr, fragment_size, conn_send).expect("errors in quic server");
 		});
 		Ok(Self {
 			conn_recv
@@ -0,0 +1,96 @@
+use nalgebra::Vector3;
+use rand::Rng;
+use crate::map::{Map, MapBackend, MapBlock, CHUNKSIZE};
+use crate::game_params::GameParams;
+
+/// How far leaves may be away from a tree block without decaying
+const LEAF_DECAY_RADIUS :isize = 3;
+
+/// Updates randomly chosen blocks in each of the loaded chunks
+///
+/// `per_chunk` blocks are chosen per chunk, which may
+/// choose the same block more than once. Returns the
+/// number of blocks chosen.
+pub fn random_tick<B :MapBackend>(map :&mut Map<B>, params :&GameParams,
+		per_chunk :u32, rng :&mut impl Rng) -> usize {
+	if per_chunk == 0 {
+		return 0;
+	}
+	let chunks = map.loaded_chunks().collect::<Vec<_>>();
+	let count = chunks.len() * per_chunk as usize;
+	for chunk_pos in chunks {
+		for _ in 0 .. per_chunk {
+			let offs = Vector3::new(
+				rng.gen_range(0, CHUNKSIZE),
+				rng.gen_range(0, CHUNKSIZE),
+				rng.gen_range(0, CHUNKSIZE));
+			update_block(map, chunk_pos + offs, params);
+		}
+	}
+	count
+}
+
+/// The random update of a single block, depending on its kind
+fn update_block<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>,
+		params :&GameParams) {
+	let roles = &params.block_roles;
+	let blk = if let Some(blk) = map.get_blk(pos) {
+		blk
+	} else {
+		return;
+	};
+	if blk == roles.leaves && leaves_detached(map, pos, roles.tree) == Some(true) {
+		if let Some(mut hdl) = map.get_blk_mut(pos) {
+			hdl.set(roles.air);
+		}
+	}
+}
+
+/// Whether there is no tree block near the leaves at the position
+///
+/// Returns None if the surroundings aren't loaded entirely.
+fn leaves_detached<B :MapBackend>(map :&Map<B>, pos :Vector3<isize>,
+		tree :MapBlock) -> Option<bool> {
+	let r = LEAF_DECAY_RADIUS;
+	for x in -r ..= r {
+		for y in -r ..= r {
+			for z in -r ..= r {
+				if map.get_blk(pos + Vector3::new(x, y, z))? == tree {
+					return Some(false);
+				}
+			}
+		}
+	}
+	Some(true)
+}
+
+#[cfg(test)]
+#[test]
+fn test_leaf_decay() {
+	use rand_pcg::Pcg32;
+	use rand::SeedableRng;
+	use crate::map::{ClientBackend, MapChunkData, spawn_tree};
+
+	let params = crate::game_params::test_params();
+	let roles = &params.p.block_roles;
+	let mut map = Map::from_backend(ClientBackend);
+	map.set_chunk(Vector3::new(0, 0, 0), MapChunkData::filled_with(roles.air));
+	let tree_pos = Vector3::new(6, 6, 1);
+	spawn_tree(&mut map, tree_pos, &params);
+	let attached = tree_pos + Vector3::new(1, 1, 5);
+	assert_eq!(map.get_blk(attached), Some(roles.leaves));
+	let detached = Vector3::new(12, 12, 12);
+	map.get_blk_mut(detached).unwrap().set(roles.leaves);
+	// Leaves at the border of the loaded area are kept
+	let border = Vector3::new(12, 12, CHUNKSIZE - 1);
+	map.get_blk_mut(border).unwrap().set(roles.leaves);
+
+	let mut rng = Pcg32::seed_from_u64(7);
+	for _ in 0 .. 20 {
+		random_tick(&mut map, &params.p, 4096, &mut rng);
+	}
+	assert_eq!(map.get_blk(detached), Some(roles.air));
+	assert_eq!(map.get_blk(attached), Some(roles.leaves));
+	assert_eq!(map.get_blk(border), Some(roles.leaves));
+	assert_eq!(map.get_blk(tree_pos), Some(roles.tree));
+}
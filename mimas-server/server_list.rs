@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use toml::{from_str, to_string};
+use crate::config::config_dirs;
+use super::StrErr;
+
+/// The name of the file in the config directory storing the server list
+const SERVER_LIST_FILE_NAME :&str = "servers.toml";
+
+/// A server the client can connect to
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ServerEntry {
+	pub name :String,
+	pub address :String,
+	/// The nick last used on the server
+	pub nick :String,
+}
+
+impl ServerEntry {
+	/// An entry named after its address
+	pub fn new(address :String, nick :String) -> Self {
+		Self {
+			name : address.clone(),
+			address,
+			nick,
+		}
+	}
+}
+
+/// The servers saved by the player, in the order they were added
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ServerList {
+	#[serde(default)]
+	servers :Vec<ServerEntry>,
+}
+
+impl ServerList {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn servers(&self) -> &[ServerEntry] {
+		&self.servers
+	}
+	pub fn get(&self, idx :usize) -> Option<&ServerEntry> {
+		self.servers.get(idx)
+	}
+	/// Adds the server, replacing the one with the same address
+	pub fn add(&mut self, entry :ServerEntry) {
+		if let Some(existing) = self.servers.iter_mut().find(|s| s.address == entry.address) {
+			*existing = entry;
+		} else {
+			self.servers.push(entry);
+		}
+	}
+	pub fn remove(&mut self, idx :usize) -> Option<ServerEntry> {
+		if idx < self.servers.len() {
+			Some(self.servers.remove(idx))
+		} else {
+			None
+		}
+	}
+	/// Records the nick used on the server with the address
+	///
+	/// Returns whether the server is in the list.
+	pub fn set_last_nick(&mut self, address :&str, nick :&str) -> bool {
+		if let Some(entry) = self.servers.iter_mut().find(|s| s.address == address) {
+			entry.nick = nick.to_owned();
+			true
+		} else {
+			false
+		}
+	}
+	/// Loads the list from the file at the path, or an empty one if there is none
+	pub fn load_from(path :impl AsRef<Path>) -> Result<Self, StrErr> {
+		let path = path.as_ref();
+		let file_str = match fs::read_to_string(path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+			Err(e) => Err(e)?,
+		};
+		Ok(from_str(&file_str)
+			.map_err(|e| format!("Malformed server list {}: {}", path.display(), e))?)
+	}
+	pub fn save_to(&self, path :impl AsRef<Path>) -> Result<(), StrErr> {
+		let path = path.as_ref();
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		fs::write(path, to_string(self)?)?;
+		Ok(())
+	}
+}
+
+/// The path of the server list file in the config directory of the OS
+pub fn server_list_path() -> Result<PathBuf, StrErr> {
+	let dir = config_dirs().into_iter().nth(1)
+		.ok_or("Couldn't find the config directory")?;
+	Ok(dir.join(SERVER_LIST_FILE_NAME))
+}
+
+#[cfg(test)]
+#[test]
+fn test_server_list_persistence() {
+	let path = std::env::temp_dir()
+		.join(format!("mimas-test-{}-servers", std::process::id()))
+		.join(SERVER_LIST_FILE_NAME);
+
+	// There is no file yet
+	assert_eq!(ServerList::load_from(&path).unwrap(), ServerList::new());
+
+	let mut list = ServerList::new();
+	list.add(ServerEntry::new("127.0.0.1:7700".to_owned(), "alice".to_owned()));
+	list.add(ServerEntry {
+		name : "Big world".to_owned(),
+		address : "10.0.0.2:7700".to_owned(),
+		nick : "bob".to_owned(),
+	});
+	assert!(list.set_last_nick("127.0.0.1:7700", "carol"));
+	assert!(!list.set_last_nick("10.0.0.3:7700", "carol"));
+	list.save_to(&path).unwrap();
+
+	let loaded = ServerList::load_from(&path).unwrap();
+	assert_eq!(loaded, list);
+	assert_eq!(loaded.servers()[0].name, "127.0.0.1:7700");
+	assert_eq!(loaded.servers()[0].nick, "carol");
+	assert_eq!(loaded.servers()[1].name, "Big world");
+
+	// Adding a server with a known address replaces it
+	let mut list = loaded;
+	list.add(ServerEntry::new("10.0.0.2:7700".to_owned(), "dave".to_owned()));
+	assert_eq!(list.servers().len(), 2);
+	assert_eq!(list.servers()[1].nick, "dave");
+	assert_eq!(list.remove(0).unwrap().address, "127.0.0.1:7700");
+	assert!(list.remove(1).is_none());
+	list.save_to(&path).unwrap();
+	assert_eq!(ServerList::load_from(&path).unwrap().servers().len(), 1);
+
+	std::fs::write(&path, "servers = 3").unwrap();
+	assert!(ServerList::load_from(&path).is_err());
+	std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
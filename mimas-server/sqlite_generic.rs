@@ -1,5 +1,5 @@
 use rusqlite::{Connection, NO_PARAMS, OpenFlags};
-use crate::StrErr;
+use crate::error::{MimasError, StorageError};
 use std::path::Path;
 
 /// Open or create a new database connection,
@@ -8,7 +8,7 @@ use std::path::Path;
 /// Returns `(conn, created)` with `conn` being a connection
 /// to a possibly new db file and `created` being true if creation
 /// was neccessary.
-pub fn open_or_create_db(path :impl AsRef<Path> + Clone) -> Result<(Connection, bool), StrErr> {
+pub fn open_or_create_db(path :impl AsRef<Path> + Clone) -> Result<(Connection, bool), MimasError> {
 	// SQLite doesn't tell us whether a newly opened sqlite file has been
 	// existing on disk previously, or just been created.
 	// Thus, we need to do two calls: first one which doesn't auto-create,
@@ -26,25 +26,93 @@ pub fn open_or_create_db(path :impl AsRef<Path> + Clone) -> Result<(Connection,
 	}
 }
 
-pub fn get_user_version(conn :&mut Connection) -> Result<u16, StrErr> {
+pub fn get_user_version(conn :&mut Connection) -> Result<u16, MimasError> {
 	let r = conn.query_row("PRAGMA user_version;", NO_PARAMS, |v| v.get(0))?;
 	Ok(r)
 }
-pub fn set_user_version(conn :&mut Connection, version :u16) -> Result<(), StrErr> {
+pub fn set_user_version(conn :&mut Connection, version :u16) -> Result<(), MimasError> {
 	// Apparently sqlite wants you to be exposed to bobby tables shit
 	// because they don't allow you to use ? or other methods to avoid
 	// string formatting :/.
 	conn.execute(&format!("PRAGMA user_version = {};", version), NO_PARAMS)?;
 	Ok(())
 }
-pub fn get_app_id(conn :&mut Connection) -> Result<i32, StrErr> {
+pub fn get_app_id(conn :&mut Connection) -> Result<i32, MimasError> {
 	let r = conn.query_row("PRAGMA application_id;", NO_PARAMS, |v| v.get(0))?;
 	Ok(r)
 }
-pub fn set_app_id(conn :&mut Connection, id :i32) -> Result<(), StrErr> {
+pub fn set_app_id(conn :&mut Connection, id :i32) -> Result<(), MimasError> {
 	// Apparently sqlite wants you to be exposed to bobby tables shit
 	// because they don't allow you to use ? or other methods to avoid
 	// string formatting :/.
 	conn.execute(&format!("PRAGMA application_id = {};", id), NO_PARAMS)?;
 	Ok(())
 }
+
+/// The value of sqlite's synchronous setting
+///
+/// This trades speed against durability of the database file:
+/// * `Full` syncs to disk at each commit, so committed transactions
+///   survive power loss or an OS crash. It is sqlite's default.
+/// * `Normal` syncs less often. A crash of the application never
+///   corrupts the database. With the default rollback journal that
+///   mimas uses, the last commits may get lost on power loss, and
+///   there is a very small chance of corruption on older file systems.
+/// * `Off` never syncs. It's the fastest, but on power loss
+///   or an OS crash the database might get corrupted.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SqliteSynchronous {
+	Off,
+	Normal,
+	Full,
+}
+
+impl Default for SqliteSynchronous {
+	fn default() -> Self {
+		SqliteSynchronous::Full
+	}
+}
+
+impl SqliteSynchronous {
+	fn pragma_value(&self) -> u8 {
+		match self {
+			SqliteSynchronous::Off => 0,
+			SqliteSynchronous::Normal => 1,
+			SqliteSynchronous::Full => 2,
+		}
+	}
+	fn from_pragma_value(v :u8) -> Result<Self, MimasError> {
+		Ok(match v {
+			0 => SqliteSynchronous::Off,
+			1 => SqliteSynchronous::Normal,
+			2 => SqliteSynchronous::Full,
+			_ => Err(StorageError::Corrupted(format!("Unsupported synchronous value {}", v)))?,
+		})
+	}
+}
+
+pub fn get_synchronous(conn :&mut Connection) -> Result<SqliteSynchronous, MimasError> {
+	let r = conn.query_row("PRAGMA synchronous;", NO_PARAMS, |v| v.get(0))?;
+	SqliteSynchronous::from_pragma_value(r)
+}
+pub fn set_synchronous(conn :&mut Connection, synchronous :SqliteSynchronous) -> Result<(), MimasError> {
+	conn.execute(&format!("PRAGMA synchronous = {};", synchronous.pragma_value()), NO_PARAMS)?;
+	Ok(())
+}
+/// Returns the size of the page cache in KiB
+pub fn get_cache_size_kb(conn :&mut Connection) -> Result<u32, MimasError> {
+	let r :i64 = conn.query_row("PRAGMA cache_size;", NO_PARAMS, |v| v.get(0))?;
+	if r > 0 {
+		// Positive values are in pages instead of KiB
+		let page_size :i64 = conn.query_row("PRAGMA page_size;", NO_PARAMS, |v| v.get(0))?;
+		Ok((r * page_size / 1024) as u32)
+	} else {
+		Ok(-r as u32)
+	}
+}
+/// Sets the size of the page cache in KiB
+pub fn set_cache_size_kb(conn :&mut Connection, kb :u32) -> Result<(), MimasError> {
+	// Negative values are interpreted as KiB by sqlite
+	conn.execute(&format!("PRAGMA cache_size = -{};", kb), NO_PARAMS)?;
+	Ok(())
+}
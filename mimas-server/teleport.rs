@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use nalgebra::Vector3;
+use crate::map_storage::{PlayerIdPair, PlayerPosition};
+
+/// How far a player may move during the warmup, in blocks
+const MAX_WARMUP_MOVEMENT :f32 = 0.2;
+
+/// Where a teleport moves the player to
+#[derive(Clone, Copy)]
+pub enum TeleportTarget {
+	Position(PlayerPosition),
+	/// Respawn at the spawn, like after dying
+	Respawn,
+}
+
+/// A teleport waiting for its warmup to pass
+struct PendingTeleport {
+	target :TeleportTarget,
+	/// The position of the player when requesting the teleport
+	start :Vector3<f32>,
+	/// Seconds until the teleport happens
+	remaining :f32,
+	/// Chat message for the player once teleported
+	msg :String,
+}
+
+/// The result of `TeleportTimers::update` for a player
+pub enum TeleportEvent {
+	Ready(PlayerIdPair, TeleportTarget, String),
+	Cancelled(PlayerIdPair),
+}
+
+/// Per player warmups and cooldowns of teleport commands
+///
+/// The warmup delays a teleport and gets cancelled if the player
+/// moves in the meantime. The cooldown is the minimum time between
+/// two teleports of a player. Both prevent escaping from fights.
+#[derive(Default)]
+pub struct TeleportTimers {
+	pending :HashMap<PlayerIdPair, PendingTeleport>,
+	/// Seconds until the player may teleport again
+	cooldowns :HashMap<PlayerIdPair, f32>,
+}
+
+impl TeleportTimers {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// The seconds until the player may teleport again, if any
+	pub fn cooldown_left(&self, id :PlayerIdPair) -> Option<f32> {
+		self.cooldowns.get(&id).copied()
+	}
+	pub fn is_pending(&self, id :PlayerIdPair) -> bool {
+		self.pending.contains_key(&id)
+	}
+	/// Schedules the teleport, replacing any pending one of the player
+	pub fn schedule(&mut self, id :PlayerIdPair, target :TeleportTarget,
+			start :Vector3<f32>, warmup :f32, msg :String) {
+		self.pending.insert(id, PendingTeleport {
+			target,
+			start,
+			remaining : warmup,
+			msg,
+		});
+	}
+	/// Starts the cooldown of a player that has just teleported
+	pub fn teleported(&mut self, id :PlayerIdPair, cooldown :f32) {
+		if cooldown > 0.0 {
+			self.cooldowns.insert(id, cooldown);
+		}
+	}
+	/// Advances the timers by `dt` seconds
+	///
+	/// `pos_of` returns the current position of a player, or `None` if
+	/// it isn't online any more. Returns the teleports that are due and
+	/// those cancelled because the player moved.
+	pub fn update(&mut self, dt :f32,
+			pos_of :impl Fn(PlayerIdPair) -> Option<Vector3<f32>>) -> Vec<TeleportEvent> {
+		self.cooldowns.retain(|_, left| {
+			*left -= dt;
+			*left > 0.0
+		});
+		let mut events = Vec::new();
+		let mut done = Vec::new();
+		for (id, pending) in self.pending.iter_mut() {
+			let pos = if let Some(pos) = pos_of(*id) {
+				pos
+			} else {
+				done.push(*id);
+				continue;
+			};
+			if (pos - pending.start).norm() > MAX_WARMUP_MOVEMENT {
+				events.push(TeleportEvent::Cancelled(*id));
+				done.push(*id);
+				continue;
+			}
+			pending.remaining -= dt;
+			if pending.remaining <= 0.0 {
+				let msg = std::mem::take(&mut pending.msg);
+				events.push(TeleportEvent::Ready(*id, pending.target, msg));
+				done.push(*id);
+			}
+		}
+		for id in done {
+			self.pending.remove(&id);
+		}
+		events
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_teleport_timers() {
+	let alice = PlayerIdPair::from_components(0, 1);
+	let bob = PlayerIdPair::from_components(0, 2);
+	let start = Vector3::new(0.0, 0.0, 0.0);
+	let moved = Vector3::new(1.0, 0.0, 0.0);
+	let target = PlayerPosition::from_pos(Vector3::new(5.0, 5.0, 5.0));
+	let target = TeleportTarget::Position(target);
+	let mut timers = TeleportTimers::new();
+	timers.schedule(alice, target, start, 1.0, "done".to_owned());
+	timers.schedule(bob, target, start, 1.0, "done".to_owned());
+	assert!(timers.update(0.5, |_| Some(start)).is_empty());
+	let events = timers.update(0.5, |id| Some(if id == bob { moved } else { start }));
+	assert_eq!(events.len(), 2);
+	for event in events {
+		match event {
+			TeleportEvent::Ready(id, TeleportTarget::Position(pos), msg) => {
+				assert!(id == alice);
+				assert_eq!(pos.pos(), Vector3::new(5.0, 5.0, 5.0));
+				assert_eq!(msg, "done");
+			},
+			TeleportEvent::Cancelled(id) => assert!(id == bob),
+			_ => panic!("expected a teleport to a position"),
+		}
+	}
+	assert!(!timers.is_pending(alice) && !timers.is_pending(bob));
+
+	timers.teleported(alice, 2.0);
+	timers.update(1.5, |_| Some(start));
+	assert_eq!(timers.cooldown_left(alice), Some(0.5));
+	timers.update(0.5, |_| Some(start));
+	assert_eq!(timers.cooldown_left(alice), None);
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+/// How many ticks the server may fall behind before
+/// it gives up on catching up
+const MAX_CATCH_UP_TICKS :u32 = 5;
+
+/// Paces the server loop to a fixed number of ticks per second
+pub struct TickPacer {
+	tick_duration :Duration,
+	next_tick :Instant,
+}
+
+impl TickPacer {
+	pub fn new(tick_rate :u32, now :Instant) -> Self {
+		let tick_duration = Duration::from_secs(1) / tick_rate.max(1);
+		Self {
+			tick_duration,
+			next_tick : now,
+		}
+	}
+	/// The duration of one tick, to be used as dt for simulation steps
+	pub fn tick_duration(&self) -> Duration {
+		self.tick_duration
+	}
+	/// Called at the end of a tick. Returns how long to sleep
+	/// before the next tick should start.
+	///
+	/// If we are behind, no time is slept so that the following
+	/// ticks can catch up. At most `MAX_CATCH_UP_TICKS` ticks
+	/// are caught up, anything beyond that is dropped.
+	pub fn end_tick(&mut self, now :Instant) -> Duration {
+		self.next_tick += self.tick_duration;
+		if self.next_tick > now {
+			return self.next_tick - now;
+		}
+		let max_behind = self.tick_duration * MAX_CATCH_UP_TICKS;
+		if now - self.next_tick > max_behind {
+			self.next_tick = now - max_behind;
+		}
+		Duration::from_secs(0)
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_tick_pacing() {
+	let ms = Duration::from_millis;
+	let start = Instant::now();
+	let mut pacer = TickPacer::new(20, start);
+	assert_eq!(pacer.tick_duration(), ms(50));
+
+	// Work took 10 ms, sleep for the remainder of the tick
+	assert_eq!(pacer.end_tick(start + ms(10)), ms(40));
+	// Second tick starts at 50 ms, work took 30 ms
+	assert_eq!(pacer.end_tick(start + ms(80)), ms(20));
+
+	// Work took 120 ms, we are behind by 70 ms: don't sleep
+	// until we have caught up
+	assert_eq!(pacer.end_tick(start + ms(220)), ms(0));
+	assert_eq!(pacer.end_tick(start + ms(221)), ms(0));
+	// Tick ends at 250 ms, we caught up
+	assert_eq!(pacer.end_tick(start + ms(230)), ms(20));
+
+	// Being behind by a lot only catches up a bounded amount of ticks
+	let now = start + ms(10_000);
+	assert_eq!(pacer.end_tick(now), ms(0));
+	let mut catch_up_ticks = 0;
+	let mut now = now;
+	loop {
+		now += ms(1);
+		if pacer.end_tick(now) > ms(0) {
+			break;
+		}
+		catch_up_ticks += 1;
+	}
+	assert_eq!(catch_up_ticks, MAX_CATCH_UP_TICKS);
+
+	// A tick rate of zero is treated like one tick per second
+	assert_eq!(TickPacer::new(0, start).tick_duration(), ms(1000));
+}
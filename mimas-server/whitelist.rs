@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use super::StrErr;
+
+/// Whether the nick only consists of characters allowed in nicks
+pub fn is_valid_nick(nick :&str) -> bool {
+	!nick.is_empty() && nick
+		.bytes()
+		.all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The nicks of the players allowed to log in
+///
+/// It is stored in the global kv of the map storage as
+/// a newline delimited list of nicks, the same format used
+/// for import and export.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Whitelist {
+	nicks :BTreeSet<String>,
+}
+
+impl Whitelist {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn contains(&self, nick :&str) -> bool {
+		self.nicks.contains(nick)
+	}
+	/// Adds the nick, returning whether it wasn't present before
+	pub fn add(&mut self, nick :&str) -> Result<bool, StrErr> {
+		if !is_valid_nick(nick) {
+			Err(format!("Invalid nick '{}'", nick))?;
+		}
+		Ok(self.nicks.insert(nick.to_owned()))
+	}
+	/// Removes the nick, returning whether it was present
+	pub fn remove(&mut self, nick :&str) -> bool {
+		self.nicks.remove(nick)
+	}
+	pub fn nicks(&self) -> impl Iterator<Item = &str> {
+		self.nicks.iter().map(|n| n.as_str())
+	}
+	pub fn len(&self) -> usize {
+		self.nicks.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.nicks.is_empty()
+	}
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut res = String::new();
+		for nick in self.nicks() {
+			res += nick;
+			res += "\n";
+		}
+		res.into_bytes()
+	}
+	pub fn deserialize(buf :&[u8]) -> Result<Self, StrErr> {
+		let mut res = Self::new();
+		res.merge(buf)?;
+		Ok(res)
+	}
+	/// Adds the nicks from the newline delimited list
+	///
+	/// Empty lines are ignored. Returns the number of nicks
+	/// that weren't present before. If any of the nicks is
+	/// invalid, nothing is added.
+	fn merge(&mut self, buf :&[u8]) -> Result<usize, StrErr> {
+		let nicks = str::from_utf8(buf)?
+			.lines()
+			.map(|l| l.trim())
+			.filter(|l| !l.is_empty())
+			.collect::<Vec<_>>();
+		if let Some(invalid) = nicks.iter().find(|n| !is_valid_nick(n)) {
+			Err(format!("Invalid nick '{}'", invalid))?;
+		}
+		let mut added = 0;
+		for nick in nicks {
+			if self.nicks.insert(nick.to_owned()) {
+				added += 1;
+			}
+		}
+		Ok(added)
+	}
+	/// Writes the whitelist to the file at the given path
+	pub fn export(&self, path :impl AsRef<Path>) -> Result<(), StrErr> {
+		fs::write(path, self.serialize())?;
+		Ok(())
+	}
+	/// Merges the nicks from the file at the given path into the whitelist
+	///
+	/// Returns the number of nicks that weren't present before.
+	pub fn import(&mut self, path :impl AsRef<Path>) -> Result<usize, StrErr> {
+		let buf = fs::read(path)?;
+		self.merge(&buf)
+	}
+}
+
+/// The path of the file with the given name in the directory
+///
+/// Only plain file names are accepted, so that the whitelist
+/// commands can't reach files outside of the directory.
+pub fn file_path_in(dir :&Path, name :&str) -> Result<PathBuf, StrErr> {
+	let mut components = Path::new(name).components();
+	match (components.next(), components.next()) {
+		(Some(Component::Normal(file_name)), None) => Ok(dir.join(file_name)),
+		_ => Err(format!("Invalid file name {}, only names without directories are allowed", name).into()),
+	}
+}
+
+#[cfg(test)]
+fn test_file_path(name :&str) -> std::path::PathBuf {
+	std::env::temp_dir().join(format!("mimas-test-{}-{}", std::process::id(), name))
+}
+
+#[cfg(test)]
+#[test]
+fn test_whitelist_export() {
+	let mut wl = Whitelist::new();
+	for nick in &["zoe", "alice", "bob_2"] {
+		assert!(wl.add(nick).unwrap());
+	}
+	assert!(!wl.add("alice").unwrap());
+	assert!(wl.add("not a nick").is_err());
+	assert_eq!(wl.len(), 3);
+
+	let path = test_file_path("whitelist-export");
+	wl.export(&path).unwrap();
+	let exported = fs::read_to_string(&path).unwrap();
+	fs::remove_file(&path).unwrap();
+	assert_eq!(exported, "alice\nbob_2\nzoe\n");
+	assert_eq!(Whitelist::deserialize(exported.as_bytes()).unwrap(), wl);
+}
+
+#[cfg(test)]
+#[test]
+fn test_whitelist_import() {
+	let path = test_file_path("whitelist-import");
+	fs::write(&path, "alice\n\nbob\r\n  alice \ncarol").unwrap();
+	let mut wl = Whitelist::new();
+	assert!(wl.is_empty());
+	assert_eq!(wl.import(&path).unwrap(), 3);
+	assert_eq!(wl.nicks().collect::<Vec<_>>(), vec!["alice", "bob", "carol"]);
+	// Importing again merges without duplicates
+	assert_eq!(wl.import(&path).unwrap(), 0);
+	assert_eq!(wl.len(), 3);
+
+	// Invalid files don't change the whitelist
+	fs::write(&path, "dave\nnot valid").unwrap();
+	assert!(wl.import(&path).is_err());
+	assert!(!wl.contains("dave"));
+	fs::remove_file(&path).unwrap();
+	assert!(wl.import(&path).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_path_in() {
+	let dir = Path::new("worlds");
+	assert_eq!(file_path_in(dir, "list.txt").unwrap(), dir.join("list.txt"));
+	for name in &["", ".", "..", "../list.txt", "sub/list.txt", "/etc/passwd", "./list.txt"] {
+		assert!(file_path_in(dir, name).is_err(), "{} accepted", name);
+	}
+}
@@ -1,5 +1,6 @@
 use mimas_server::StrErr;
-use mimas_server::game_params::{GameParamsHdl, DrawStyle};
+use mimas_server::game_params::{GameParamsHdl, DrawStyle, Transparency};
+use mimas_server::logging::TARGET_RENDER;
 
 use std::fs::File;
 use std::io::Read;
@@ -13,10 +14,12 @@ use image::{RgbaImage, Pixel};
 
 use sha2::{Sha256, Digest};
 
-use mimas_meshgen::{TextureId, BlockTextureIds, MeshDrawStyle};
+use mimas_meshgen::{TextureId, BlockTextureIds, MeshDrawStyle,
+	ATLAS_LAYERS, MAX_ATLASES};
 
 pub struct Assets {
 	assets :Vec<(Vec<f32>, (u32, u32))>,
+	missing_texture :Option<TextureId>,
 }
 
 fn load_image_inner(game_params :&GameParamsHdl, path :&str) -> Result<RgbaImage, StrErr> {
@@ -101,7 +104,13 @@ pub fn store_hashed_blobs(hashed_blobs :&[(Vec<u8>, Vec<u8>)]) -> Result<(), Str
 	Ok(())
 }
 
-fn load_image(game_params :&GameParamsHdl, path :&str, opaque :bool) -> Result<(Vec<f32>, (u32, u32)), StrErr> {
+/// Alpha value of the textures of translucent blocks
+///
+/// It needs to stay above the threshold at which
+/// the fragment shader discards pixels.
+const TRANSLUCENT_ALPHA :u8 = 180;
+
+fn load_image(game_params :&GameParamsHdl, path :&str, alpha :Option<u8>) -> Result<(Vec<f32>, (u32, u32)), StrErr> {
 	let mut imgs_iter = path.split("^")
 		.map(|p| load_image_inner(game_params, p));
 	let mut image = imgs_iter.next().ok_or("No image path specified")??;
@@ -113,12 +122,10 @@ fn load_image(game_params :&GameParamsHdl, path :&str, opaque :bool) -> Result<(
 				img_pixel.blend(overlay_pixel);
 			});
 	}
-	if opaque {
-		// Make texture opaque if requested.
-		// Currently, mesh generation can't deal
-		// with transparency of some textures.
+	if let Some(alpha) = alpha {
+		// Make texture opaque (or uniformly translucent) if requested.
 		image.pixels_mut()
-			.for_each(|px| px.0[3] = 255);
+			.for_each(|px| px.0[3] = alpha);
 	}
 	let dimensions = image.dimensions();
 	let buf = image.into_raw()
@@ -134,52 +141,87 @@ impl Assets {
 	pub fn new() -> Self {
 		Self {
 			assets : Vec::new(),
+			missing_texture : None,
 		}
 	}
 	fn add_asset(&mut self, asset :(Vec<f32>, (u32, u32))) -> TextureId {
 		let id = self.assets.len();
+		let (atlas, layer) = (id / ATLAS_LAYERS as usize, id % ATLAS_LAYERS as usize);
+		if atlas >= MAX_ATLASES as usize {
+			panic!("too many textures: the limit is {}",
+				MAX_ATLASES as usize * ATLAS_LAYERS as usize);
+		}
 		self.assets.push(asset);
-		TextureId(id as u16)
+		TextureId::new(atlas as u16, layer as u16)
+	}
+	/// The magenta and black checkerboard drawn in place of textures that couldn't be loaded
+	pub fn missing_texture(&mut self) -> TextureId {
+		if let Some(id) = self.missing_texture {
+			return id;
+		}
+		let pixels = (0 .. 16 * 16)
+			.flat_map(|i| {
+				let (x, y) = (i % 16, i / 16);
+				let color = if (x / 8 + y / 8) % 2 == 0 {
+					[1.0, 0.0, 1.0, 1.0]
+				} else {
+					[0.0, 0.0, 0.0, 1.0]
+				};
+				color.to_vec()
+			})
+			.collect::<Vec<_>>();
+		let id = self.add_asset((pixels, (16, 16)));
+		self.missing_texture = Some(id);
+		id
+	}
+	/// Adds the image at the path, or the missing texture if it can't be loaded
+	fn add_image(&mut self, game_params :&GameParamsHdl,
+			path :&str, alpha :Option<u8>) -> TextureId {
+		match load_image(game_params, path, alpha) {
+			Ok(asset) => self.add_asset(asset),
+			Err(e) => {
+				warn!(target : TARGET_RENDER, "Couldn't load texture {}, \
+					using the missing texture: {:?}", path, e);
+				self.missing_texture()
+			},
+		}
 	}
 	pub fn add_draw_style(&mut self, game_params :&GameParamsHdl,
-			ds :&DrawStyle) -> MeshDrawStyle {
+			ds :&DrawStyle, transparency :Transparency) -> MeshDrawStyle {
+		// Cutout textures keep their alpha, so that the
+		// fragment shader discards the transparent pixels
+		let alpha = match transparency {
+			Transparency::Opaque => Some(255),
+			Transparency::Cutout => None,
+			Transparency::Blend => Some(TRANSLUCENT_ALPHA),
+		};
 		MeshDrawStyle::Blocky(match ds {
 			DrawStyle::Colored(color) => {
-				let id = self.add_color(*color);
-				let id_h = self.add_color(mimas_meshgen::colorh(*color));
+				let mut color = *color;
+				if transparency == Transparency::Blend {
+					color[3] = TRANSLUCENT_ALPHA as f32 / 255.0;
+				}
+				let id = self.add_color(color);
+				let id_h = self.add_color(mimas_meshgen::colorh(color));
 				BlockTextureIds::new_tb(id, id_h)
 			},
 			DrawStyle::Crossed(path) => {
-				let asset = load_image(game_params, path, false)
-					.expect("couldn't load image");
-				let id = self.add_asset(asset);
+				let id = self.add_image(game_params, path, None);
 				return MeshDrawStyle::Crossed(id);
 			},
 			DrawStyle::Texture(path) => {
-				let asset = load_image(game_params, path, true)
-					.expect("couldn't load image");
-				let id = self.add_asset(asset);
+				let id = self.add_image(game_params, path, alpha);
 				BlockTextureIds::uniform(id)
 			},
 			DrawStyle::TextureSidesTop(path_s, path_tb) => {
-				let image_s = load_image(game_params, path_s, true)
-					.expect("couldn't load image");
-				let image_tb = load_image(game_params, path_tb, true)
-					.expect("couldn't load image");
-				let id_s = self.add_asset(image_s);
-				let id_tb = self.add_asset(image_tb);
+				let id_s = self.add_image(game_params, path_s, alpha);
+				let id_tb = self.add_image(game_params, path_tb, alpha);
 				BlockTextureIds::new_tb(id_tb, id_s)
 			},
 			DrawStyle::TextureSidesTopBottom(path_s, path_t, path_b) => {
-				let image_s = load_image(game_params, path_s, true)
-					.expect("couldn't load image");
-				let image_t = load_image(game_params, path_t, true)
-					.expect("couldn't load image");
-				let image_b = load_image(game_params, path_b, true)
-					.expect("couldn't load image");
-				let id_s = self.add_asset(image_s);
-				let id_t = self.add_asset(image_t);
-				let id_b = self.add_asset(image_b);
+				let id_s = self.add_image(game_params, path_s, alpha);
+				let id_t = self.add_image(game_params, path_t, alpha);
+				let id_b = self.add_image(game_params, path_b, alpha);
 				BlockTextureIds::new(id_s, id_t, id_b)
 			},
 		})
@@ -192,14 +234,18 @@ impl Assets {
 			.collect::<Vec<_>>();
 		self.add_asset((pixels, (16, 16)))
 	}
-	pub fn into_texture_array<F: Facade>(self,
-			facade :&F) -> Result<SrgbTexture2dArray, StrErr> {
-		let imgs = self.assets.into_iter()
-			.map(|(pixels, dimensions)| {
-				RawImage2d::from_raw_rgba_reversed(&pixels, dimensions)
-			})
-			.collect::<Vec<_>>();
-		let res = SrgbTexture2dArray::new(facade, imgs)?;
+	/// Creates one texture array per atlas, in atlas order
+	pub fn into_texture_arrays<F: Facade>(self,
+			facade :&F) -> Result<Vec<SrgbTexture2dArray>, StrErr> {
+		let mut res = Vec::new();
+		for atlas in self.assets.chunks(ATLAS_LAYERS as usize) {
+			let imgs = atlas.iter()
+				.map(|(pixels, dimensions)| {
+					RawImage2d::from_raw_rgba_reversed(pixels, *dimensions)
+				})
+				.collect::<Vec<_>>();
+			res.push(SrgbTexture2dArray::new(facade, imgs)?);
+		}
 		Ok(res)
 	}
 }
@@ -210,22 +256,66 @@ pub struct UiColors {
 	pub selected_slot_color :TextureId,
 	pub hovered_slot_color :TextureId,
 	pub block_selection_color :TextureId,
+	pub spawn_protection_color :TextureId,
 	pub crosshair_color :TextureId,
+	pub hunger_color :TextureId,
 	pub color_body :TextureId,
 	pub color_head :TextureId,
 }
 
 impl UiColors {
-	pub fn new(assets :&mut Assets) -> Self {
+	pub fn new(assets :&mut Assets, crosshair_color :[f32; 4]) -> Self {
 		Self {
 			background_color : assets.add_color([0.4, 0.4, 0.4, 0.85]),
 			slot_color : assets.add_color([0.5, 0.5, 0.5, 0.85]),
 			selected_slot_color : assets.add_color([0.3, 0.3, 0.3, 0.85]),
 			hovered_slot_color : assets.add_color([0.8, 0.8, 0.8, 0.85]),
-			block_selection_color : assets.add_color([0.0, 0.0, 0.3, 0.5]),
-			crosshair_color : assets.add_color([0.8, 0.8, 0.8, 0.85]),
+			block_selection_color : assets.add_color([0.1, 0.1, 0.1, 1.0]),
+			spawn_protection_color : assets.add_color([0.9, 0.8, 0.3, 1.0]),
+			crosshair_color : assets.add_color(crosshair_color),
+			hunger_color : assets.add_color([0.7, 0.4, 0.1, 0.85]),
 			color_body : assets.add_color([0.3, 0.3, 0.5, 1.0]),
 			color_head : assets.add_color([0.94, 0.76, 0.49, 1.0]),
 		}
 	}
 }
+
+#[cfg(test)]
+#[test]
+fn test_texture_id_allocation() {
+	let mut assets = Assets::new();
+	let ids = (0 .. ATLAS_LAYERS + 2)
+		.map(|_| assets.add_color([1.0; 4]))
+		.collect::<Vec<_>>();
+	assert_eq!(ids[0], TextureId::new(0, 0));
+	assert_eq!((ids[0].atlas(), ids[0].layer()), (0, 0));
+	let last_in_first = ids[ATLAS_LAYERS as usize - 1];
+	assert_eq!((last_in_first.atlas(), last_in_first.layer()), (0, ATLAS_LAYERS - 1));
+	// Once the first atlas is full, allocation continues in the next one
+	let first_in_second = ids[ATLAS_LAYERS as usize];
+	assert_eq!((first_in_second.atlas(), first_in_second.layer()), (1, 0));
+	let second_in_second = ids[ATLAS_LAYERS as usize + 1];
+	assert_eq!((second_in_second.atlas(), second_in_second.layer()), (1, 1));
+	// All ids are distinct
+	let mut raw = ids.iter().map(|id| id.0).collect::<Vec<_>>();
+	raw.dedup();
+	assert_eq!(raw.len(), ids.len());
+}
+
+#[cfg(test)]
+#[test]
+fn test_missing_texture() {
+	let mut assets = Assets::new();
+	let color = assets.add_color([1.0; 4]);
+	let missing = assets.missing_texture();
+	assert!(missing != color);
+	// It's only added once
+	assert_eq!(assets.missing_texture(), missing);
+	assert_eq!(assets.assets.len(), 2);
+	let (pixels, dimensions) = &assets.assets[missing.0 as usize];
+	assert_eq!(*dimensions, (16, 16));
+	let pixel = |x :usize, y :usize| &pixels[(y * 16 + x) * 4 ..][.. 4];
+	assert_eq!(pixel(0, 0), [1.0, 0.0, 1.0, 1.0]);
+	assert_eq!(pixel(8, 0), [0.0, 0.0, 0.0, 1.0]);
+	assert_eq!(pixel(15, 15), [1.0, 0.0, 1.0, 1.0]);
+}
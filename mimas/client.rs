@@ -1,51 +1,76 @@
 use mimas_server::map::{Map, MapBackend, ClientMap,
-	CHUNKSIZE, MapBlock, MetadataEntry};
+	CHUNKSIZE, MapBlock, MapChunkData, MetadataEntry, Dir6};
 use glium::{glutin, Surface, VertexBuffer};
 use glium::texture::SrgbTexture2dArray;
 use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction};
 use glium::glutin::platform::desktop::EventLoopExtDesktop;
-use glutin::dpi::PhysicalPosition;
+use glutin::dpi::{PhysicalPosition, PhysicalSize};
 use glutin::event_loop::{EventLoop, ControlFlow};
+use glutin::monitor::MonitorHandle;
+use glutin::window::Fullscreen;
 use glutin::event::{Event, ElementState, KeyboardInput, VirtualKeyCode,
-	WindowEvent, MouseButton, MouseScrollDelta};
+	WindowEvent, MouseButton, MouseScrollDelta, ModifiersState};
 use nalgebra::{Vector3, Matrix4, Point3, Rotation3};
 use num_traits::identities::Zero;
 use glium_glyph::GlyphBrush;
 use glium_glyph::glyph_brush::{
-	rusttype::Font, Section,
+	rusttype::Font, Section, Layout, HorizontalAlign,
 };
 use std::collections::{HashMap, VecDeque};
+use std::cmp::Ordering;
 use std::time::{Instant, Duration};
 use std::thread;
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use frustum_query::frustum::Frustum;
-use collide::collide;
+use mimas_server::collide::{collide, step_up_height, StuckDetector, Unstuck};
 use srp::client::SrpClient;
 use srp::groups::G_4096;
 use sha2::Sha256;
 use rand::RngCore;
 
-use mimas_server::{btchn, ServerToClientMsg, ClientToServerMsg};
+use mimas_server::{btchn, ServerToClientMsg, ClientToServerMsg, PROTOCOL_VERSION};
 use mimas_server::generic_net::NetworkClientConn;
 use mimas_server::local_auth::{PlayerPwHash, HashParams};
-use mimas_server::config::Config;
+use mimas_server::config::{Config, WindowMode};
+use mimas_server::logging::TARGET_CLIENT;
 use mimas_server::map_storage::{PlayerPosition, PlayerIdPair};
 use mimas_server::inventory::SelectableInventory;
-use mimas_server::game_params::GameParamsHdl;
-
-use mimas_meshgen::{Vertex, mesh_for_chunk, push_block,
-	BlockTextureIds, TextureIdCache, ChunkMesh};
+use mimas_server::crafting::CraftingGrid;
+use mimas_server::inventory_action::InventoryAction;
+use mimas_server::hunger::{Hunger, MAX_HUNGER};
+use mimas_server::clock::WorldClock;
+use mimas_server::game_mode::GameMode;
+use mimas_server::entities::{EntityId, EntityKind};
+use mimas_server::game_params::{GameParamsHdl, drops_for, is_solid};
+use mimas_server::server_list::{ServerList, ServerEntry, server_list_path};
+
+use mimas_meshgen::{Vertex, push_block,
+	BlockTextureIds, TextureIdCache, ChunkMesh,
+	OcclusionTracker, MeshPartsCache, chunk_fully_opaque, block_aabb};
 
 use assets::{Assets, UiColors};
 
-use ui::{render_menu, square_mesh, ChatWindow, ChatWindowEvent,
-	ChestMenu, InventoryMenu, IDENTITY, render_inventory_hud};
+use ui::{render_menu, ChatWindow, ChatWindowEvent, ChatHistory,
+	ChestMenu, InventoryMenu, NO_FOG, render_inventory_hud,
+	UiStack, Modal, ServerListMenu, ServerListEvent,
+	render_hunger_bar, render_crosshair};
 
 use voxel_walk::VoxelWalker;
+use particles::Particles;
+use keys::MovementKeys;
 
 type MeshResReceiver = Receiver<(Vector3<isize>, ChunkMesh)>;
 
+/// A change of the map, sent to the mesh generation thread
+enum MeshgenMsg {
+	Chunk(Vector3<isize>, MapChunkData),
+	/// A single block edit, for which only part of the chunk gets remeshed
+	Block(Vector3<isize>, MapChunkData),
+	/// The camera moved into the chunk at the position
+	CameraChunk(Vector3<isize>),
+}
+
 fn gen_chunks_around<B :MapBackend>(map :&mut Map<B>, pos :Vector3<isize>, xyradius :isize, zradius :isize) {
 	let chunk_pos = btchn(pos);
 	let radius = Vector3::new(xyradius, xyradius, zradius) * CHUNKSIZE;
@@ -73,37 +98,64 @@ pub struct Game<C :NetworkClientConn> {
 	params :Option<GameParamsHdl>,
 	ui_colors :Option<UiColors>,
 	texture_id_cache :Option<TextureIdCache>,
-	texture_array :Option<SrgbTexture2dArray>,
+	texture_arrays :Vec<SrgbTexture2dArray>,
 
 	meshgen_spawner :Option<Box<dyn FnOnce(TextureIdCache)>>,
 	meshres_r :MeshResReceiver,
+	meshgen_s :Sender<MeshgenMsg>,
+	/// Chunks that became visible again and need to be sent for meshing
+	remesh_r :Receiver<Vector3<isize>>,
+	/// The camera chunk last sent to the mesh generation thread
+	meshgen_camera_chunk :Option<Vector3<isize>>,
 
 	display :glium::Display,
+	window_mode :WindowMode,
 	program :glium::Program,
+	render_debug :RenderDebug,
 	vbuffs :HashMap<Vector3<isize>, (VertexBuffer<Vertex>, Option<VertexBuffer<Vertex>>)>,
+	mesh_uploads :MeshUploadQueue,
 
 	selected_pos :Option<(Vector3<isize>, Vector3<isize>)>,
+	particles :Particles,
+	/// Blocks dug locally whose removal the server hasn't confirmed yet
+	pending_breaks :HashMap<Vector3<isize>, MapBlock>,
 	sel_inventory :SelectableInventory,
 	craft_inv :SelectableInventory,
+	table_craft_inv :SelectableInventory,
+	hunger :Hunger,
+	clock :WorldClock,
 
 	last_pos :Option<PhysicalPosition<f64>>,
 
 	last_frame_time :Instant,
 	last_fps :f32,
 
-	player_positions :Option<(PlayerIdPair, Vec<(PlayerIdPair, Vector3<f32>)>)>,
+	player_positions :Option<(PlayerIdPair, Vec<(PlayerIdPair, String, Vector3<f32>)>)>,
+	entities :Vec<(EntityId, Vector3<f32>, EntityKind)>,
+	/// The area around the spawn only admins can edit, with exclusive maximum
+	spawn_protection :Option<(Vector3<isize>, Vector3<isize>)>,
 
 	grab_cursor :bool,
 	grabbing_cursor :bool,
 	has_focus :bool,
+	modifiers :ModifiersState,
 	chat_msgs :VecDeque<String>,
 	chat_window :Option<ChatWindow>,
+	chat_history :ChatHistory,
 	inventory_menu :Option<InventoryMenu>,
 	chest_menu :Option<ChestMenu>,
-	menu_enabled :bool,
+	server_list_menu :Option<ServerListMenu>,
+	ui_stack :UiStack,
+
+	server_list :ServerList,
+	/// The server connected to, if not in singleplayer mode
+	current_server :Option<ServerEntry>,
+	/// The server selected in the server list to connect to next
+	connect_to :Option<ServerEntry>,
 
 	map :ClientMap,
 	camera :Camera,
+	stuck_detector :StuckDetector,
 
 	swidth :f64,
 	sheight :f64,
@@ -111,35 +163,37 @@ pub struct Game<C :NetworkClientConn> {
 
 macro_rules! maybe_inventory_change {
 	($m:ident, $this:ident) => {
+		for action in $m.take_actions() {
+			let _ = $this.srv_conn.send(ClientToServerMsg::InventoryAction(action));
+		}
 		if $m.inventory() != &$this.sel_inventory {
 			$this.sel_inventory = $m.inventory().clone();
-			let msg = ClientToServerMsg::SetInventory($this.sel_inventory.clone());
-			let _ = $this.srv_conn.send(msg);
 		}
-		if $m.craft_inv() != &$this.craft_inv {
-			$this.craft_inv = $m.craft_inv().clone();
-			// TODO send craft inventory to server
+		let craft_inv = if $m.craft_grid() == CraftingGrid::PERSONAL {
+			&mut $this.craft_inv
+		} else {
+			&mut $this.table_craft_inv
+		};
+		if $m.craft_inv() != craft_inv {
+			*craft_inv = $m.craft_inv().clone();
 		}
 	};
 }
 
 macro_rules! maybe_chest_inventory_change {
 	($m:ident, $this:ident) => {
+		for action in $m.take_actions() {
+			let _ = $this.srv_conn.send(ClientToServerMsg::InventoryAction(action));
+		}
 		if $m.inventory() != &$this.sel_inventory {
 			$this.sel_inventory = $m.inventory().clone();
-			let msg = ClientToServerMsg::SetInventory($this.sel_inventory.clone());
-			let _ = $this.srv_conn.send(msg);
 		}
 		let mut chest_meta = $this.map.get_blk_meta_mut($m.chest_pos()).unwrap();
-		if Some($m.chest_inv()) != chest_meta.get().map(|v| {
-			let MetadataEntry::Inventory(inv) = v;
-			inv
+		if Some($m.chest_inv()) != chest_meta.get().and_then(|v| match v {
+			MetadataEntry::Inventory(inv) => Some(inv),
+			_ => None,
 		}) {
 			chest_meta.set(MetadataEntry::Inventory($m.chest_inv().clone()));
-
-			let meta = MetadataEntry::Inventory($m.chest_inv().clone());
-			let msg = ClientToServerMsg::SetMetadata($m.chest_pos(), meta);
-			let _ = $this.srv_conn.send(msg);
 		}
 	};
 }
@@ -155,26 +209,51 @@ fn title() -> String {
 
 impl<C :NetworkClientConn> Game<C> {
 	pub fn new(event_loop :&EventLoop<()>,
-			srv_conn :C, config :Config, nick_pw :Option<(String, String)>) -> Self {
-		let window = glutin::window::WindowBuilder::new()
-			.with_title(&title());
+			srv_conn :C, config :Config, nick_pw :Option<(String, String)>,
+			server_list :ServerList, current_server :Option<ServerEntry>) -> Self {
+		let window_mode = config.window_mode;
+		let (fullscreen_kind, size) = window_setup(window_mode);
+		let mut window = glutin::window::WindowBuilder::new()
+			.with_title(&title())
+			.with_fullscreen(fullscreen_for(fullscreen_kind, event_loop.primary_monitor()));
+		if let Some((w, h)) = size {
+			window = window.with_inner_size(PhysicalSize::new(w, h));
+		}
 		let context = glutin::ContextBuilder::new().with_depth_buffer(24);
 		let display = glium::Display::new(window, context, event_loop).unwrap();
 
 		let mut map = ClientMap::new();
-		let camera = Camera::new();
+		let movement_keys = MovementKeys::from_config(&config).unwrap_or_else(|e| {
+			warn!(target : TARGET_CLIENT, "Using the default movement keys due to error: {:?}", e);
+			MovementKeys::for_layout(config.keyboard_layout)
+		});
+		let mut camera = Camera::new(movement_keys);
+		let inner_size = display.gl_window().window().inner_size();
+		camera.aspect_ratio = aspect_ratio(inner_size);
+		let render_debug = if config.draw_poly_lines {
+			RenderDebug::Wireframe
+		} else {
+			RenderDebug::Off
+		};
 
 		let program = glium::Program::from_source(&display, VERTEX_SHADER_SRC,
 			FRAGMENT_SHADER_SRC, None).unwrap();
 
 		let (meshgen_s, meshgen_r) = channel();
 		let (meshres_s, meshres_r) = channel();
+		let (remesh_s, remesh_r) = channel();
 
 
+		let meshgen_chunk_s = meshgen_s.clone();
+		let meshgen_block_s = meshgen_s.clone();
 		map.register_on_change(Box::new(move |chunk_pos, chunk| {
-			meshgen_s.send((chunk_pos, chunk.clone())).unwrap();
+			meshgen_chunk_s.send(MeshgenMsg::Chunk(chunk_pos, chunk.clone())).unwrap();
+		}));
+		map.register_on_block_change(Box::new(move |pos, _, chunk| {
+			meshgen_block_s.send(MeshgenMsg::Block(pos, chunk.clone())).unwrap();
 		}));
 
+		let _ = srv_conn.send(ClientToServerMsg::Hello(PROTOCOL_VERSION));
 		let auth_state = if let Some((nick, pw)) = nick_pw {
 			// Start doing the login
 			let mut a = [0; 64];
@@ -192,8 +271,8 @@ impl<C :NetworkClientConn> Game<C> {
 		// close to the player at the beginning.
 		gen_chunks_around(&mut map, camera.pos.map(|v| v as isize), 1, 1);
 
-		let swidth = 1024.0;
-		let sheight = 768.0;
+		let swidth = inner_size.width.into();
+		let sheight = inner_size.height.into();
 
 		Game {
 			srv_conn,
@@ -203,45 +282,99 @@ impl<C :NetworkClientConn> Game<C> {
 			params : None,
 			ui_colors : None,
 			texture_id_cache : None,
-			texture_array : None,
+			texture_arrays : Vec::new(),
 
 			meshgen_spawner : Some(Box::new(move |cache| {
 				thread::spawn(move || {
 					let cache = cache;
-					while let Ok((p, chunk)) = meshgen_r.recv() {
+					let mut occlusion = OcclusionTracker::new();
+					let mut mesh_parts = MeshPartsCache::new();
+					while let Ok(msg) = meshgen_r.recv() {
 						//let start = Instant::now();
-						let mesh = mesh_for_chunk(p, &chunk, &cache);
-						let _ = meshres_s.send((p, mesh));
+						let (chunk_pos, chunk, edited) = match msg {
+							MeshgenMsg::Chunk(p, chunk) => (p, chunk, None),
+							MeshgenMsg::Block(pos, chunk) => {
+								let p = btchn(pos);
+								(p, chunk, Some(pos - p))
+							},
+							MeshgenMsg::CameraChunk(p) => {
+								for (p, occluded) in occlusion.set_camera_chunk(p) {
+									if occluded {
+										mesh_parts.remove(p);
+										let _ = meshres_s.send((p, ChunkMesh::empty()));
+									} else {
+										let _ = remesh_s.send(p);
+									}
+								}
+								continue;
+							},
+						};
+						let opaque = chunk_fully_opaque(&chunk, &cache);
+						for (p, occluded) in occlusion.update(chunk_pos, opaque) {
+							// Chunks hidden by their neighbours get an empty mesh
+							let mesh = if occluded {
+								mesh_parts.remove(p);
+								ChunkMesh::empty()
+							} else if p == chunk_pos {
+								mesh_parts.mesh(p, &chunk, edited, &cache)
+							} else {
+								// Only the map has the contents of the neighbour
+								let _ = remesh_s.send(p);
+								continue;
+							};
+							let _ = meshres_s.send((p, mesh));
+						}
 						//println!("Generated mesh in {:?}", Instant::now() - start);
 					}
 				});
 			})),
 			meshres_r,
+			meshgen_s,
+			remesh_r,
+			meshgen_camera_chunk : None,
 
 			display,
+			window_mode,
 			program,
+			render_debug,
 			vbuffs : HashMap::new(),
+			mesh_uploads : MeshUploadQueue::new(),
 
 			selected_pos : None,
+			particles : Particles::new(),
+			pending_breaks : HashMap::new(),
 			sel_inventory : SelectableInventory::new(),
-			craft_inv : SelectableInventory::crafting_inv(),
+			craft_inv : SelectableInventory::crafting_inv(CraftingGrid::PERSONAL),
+			table_craft_inv : SelectableInventory::crafting_inv(CraftingGrid::TABLE),
+			hunger : Hunger::default(),
+			clock : WorldClock::default(),
 
 			last_pos : None,
 			last_frame_time : Instant::now(),
 			last_fps : 0.0,
 
 			player_positions : None,
+			entities : Vec::new(),
+			spawn_protection : None,
 
 			grab_cursor : true,
 			grabbing_cursor : false,
 			has_focus : false,
+			modifiers : ModifiersState::empty(),
 			chat_msgs : VecDeque::new(),
 			chat_window : None,
+			chat_history : ChatHistory::new(),
 			inventory_menu : None,
 			chest_menu : None,
-			menu_enabled : false,
+			server_list_menu : None,
+			ui_stack : UiStack::new(),
+
+			server_list,
+			current_server,
+			connect_to : None,
 			map,
 			camera,
+			stuck_detector : StuckDetector::default(),
 
 			swidth,
 			sheight,
@@ -282,12 +415,13 @@ impl<C :NetworkClientConn> Game<C> {
 		float_delta
 	}
 	fn in_background(&self) -> bool {
-		self.chat_window.is_some() ||
-			self.inventory_menu.is_some() ||
-			self.chest_menu.is_some() ||
-			self.menu_enabled
+		!self.ui_stack.is_empty()
 	}
-	pub fn run_loop(&mut self, event_loop :&mut EventLoop<()>) {
+	/// Runs the game until it's closed
+	///
+	/// Returns the server to connect to next, if one has
+	/// been selected in the server list.
+	pub fn run_loop(&mut self, event_loop :&mut EventLoop<()>) -> Option<ServerEntry> {
 		let fonts = vec![Font::from_bytes(KENPIXEL).unwrap()];
 		let mut glyph_brush = GlyphBrush::new(&self.display, fonts);
 		'game_main_loop :loop {
@@ -295,6 +429,8 @@ impl<C :NetworkClientConn> Game<C> {
 				self.camera.pos.map(|v| v as isize), 4, 2);
 			self.render(&mut glyph_brush);
 			let float_delta = self.update_fps();
+			self.particles.tick(float_delta);
+			self.clock.tick(float_delta);
 			let close = self.handle_events(event_loop);
 			self.handle_mouse_buttons(float_delta);
 			if !self.in_background() {
@@ -337,6 +473,12 @@ impl<C :NetworkClientConn> Game<C> {
 							eprintln!("Error: received hash params msg.");
 						}
 					},
+					ServerToClientMsg::IncompatibleVersion(srv_version, min_version) => {
+						warn!(target : TARGET_CLIENT, "Incompatible protocol version. The server has version {} \
+							and accepts clients from version {}, this client has version {}.",
+							srv_version, min_version, PROTOCOL_VERSION);
+						break 'game_main_loop;
+					},
 					ServerToClientMsg::LogInFail(reason) => {
 						println!("Log-In failed. Reason: {}", reason);
 						break 'game_main_loop;
@@ -355,14 +497,16 @@ impl<C :NetworkClientConn> Game<C> {
 							crate::assets::store_hashed_blobs(&blobs).unwrap();
 							if let Some(spawner) = self.meshgen_spawner.take() {
 								let mut assets = Assets::new();
-								let cache = TextureIdCache::from_hdl(params, |ds| {
-									assets.add_draw_style(params, ds)
+								// Add the UI colors first so that they end up in
+								// the first atlas, which the UI passes sample from.
+								self.ui_colors = Some(UiColors::new(&mut assets, self.config.crosshair_color));
+								let missing_texture = assets.missing_texture();
+								let cache = TextureIdCache::from_hdl(params, missing_texture, |ds, transparency| {
+									assets.add_draw_style(params, ds, transparency)
 								});
 								spawner(cache.clone());
 								self.texture_id_cache = Some(cache);
-								self.ui_colors = Some(UiColors::new(&mut assets));
-								let texture_array = assets.into_texture_array(&self.display).unwrap();
-								self.texture_array = Some(texture_array);
+								self.texture_arrays = assets.into_texture_arrays(&self.display).unwrap();
 							} else {
 								// TODO print a warning about duplicate GameParams or sth
 							}
@@ -379,8 +523,50 @@ impl<C :NetworkClientConn> Game<C> {
 					ServerToClientMsg::SetInventory(inv) => {
 						self.sel_inventory = inv;
 					},
-					ServerToClientMsg::ChunkUpdated(p, c) => {
+					ServerToClientMsg::SetCraftInventory(grid, inv) => {
+						if grid == CraftingGrid::PERSONAL {
+							self.craft_inv = inv;
+						} else {
+							self.table_craft_inv = inv;
+						}
+					},
+					ServerToClientMsg::SetHunger(hunger) => {
+						self.hunger = hunger;
+					},
+					ServerToClientMsg::SetTime(clock) => {
+						self.clock = clock;
+					},
+					ServerToClientMsg::SetGameMode(mode) => {
+						self.camera.set_game_mode(mode);
+					},
+					ServerToClientMsg::Entities(entities) => {
+						self.entities = entities;
+					},
+					ServerToClientMsg::SetSpawnProtection(area) => {
+						self.spawn_protection = area;
+					},
+					ServerToClientMsg::ChunkUpdated(p, c, version) => {
+						// The server answers digs with the whole chunk if it
+						// rejects them or if the dug block had metadata
+						let changes = self.pending_breaks.keys()
+							.filter(|pos| btchn(**pos) == p)
+							.map(|pos| {
+								let b = *c.get_blk(*pos - p);
+								(*pos, self.map.get_blk(*pos).unwrap_or(b), b)
+							})
+							.collect::<Vec<_>>();
 						self.map.set_chunk(p, c);
+						self.handle_block_changes(&changes);
+						let msg = ClientToServerMsg::AckChunk(p, version);
+						let _ = self.srv_conn.send(msg);
+					},
+					ServerToClientMsg::BlockBatch(batch) => {
+						let previous = self.map.apply_block_batch(&batch);
+						let changes = batch.edits.iter()
+							.zip(previous)
+							.filter_map(|(&(pos, b), prev)| prev.map(|prev| (pos, prev, b)))
+							.collect::<Vec<_>>();
+						self.handle_block_changes(&changes);
 					},
 					ServerToClientMsg::Chat(s) => {
 						self.chat_msgs.push_back(s);
@@ -392,7 +578,7 @@ impl<C :NetworkClientConn> Game<C> {
 				}
 			}
 
-			if close {
+			if close || self.connect_to.is_some() {
 				break;
 			}
 			if self.grabbing_cursor {
@@ -402,6 +588,25 @@ impl<C :NetworkClientConn> Game<C> {
 				}).unwrap();
 			}
 		}
+		self.connect_to.take()
+	}
+	pub fn server_list(&self) -> &ServerList {
+		&self.server_list
+	}
+	fn handle_server_list_ev(&mut self, ev :ServerListEvent) {
+		match ev {
+			ServerListEvent::Connect(entry) => {
+				self.connect_to = Some(entry);
+			},
+			ServerListEvent::Changed => {
+				let saved = server_list_path()
+					.and_then(|path| self.server_list.save_to(path));
+				if let Err(e) = saved {
+					warn!(target : TARGET_CLIENT, "Couldn't save the server list: {:?}", e);
+				}
+			},
+			ServerListEvent::None => (),
+		}
 	}
 	fn collide_delta_pos(&mut self, mut delta_pos :Vector3<f32>, time_delta :f32) -> Vector3<f32> {
 		let pos = self.camera.pos.map(|v| v as isize);
@@ -415,20 +620,46 @@ impl<C :NetworkClientConn> Game<C> {
 		} else {
 			return Vector3::new(0.0, 0.0, 0.0);
 		};
+		let map = &self.map;
+		let solid_at = |p| {
+			map.get_blk(p)
+				.map(|v| is_solid(v, params))
+		};
+		let player_pos = self.camera.pos - Vector3::new(0.35, 0.35, 1.6);
+		match self.stuck_detector.check(player_pos, solid_at) {
+			Some(Unstuck::Teleport(p)) => {
+				info!(target : TARGET_CLIENT, "Stuck inside solid blocks at {:?}, moving up", self.camera.pos);
+				self.camera.pos += p - player_pos;
+				self.camera.velocity = nalgebra::zero();
+				return Vector3::new(0.0, 0.0, 0.0);
+			},
+			Some(Unstuck::ToSpawn) => {
+				info!(target : TARGET_CLIENT, "Stuck inside solid blocks at {:?}, returning to spawn",
+					self.camera.pos);
+				let msg = ClientToServerMsg::Chat("/spawn".to_owned());
+				let _ = self.srv_conn.send(msg);
+			},
+			None => (),
+		}
 		for x in cubes_min.x .. cubes_max.x {
 			for y in cubes_min.y .. cubes_max.y {
 				for z in cubes_min.z .. cubes_max.z {
 					let p = Vector3::new(x, y, z);
 					if self.map.get_blk(p)
-							.and_then(|v| params.get_block_params(v))
-							.map(|v| v.solid) == Some(false) {
+							.map(|v| is_solid(v, params)) == Some(false) {
 						continue;
 					}
 					cubes.push(p);
 				}
 			}
 		}
-		let player_pos = self.camera.pos - Vector3::new(0.35, 0.35, 1.6);
+		let mut player_pos = self.camera.pos - Vector3::new(0.35, 0.35, 1.6);
+		if self.config.auto_jump && !self.camera.fly_mode {
+			if let Some(h) = step_up_height(player_pos, delta_pos, &cubes) {
+				self.camera.pos.z += h;
+				player_pos.z += h;
+			}
+		}
 		let mut touches_ground = false;
 		for pos in cubes.into_iter() {
 			// X coord
@@ -483,7 +714,7 @@ impl<C :NetworkClientConn> Game<C> {
 	}
 	fn movement(&mut self, time_delta :f32) {
 		let mut delta_pos = self.camera.delta_pos();
-		if self.camera.fast_speed() {
+		if self.camera.fast_speed() && self.hunger.can_sprint() {
 			const FAST_DELTA :f32 = 40.0;
 			delta_pos *= FAST_DELTA;
 		} else {
@@ -503,29 +734,56 @@ impl<C :NetworkClientConn> Game<C> {
 		self.chat_msgs.iter().fold(String::new(), |v, w| v + "\n" + w)
 	}
 	fn render<'a, 'b>(&mut self, glyph_brush :&mut GlyphBrush<'a, 'b>) {
-		self.recv_vbuffs();
 		let pmatrix = self.camera.get_perspective();
 		let vmatrix = self.camera.get_matrix();
 		let frustum = Frustum::from_modelview_and_projection_2d(
 			&vmatrix,
 			&pmatrix,
 		);
-		let texture_array = if let Some(texture_array) = &self.texture_array {
-			texture_array
-		} else {
+		self.recv_vbuffs(&frustum);
+		let texture_arrays = &self.texture_arrays;
+		if texture_arrays.is_empty() {
 			return;
+		}
+		// Unused atlas slots are bound to the first atlas,
+		// the shader never samples from them.
+		let texture_arr = |atlas :usize| {
+			texture_arrays.get(atlas)
+				.unwrap_or(&texture_arrays[0])
+				.sampled()
+				.wrap_function(SamplerWrapFunction::Repeat)
+				.magnify_filter(MagnifySamplerFilter::Nearest)
+		};
+		let underwater = self.params.as_ref()
+			.map(|p| camera_underwater(&self.map, self.camera.pos, p.block_roles.water))
+			.unwrap_or(false);
+		let fog = if underwater {
+			UNDERWATER_FOG
+		} else {
+			fog_near_far(self.config.viewing_range)
 		};
-		let texture_arr = texture_array.sampled()
-			.wrap_function(SamplerWrapFunction::Repeat)
-			.magnify_filter(MagnifySamplerFilter::Nearest);
+		let (sky_color, fog_color) = sky_and_fog_colors(&self.config, &self.clock, underwater);
 		// building the uniforms
 		let uniforms = uniform! {
 			vmatrix : vmatrix,
 			pmatrix : pmatrix,
-			texture_arr : texture_arr,
-			fog_near_far : [self.config.fog_near, self.config.fog_far]
+			texture_arr_0 : texture_arr(0),
+			texture_arr_1 : texture_arr(1),
+			texture_arr_2 : texture_arr(2),
+			texture_arr_3 : texture_arr(3),
+			fog_near_far : fog,
+			fog_color : fog_color,
+			darkness : self.clock.darkness(),
+			underwater : if underwater { 1.0f32 } else { 0.0 },
+			show_normals : if self.render_debug.show_normals() { 1.0f32 } else { 0.0 }
+		};
+		// Spectators can't edit, so they don't target any blocks
+		self.selected_pos = if self.camera.game_mode.can_edit() {
+			let reach = self.config.reach_distance;
+			self.params.as_ref().and_then(|params| self.camera.get_selected_pos(&self.map, params, reach))
+		} else {
+			None
 		};
-		self.selected_pos = self.params.as_ref().and_then(|params| self.camera.get_selected_pos(&self.map, params));
 		let mut sel_text = "sel = None".to_string();
 		let mut selbuff = Vec::new();
 		if let (Some((selected_pos, _)), Some(ui_colors)) = (self.selected_pos, &self.ui_colors) {
@@ -542,44 +800,59 @@ impl<C :NetworkClientConn> Game<C> {
 
 			// TODO: only update if the position actually changed from the prior one
 			// this spares us needless chatter with the GPU
-			let vertices = selection_mesh(selected_pos, &ui_colors);
+			let mds = self.texture_id_cache.as_ref()
+				.and_then(|c| c.get_mesh_draw_style(&blk));
+			let vertices = selection_mesh(selected_pos, block_aabb(mds), &ui_colors);
 			let vbuff = VertexBuffer::new(&self.display, &vertices).unwrap();
 			selbuff = vec![vbuff];
 		}
+		if let (Some(area), Some(ui_colors)) = (self.spawn_protection, &self.ui_colors) {
+			let vertices = spawn_protection_mesh(area, &ui_colors);
+			selbuff.push(VertexBuffer::new(&self.display, &vertices).unwrap());
+		}
 		let mut pl_buf = Vec::new();
 		if let (Some((own_id, positions)), Some(ui_colors)) = (&self.player_positions, &self.ui_colors) {
-			for (id, pos) in positions {
+			for (id, _nick, pos) in positions {
 				if id == own_id {
 					continue;
 				}
+				let (aabb_min, aabb_max) = player_aabb(*pos);
+				if !is_visible(aabb_min, aabb_max, &frustum) {
+					continue;
+				}
 				let v = player_mesh(*pos, &ui_colors);
 				let vbuff = VertexBuffer::new(&self.display, &v).unwrap();
 				pl_buf.push(vbuff);
 			}
 		}
-		let screen_dims = self.display.get_framebuffer_dimensions();
-
-		let polygon_mode = if !self.config.draw_poly_lines {
-			glium::draw_parameters::PolygonMode::Fill
+		let particle_vertices = self.particles.mesh();
+		let particle_buf = if !particle_vertices.is_empty() {
+			vec![VertexBuffer::new(&self.display, &particle_vertices).unwrap()]
 		} else {
-			glium::draw_parameters::PolygonMode::Line
+			Vec::new()
 		};
+		let mut entity_buf = Vec::new();
+		if let Some(texture_id_cache) = &self.texture_id_cache {
+			let visible_entities = self.entities.iter()
+				.filter(|(_id, pos, _kind)| {
+					let (aabb_min, aabb_max) = dropped_item_aabb(*pos);
+					is_visible(aabb_min, aabb_max, &frustum)
+				})
+				.cloned()
+				.collect::<Vec<_>>();
+			let vertices = entities_mesh(&visible_entities, texture_id_cache);
+			if !vertices.is_empty() {
+				entity_buf.push(VertexBuffer::new(&self.display, &vertices).unwrap());
+			}
+		}
+		let screen_dims = self.display.get_framebuffer_dimensions();
 
-		let params = glium::draw_parameters::DrawParameters {
-			depth : glium::Depth {
-				test : glium::draw_parameters::DepthTest::IfLess,
-				write : true,
-				.. Default::default()
-			},
-			backface_culling : glium::draw_parameters::BackfaceCullingMode::CullCounterClockwise,
-			blend :glium::Blend::alpha_blending(),
-			polygon_mode,
-			.. Default::default()
-		};
+		let params = world_draw_params(self.render_debug);
 
 		// drawing a frame
 		let mut target = self.display.draw();
-		target.clear_color_and_depth((0.05, 0.01, 0.6, 0.0), 1.0);
+		let [r, g, b, a] = sky_color;
+		target.clear_color_and_depth((r, g, b, a), 1.0);
 
 		let player_pos = self.camera.pos;
 		let mut drawn_chunks_count = 0;
@@ -605,13 +878,19 @@ impl<C :NetworkClientConn> Game<C> {
 			.map(|m| &m.0)
 			.chain(vbuffs_to_draw.iter().filter_map(|m| m.1.as_ref()));
 		for buff in vbuffs_to_draw_iter
-				.chain(selbuff.iter())
-				.chain(pl_buf.iter()) {
+				.chain(pl_buf.iter())
+				.chain(particle_buf.iter())
+				.chain(entity_buf.iter()) {
 			drawn_chunks_count += 1;
 			target.draw(buff,
 				&glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
 				&self.program, &uniforms, &params).unwrap();
 		}
+		for buff in selbuff.iter() {
+			target.draw(buff,
+				&glium::index::NoIndices(glium::index::PrimitiveType::LinesList),
+				&self.program, &uniforms, &params).unwrap();
+		}
 
 		// TODO turn off anti-aliasing of the font
 		// https://gitlab.redox-os.org/redox-os/rusttype/issues/61
@@ -626,6 +905,30 @@ impl<C :NetworkClientConn> Game<C> {
 			color : [0.9, 0.9, 0.9, 1.0],
 			.. Section::default()
 		});
+		if let (Some((own_id, positions)), Some(params)) = (&self.player_positions, &self.params) {
+			let (pmatrix, vmatrix) = (Matrix4::from(pmatrix), Matrix4::from(vmatrix));
+			for (id, nick, pos) in positions {
+				if id == own_id {
+					continue;
+				}
+				let label = match nameplate(&pmatrix, &vmatrix, self.camera.pos, *pos, screen_dims) {
+					Some(l) => l,
+					None => continue,
+				};
+				if line_occluded(&self.map, params, self.camera.pos, label.world_pos) {
+					continue;
+				}
+				glyph_brush.queue(Section {
+					text : nick,
+					screen_position : label.screen_pos,
+					bounds : (screen_dims.0 as f32, screen_dims.1 as f32),
+					layout : Layout::default()
+						.h_align(HorizontalAlign::Center),
+					color : [1.0, 1.0, 1.0, label.alpha],
+					.. Section::default()
+				});
+			}
+		}
 
 		glyph_brush.draw_queued(&self.display, &mut target);
 		// Draw the wielded item
@@ -649,7 +952,12 @@ impl<C :NetworkClientConn> Game<C> {
 			let uniforms = uniform! {
 				vmatrix : vmatrix,
 				pmatrix : pmatrix,
-				fog_near_far : [40.0f32, 60.0]
+				texture_arr_0 : texture_arr(0),
+				texture_arr_1 : texture_arr(1),
+				texture_arr_2 : texture_arr(2),
+				texture_arr_3 : texture_arr(3),
+				fog_near_far : NO_FOG,
+				darkness : self.clock.darkness()
 			};
 			let hand_mesh_pos = Vector3::new(3.0, 1.0, -1.5) * 2.0;
 			if let (Some(item), Some(texture_id_cache)) = (self.sel_inventory.get_selected(), &self.texture_id_cache) {
@@ -663,23 +971,34 @@ impl<C :NetworkClientConn> Game<C> {
 		if let (Some(params), Some(ui_colors)) = (&self.params, &self.ui_colors) {
 			render_inventory_hud(
 				&self.sel_inventory,
+				self.config.hud_slot_count,
 				ui_colors,
 				&mut self.display,
 				&self.program, glyph_brush,
 				params, &mut target);
+			render_hunger_bar(
+				self.hunger.value() / MAX_HUNGER,
+				self.config.hud_slot_count,
+				ui_colors,
+				&self.display,
+				&self.program, &mut target);
 		}
 		if self.in_background() {
-			if let (true, Some(ui_colors)) = (self.menu_enabled, &self.ui_colors) {
+			// Only the topmost modal is rendered
+			let top = self.ui_stack.top();
+			if let (Some(Modal::PauseMenu), Some(ui_colors)) = (top, &self.ui_colors) {
 				render_menu(ui_colors, &mut self.display, &self.program, glyph_brush, &mut target);
-			} else if let (Some(cw), Some(ui_colors)) = (&self.chat_window, &self.ui_colors) {
+			} else if let (Some(Modal::ServerList), Some(m), Some(ui_colors)) = (top, &self.server_list_menu, &self.ui_colors) {
+				m.render(&self.server_list, ui_colors, &mut self.display, &self.program, glyph_brush, &mut target);
+			} else if let (Some(Modal::Chat), Some(cw), Some(ui_colors)) = (top, &self.chat_window, &self.ui_colors) {
 				cw.render(ui_colors, &mut self.display, &self.program, glyph_brush, &mut target);
-			} else if let (Some(m), Some(ui_colors)) = (&mut self.inventory_menu, &self.ui_colors) {
+			} else if let (Some(Modal::Inventory), Some(m), Some(ui_colors)) = (top, &mut self.inventory_menu, &self.ui_colors) {
 				m.render(
 					ui_colors,
 					&mut self.display,
 					&self.program, glyph_brush, &mut target);
 				maybe_inventory_change!(m, self);
-			} else if let (Some(m), Some(ui_colors)) = (&mut self.chest_menu, &self.ui_colors) {
+			} else if let (Some(Modal::Chest), Some(m), Some(ui_colors)) = (top, &mut self.chest_menu, &self.ui_colors) {
 				m.render(
 					ui_colors,
 					&mut self.display,
@@ -687,32 +1006,41 @@ impl<C :NetworkClientConn> Game<C> {
 				maybe_chest_inventory_change!(m, self);
 			}
 		} else if let Some(ui_colors) = &self.ui_colors {
-			let params = glium::draw_parameters::DrawParameters {
-				blend :glium::Blend::alpha_blending(),
-				.. Default::default()
-			};
-
-			let uniforms = uniform! {
-				vmatrix : IDENTITY,
-				pmatrix : IDENTITY,
-				fog_near_far : [40.0f32, 60.0]
-			};
-			// Draw crosshair
-			let vertices_horiz = square_mesh((20, 2), screen_dims, ui_colors.crosshair_color);
-			let vertices_vert = square_mesh((2, 20), screen_dims, ui_colors.crosshair_color);
-			let mut vertices = vertices_horiz;
-			vertices.extend_from_slice(&vertices_vert);
-			let vbuff = VertexBuffer::new(&self.display, &vertices).unwrap();
-			target.draw(&vbuff,
-				&glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
-				&self.program, &uniforms, &params).unwrap();
+			render_crosshair(self.config.crosshair, self.config.crosshair_inverted,
+				ui_colors, &self.display, &self.program, &mut target);
 		}
 
 		target.finish().unwrap();
 	}
 
-	fn recv_vbuffs(&mut self) {
+	fn recv_vbuffs(&mut self, frustum :&Frustum) {
+		let camera_chunk = btchn(self.camera.pos.map(|v| v.floor() as isize));
+		if self.meshgen_camera_chunk != Some(camera_chunk) {
+			self.meshgen_camera_chunk = Some(camera_chunk);
+			let _ = self.meshgen_s.send(MeshgenMsg::CameraChunk(camera_chunk));
+		}
+		while let Ok(p) = self.remesh_r.try_recv() {
+			if let Some(chunk) = self.map.get_chunk(p) {
+				let _ = self.meshgen_s.send(MeshgenMsg::Chunk(p, chunk.clone()));
+			}
+		}
 		while let Ok((p, m)) = self.meshres_r.try_recv() {
+			if m.intransparent.is_empty() && m.transparent.is_empty() {
+				// Nothing to draw, e.g. because the chunk is occluded
+				self.vbuffs.remove(&p);
+				self.mesh_uploads.remove(p);
+				continue;
+			}
+			self.mesh_uploads.push(p, m);
+		}
+		// Uploading many meshes at once makes the frame stutter,
+		// so the visible and close chunks come first and the rest waits.
+		let camera_pos = self.camera.pos;
+		let max = self.config.max_mesh_uploads_per_frame;
+		let to_upload = self.mesh_uploads.take_frame(max, |p| {
+			chunk_upload_priority(p, camera_pos, frustum)
+		});
+		for (p, m) in to_upload {
 			let vbuff = VertexBuffer::new(&self.display, &m.intransparent).unwrap();
 			let vbuff_t = if m.transparent.len() > 0 {
 				Some(VertexBuffer::new(&self.display, &m.transparent).unwrap())
@@ -732,21 +1060,62 @@ impl<C :NetworkClientConn> Game<C> {
 			self.grabbing_cursor = grabbing_cursor;
 		}
 	}
+	/// Switches the window to the current window mode
+	///
+	/// Fullscreen modes use the monitor the window is currently on.
+	/// The projection and the UI adapt through the resize event.
+	fn apply_window_mode(&mut self) {
+		let gl_window = self.display.gl_window();
+		let window = gl_window.window();
+		let (kind, size) = window_setup(self.window_mode);
+		window.set_fullscreen(fullscreen_for(kind, window.current_monitor()));
+		if let Some((w, h)) = size {
+			window.set_inner_size(PhysicalSize::new(w, h));
+		}
+	}
 
+	fn open_modal(&mut self, modal :Modal) {
+		self.ui_stack.push(modal);
+		self.check_grab_change();
+	}
+	/// Closes the given modal and applies any changes done in it
+	fn close_modal(&mut self, modal :Modal) {
+		self.ui_stack.remove(modal);
+		match modal {
+			Modal::PauseMenu => (),
+			Modal::ServerList => {
+				self.server_list_menu = None;
+			},
+			Modal::Chat => {
+				self.chat_window = None;
+			},
+			Modal::Inventory => {
+				if let Some(mut m) = self.inventory_menu.take() {
+					m.close();
+					maybe_inventory_change!(m, self);
+				}
+			},
+			Modal::Chest => {
+				if let Some(mut m) = self.chest_menu.take() {
+					maybe_chest_inventory_change!(m, self);
+				}
+			},
+		}
+		self.check_grab_change();
+	}
 	fn handle_chat_win_ev(&mut self, ev :ChatWindowEvent) {
 		match ev {
 			ChatWindowEvent::CloseChatWindow => {
-				self.chat_window = None;
-				self.check_grab_change();
+				self.close_modal(Modal::Chat);
 			},
 			ChatWindowEvent::SendChat => {
 				{
 					let text = &self.chat_window.as_ref().unwrap().text();
+					self.chat_history.push(text);
 					let msg = ClientToServerMsg::Chat(text.to_string());
 					let _ = self.srv_conn.send(msg);
 				}
-				self.chat_window = None;
-				self.check_grab_change();
+				self.close_modal(Modal::Chat);
 			},
 			ChatWindowEvent::None => (),
 		}
@@ -759,44 +1128,79 @@ impl<C :NetworkClientConn> Game<C> {
 			_ => (),
 		}
 
-		if let Some(ev) = (&mut self.chat_window).as_mut().map(|w| w.handle_kinput(input)) {
-			self.handle_chat_win_ev(ev);
+		// Keyboard input is only routed to the topmost modal
+		if self.ui_stack.top() == Some(Modal::Chat) {
+			if let Some(w) = self.chat_window.as_mut() {
+				let ev = w.handle_kinput(input, self.modifiers, &self.chat_history);
+				self.handle_chat_win_ev(ev);
+			}
 			return false;
 		}
-		if input.virtual_keycode == Some(VirtualKeyCode::Escape) {
-			if let Some(m) = self.inventory_menu.take() {
-				maybe_inventory_change!(m, self);
-
-				self.check_grab_change();
-				return false;
-			} else if let Some(m) = self.chest_menu.take() {
-				maybe_chest_inventory_change!(m, self);
-
-				self.check_grab_change();
-				return false;
+		if self.ui_stack.top() == Some(Modal::ServerList) &&
+				input.virtual_keycode != Some(VirtualKeyCode::Escape) {
+			if let Some(m) = self.server_list_menu.as_mut() {
+				let ev = m.handle_kinput(input, &mut self.server_list,
+					self.current_server.as_ref());
+				self.handle_server_list_ev(ev);
 			}
+			return false;
 		}
 
 		match input.virtual_keycode {
 			Some(VirtualKeyCode::Escape) => {
 				if input.state == ElementState::Pressed {
-					self.menu_enabled = !self.menu_enabled;
-					self.check_grab_change();
+					if let Some(closed) = self.ui_stack.escape() {
+						self.close_modal(closed);
+					} else {
+						self.check_grab_change();
+					}
 				}
 			},
-			Some(VirtualKeyCode::I) => {
+			Some(key) if hud_slot_for_key(key).is_some() => {
+				let slot = hud_slot_for_key(key).unwrap();
+				if input.state == ElementState::Pressed && !self.in_background() &&
+						slot < self.config.hud_slot_count {
+					self.sel_inventory.select(slot);
+					let msg = ClientToServerMsg::InventoryAction(InventoryAction::Select(slot));
+					let _ = self.srv_conn.send(msg);
+				}
+			},
+			Some(VirtualKeyCode::F11) => {
 				if input.state == ElementState::Pressed {
-					if let Some(m) = self.inventory_menu.take() {
-						maybe_inventory_change!(m, self);
+					self.window_mode = toggled_window_mode(self.window_mode,
+						self.config.window_mode);
+					self.apply_window_mode();
+				}
+			},
+			Some(VirtualKeyCode::F9) => {
+				if input.state == ElementState::Pressed {
+					self.render_debug = self.render_debug.next();
+					self.chat_msgs.push_back(format!("Render debug mode: {:?}", self.render_debug));
+				}
+			},
+			Some(VirtualKeyCode::L) => {
+				if input.state == ElementState::Pressed &&
+						self.ui_stack.top() == Some(Modal::PauseMenu) {
+					self.server_list_menu = Some(ServerListMenu::new());
+					self.open_modal(Modal::ServerList);
+				}
+			},
+			Some(VirtualKeyCode::I) => {
+				let top = self.ui_stack.top();
+				if input.state == ElementState::Pressed &&
+						(top.is_none() || top == Some(Modal::Inventory)) {
+					if top == Some(Modal::Inventory) {
+						self.close_modal(Modal::Inventory);
 					} else {
 						// TODO unwrap below is a bit bad because players might
 						// want to open inventory before the server has sent the params
 						self.inventory_menu = Some(InventoryMenu::new(
 							self.params.as_ref().unwrap().clone(),
 							self.sel_inventory.clone(),
-							self.craft_inv.clone()));
+							self.craft_inv.clone(),
+							CraftingGrid::PERSONAL));
+						self.open_modal(Modal::Inventory);
 					}
-					self.check_grab_change();
 				}
 			},
 			_ => (),
@@ -804,6 +1208,49 @@ impl<C :NetworkClientConn> Game<C> {
 		self.camera.handle_kinput(input);
 		return false;
 	}
+	/// Puts the block at the position into the selected hud slot
+	fn pick_block(&mut self, pos :Vector3<isize>) {
+		let blk = if let Some(blk) = self.map.get_blk(pos) {
+			blk
+		} else {
+			return;
+		};
+		let slot_count = self.config.hud_slot_count;
+		// Predict the outcome, the server sends the inventory if it differs
+		self.sel_inventory.pick_block(blk, slot_count);
+		let msg = ClientToServerMsg::InventoryAction(InventoryAction::PickBlock(blk, slot_count));
+		let _ = self.srv_conn.send(msg);
+	}
+	/// Reacts to the block edits the server sent, as (position, previous, new)
+	///
+	/// Spawns the break particles for the blocks that became air,
+	/// also when they had already been removed by our own prediction.
+	/// Pending predictions the server didn't confirm are dropped.
+	fn handle_block_changes(&mut self, changes :&[(Vector3<isize>, MapBlock, MapBlock)]) {
+		let air_bl = if let Some(params) = &self.params {
+			params.block_roles.air
+		} else {
+			return
+		};
+		for &(pos, prev, b) in changes {
+			let predicted = self.pending_breaks.remove(&pos);
+			if b != air_bl {
+				continue;
+			}
+			let broken = if prev != air_bl {
+				prev
+			} else if let Some(broken) = predicted {
+				broken
+			} else {
+				continue;
+			};
+			if let Some(texture_ids) = self.texture_id_cache.as_ref()
+					.and_then(|c| c.get_bl_tex_ids(&broken)) {
+				self.particles.spawn_break_burst(pos,
+					texture_ids, &mut rand::thread_rng());
+			}
+		}
+	}
 	fn handle_mouse_buttons(&mut self, float_delta :f32) {
 		let params = if let Some(params) = &self.params {
 			params
@@ -816,59 +1263,89 @@ impl<C :NetworkClientConn> Game<C> {
 		const RIGHT_BUTTON_COOLDOWN :f32 = 0.2;
 		self.camera.dig_cooldown.as_mut().map(|(_p, c)| *c -= float_delta);
 		self.camera.mouse_right_cooldown -= float_delta;
+		if self.camera.mouse_right_down
+				&& self.camera.mouse_right_cooldown <= 0.0 {
+			let edible = self.sel_inventory.get_selected()
+				.and_then(|sel| params.get_block_params(sel))
+				.map(|p| p.food.is_some() && !p.placeable)
+				.unwrap_or(false);
+			if edible {
+				// The server takes the item and sends the updated inventory
+				let _ = self.srv_conn.send(ClientToServerMsg::Eat);
+				self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
+				return;
+			}
+		}
 		if let Some((selected_pos, before_selected)) = self.selected_pos {
 			if self.camera.mouse_left_down {
-				match &mut self.camera.dig_cooldown {
-					Some((pos, dc)) => if *pos != selected_pos {
-						self.camera.dig_cooldown = None;
-					} else if *dc <= 0.0 {
-						let mut blk = self.map.get_blk_mut(*pos).unwrap();
-						let drops = params.get_block_params(blk.get()).unwrap().drops;
-						self.sel_inventory.put(drops);
-						let air_bl = params.block_roles.air;
-						blk.set(air_bl);
-						let msg = ClientToServerMsg::Dig(selected_pos);
-						let _ = self.srv_conn.send(msg);
-					},
-					v @ None => {
-						let blk = self.map.get_blk(selected_pos).unwrap();
-						let dig_group_id = params.get_block_params(blk).unwrap().dig_group;
-						let sel = self.sel_inventory.get_selected();
-						// Set block specific cooldown
-						let tool_cooldown = if let Some(sel) = sel {
-							let tool_groups = &params.get_block_params(sel).unwrap().tool_groups;
-							if let Some(tg) = tool_groups.iter().find(|g| g.group == dig_group_id) {
-								Some((0.01 + 1.0/tg.speed) as f32)
-							} else {
-								None
-							}
+				let instant = self.config.creative_instabreak &&
+					self.camera.game_mode.digs_instantly();
+				let map = &self.map;
+				let sel = self.sel_inventory.get_selected();
+				let breaks = advance_dig(&mut self.camera.dig_cooldown,
+						selected_pos, instant, || {
+					let blk = map.get_blk(selected_pos).unwrap();
+					let dig_group_id = params.get_block_params(blk).unwrap().dig_group;
+					// Set block specific cooldown
+					let tool_cooldown = if let Some(sel) = sel {
+						let tool_groups = &params.get_block_params(sel).unwrap().tool_groups;
+						if let Some(tg) = tool_groups.iter().find(|g| g.group == dig_group_id) {
+							Some((0.01 + 1.0/tg.speed) as f32)
 						} else {
 							None
-						};
-						let hand_tool_groups = &params.hand_tool_groups;
-						let cooldown = tool_cooldown.or_else(|| {
-							if let Some(tg) = hand_tool_groups.iter().find(|g| g.group == dig_group_id) {
-								Some((0.01 + 1.0/tg.speed) as f32)
-							} else {
-								None
-							}
-						});
-						if let Some(cooldown) = cooldown {
-							*v = Some((selected_pos, cooldown));
 						}
-					},
+					} else {
+						None
+					};
+					let hand_tool_groups = &params.hand_tool_groups;
+					tool_cooldown.or_else(|| {
+						if let Some(tg) = hand_tool_groups.iter().find(|g| g.group == dig_group_id) {
+							Some((0.01 + 1.0/tg.speed) as f32)
+						} else {
+							None
+						}
+					})
+				});
+				if breaks {
+					let broken = self.map.get_blk(selected_pos).unwrap();
+					if let Some(drops) = drops_for(broken, params) {
+						self.sel_inventory.put(drops);
+					}
+					// This also removes the metadata, like the facing
+					let air_bl = params.block_roles.air;
+					self.map.set_blocks(&[(selected_pos, air_bl)]);
+					let msg = ClientToServerMsg::Dig(selected_pos);
+					let _ = self.srv_conn.send(msg);
+					// The particles are spawned once the server confirms it
+					self.pending_breaks.insert(selected_pos, broken);
 				}
 			}
 			if self.camera.mouse_right_down
 					&& self.camera.mouse_right_cooldown <= 0.0 {
 				let blk_sel = self.map.get_blk(selected_pos).unwrap();
-				let has_inv = params.get_block_params(blk_sel).unwrap().inventory;
+				let blk_sel_params = params.get_block_params(blk_sel).unwrap();
+				if let Some(grid) = blk_sel_params.crafting_grid {
+					// open crafting table
+					if self.table_craft_inv.stacks().len() != grid.slot_count() {
+						self.table_craft_inv = SelectableInventory::crafting_inv(grid);
+					}
+					self.inventory_menu = Some(InventoryMenu::new(
+						params.clone(),
+						self.sel_inventory.clone(),
+						self.table_craft_inv.clone(),
+						grid));
+					self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
+					self.camera.mouse_right_down = false;
+					self.open_modal(Modal::Inventory);
+					return;
+				}
+				let has_inv = blk_sel_params.inventory;
 				if let Some(stack_num) = has_inv {
 					// open chest inventory
 					let chest_inv = self.map.get_blk_meta(selected_pos).unwrap()
-						.map(|v| {
-							let MetadataEntry::Inventory(inv) = v.clone();
-							inv
+						.and_then(|v| match v {
+							MetadataEntry::Inventory(inv) => Some(inv.clone()),
+							_ => None,
 						})
 						.unwrap_or_else(|| SelectableInventory::empty_with_size(stack_num as usize));
 					self.chest_menu = Some(ChestMenu::new(
@@ -878,22 +1355,28 @@ impl<C :NetworkClientConn> Game<C> {
 						selected_pos));
 					self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
 					self.camera.mouse_right_down = false;
-					self.check_grab_change();
+					self.open_modal(Modal::Chest);
 					return;
 				}
 
 				let sel = self.sel_inventory.get_selected();
 				if let Some(sel) = sel {
-					let placeable = params.get_block_params(sel).unwrap().placeable;
-					if placeable {
+					let sel_params = params.get_block_params(sel).unwrap();
+					if sel_params.placeable {
 						let taken = self.sel_inventory.take_selected();
 						assert_eq!(taken, Some(sel));
-						let mut blk = self.map.get_blk_mut(before_selected).unwrap();
-						blk.set(sel);
-						let msg = ClientToServerMsg::SetInventory(self.sel_inventory.clone());
-						let _ = self.srv_conn.send(msg);
+						self.map.set_blocks(&[(before_selected, sel)]);
+						// The server takes the block from the selection on its own
 						let msg = ClientToServerMsg::SetBlock(before_selected, sel);
 						let _ = self.srv_conn.send(msg);
+						if sel_params.directional {
+							let facing = Dir6::placement_facing(self.camera.direction().coords);
+							let meta = MetadataEntry::Facing(facing);
+							let mut hdl = self.map.get_blk_meta_mut(before_selected).unwrap();
+							hdl.set(meta.clone());
+							let msg = ClientToServerMsg::SetMetadata(before_selected, meta);
+							let _ = self.srv_conn.send(msg);
+						}
 						self.camera.mouse_right_cooldown = RIGHT_BUTTON_COOLDOWN;
 					}
 				}
@@ -910,19 +1393,22 @@ impl<C :NetworkClientConn> Game<C> {
 						self.has_focus = focus;
 						self.check_grab_change();
 					},
+					WindowEvent::ModifiersChanged(modifiers) => {
+						self.modifiers = modifiers;
+					},
 
 					WindowEvent::CloseRequested => close = true,
 
-					WindowEvent::Resized(glutin::dpi::PhysicalSize {width, height}) => {
-						self.swidth = width.into();
-						self.sheight = height.into();
-						self.camera.aspect_ratio = (width / height) as f32;
+					WindowEvent::Resized(size) => {
+						self.swidth = size.width.into();
+						self.sheight = size.height.into();
+						self.camera.aspect_ratio = aspect_ratio(size);
 					},
 					WindowEvent::KeyboardInput { input, .. } => {
 						close |= self.handle_kinput(&input);
 					},
 					WindowEvent::ReceivedCharacter(ch) => {
-						let ev = if let Some(ref mut w) = self.chat_window {
+						let ev = if let (Some(Modal::Chat), Some(w)) = (self.ui_stack.top(), &mut self.chat_window) {
 							w.handle_character(ch)
 						} else {
 							if ch == 't' || ch == '/' {
@@ -932,7 +1418,7 @@ impl<C :NetworkClientConn> Game<C> {
 									ChatWindow::new()
 								};
 								self.chat_window = Some(chwin);
-								self.check_grab_change();
+								self.open_modal(Modal::Chat);
 							}
 							ChatWindowEvent::None
 						};
@@ -957,10 +1443,10 @@ impl<C :NetworkClientConn> Game<C> {
 							self.last_pos = Some(position);
 						}
 						if self.has_focus {
-							if let Some(m) = &mut self.inventory_menu {
-								m.handle_mouse_moved(position);
-							} else if let Some(m) = &mut self.chest_menu {
-								m.handle_mouse_moved(position);
+							match (self.ui_stack.top(), &mut self.inventory_menu, &mut self.chest_menu) {
+								(Some(Modal::Inventory), Some(m), _) => m.handle_mouse_moved(position),
+								(Some(Modal::Chest), _, Some(m)) => m.handle_mouse_moved(position),
+								_ => (),
 							}
 						}
 					},
@@ -972,35 +1458,48 @@ impl<C :NetworkClientConn> Game<C> {
 							} else if button == MouseButton::Right {
 								self.camera.handle_mouse_right(pressed);
 							}
-							if let Some((_selected_pos, before_selected))
+							if let Some((selected_pos, before_selected))
 									= self.selected_pos {
 								if pressed && button == MouseButton::Middle {
-									let msg = ClientToServerMsg::PlaceTree(before_selected);
-									let _ = self.srv_conn.send(msg);
+									if self.modifiers.ctrl() {
+										if self.camera.game_mode == GameMode::Creative {
+											let msg = ClientToServerMsg::PlaceTree(before_selected);
+											let _ = self.srv_conn.send(msg);
+										}
+									} else if self.camera.game_mode.can_pick_blocks() {
+										self.pick_block(selected_pos);
+									}
 								}
 							}
 						}
 						if self.has_focus {
-							if let Some(m) = &mut self.inventory_menu {
-								m.handle_mouse_input(state, button);
-							} else if let Some(m) = &mut self.chest_menu {
-								m.handle_mouse_input(state, button);
+							match (self.ui_stack.top(), &mut self.inventory_menu, &mut self.chest_menu) {
+								(Some(Modal::Inventory), Some(m), _) => m.handle_mouse_input(state, button),
+								(Some(Modal::Chest), _, Some(m)) => {
+									m.handle_mouse_input(state, button, self.camera.down_pressed)
+								},
+								_ => (),
 							}
 						}
 					},
 					WindowEvent::MouseWheel { delta, .. } => {
-						if !self.in_background() {
-							let lines_diff = match delta {
-								MouseScrollDelta::LineDelta(_x, y) => y,
-								MouseScrollDelta::PixelDelta(p) => p.y as f32,
-							};
+						let lines_diff = match delta {
+							MouseScrollDelta::LineDelta(_x, y) => y,
+							MouseScrollDelta::PixelDelta(p) => p.y as f32,
+						};
+						if let (Some(Modal::Inventory), Some(m)) = (self.ui_stack.top(), &mut self.inventory_menu) {
+							m.handle_scroll(lines_diff);
+						} else if !self.in_background() {
+							let slot_count = self.config.hud_slot_count;
 							if lines_diff < 0.0 {
-								self.sel_inventory.rotate(true);
+								self.sel_inventory.rotate(true, slot_count);
 							} else if lines_diff > 0.0 {
-								self.sel_inventory.rotate(false);
+								self.sel_inventory.rotate(false, slot_count);
+							}
+							if let Some(idx) = self.sel_inventory.selection() {
+								let msg = ClientToServerMsg::InventoryAction(InventoryAction::Select(idx));
+								let _ = self.srv_conn.send(msg);
 							}
-							let msg = ClientToServerMsg::SetInventory(self.sel_inventory.clone());
-							let _ = self.srv_conn.send(msg);
 						}
 					},
 
@@ -1016,19 +1515,77 @@ impl<C :NetworkClientConn> Game<C> {
 	}
 }
 
-fn selection_mesh(pos :Vector3<isize>, ui_colors :&UiColors) -> Vec<Vertex> {
-	const DELTA :f32 = 0.05;
-	const DELTAH :f32 = DELTA / 2.0;
-	let mut vertices = Vec::new();
+/// The wireframe around the targeted block
+fn selection_mesh(pos :Vector3<isize>, (min, max) :([f32; 3], [f32; 3]),
+		ui_colors :&UiColors) -> Vec<Vertex> {
+	// Enlarge the box a little so that the lines don't z-fight with the faces
+	const DELTAH :f32 = 0.005;
+	let pos = pos.map(|v| v as f32);
+	let min = pos + Vector3::from(min).add_scalar(-DELTAH);
+	let max = pos + Vector3::from(max).add_scalar(DELTAH);
+	wireframe_mesh(min, max, ui_colors.block_selection_color.0)
+}
 
-	let texture_ids = BlockTextureIds::uniform(ui_colors.block_selection_color);
+/// The outline of the spawn protection area
+fn spawn_protection_mesh((min, max) :(Vector3<isize>, Vector3<isize>),
+		ui_colors :&UiColors) -> Vec<Vertex> {
+	wireframe_mesh(min.map(|v| v as f32), max.map(|v| v as f32),
+		ui_colors.spawn_protection_color.0)
+}
 
-	push_block(&mut vertices,
-		[pos.x as f32 - DELTAH, pos.y as f32 - DELTAH, pos.z as f32 - DELTAH],
-		texture_ids, 1.0 + DELTA, |_| false);
+/// The 12 edges of the box as a line list
+fn wireframe_mesh(min :Vector3<f32>, max :Vector3<f32>, tex_ind :u16) -> Vec<Vertex> {
+	let mut vertices = Vec::with_capacity(24);
+	let mut push = |p :Vector3<f32>| {
+		vertices.push(Vertex {
+			tex_ind,
+			tex_pos : [0.0, 0.0],
+			position : [p.x, p.y, p.z],
+			normal : [0.0, 0.0, 1.0],
+		});
+	};
+	// For each axis, the four edges along it
+	for axis in 0 .. 3 {
+		let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+		for &(at_max_a, at_max_b) in &[(false, false), (true, false), (false, true), (true, true)] {
+			let mut start = min;
+			if at_max_a {
+				start[a] = max[a];
+			}
+			if at_max_b {
+				start[b] = max[b];
+			}
+			let mut end = start;
+			end[axis] = max[axis];
+			push(start);
+			push(end);
+		}
+	}
 	vertices
 }
 
+/// Whether the given axis aligned box intersects the view frustum
+///
+/// Like for chunks, the box is approximated by its bounding sphere.
+fn is_visible(aabb_min :Vector3<f32>, aabb_max :Vector3<f32>, frustum :&Frustum) -> bool {
+	let center = (aabb_min + aabb_max) / 2.0;
+	let r = (aabb_max - aabb_min).norm() / 2.0;
+	frustum.sphere_intersecting(&center.x, &center.y, &center.z, &r)
+}
+
+/// Size of the cube a dropped item is rendered as
+const DROPPED_ITEM_SIZE :f32 = 0.3;
+
+fn dropped_item_aabb(pos :Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+	let half = Vector3::new(1.0, 1.0, 1.0) * (DROPPED_ITEM_SIZE / 2.0);
+	(pos - half, pos + half)
+}
+
+/// The box covered by the mesh from player_mesh
+fn player_aabb(pos :Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+	(pos + Vector3::new(0.0, 0.0, -2.0), pos + Vector3::new(0.8, 0.8, 0.4))
+}
+
 fn player_mesh(pos :Vector3<f32>, ui_colors :&UiColors) -> Vec<Vertex> {
 	let mut vertices = Vec::new();
 
@@ -1047,6 +1604,22 @@ fn player_mesh(pos :Vector3<f32>, ui_colors :&UiColors) -> Vec<Vertex> {
 	vertices
 }
 
+fn entities_mesh(entities :&[(EntityId, Vector3<f32>, EntityKind)],
+		texture_id_cache :&TextureIdCache) -> Vec<Vertex> {
+	let mut vertices = Vec::new();
+	for (_id, pos, kind) in entities.iter() {
+		let EntityKind::DroppedItem(stack) = kind;
+		let texture_ids = stack.content()
+			.and_then(|(item, _count)| texture_id_cache.get_bl_tex_ids(&item));
+		if let Some(texture_ids) = texture_ids {
+			let pos = pos.map(|v| v - DROPPED_ITEM_SIZE / 2.0);
+			push_block(&mut vertices, [pos.x, pos.y, pos.z],
+				texture_ids, DROPPED_ITEM_SIZE, |_| false);
+		}
+	}
+	vertices
+}
+
 fn hand_mesh(pos :Vector3<f32>, blk :MapBlock,
 		texture_id_cache :&TextureIdCache) -> Vec<Vertex> {
 	let mut vertices = Vec::new();
@@ -1062,6 +1635,633 @@ fn hand_mesh(pos :Vector3<f32>, blk :MapBlock,
 	vertices
 }
 
+/// Chunk meshes waiting to be uploaded to the GPU
+struct MeshUploadQueue {
+	pending :HashMap<Vector3<isize>, ChunkMesh>,
+}
+
+impl MeshUploadQueue {
+	fn new() -> Self {
+		Self {
+			pending : HashMap::new(),
+		}
+	}
+	/// Adds the mesh, replacing any pending older mesh of the same chunk
+	fn push(&mut self, pos :Vector3<isize>, mesh :ChunkMesh) {
+		self.pending.insert(pos, mesh);
+	}
+	fn remove(&mut self, pos :Vector3<isize>) {
+		self.pending.remove(&pos);
+	}
+	#[cfg(test)]
+	fn len(&self) -> usize {
+		self.pending.len()
+	}
+	/// Takes up to `max` meshes, the ones with the lowest priority value first
+	fn take_frame<P :PartialOrd>(&mut self, max :usize,
+			priority :impl Fn(Vector3<isize>) -> P) -> Vec<(Vector3<isize>, ChunkMesh)> {
+		let mut positions = self.pending.keys()
+			.map(|p| (priority(*p), *p))
+			.collect::<Vec<_>>();
+		positions.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+		positions.into_iter()
+			.take(max)
+			.map(|(_, p)| (p, self.pending.remove(&p).unwrap()))
+			.collect()
+	}
+}
+
+/// The upload priority of the chunk at the given position, lower is more important
+///
+/// Chunks inside the view frustum come first, then the closer ones.
+fn chunk_upload_priority(pos :Vector3<isize>, camera_pos :Vector3<f32>,
+		frustum :&Frustum) -> (bool, f32) {
+	let center = pos.map(|v| (v + CHUNKSIZE / 2) as f32);
+	let r = CHUNKSIZE as f32 * 3.0_f32.sqrt();
+	let visible = frustum.sphere_intersecting(&center.x, &center.y, &center.z, &r);
+	(!visible, (center - camera_pos).norm())
+}
+
+/// Fog parameters while the camera is inside water
+const UNDERWATER_FOG :[f32; 2] = [10.0, 1.0];
+/// Color of the fog inside water, same as in the fragment shader
+const UNDERWATER_COLOR :(f32, f32, f32) = (0.1, 0.25, 0.5);
+
+/// Whether the camera at the given position is inside a water block
+fn camera_underwater<B :MapBackend>(map :&Map<B>, pos :Vector3<f32>,
+		water :MapBlock) -> bool {
+	let blk_pos = pos.map(|v| v.floor() as isize);
+	map.get_blk(blk_pos) == Some(water)
+}
+
+/// How far above the eyes of players their nameplate is shown
+const NAMEPLATE_HEIGHT :f32 = 0.8;
+/// The distance from which on nameplates are hidden
+const NAMEPLATE_RANGE :f32 = 48.0;
+
+/// Where and how to draw the nameplate of a player
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Nameplate {
+	world_pos :Vector3<f32>,
+	screen_pos :(f32, f32),
+	alpha :f32,
+}
+
+/// Projects the position into pixel coordinates of the screen
+///
+/// Returns None for positions behind the camera or outside of the screen.
+fn world_to_screen(pmatrix :&Matrix4<f32>, vmatrix :&Matrix4<f32>,
+		pos :Vector3<f32>, screen_dims :(u32, u32)) -> Option<(f32, f32)> {
+	let clip = pmatrix * vmatrix * Point3::from(pos).to_homogeneous();
+	if clip.w <= 0.0 {
+		return None;
+	}
+	let ndc = clip.xyz() / clip.w;
+	if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 || ndc.z.abs() > 1.0 {
+		return None;
+	}
+	let x = (ndc.x + 1.0) / 2.0 * screen_dims.0 as f32;
+	let y = (1.0 - ndc.y) / 2.0 * screen_dims.1 as f32;
+	Some((x, y))
+}
+
+/// The nameplate of the player at the given (eye) position
+///
+/// Nameplates fade out with the distance to the camera.
+fn nameplate(pmatrix :&Matrix4<f32>, vmatrix :&Matrix4<f32>,
+		camera_pos :Vector3<f32>, player_pos :Vector3<f32>,
+		screen_dims :(u32, u32)) -> Option<Nameplate> {
+	let world_pos = player_pos + Vector3::new(0.0, 0.0, NAMEPLATE_HEIGHT);
+	let distance = (world_pos - camera_pos).norm();
+	if distance >= NAMEPLATE_RANGE {
+		return None;
+	}
+	let screen_pos = world_to_screen(pmatrix, vmatrix, world_pos, screen_dims)?;
+	// Fully opaque up to half the range
+	let alpha = (2.0 * (1.0 - distance / NAMEPLATE_RANGE)).min(1.0);
+	Some(Nameplate {
+		world_pos,
+		screen_pos,
+		alpha,
+	})
+}
+
+/// Whether solid blocks are between the two positions
+fn line_occluded<B :MapBackend>(map :&Map<B>, params :&GameParamsHdl,
+		from :Vector3<f32>, to :Vector3<f32>) -> bool {
+	const STEP :f32 = 0.25;
+	let dist = (to - from).norm();
+	let steps = (dist / STEP) as usize;
+	(1 .. steps).any(|i| {
+		let p = from + (to - from) * (i as f32 * STEP / dist);
+		map.get_blk(p.map(|v| v.floor() as isize))
+			.map(|b| is_solid(b, params))
+			.unwrap_or(false)
+	})
+}
+
+/// A camera at the position looking along the x axis, and its frustum
+#[cfg(test)]
+fn test_camera(pos :Vector3<f32>) -> (Camera, Frustum) {
+	use mimas_server::config::KeyboardLayout;
+
+	let mut camera = Camera::new(MovementKeys::for_layout(KeyboardLayout::Qwerty));
+	camera.pos = pos;
+	camera.pitch = 0.0;
+	camera.yaw = 0.0;
+	camera.aspect_ratio = 4.0 / 3.0;
+	let frustum = Frustum::from_modelview_and_projection_2d(
+		&camera.get_matrix(),
+		&camera.get_perspective(),
+	);
+	(camera, frustum)
+}
+
+#[cfg(test)]
+#[test]
+fn test_mesh_upload_queue() {
+	let (camera, frustum) = test_camera(Vector3::new(8.0, 8.0, 8.0));
+	let priority = |p| chunk_upload_priority(p, camera.pos, &frustum);
+
+	let mut queue = MeshUploadQueue::new();
+	// Chunks in front of the camera, then chunks behind it
+	let ahead = (1 ..= 5).map(|i| Vector3::new(i * CHUNKSIZE, 0, 0)).collect::<Vec<_>>();
+	let behind = (1 ..= 5).map(|i| Vector3::new(-i * CHUNKSIZE * 2, 0, 0)).collect::<Vec<_>>();
+	for p in behind.iter().chain(ahead.iter()) {
+		queue.push(*p, ChunkMesh::empty());
+	}
+	// Newer meshes replace pending ones
+	queue.push(ahead[0], ChunkMesh::empty());
+	assert_eq!(queue.len(), 10);
+
+	let mut frames = Vec::new();
+	while queue.len() > 0 {
+		let frame = queue.take_frame(3, priority)
+			.into_iter()
+			.map(|(p, _)| p)
+			.collect::<Vec<_>>();
+		assert!(frame.len() <= 3);
+		frames.push(frame);
+	}
+	// The cap is used up in every frame but the last one
+	assert_eq!(frames.iter().map(|f| f.len()).collect::<Vec<_>>(), vec![3, 3, 3, 1]);
+	// Visible chunks come first, closest first, then the ones behind
+	let order = frames.concat();
+	let expected = ahead.iter().chain(behind.iter()).cloned().collect::<Vec<_>>();
+	assert_eq!(order, expected);
+
+	assert!(queue.take_frame(3, priority).is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_entity_culling() {
+	let (camera, frustum) = test_camera(Vector3::new(0.0, 0.0, 10.0));
+	let visible = |pos :Vector3<f32>| {
+		let (aabb_min, aabb_max) = dropped_item_aabb(camera.pos + pos);
+		is_visible(aabb_min, aabb_max, &frustum)
+	};
+
+	// Straight ahead and slightly off center
+	assert!(visible(Vector3::new(10.0, 0.0, 0.0)));
+	assert!(visible(Vector3::new(10.0, 2.0, -1.0)));
+	// Behind the camera
+	assert!(!visible(Vector3::new(-10.0, 0.0, 0.0)));
+	// Far off to the side and above
+	assert!(!visible(Vector3::new(10.0, 100.0, 0.0)));
+	assert!(!visible(Vector3::new(10.0, 0.0, 100.0)));
+	// Beyond the far plane
+	assert!(!visible(Vector3::new(10_000.0, 0.0, 0.0)));
+
+	// Players are culled by their whole body
+	let (aabb_min, aabb_max) = player_aabb(camera.pos + Vector3::new(10.0, 0.0, 0.0));
+	assert!(is_visible(aabb_min, aabb_max, &frustum));
+	let (aabb_min, aabb_max) = player_aabb(camera.pos + Vector3::new(-10.0, 0.0, 0.0));
+	assert!(!is_visible(aabb_min, aabb_max, &frustum));
+}
+
+#[cfg(test)]
+#[test]
+fn test_nameplate_projection() {
+	let (camera, _frustum) = test_camera(Vector3::new(0.0, 0.0, 10.0));
+	let dims = (800, 600);
+	let pmatrix = Matrix4::from(camera.get_perspective());
+	let vmatrix = Matrix4::from(camera.get_matrix());
+
+	// Straight ahead, the label is centered horizontally and above the center
+	let ahead = camera.pos + Vector3::new(10.0, 0.0, 0.0);
+	let label = nameplate(&pmatrix, &vmatrix, camera.pos, ahead, dims).unwrap();
+	assert!((label.screen_pos.0 - 400.0).abs() < 0.01, "{:?}", label);
+	assert!(label.screen_pos.1 < 300.0, "{:?}", label);
+	assert_eq!(label.world_pos, ahead + Vector3::new(0.0, 0.0, NAMEPLATE_HEIGHT));
+	assert_eq!(label.alpha, 1.0);
+
+	// Players to the left are shown on the left side
+	let left = camera.pos + Vector3::new(10.0, 5.0, 0.0);
+	let label = nameplate(&pmatrix, &vmatrix, camera.pos, left, dims).unwrap();
+	assert!(label.screen_pos.0 < 400.0, "{:?}", label);
+
+	// Labels fade out with distance
+	let far = camera.pos + Vector3::new(NAMEPLATE_RANGE * 0.75, 0.0, 0.0);
+	let label = nameplate(&pmatrix, &vmatrix, camera.pos, far, dims).unwrap();
+	assert!(label.alpha > 0.0 && label.alpha < 1.0, "{:?}", label);
+	let too_far = camera.pos + Vector3::new(NAMEPLATE_RANGE * 2.0, 0.0, 0.0);
+	assert_eq!(nameplate(&pmatrix, &vmatrix, camera.pos, too_far, dims), None);
+
+	// Players behind the camera are culled
+	let behind = camera.pos - Vector3::new(10.0, 0.0, 0.0);
+	assert_eq!(world_to_screen(&pmatrix, &vmatrix, behind, dims), None);
+	assert_eq!(nameplate(&pmatrix, &vmatrix, camera.pos, behind, dims), None);
+}
+
+/// Debug views of the world rendering, for diagnosing meshing bugs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RenderDebug {
+	Off,
+	/// Only draw the edges of the triangles
+	Wireframe,
+	/// Draw the back faces too
+	NoCulling,
+	/// Color the faces by their normals
+	Normals,
+}
+
+impl RenderDebug {
+	fn next(self) -> Self {
+		match self {
+			RenderDebug::Off => RenderDebug::Wireframe,
+			RenderDebug::Wireframe => RenderDebug::NoCulling,
+			RenderDebug::NoCulling => RenderDebug::Normals,
+			RenderDebug::Normals => RenderDebug::Off,
+		}
+	}
+	fn show_normals(self) -> bool {
+		self == RenderDebug::Normals
+	}
+}
+
+fn world_draw_params(render_debug :RenderDebug) -> glium::DrawParameters<'static> {
+	use glium::draw_parameters::{PolygonMode, BackfaceCullingMode};
+	let polygon_mode = if render_debug == RenderDebug::Wireframe {
+		PolygonMode::Line
+	} else {
+		PolygonMode::Fill
+	};
+	let backface_culling = if render_debug == RenderDebug::NoCulling {
+		BackfaceCullingMode::CullingDisabled
+	} else {
+		BackfaceCullingMode::CullCounterClockwise
+	};
+	glium::draw_parameters::DrawParameters {
+		depth : glium::Depth {
+			test : glium::draw_parameters::DepthTest::IfLess,
+			write : true,
+			.. Default::default()
+		},
+		backface_culling,
+		blend :glium::Blend::alpha_blending(),
+		polygon_mode,
+		.. Default::default()
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_world_draw_params() {
+	use glium::draw_parameters::{PolygonMode, BackfaceCullingMode};
+	let mut mode = RenderDebug::Off;
+	let mut seen = Vec::new();
+	loop {
+		let params = world_draw_params(mode);
+		let expected = match mode {
+			RenderDebug::Off | RenderDebug::Normals =>
+				(PolygonMode::Fill, BackfaceCullingMode::CullCounterClockwise),
+			RenderDebug::Wireframe =>
+				(PolygonMode::Line, BackfaceCullingMode::CullCounterClockwise),
+			RenderDebug::NoCulling =>
+				(PolygonMode::Fill, BackfaceCullingMode::CullingDisabled),
+		};
+		assert_eq!((params.polygon_mode, params.backface_culling), expected, "{:?}", mode);
+		assert_eq!(mode.show_normals(), mode == RenderDebug::Normals);
+		seen.push(mode);
+		mode = mode.next();
+		if mode == RenderDebug::Off {
+			break;
+		}
+	}
+	assert_eq!(seen.len(), 4);
+}
+
+/// The hud slot selected by the number key, with 0 for the tenth slot
+fn hud_slot_for_key(key :VirtualKeyCode) -> Option<usize> {
+	use glutin::event::VirtualKeyCode::*;
+	const DIGITS :[VirtualKeyCode; 10] = [
+		Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+	];
+	DIGITS.iter().position(|k| *k == key)
+}
+
+/// Which kind of fullscreen a window mode uses
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum FullscreenKind {
+	None,
+	Borderless,
+	Exclusive,
+}
+
+/// The fullscreen kind and the inner window size for the window mode
+fn window_setup(mode :WindowMode) -> (FullscreenKind, Option<(u32, u32)>) {
+	match mode {
+		WindowMode::Windowed { w, h } => (FullscreenKind::None, Some((w, h))),
+		WindowMode::Borderless => (FullscreenKind::Borderless, None),
+		WindowMode::Fullscreen => (FullscreenKind::Exclusive, None),
+	}
+}
+
+/// The fullscreen setting for the kind on the given monitor
+///
+/// Exclusive fullscreen uses the largest video mode with the highest
+/// refresh rate, falling back to borderless if the monitor reports none.
+fn fullscreen_for(kind :FullscreenKind, monitor :MonitorHandle) -> Option<Fullscreen> {
+	match kind {
+		FullscreenKind::None => None,
+		FullscreenKind::Borderless => Some(Fullscreen::Borderless(monitor)),
+		FullscreenKind::Exclusive => {
+			let video_mode = monitor.video_modes()
+				.max_by_key(|m| (m.size().width * m.size().height, m.refresh_rate()));
+			if let Some(video_mode) = video_mode {
+				Some(Fullscreen::Exclusive(video_mode))
+			} else {
+				Some(Fullscreen::Borderless(monitor))
+			}
+		},
+	}
+}
+
+/// The mode to switch to when toggling fullscreen
+///
+/// Toggling into fullscreen uses the configured fullscreen mode,
+/// or borderless if a window is configured. Toggling back uses the
+/// configured window size, or the default one.
+fn toggled_window_mode(current :WindowMode, configured :WindowMode) -> WindowMode {
+	match (current, configured) {
+		(WindowMode::Windowed { .. }, WindowMode::Windowed { .. }) => WindowMode::Borderless,
+		(WindowMode::Windowed { .. }, configured) => configured,
+		(_, WindowMode::Windowed { w, h }) => WindowMode::Windowed { w, h },
+		(_, _) => WindowMode::default(),
+	}
+}
+
+fn aspect_ratio(size :PhysicalSize<u32>) -> f32 {
+	size.width as f32 / size.height.max(1) as f32
+}
+
+#[cfg(test)]
+#[test]
+fn test_window_setup() {
+	let windowed = WindowMode::Windowed { w : 1280, h : 720 };
+	assert_eq!(window_setup(windowed), (FullscreenKind::None, Some((1280, 720))));
+	assert_eq!(window_setup(WindowMode::Borderless), (FullscreenKind::Borderless, None));
+	assert_eq!(window_setup(WindowMode::Fullscreen), (FullscreenKind::Exclusive, None));
+
+	// Toggling goes back and forth between the configured modes
+	let toggled = toggled_window_mode(windowed, windowed);
+	assert_eq!(toggled, WindowMode::Borderless);
+	assert_eq!(toggled_window_mode(toggled, windowed), windowed);
+	let fullscreen = WindowMode::Fullscreen;
+	let toggled = toggled_window_mode(fullscreen, fullscreen);
+	assert_eq!(toggled, WindowMode::default());
+	assert_eq!(toggled_window_mode(toggled, fullscreen), fullscreen);
+
+	assert_eq!(aspect_ratio(PhysicalSize::new(1024, 768)), 1024.0 / 768.0);
+	assert_eq!(aspect_ratio(PhysicalSize::new(1024, 0)), 1024.0);
+}
+
+/// Interpolates between the day and the night color
+fn day_night_color(day :[f32; 4], night :[f32; 4], night_fraction :f32) -> [f32; 4] {
+	let f = night_fraction.max(0.0).min(1.0);
+	let mut res = [0.0; 4];
+	for ((r, d), n) in res.iter_mut().zip(day.iter()).zip(night.iter()) {
+		*r = d + (n - d) * f;
+	}
+	res
+}
+
+/// The colors of the sky and of the fog at the time of the clock
+///
+/// Inside water, the sky has the color of the water fog, which
+/// the shader applies itself.
+fn sky_and_fog_colors(config :&Config, clock :&WorldClock, underwater :bool) -> ([f32; 4], [f32; 4]) {
+	let night_fraction = clock.night_fraction();
+	let sky = if underwater {
+		let light = 1.0 - clock.darkness();
+		let (r, g, b) = UNDERWATER_COLOR;
+		[r * light, g * light, b * light, 1.0]
+	} else {
+		day_night_color(config.sky_color, config.sky_color_night, night_fraction)
+	};
+	let fog = day_night_color(config.fog_color, config.fog_color_night, night_fraction);
+	(sky, fog)
+}
+
+#[cfg(test)]
+#[test]
+fn test_day_night_colors() {
+	let day = [1.0, 0.5, 0.0, 1.0];
+	let night = [0.0, 0.25, 0.5, 1.0];
+	assert_eq!(day_night_color(day, night, 0.0), day);
+	assert_eq!(day_night_color(day, night, 1.0), night);
+	assert_eq!(day_night_color(day, night, 0.5), [0.5, 0.375, 0.25, 1.0]);
+	assert_eq!(day_night_color(day, night, 2.0), night);
+
+	let mut config = Config::default();
+	config.sky_color = day;
+	config.sky_color_night = night;
+	config.fog_color = night;
+	config.fog_color_night = day;
+	let mut clock = WorldClock::default();
+	clock.set_time(12.0).unwrap();
+	assert_eq!(sky_and_fog_colors(&config, &clock, false), (day, night));
+	clock.set_time(0.0).unwrap();
+	assert_eq!(sky_and_fog_colors(&config, &clock, false), (night, day));
+	// At dusk, the colors are in between
+	clock.set_time(18.0).unwrap();
+	let (sky, fog) = sky_and_fog_colors(&config, &clock, false);
+	assert!(sky[0] > night[0] && sky[0] < day[0]);
+	assert!(fog[2] > day[2] && fog[2] < night[2]);
+}
+
+/// Fog parameters for the given viewing range in blocks
+///
+/// Chunks are culled based on the distance of their origin,
+/// so parts of chunks up to a chunk diagonal closer than the
+/// viewing range can disappear. The fog is made fully opaque
+/// before that point so that no chunks visibly pop in or out.
+fn fog_near_far(viewing_range :f32) -> [f32; 2] {
+	let chunk_diagonal = (CHUNKSIZE as f32) * 3.0f32.sqrt();
+	let fog_end = (viewing_range - chunk_diagonal).max(1.0);
+	let fog_start = fog_end * 0.6;
+	// The shader expects the length of the fog first
+	[fog_end - fog_start, fog_start]
+}
+
+#[cfg(test)]
+#[test]
+fn test_fog_near_far() {
+	for &range in &[16.0, 64.0, 128.0, 512.0] {
+		let [len, start] = fog_near_far(range);
+		let end = start + len;
+		assert!(len > 0.0);
+		assert!(start >= 0.0);
+		// The fog is opaque before the furthest possibly culled block
+		assert!(end <= range, "{} {}", range, end);
+		if range > 64.0 {
+			assert!(end >= range - 2.0 * CHUNKSIZE as f32);
+		}
+	}
+	// The default range keeps the former hard coded fog
+	let [len, start] = fog_near_far(128.0);
+	assert!((start - 60.0).abs() < 1.0);
+	assert!((start + len - 100.0).abs() < 1.0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_wireframe_mesh() {
+	let pos = Vector3::new(3.0, -2.0, 5.0);
+	let vertices = wireframe_mesh(pos, pos.add_scalar(1.0), 0);
+	assert_eq!(vertices.len(), 24);
+	let mut edges = vertices.chunks(2)
+		.map(|edge| {
+			let s = Vector3::from(edge[0].position);
+			let e = Vector3::from(edge[1].position);
+			// Every edge has length 1 and goes along one axis
+			assert_eq!((e - s).map(|v| v.abs()).sum(), 1.0);
+			assert_eq!((e - s).norm(), 1.0);
+			((s - pos).map(|v| v as u8), (e - pos).map(|v| v as u8))
+		})
+		.collect::<Vec<_>>();
+	edges.sort_by_key(|(s, e)| (s.x, s.y, s.z, e.x, e.y, e.z));
+	edges.dedup();
+	assert_eq!(edges.len(), 12);
+	// Each corner of the block is the end of three edges
+	for x in 0 .. 2 {
+		for y in 0 .. 2 {
+			for z in 0 .. 2 {
+				let corner = Vector3::new(x, y, z);
+				let count = edges.iter()
+					.filter(|(s, e)| *s == corner || *e == corner)
+					.count();
+				assert_eq!(count, 3);
+			}
+		}
+	}
+}
+
+/// Seconds between two blocks broken instantly while holding the button
+const INSTABREAK_COOLDOWN :f32 = 0.2;
+
+/// Advances digging the selected block by one frame
+///
+/// Returns whether the block breaks in this frame. Unless `instant`
+/// is set, digging takes the time returned by `dig_time`, which is
+/// `None` for blocks that can't be dug.
+fn advance_dig(dig_cooldown :&mut Option<(Vector3<isize>, f32)>,
+		selected_pos :Vector3<isize>, instant :bool,
+		dig_time :impl FnOnce() -> Option<f32>) -> bool {
+	if instant {
+		let ready = dig_cooldown.map(|(_p, c)| c <= 0.0).unwrap_or(true);
+		if ready {
+			*dig_cooldown = Some((selected_pos, INSTABREAK_COOLDOWN));
+		}
+		return ready;
+	}
+	match dig_cooldown {
+		Some((pos, dc)) => if *pos != selected_pos {
+			*dig_cooldown = None;
+			false
+		} else {
+			*dc <= 0.0
+		},
+		None => {
+			if let Some(time) = dig_time() {
+				*dig_cooldown = Some((selected_pos, time));
+			}
+			false
+		},
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_advance_dig() {
+	let pos = Vector3::new(1, 2, 3);
+	// Creative breaks the block when the button goes down
+	let mut dig_cooldown = None;
+	assert!(advance_dig(&mut dig_cooldown, pos, true, || Some(1.0)));
+	// Holding the button doesn't break a block each frame
+	let next = Vector3::new(1, 2, 2);
+	assert!(!advance_dig(&mut dig_cooldown, next, true, || Some(1.0)));
+	dig_cooldown.as_mut().map(|(_p, c)| *c -= INSTABREAK_COOLDOWN);
+	assert!(advance_dig(&mut dig_cooldown, next, true, || Some(1.0)));
+
+	// Survival needs the dig time to pass first
+	let mut dig_cooldown = None;
+	assert!(!advance_dig(&mut dig_cooldown, pos, false, || Some(0.5)));
+	assert!(!advance_dig(&mut dig_cooldown, pos, false, || Some(0.5)));
+	dig_cooldown.as_mut().map(|(_p, c)| *c -= 0.5);
+	assert!(advance_dig(&mut dig_cooldown, pos, false, || Some(0.5)));
+	// Selecting another block restarts digging
+	assert!(!advance_dig(&mut dig_cooldown, next, false, || Some(0.5)));
+	assert_eq!(dig_cooldown, None);
+	// Blocks without dig time are never dug
+	assert!(!advance_dig(&mut dig_cooldown, pos, false, || None));
+	assert!(!advance_dig(&mut dig_cooldown, pos, false, || None));
+}
+
+#[cfg(test)]
+#[test]
+fn test_spectator_camera() {
+	use mimas_server::config::KeyboardLayout;
+
+	let mut camera = Camera::new(MovementKeys::for_layout(KeyboardLayout::Qwerty));
+	camera.fly_mode = false;
+	assert!(!camera.is_noclip());
+	// Spectators fly through the terrain without collision
+	camera.set_game_mode(GameMode::Spectator);
+	assert!(camera.is_noclip());
+	assert!(camera.fly_mode);
+	camera.set_game_mode(GameMode::Survival);
+	assert!(!camera.is_noclip());
+}
+
+#[cfg(test)]
+#[test]
+fn test_camera_underwater() {
+	use mimas_server::game_params::NameIdMap;
+	use mimas_server::map::MapChunkData;
+
+	let nm = NameIdMap::builtin_name_list();
+	let air = nm.get_id("default:air").unwrap();
+	let water = nm.get_id("default:water").unwrap();
+	let mut map = ClientMap::new();
+	let mut chunk = MapChunkData::filled_with(air);
+	*chunk.get_blk_mut(Vector3::new(1, 2, 3)) = water;
+	map.set_chunk(Vector3::new(0, 0, 0), chunk);
+	let mut chunk = MapChunkData::filled_with(water);
+	*chunk.get_blk_mut(Vector3::new(CHUNKSIZE - 1, 0, 0)) = air;
+	map.set_chunk(Vector3::new(-CHUNKSIZE, 0, 0), chunk);
+
+	assert!(camera_underwater(&map, Vector3::new(1.5, 2.1, 3.9), water));
+	assert!(!camera_underwater(&map, Vector3::new(1.5, 2.1, 4.0), water));
+	assert!(!camera_underwater(&map, Vector3::new(0.9, 2.1, 3.9), water));
+	// Negative coordinates are rounded down, not towards zero
+	assert!(!camera_underwater(&map, Vector3::new(-0.5, 0.5, 0.5), water));
+	assert!(camera_underwater(&map, Vector3::new(-1.5, 0.5, 0.5), water));
+	// Chunks that aren't loaded yet don't count as water
+	assert!(!camera_underwater(&map, Vector3::new(0.5, 0.5, -0.5), water));
+}
+
 fn clamp(a :f32, min :f32, max :f32) -> f32 {
 	if a > min {
 		if a < max {
@@ -1086,6 +2286,7 @@ struct Camera {
 	pos :Vector3<f32>,
 	velocity :Vector3<f32>,
 
+	movement_keys :MovementKeys,
 	forward_pressed :bool,
 	left_pressed :bool,
 	right_pressed :bool,
@@ -1095,6 +2296,7 @@ struct Camera {
 	fast_mode :bool,
 	noclip_mode :bool,
 	fly_mode :bool,
+	game_mode :GameMode,
 
 	up_pressed :bool,
 	down_pressed :bool,
@@ -1106,7 +2308,7 @@ struct Camera {
 }
 
 impl Camera {
-	fn new() -> Self {
+	fn new(movement_keys :MovementKeys) -> Self {
 		Camera {
 			aspect_ratio : 1024.0 / 768.0,
 			pitch : 0.0,
@@ -1114,6 +2316,7 @@ impl Camera {
 			pos : Vector3::new(60.0, 40.0, 20.0),
 			velocity : Vector3::new(0.0, 0.0, 0.0),
 
+			movement_keys,
 			forward_pressed : false,
 			left_pressed : false,
 			right_pressed : false,
@@ -1123,6 +2326,7 @@ impl Camera {
 			fast_mode : false,
 			noclip_mode : false,
 			fly_mode : true,
+			game_mode : GameMode::default(),
 
 			up_pressed : false,
 			down_pressed : false,
@@ -1145,20 +2349,28 @@ impl Camera {
 			None => return,
 		};
 		let mut b = None;
+		let keys = self.movement_keys;
 		match key {
-			VirtualKeyCode::W => b = Some(&mut self.forward_pressed),
-			VirtualKeyCode::A => b = Some(&mut self.left_pressed),
-			VirtualKeyCode::S => b = Some(&mut self.backward_pressed),
-			VirtualKeyCode::D => b = Some(&mut self.right_pressed),
+			_ if key == keys.forward => b = Some(&mut self.forward_pressed),
+			_ if key == keys.left => b = Some(&mut self.left_pressed),
+			_ if key == keys.backward => b = Some(&mut self.backward_pressed),
+			_ if key == keys.right => b = Some(&mut self.right_pressed),
 			VirtualKeyCode::Space => b = Some(&mut self.up_pressed),
 			VirtualKeyCode::LShift => b = Some(&mut self.down_pressed),
 		_ => (),
 		}
+		// Movement keys take precedence, as with some
+		// layouts they overlap with the other keys
+		if let Some(b) = b {
+			*b = input.state == ElementState::Pressed;
+			return;
+		}
 		if key == VirtualKeyCode::E {
 			self.fast_pressed = input.state == ElementState::Pressed;
 		}
 		if key == VirtualKeyCode::K {
-			if input.state == ElementState::Pressed {
+			// Spectators always fly
+			if input.state == ElementState::Pressed && self.game_mode.has_collision() {
 				self.fly_mode = !self.fly_mode;
 			}
 		}
@@ -1172,10 +2384,6 @@ impl Camera {
 				self.noclip_mode = !self.noclip_mode;
 			}
 		}
-
-		if let Some(b) = b {
-			*b = input.state == ElementState::Pressed;
-		}
 	}
 	fn delta_pos(&mut self) -> Vector3<f32> {
 		let mut delta_pos = Vector3::zero();
@@ -1222,7 +2430,13 @@ impl Camera {
 		self.fast_mode || self.fast_pressed
 	}
 	fn is_noclip(&self) -> bool {
-		self.noclip_mode && self.fly_mode
+		!self.game_mode.has_collision() || (self.noclip_mode && self.fly_mode)
+	}
+	fn set_game_mode(&mut self, mode :GameMode) {
+		self.game_mode = mode;
+		if !mode.has_collision() {
+			self.fly_mode = true;
+		}
 	}
 
 	fn direction(&self) -> Point3<f32> {
@@ -1245,9 +2459,10 @@ impl Camera {
 		Matrix4::new_perspective(self.aspect_ratio, fov, znear, zfar).into()
 	}
 
-	pub fn get_selected_pos<B :MapBackend>(&self, map :&Map<B>, params :&GameParamsHdl) -> Option<(Vector3<isize>, Vector3<isize>)> {
+	pub fn get_selected_pos<B :MapBackend>(&self, map :&Map<B>, params :&GameParamsHdl,
+			reach :f32) -> Option<(Vector3<isize>, Vector3<isize>)> {
 		for (vs, ve) in VoxelWalker::new(self.pos,
-				self.direction().coords) {
+				self.direction().coords, reach) {
 			let vs = vs.map(|v| v.floor() as isize);
 			let ve = ve.map(|v| v.floor() as isize);
 			if let Some(blk) = map.get_blk(ve) {
@@ -3,21 +3,60 @@ in float vlamb;
 in vec2 vtex_pos;
 flat in uint vtex_ind;
 in vec4 vposition;
+in vec3 vnormal;
 
 out vec4 fcolor;
 
-uniform sampler2DArray texture_arr;
+// One sampler per texture atlas, see MAX_ATLASES
+uniform sampler2DArray texture_arr_0;
+uniform sampler2DArray texture_arr_1;
+uniform sampler2DArray texture_arr_2;
+uniform sampler2DArray texture_arr_3;
 uniform vec2 fog_near_far;
+// The color of the fog outside of water
+uniform vec4 fog_color;
+// How much darker than at noon the world is,
+// zero for things like the UI
+uniform float darkness;
+// 1.0 if the camera is inside water, 0.0 otherwise
+uniform float underwater;
+// 1.0 to color faces by their normals, for debugging
+uniform float show_normals;
 
-const vec4 fog = vec4(0.5, 0.5, 0.5, 1.0);
+const vec4 water_fog = vec4(0.1, 0.25, 0.5, 1.0);
+
+// Same as ATLAS_LAYERS
+const uint atlas_layers = 256u;
+
+vec4 atlas_texture(uint ind, vec2 pos) {
+	uint atlas = ind / atlas_layers;
+	vec3 p = vec3(pos, float(ind % atlas_layers));
+	if (atlas == 0u) {
+		return texture(texture_arr_0, p);
+	} else if (atlas == 1u) {
+		return texture(texture_arr_1, p);
+	} else if (atlas == 2u) {
+		return texture(texture_arr_2, p);
+	}
+	return texture(texture_arr_3, p);
+}
 
 void main() {
-	vec4 tcolor = texture(texture_arr, vec3(vtex_pos, vtex_ind));
+	vec4 tcolor = atlas_texture(vtex_ind, vtex_pos);
 	if (tcolor.a < 0.5) {
 		discard;
 	}
 
-	vec4 color_lamb = vlamb * tcolor;
+	if (show_normals > 0.5) {
+		fcolor = vec4(vnormal * 0.5 + 0.5, 1.0);
+		return;
+	}
+
+	float light = 1.0 - darkness;
+	vec4 fog = mix(fog_color, vec4(water_fog.rgb * light, water_fog.a), underwater);
+	vec4 color_lamb = vlamb * light * tcolor;
+	// Tint everything blue when inside water
+	color_lamb.rgb = mix(color_lamb.rgb, color_lamb.rgb * water_fog.rgb * 2.0, underwater);
 	color_lamb.a = tcolor.a;
 	float fog_factor = clamp((length(vposition) - fog_near_far.y) / fog_near_far.x, 0.0, 1.0);
 	fcolor = mix(color_lamb, fog, fog_factor);
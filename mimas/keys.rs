@@ -0,0 +1,125 @@
+use glium::glutin::event::VirtualKeyCode;
+use mimas_server::config::{Config, KeyboardLayout};
+use mimas_server::StrErr;
+
+/// The keys used to move around
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MovementKeys {
+	pub forward :VirtualKeyCode,
+	pub left :VirtualKeyCode,
+	pub backward :VirtualKeyCode,
+	pub right :VirtualKeyCode,
+}
+
+impl MovementKeys {
+	/// The default movement keys of the given layout
+	///
+	/// The keys are at the positions of WASD on a qwerty keyboard.
+	pub fn for_layout(layout :KeyboardLayout) -> Self {
+		use self::VirtualKeyCode::*;
+		let (forward, left, backward, right) = match layout {
+			KeyboardLayout::Qwerty => (W, A, S, D),
+			KeyboardLayout::Azerty => (Z, Q, S, D),
+			KeyboardLayout::Dvorak => (Comma, A, O, E),
+		};
+		Self {
+			forward,
+			left,
+			backward,
+			right,
+		}
+	}
+	/// The movement keys of the layout preset, overridden by the configured keys
+	pub fn from_config(config :&Config) -> Result<Self, StrErr> {
+		let mut res = Self::for_layout(config.keyboard_layout);
+		let keys = &config.keys;
+		override_key(&mut res.forward, &keys.forward)?;
+		override_key(&mut res.left, &keys.left)?;
+		override_key(&mut res.backward, &keys.backward)?;
+		override_key(&mut res.right, &keys.right)?;
+		Ok(res)
+	}
+}
+
+fn override_key(key :&mut VirtualKeyCode, name :&Option<String>) -> Result<(), StrErr> {
+	if let Some(name) = name {
+		*key = key_from_name(name)
+			.ok_or_else(|| format!("Unknown key name '{}'", name))?;
+	}
+	Ok(())
+}
+
+/// Looks up a key by its name, ignoring case
+///
+/// Supports letters, digits and a few punctuation keys.
+pub fn key_from_name(name :&str) -> Option<VirtualKeyCode> {
+	use self::VirtualKeyCode::*;
+	const LETTERS :[VirtualKeyCode; 26] = [
+		A, B, C, D, E, F, G, H, I, J, K, L, M,
+		N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+	];
+	const DIGITS :[VirtualKeyCode; 10] = [
+		Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+	];
+	let mut chars = name.chars();
+	if let (Some(c), None) = (chars.next(), chars.next()) {
+		let c = c.to_ascii_uppercase();
+		return match c {
+			'A' ..= 'Z' => Some(LETTERS[(c as u8 - b'A') as usize]),
+			'0' ..= '9' => Some(DIGITS[(c as u8 - b'0') as usize]),
+			',' => Some(Comma),
+			'.' => Some(Period),
+			';' => Some(Semicolon),
+			'\'' => Some(Apostrophe),
+			'/' => Some(Slash),
+			'-' => Some(Minus),
+			_ => None,
+		};
+	}
+	let key = match name.to_ascii_lowercase().as_str() {
+		"comma" => Comma,
+		"period" => Period,
+		"semicolon" => Semicolon,
+		"apostrophe" => Apostrophe,
+		"slash" => Slash,
+		"minus" => Minus,
+		"up" => Up,
+		"down" => Down,
+		"left" => Left,
+		"right" => Right,
+		_ => return None,
+	};
+	Some(key)
+}
+
+#[cfg(test)]
+#[test]
+fn test_azerty_movement_keys() {
+	use self::VirtualKeyCode::*;
+
+	let mut config = Config::default();
+	assert_eq!(MovementKeys::from_config(&config).unwrap(),
+		MovementKeys::for_layout(KeyboardLayout::Qwerty));
+
+	config.keyboard_layout = KeyboardLayout::Azerty;
+	let keys = MovementKeys::from_config(&config).unwrap();
+	assert_eq!(keys, MovementKeys {
+		forward : Z,
+		left : Q,
+		backward : S,
+		right : D,
+	});
+
+	// Configured keys override the preset
+	config.keys.left = Some("a".to_owned());
+	config.keys.forward = Some("Up".to_owned());
+	let keys = MovementKeys::from_config(&config).unwrap();
+	assert_eq!((keys.forward, keys.left, keys.backward), (Up, A, S));
+
+	config.keys.right = Some("nope".to_owned());
+	assert!(MovementKeys::from_config(&config).is_err());
+
+	assert_eq!(key_from_name(","), Some(Comma));
+	assert_eq!(key_from_name("7"), Some(Key7));
+	assert_eq!(key_from_name(""), None);
+}
@@ -13,13 +13,17 @@ extern crate srp;
 extern crate sha2;
 extern crate image;
 extern crate dirs;
+extern crate copypasta;
+#[macro_use]
+extern crate log;
 
 extern crate mimas_server;
 extern crate mimas_meshgen;
 
 mod assets;
 mod client;
-mod collide;
+mod keys;
+mod particles;
 mod ui;
 mod voxel_walk;
 
@@ -28,11 +32,16 @@ use client::Game;
 
 use structopt::StructOpt;
 
-use std::thread;
-use mimas_server::{Server, StrErr};
+use std::thread::{self, JoinHandle};
+use std::sync::mpsc::channel;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use mimas_server::{Server, ShutdownHandle, StrErr};
 use mimas_server::generic_net::{MpscServerSocket, NetworkClientConn};
-use mimas_server::quic_net::QuicClientConn;
-use mimas_server::config::load_config;
+use mimas_server::quic_net::{QuicClientConn, TicketCache};
+use mimas_server::config::{Config, load_config};
+use mimas_server::logging::{self, TARGET_SERVER};
+use mimas_server::server_list::{ServerList, ServerEntry, server_list_path};
 
 /// Mimas client
 #[derive(StructOpt, Debug)]
@@ -51,37 +60,92 @@ struct Options {
 	pw :Option<String>,
 }
 
-fn main() -> Result<(), StrErr> {
+/// The server thread of a singleplayer game
+struct SingleplayerServer {
+	shutdown :ShutdownHandle,
+	thread :JoinHandle<()>,
+}
 
-	let options = Options::from_args();
-	let config = load_config();
-	let mut nick_pw = None;
+impl SingleplayerServer {
+	/// Shuts the server down and waits until it has stored the world
+	fn stop(self) {
+		self.shutdown.request();
+		if self.thread.join().is_err() {
+			warn!(target : TARGET_SERVER, "The singleplayer server thread panicked");
+		}
+	}
+}
 
-	let client_conn :Box<dyn NetworkClientConn> = if let Some(addr) = options.connect.clone() {
-		let addr = addr.parse().expect("couldn't parse address");
-		let client_conn = QuicClientConn::from_socket_addr(&addr)?;
-		let nick = options.nick.unwrap_or_else(|| {
-			panic!("No nick specified but needed to connect to server.");
-		});
-		let pw = options.pw.unwrap_or_else(|| {
-			panic!("No password specified but needed to connect to server.");
-		});
-		nick_pw = Some((nick.clone(), pw));
-		Box::new(client_conn)
+/// Connects to the server, or starts a singleplayer one if none is given
+fn connect(server :Option<&ServerEntry>, pw :Option<&String>, config :&Config,
+		tickets :&Arc<TicketCache>) -> Result<(Box<dyn NetworkClientConn>,
+			Option<(String, String)>, Option<SingleplayerServer>), StrErr> {
+	if let Some(server) = server {
+		let addr :SocketAddr = server.address.parse()
+			.map_err(|e| format!("Couldn't parse address {}: {}", server.address, e))?;
+		let client_conn = QuicClientConn::with_ticket_cache(&addr,
+			config.net_fragment_size, tickets.clone())?;
+		// Passwords are not stored in the server list
+		let pw = pw.ok_or("No password specified but needed to connect to server.")?;
+		Ok((Box::new(client_conn), Some((server.nick.clone(), pw.clone())), None))
 	} else {
 		let (server_socket, client_conn) = MpscServerSocket::new();
 		let config = config.clone();
-		thread::spawn(move || {
+		let (shutdown_s, shutdown_r) = channel();
+		let thread = thread::spawn(move || {
 			let mut server = Server::new(server_socket, true, config);
+			let _ = shutdown_s.send(server.shutdown_handle());
 			server.run_loop();
 		});
-		Box::new(client_conn)
+		let shutdown = shutdown_r.recv()
+			.map_err(|_| "The singleplayer server failed to start")?;
+		let srv = SingleplayerServer { shutdown, thread };
+		Ok((Box::new(client_conn), None, Some(srv)))
+	}
+}
+
+fn main() -> Result<(), StrErr> {
+
+	let options = Options::from_args();
+	logging::init();
+	let config = load_config()?;
+	let server_list_path = server_list_path()?;
+	let mut server_list = ServerList::load_from(&server_list_path)?;
+
+	let mut server = if let Some(addr) = options.connect.clone() {
+		let nick = options.nick.unwrap_or_else(|| {
+			panic!("No nick specified but needed to connect to server.");
+		});
+		Some(ServerEntry::new(addr, nick))
+	} else {
+		None
 	};
 
+	// Shared by all connections so that reconnecting can resume the session
+	let tickets = Arc::new(TicketCache::default());
+
 	let mut event_loop = glutin::event_loop::EventLoop::new();
-	let mut game = Game::new(&event_loop, client_conn, config, nick_pw);
+	loop {
+		let (client_conn, nick_pw, singleplayer) = connect(server.as_ref(),
+			options.pw.as_ref(), &config, &tickets)?;
+		if let Some(server) = &server {
+			if server_list.set_last_nick(&server.address, &server.nick) {
+				server_list.save_to(&server_list_path)?;
+			}
+		}
+		let mut game = Game::new(&event_loop, client_conn, config.clone(), nick_pw,
+			server_list, server.clone());
 
-	game.run_loop(&mut event_loop);
+		server = game.run_loop(&mut event_loop);
+		server_list = game.server_list().clone();
+		// Store the world before leaving it or starting another server on it
+		if let Some(singleplayer) = singleplayer {
+			singleplayer.stop();
+		}
+		if server.is_none() {
+			break;
+		}
+	}
 
 	Ok(())
 }
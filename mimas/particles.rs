@@ -0,0 +1,126 @@
+use nalgebra::Vector3;
+use rand::Rng;
+use mimas_meshgen::{Vertex, push_block, BlockTextureIds};
+
+/// Number of particles spawned when a block is broken
+pub const BREAK_PARTICLE_COUNT :usize = 12;
+/// Upper bound on the number of simultaneously alive particles
+pub const MAX_PARTICLES :usize = 256;
+
+const GRAVITY :f32 = 9.81;
+const PARTICLE_LIFETIME :f32 = 0.8;
+const PARTICLE_SIZE :f32 = 0.12;
+
+#[derive(Clone)]
+pub struct Particle {
+	pub pos :Vector3<f32>,
+	pub velocity :Vector3<f32>,
+	pub texture_ids :BlockTextureIds,
+	pub age :f32,
+}
+
+impl Particle {
+	fn size(&self) -> f32 {
+		// Shrink the particle as it ages so that it fades out
+		PARTICLE_SIZE * (1.0 - self.age / PARTICLE_LIFETIME).max(0.0)
+	}
+}
+
+pub struct Particles {
+	particles :Vec<Particle>,
+}
+
+impl Particles {
+	pub fn new() -> Self {
+		Self {
+			particles : Vec::new(),
+		}
+	}
+	/// Spawns a burst of particles for a block broken at the given position
+	pub fn spawn_break_burst(&mut self, pos :Vector3<isize>,
+			texture_ids :BlockTextureIds, rng :&mut impl Rng) {
+		let center = pos.map(|v| v as f32) + Vector3::new(0.5, 0.5, 0.5);
+		for _ in 0 .. BREAK_PARTICLE_COUNT {
+			let offs = Vector3::new(
+				rng.gen_range(-0.3, 0.3),
+				rng.gen_range(-0.3, 0.3),
+				rng.gen_range(-0.3, 0.3));
+			let velocity = Vector3::new(
+				rng.gen_range(-1.5, 1.5),
+				rng.gen_range(-1.5, 1.5),
+				rng.gen_range(1.0, 3.0));
+			self.particles.push(Particle {
+				pos : center + offs,
+				velocity,
+				texture_ids,
+				age : 0.0,
+			});
+		}
+		// Drop the oldest particles if we are above the limit
+		if self.particles.len() > MAX_PARTICLES {
+			let excess = self.particles.len() - MAX_PARTICLES;
+			self.particles.drain(.. excess);
+		}
+	}
+	pub fn tick(&mut self, time_delta :f32) {
+		for p in self.particles.iter_mut() {
+			p.velocity.z -= GRAVITY * time_delta;
+			p.pos += p.velocity * time_delta;
+			p.age += time_delta;
+		}
+		self.particles.retain(|p| p.age < PARTICLE_LIFETIME);
+	}
+	pub fn mesh(&self) -> Vec<Vertex> {
+		let mut vertices = Vec::new();
+		for p in self.particles.iter() {
+			let siz = p.size();
+			let pos = p.pos.map(|v| v - siz / 2.0);
+			push_block(&mut vertices, [pos.x, pos.y, pos.z],
+				p.texture_ids, siz, |_| false);
+		}
+		vertices
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_break_particles() {
+	use rand_pcg::Pcg32;
+	use mimas_meshgen::TextureId;
+
+	let mut rng = Pcg32::new(42, 0);
+	let mut particles = Particles::new();
+	let tex = BlockTextureIds::new(TextureId(3), TextureId(4), TextureId(5));
+	let pos = Vector3::new(10, -4, 7);
+	particles.spawn_break_burst(pos, tex, &mut rng);
+
+	// Initial particle set
+	assert_eq!(particles.particles.len(), BREAK_PARTICLE_COUNT);
+	for p in particles.particles.iter() {
+		assert!(p.texture_ids.id_sides == TextureId(3));
+		assert!(p.texture_ids.id_top == TextureId(4));
+		assert_eq!(p.age, 0.0);
+		assert!(p.velocity.z > 0.0);
+		let center = pos.map(|v| v as f32) + Vector3::new(0.5, 0.5, 0.5);
+		assert!((p.pos - center).amax() <= 0.3);
+	}
+	assert_eq!(particles.mesh().len(), BREAK_PARTICLE_COUNT * 36);
+
+	// Gravity pulls the particles down over time
+	let before = particles.particles[0].clone();
+	particles.tick(0.1);
+	let after = &particles.particles[0];
+	assert!(after.velocity.z < before.velocity.z);
+	assert!(after.size() < before.size());
+
+	// All particles expire after their lifetime
+	particles.tick(PARTICLE_LIFETIME);
+	assert!(particles.particles.is_empty());
+	assert!(particles.mesh().is_empty());
+
+	// The particle count is bounded
+	for _ in 0 .. MAX_PARTICLES {
+		particles.spawn_break_burst(pos, tex, &mut rng);
+	}
+	assert_eq!(particles.particles.len(), MAX_PARTICLES);
+}
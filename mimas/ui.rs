@@ -1,17 +1,24 @@
-use glium::{Surface, VertexBuffer};
+use glium::{Surface, VertexBuffer, BlendingFunction, LinearBlendingFactor};
 use glium_glyph::GlyphBrush;
 use glium_glyph::glyph_brush::{
 	Section, Layout, HorizontalAlign,
 };
 use nalgebra::Vector3;
 use glium::glutin::event::{KeyboardInput, VirtualKeyCode,
-	ElementState, MouseButton};
+	ElementState, MouseButton, ModifiersState};
 use glium::glutin::dpi::PhysicalPosition;
 use glium_glyph::glyph_brush::GlyphCruncher;
-use mimas_server::inventory::{SelectableInventory, Stack,
-	HUD_SLOT_COUNT};
-use mimas_server::crafting::get_matching_recipe;
+use copypasta::{ClipboardContext, ClipboardProvider};
+use std::collections::VecDeque;
+use std::mem::take;
+use std::time::{Duration, Instant};
+use mimas_server::inventory::{SelectableInventory, Stack};
+use mimas_server::crafting::{get_matching_recipe, fill_grid, CraftingGrid};
+use mimas_server::inventory_action::{InventoryAction, InvLocation, InvSlot};
 use mimas_server::game_params::GameParamsHdl;
+use mimas_server::server_list::{ServerList, ServerEntry};
+use mimas_server::config::CrosshairStyle;
+use mimas_server::logging::TARGET_CLIENT;
 
 use mimas_meshgen::{Vertex, TextureId};
 
@@ -24,6 +31,10 @@ pub const IDENTITY :[[f32; 4]; 4] = [
 	[0.0, 0.0, 0.0, 1.0],
 ];
 
+/// Fog parameters for drawing things that should never be fogged,
+/// like the UI or the wielded item
+pub const NO_FOG :[f32; 2] = [1.0, f32::MAX];
+
 fn render_text<'a, 'b>(text :&str, ui_colors :&UiColors,
 		display :&glium::Display, program :&glium::Program,
 		glyph_brush :&mut GlyphBrush<'a, 'b>, target :&mut glium::Frame) {
@@ -53,11 +64,168 @@ fn render_text<'a, 'b>(text :&str, ui_colors :&UiColors,
 
 pub fn render_menu<'a, 'b>(ui_colors :&UiColors, display :&glium::Display, program :&glium::Program,
 		glyph_brush :&mut GlyphBrush<'a, 'b>, target :&mut glium::Frame) {
-	render_text("Menu\nPress esc to continue Game", ui_colors, display, program, glyph_brush, target);
+	render_text("Menu\nPress esc to continue Game\nPress l for the server list",
+		ui_colors, display, program, glyph_brush, target);
+}
+
+pub enum ServerListEvent {
+	Connect(ServerEntry),
+	/// The list has been changed and needs saving
+	Changed,
+	None,
+}
+
+/// The screen listing the saved servers
+///
+/// Below the servers, there are entries to add the server
+/// currently connected to, and to remove a server.
+pub struct ServerListMenu {
+	selected :usize,
+	/// Whether activating a server removes it instead of connecting to it
+	removing :bool,
+}
+
+impl ServerListMenu {
+	pub fn new() -> Self {
+		Self {
+			selected : 0,
+			removing : false,
+		}
+	}
+	fn entry_count(list :&ServerList) -> usize {
+		list.servers().len() + 2
+	}
+	pub fn select_next(&mut self, list :&ServerList) {
+		self.selected = (self.selected + 1) % Self::entry_count(list);
+	}
+	pub fn select_prev(&mut self, list :&ServerList) {
+		let count = Self::entry_count(list);
+		self.selected = (self.selected + count - 1) % count;
+	}
+	/// Activates the selected entry
+	///
+	/// `current` is the server the client is connected to, if any.
+	pub fn activate(&mut self, list :&mut ServerList,
+			current :Option<&ServerEntry>) -> ServerListEvent {
+		let server_count = list.servers().len();
+		if self.selected < server_count {
+			if self.removing {
+				list.remove(self.selected);
+				self.removing = false;
+				self.selected = self.selected.min(Self::entry_count(list) - 1);
+				ServerListEvent::Changed
+			} else {
+				ServerListEvent::Connect(list.servers()[self.selected].clone())
+			}
+		} else if self.selected == server_count {
+			if let Some(current) = current {
+				list.add(current.clone());
+				// Keep the add entry selected
+				self.selected = list.servers().len();
+				ServerListEvent::Changed
+			} else {
+				ServerListEvent::None
+			}
+		} else {
+			self.removing = !self.removing;
+			ServerListEvent::None
+		}
+	}
+	pub fn handle_kinput(&mut self, input :&KeyboardInput, list :&mut ServerList,
+			current :Option<&ServerEntry>) -> ServerListEvent {
+		if input.state != ElementState::Pressed {
+			return ServerListEvent::None;
+		}
+		match input.virtual_keycode {
+			Some(VirtualKeyCode::Up) => self.select_prev(list),
+			Some(VirtualKeyCode::Down) => self.select_next(list),
+			Some(VirtualKeyCode::Return) => return self.activate(list, current),
+			_ => (),
+		}
+		ServerListEvent::None
+	}
+	fn text(&self, list :&ServerList) -> String {
+		let mut res = if self.removing {
+			"Select the server to remove\n".to_owned()
+		} else {
+			"Servers\n".to_owned()
+		};
+		let entries = list.servers().iter()
+			.map(|s| format!("{} ({})", s.name, s.nick))
+			.chain(Some("Add this server".to_owned()))
+			.chain(Some(if self.removing { "Cancel" } else { "Remove a server" }.to_owned()));
+		for (i, entry) in entries.enumerate() {
+			let marker = if i == self.selected { "> " } else { "" };
+			res += &format!("\n{}{}", marker, entry);
+		}
+		res
+	}
+	pub fn render<'a, 'b>(&self, list :&ServerList, ui_colors :&UiColors,
+			display :&glium::Display, program :&glium::Program,
+			glyph_brush :&mut GlyphBrush<'a, 'b>, target :&mut glium::Frame) {
+		render_text(&self.text(list), ui_colors, display, program, glyph_brush, target);
+	}
+}
+
+/// Maximum number of characters in the chat input
+pub const CHAT_MAX_LEN :usize = 256;
+
+/// Turns clipboard contents into something that can be inserted into the chat input
+///
+/// Line breaks are turned into spaces, other control characters
+/// are removed, and the result is truncated to at most `max_len` characters.
+pub fn sanitize_paste(text :&str, max_len :usize) -> String {
+	text.lines()
+		.collect::<Vec<_>>()
+		.join(" ")
+		.chars()
+		.filter(|c| !c.is_control())
+		.take(max_len)
+		.collect()
+}
+
+/// Maximum number of entries in the chat history
+pub const CHAT_HISTORY_LEN :usize = 100;
+
+/// Previously sent chat messages and commands, oldest first
+pub struct ChatHistory {
+	entries :VecDeque<String>,
+}
+
+impl ChatHistory {
+	pub fn new() -> Self {
+		ChatHistory {
+			entries : VecDeque::new(),
+		}
+	}
+	/// Adds a sent message, dropping the oldest one if the history is full
+	///
+	/// Empty messages and repetitions of the newest one are ignored.
+	pub fn push(&mut self, text :&str) {
+		if text.is_empty() || self.entries.back().map(|e| e == text) == Some(true) {
+			return;
+		}
+		if self.entries.len() >= CHAT_HISTORY_LEN {
+			self.entries.pop_front();
+		}
+		self.entries.push_back(text.to_owned());
+	}
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+	pub fn get(&self, idx :usize) -> Option<&str> {
+		self.entries.get(idx).map(|e| e.as_str())
+	}
 }
 
 pub struct ChatWindow {
 	text : String,
+	/// Position of the caret, in characters
+	caret : usize,
+	/// Index of the history entry being shown, if any
+	history_idx : Option<usize>,
+	/// The input typed before going through the history
+	draft : String,
 }
 
 pub enum ChatWindowEvent {
@@ -71,16 +239,98 @@ impl ChatWindow {
 		Self::with_text("".to_owned())
 	}
 	pub fn with_text(text :String) -> Self {
+		let caret = text.chars().count();
 		ChatWindow {
 			text,
+			caret,
+			history_idx : None,
+			draft : String::new(),
+		}
+	}
+	fn set_text(&mut self, text :&str) {
+		self.text = text.to_owned();
+		self.caret_end();
+	}
+	/// Replaces the input with the previous entry of the history
+	pub fn history_up(&mut self, history :&ChatHistory) {
+		let idx = match self.history_idx {
+			_ if history.len() == 0 => return,
+			None => {
+				self.draft = self.text.clone();
+				history.len() - 1
+			},
+			Some(idx) => idx.saturating_sub(1),
+		};
+		self.history_idx = Some(idx);
+		self.set_text(history.get(idx).unwrap_or(""));
+	}
+	/// Replaces the input with the next entry of the history
+	///
+	/// After the newest entry, the input typed before is restored.
+	pub fn history_down(&mut self, history :&ChatHistory) {
+		match self.history_idx {
+			None => (),
+			Some(idx) if idx + 1 < history.len() => {
+				self.history_idx = Some(idx + 1);
+				self.set_text(history.get(idx + 1).unwrap_or(""));
+			},
+			Some(_) => {
+				self.history_idx = None;
+				let draft = std::mem::replace(&mut self.draft, String::new());
+				self.set_text(&draft);
+			},
 		}
 	}
 	pub fn text(&self) -> &str {
 		&self.text
 	}
+	#[cfg(test)]
+	pub fn caret(&self) -> usize {
+		self.caret
+	}
+	/// Byte index of the given character position
+	fn byte_idx(&self, pos :usize) -> usize {
+		self.text.char_indices()
+			.nth(pos)
+			.map(|(i, _)| i)
+			.unwrap_or(self.text.len())
+	}
+	fn insert_str(&mut self, text :&str) {
+		let idx = self.byte_idx(self.caret);
+		self.text.insert_str(idx, text);
+		self.caret += text.chars().count();
+	}
+	/// Removes the character before the caret
+	pub fn backspace(&mut self) {
+		if self.caret > 0 {
+			self.caret -= 1;
+			let idx = self.byte_idx(self.caret);
+			self.text.remove(idx);
+		}
+	}
+	/// Removes the character after the caret
+	pub fn delete(&mut self) {
+		if self.caret < self.text.chars().count() {
+			let idx = self.byte_idx(self.caret);
+			self.text.remove(idx);
+		}
+	}
+	pub fn caret_left(&mut self) {
+		self.caret = self.caret.saturating_sub(1);
+	}
+	pub fn caret_right(&mut self) {
+		self.caret = (self.caret + 1).min(self.text.chars().count());
+	}
+	pub fn caret_home(&mut self) {
+		self.caret = 0;
+	}
+	pub fn caret_end(&mut self) {
+		self.caret = self.text.chars().count();
+	}
 	pub fn render<'a, 'b>(&self, ui_colors :&UiColors, display :&glium::Display,
 			program :&glium::Program, glyph_brush :&mut GlyphBrush<'a, 'b>, target :&mut glium::Frame) {
-		let text = "Type to chat\n".to_owned() + &self.text;
+		let idx = self.byte_idx(self.caret);
+		let text = format!("Type to chat\n{}|{}", &self.text[..idx], &self.text[idx..]);
 		render_text(&text, ui_colors, display, program, glyph_brush, target);
 	}
 	pub fn handle_character(&mut self, input :char) -> ChatWindowEvent {
@@ -88,14 +338,42 @@ impl ChatWindow {
 			return ChatWindowEvent::SendChat;
 		}
 		if input == '\x08' {
-			// Backspace. Remove last character.
-			self.text.pop();
+			// Backspace. Remove the character before the caret.
+			self.backspace();
 			return ChatWindowEvent::None;
 		}
-		self.text.push(input);
+		// Ignore control characters, like the ones
+		// sent alongside ctrl+c or ctrl+v.
+		if input.is_control() {
+			return ChatWindowEvent::None;
+		}
+		if self.text.chars().count() < CHAT_MAX_LEN {
+			self.insert_str(input.encode_utf8(&mut [0; 4]));
+		}
 		ChatWindowEvent::None
 	}
-	pub fn handle_kinput(&mut self, input :&KeyboardInput) -> ChatWindowEvent {
+	/// Inserts text from the clipboard
+	pub fn paste(&mut self, text :&str) {
+		let remaining = CHAT_MAX_LEN.saturating_sub(self.text.chars().count());
+		self.insert_str(&sanitize_paste(text, remaining));
+	}
+	fn paste_from_clipboard(&mut self) {
+		let contents = ClipboardContext::new()
+			.and_then(|mut ctx| ctx.get_contents());
+		match contents {
+			Ok(contents) => self.paste(&contents),
+			Err(e) => warn!(target : TARGET_CLIENT, "Couldn't read from clipboard: {}", e),
+		}
+	}
+	fn copy_to_clipboard(&self) {
+		let res = ClipboardContext::new()
+			.and_then(|mut ctx| ctx.set_contents(self.text.clone()));
+		if let Err(e) = res {
+			warn!(target : TARGET_CLIENT, "Couldn't write to clipboard: {}", e);
+		}
+	}
+	pub fn handle_kinput(&mut self, input :&KeyboardInput, modifiers :ModifiersState,
+			history :&ChatHistory) -> ChatWindowEvent {
 		match (input.virtual_keycode, input.state) {
 			(Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
 				ChatWindowEvent::CloseChatWindow
@@ -103,11 +381,354 @@ impl ChatWindow {
 			(Some(VirtualKeyCode::Return), ElementState::Pressed) => {
 				ChatWindowEvent::SendChat
 			},
+			(Some(VirtualKeyCode::Left), ElementState::Pressed) => {
+				self.caret_left();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::Right), ElementState::Pressed) => {
+				self.caret_right();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::Up), ElementState::Pressed) => {
+				self.history_up(history);
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::Down), ElementState::Pressed) => {
+				self.history_down(history);
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::Home), ElementState::Pressed) => {
+				self.caret_home();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::End), ElementState::Pressed) => {
+				self.caret_end();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::Delete), ElementState::Pressed) => {
+				self.delete();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::V), ElementState::Pressed) if modifiers.ctrl() => {
+				self.paste_from_clipboard();
+				ChatWindowEvent::None
+			},
+			(Some(VirtualKeyCode::C), ElementState::Pressed) if modifiers.ctrl() => {
+				self.copy_to_clipboard();
+				ChatWindowEvent::None
+			},
 			_ => ChatWindowEvent::None,
 		}
 	}
 }
 
+#[cfg(test)]
+#[test]
+fn test_sanitize_paste() {
+	assert_eq!(sanitize_paste("hello world", 100), "hello world");
+	// Control characters are removed
+	assert_eq!(sanitize_paste("a\x08b\x1bc\td\x7f", 100), "abcd");
+	// Line breaks are turned into spaces
+	assert_eq!(sanitize_paste("one\ntwo\r\nthree\n", 100), "one two three");
+	// The length cap is enforced in characters, not bytes
+	assert_eq!(sanitize_paste("abcdef", 3), "abc");
+	assert_eq!(sanitize_paste("äöüß", 2), "äö");
+	assert_eq!(sanitize_paste("abc", 0), "");
+
+	let mut cw = ChatWindow::with_text("x".repeat(CHAT_MAX_LEN - 2));
+	cw.paste("abcd");
+	assert_eq!(cw.text().chars().count(), CHAT_MAX_LEN);
+	assert!(cw.text().ends_with("xab"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_window_caret() {
+	let mut cw = ChatWindow::with_text("/".to_owned());
+	assert_eq!(cw.caret(), 1);
+	for ch in "helo".chars() {
+		cw.handle_character(ch);
+	}
+	assert_eq!((cw.text(), cw.caret()), ("/helo", 5));
+
+	// Insertion happens at the caret
+	cw.caret_left();
+	cw.handle_character('l');
+	assert_eq!((cw.text(), cw.caret()), ("/hello", 5));
+
+	// Backspace removes before the caret, delete after it
+	cw.caret_home();
+	cw.caret_left();
+	assert_eq!(cw.caret(), 0);
+	cw.handle_character('\x08');
+	assert_eq!((cw.text(), cw.caret()), ("/hello", 0));
+	cw.delete();
+	assert_eq!((cw.text(), cw.caret()), ("hello", 0));
+	cw.caret_end();
+	cw.caret_right();
+	assert_eq!(cw.caret(), 5);
+	cw.delete();
+	assert_eq!((cw.text(), cw.caret()), ("hello", 5));
+	cw.caret_left();
+	cw.caret_left();
+	cw.backspace();
+	assert_eq!((cw.text(), cw.caret()), ("helo", 2));
+
+	// Multi byte characters and pastes
+	cw.handle_character('ä');
+	cw.paste("ß\nx");
+	assert_eq!((cw.text(), cw.caret()), ("heäß xlo", 6));
+	cw.delete();
+	cw.backspace();
+	assert_eq!((cw.text(), cw.caret()), ("heäß o", 5));
+}
+
+/// A modal window of the UI
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Modal {
+	PauseMenu,
+	Chat,
+	Inventory,
+	Chest,
+	ServerList,
+}
+
+/// The stack of open modal windows
+///
+/// Only the topmost modal receives input,
+/// and escape always closes the topmost modal.
+#[derive(Default)]
+pub struct UiStack {
+	modals :Vec<Modal>,
+}
+
+impl UiStack {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.modals.is_empty()
+	}
+	pub fn top(&self) -> Option<Modal> {
+		self.modals.last().copied()
+	}
+	/// Opens the given modal on top of the others
+	///
+	/// If the modal is already open, it is moved to the top.
+	pub fn push(&mut self, modal :Modal) {
+		self.remove(modal);
+		self.modals.push(modal);
+	}
+	pub fn remove(&mut self, modal :Modal) {
+		self.modals.retain(|m| *m != modal);
+	}
+	/// Handles a press of the escape key
+	///
+	/// Closes the topmost modal and returns it.
+	/// If no modal is open, the pause menu is opened.
+	pub fn escape(&mut self) -> Option<Modal> {
+		let top = self.modals.pop();
+		if top.is_none() {
+			self.modals.push(Modal::PauseMenu);
+		}
+		top
+	}
+}
+
+#[cfg(test)]
+#[test]
+fn test_chat_history() {
+	let mut history = ChatHistory::new();
+	let mut cw = ChatWindow::new();
+	cw.history_up(&history);
+	assert_eq!(cw.text(), "");
+
+	for msg in &["hello", "", "/time query", "/time query"] {
+		history.push(msg);
+	}
+	assert_eq!(history.len(), 2);
+
+	// Up recalls the older entries, stopping at the oldest
+	let mut cw = ChatWindow::with_text("/ti".to_owned());
+	cw.history_up(&history);
+	assert_eq!((cw.text(), cw.caret()), ("/time query", 11));
+	cw.history_up(&history);
+	assert_eq!(cw.text(), "hello");
+	cw.history_up(&history);
+	assert_eq!(cw.text(), "hello");
+	// Down goes back to the newest entry and then to the typed input
+	cw.history_down(&history);
+	assert_eq!(cw.text(), "/time query");
+	cw.history_down(&history);
+	assert_eq!((cw.text(), cw.caret()), ("/ti", 3));
+	cw.history_down(&history);
+	assert_eq!(cw.text(), "/ti");
+
+	// The history is bounded
+	for i in 0 .. CHAT_HISTORY_LEN + 5 {
+		history.push(&format!("msg {}", i));
+	}
+	assert_eq!(history.len(), CHAT_HISTORY_LEN);
+	assert_eq!(history.get(0), Some("msg 5"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_slot_drag() {
+	use mimas_server::game_params::NameIdMap;
+
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let sand = nm.get_id("default:sand").unwrap();
+	let new_invs = || {
+		let mut inv = SelectableInventory::empty_with_size(8);
+		inv.stacks_mut()[0] = Stack::with(stone, 10);
+		inv.stacks_mut()[5] = Stack::with(sand, 1);
+		inv.stacks_mut()[6] = Stack::with(stone, 2);
+		[inv]
+	};
+	let counts = |invs :&[SelectableInventory; 1]| {
+		invs[0].stacks().iter()
+			.map(|st| st.content().map(|(_, n)| n).unwrap_or(0))
+			.collect::<Vec<_>>()
+	};
+	let drag_over = |invs :&[SelectableInventory], button, slots :&[usize]| {
+		let mut drag = SlotDrag::new(button, (0, 0));
+		for &slot in slots {
+			drag.hover(invs, (0, slot));
+		}
+		drag
+	};
+	let player_slot = |(_, slot) :(usize, usize)| (InvLocation::Player, slot);
+
+	// Dragging the held stack of 10 across three slots with the left
+	// button gives each an even share, the remainder stays held.
+	// Visiting a slot twice, the held slot and slots with
+	// different items don't count.
+	let mut invs = new_invs();
+	let drag = drag_over(&invs, MouseButton::Left, &[0, 1, 2, 1, 5, 6]);
+	assert_eq!(drag.slots, vec![(0, 1), (0, 2), (0, 6)]);
+	let action = drag.finish(&mut invs, player_slot);
+	assert_eq!(counts(&invs), vec![1, 3, 3, 0, 0, 1, 5, 0]);
+	let slots = [1, 2, 6].iter().map(|s| (InvLocation::Player, *s)).collect();
+	assert_eq!(action, InventoryAction::Distribute((InvLocation::Player, 0), slots, None));
+
+	// With the right button, each slot gets one item
+	let mut invs = new_invs();
+	drag_over(&invs, MouseButton::Right, &[1, 2, 3, 4]).finish(&mut invs, player_slot);
+	assert_eq!(counts(&invs), vec![6, 1, 1, 1, 1, 1, 2, 0]);
+
+	// With fewer items than slots, the first slots get one each
+	let mut invs = new_invs();
+	invs[0].stacks_mut()[0] = Stack::with(stone, 2);
+	drag_over(&invs, MouseButton::Left, &[1, 2, 3]).finish(&mut invs, player_slot);
+	assert_eq!(counts(&invs), vec![0, 1, 1, 0, 0, 1, 2, 0]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_return_crafting_items() {
+	use mimas_server::game_params::NameIdMap;
+
+	let nm = NameIdMap::builtin_name_list();
+	let stone = nm.get_id("default:stone").unwrap();
+	let sand = nm.get_id("default:sand").unwrap();
+	let new_craft_inv = || {
+		let mut craft_inv = SelectableInventory::crafting_inv(CraftingGrid::PERSONAL);
+		craft_inv.stacks_mut()[0] = Stack::with(stone, 3);
+		craft_inv.stacks_mut()[3] = Stack::with(sand, 2);
+		craft_inv
+	};
+
+	// With space in the inventory, the grid gets emptied
+	let mut craft_inv = new_craft_inv();
+	let mut inv = SelectableInventory::empty_with_size(4);
+	inv.stacks_mut()[1] = Stack::with(stone, 1);
+	return_crafting_items(&mut craft_inv, &mut inv);
+	assert!(craft_inv.stacks().iter().all(|st| st.is_empty()));
+	assert_eq!(inv.stacks()[1], Stack::with(stone, 4));
+	assert!(inv.stacks().contains(&Stack::with(sand, 2)));
+
+	// With a full inventory, the items that don't fit stay in the grid
+	let mut craft_inv = new_craft_inv();
+	let mut inv = SelectableInventory::empty_with_size(1);
+	inv.stacks_mut()[0] = Stack::with(sand, 1);
+	return_crafting_items(&mut craft_inv, &mut inv);
+	assert_eq!(craft_inv.stacks()[0], Stack::with(stone, 3));
+	assert!(craft_inv.stacks()[3].is_empty());
+	assert_eq!(inv.stacks()[0], Stack::with(sand, 3));
+}
+
+#[cfg(test)]
+#[test]
+fn test_server_list_menu() {
+	let entry = |address :&str| ServerEntry::new(address.to_owned(), "alice".to_owned());
+	let mut list = ServerList::new();
+	list.add(entry("10.0.0.1:7700"));
+	list.add(entry("10.0.0.2:7700"));
+	let mut menu = ServerListMenu::new();
+
+	// Selecting a server connects to it
+	menu.select_next(&list);
+	match menu.activate(&mut list, None) {
+		ServerListEvent::Connect(e) => assert_eq!(e, entry("10.0.0.2:7700")),
+		_ => panic!("Expected to connect"),
+	}
+	menu.select_prev(&list);
+	match menu.activate(&mut list, None) {
+		ServerListEvent::Connect(e) => assert_eq!(e.address, "10.0.0.1:7700"),
+		_ => panic!("Expected to connect"),
+	}
+	// Going up from the first entry wraps around to the remove entry
+	menu.select_prev(&list);
+	assert!(menu.text(&list).ends_with("> Remove a server"));
+
+	// Removing the first server
+	assert!(matches!(menu.activate(&mut list, None), ServerListEvent::None));
+	assert!(menu.text(&list).starts_with("Select the server to remove"));
+	menu.select_next(&list);
+	assert!(matches!(menu.activate(&mut list, None), ServerListEvent::Changed));
+	assert_eq!(list.servers(), &[entry("10.0.0.2:7700")]);
+
+	// Adding the current server, if there is one
+	menu.select_next(&list);
+	assert!(matches!(menu.activate(&mut list, None), ServerListEvent::None));
+	let current = entry("10.0.0.3:7700");
+	assert!(matches!(menu.activate(&mut list, Some(&current)), ServerListEvent::Changed));
+	assert_eq!(list.servers().len(), 2);
+	assert_eq!(menu.text(&list), "Servers\n\n10.0.0.2:7700 (alice)\n\
+		10.0.0.3:7700 (alice)\n> Add this server\nRemove a server");
+}
+
+#[cfg(test)]
+#[test]
+fn test_ui_stack_escape() {
+	let mut stack = UiStack::new();
+	stack.push(Modal::Inventory);
+	stack.push(Modal::Chat);
+	assert_eq!(stack.top(), Some(Modal::Chat));
+
+	// Escape closes the modals from top to bottom
+	assert_eq!(stack.escape(), Some(Modal::Chat));
+	assert_eq!(stack.top(), Some(Modal::Inventory));
+	assert_eq!(stack.escape(), Some(Modal::Inventory));
+	assert!(stack.is_empty());
+
+	// Then it opens the pause menu, and closes it again
+	assert_eq!(stack.escape(), None);
+	assert_eq!(stack.top(), Some(Modal::PauseMenu));
+	assert_eq!(stack.escape(), Some(Modal::PauseMenu));
+	assert!(stack.is_empty());
+
+	// Pushing an open modal moves it to the top
+	stack.push(Modal::Chat);
+	stack.push(Modal::Inventory);
+	stack.push(Modal::Chat);
+	assert_eq!(stack.escape(), Some(Modal::Chat));
+	assert_eq!(stack.escape(), Some(Modal::Inventory));
+	assert!(stack.is_empty());
+}
+
 enum LayoutNodeKind {
 	Container {
 		children :Vec<LayoutNode>,
@@ -343,30 +964,118 @@ impl LayoutNode {
 const CRAFTING_ID :usize = 0;
 const CRAFTING_OUTPUT_ID :usize = 1;
 const NORMAL_INV_ID :usize = 2;
+const RECIPE_BOOK_ID :usize = 3;
+const RECIPE_INPUTS_ID :usize = 4;
+
+/// Number of recipes shown per line of the recipe book
+const RECIPE_BOOK_SLOTS_X :usize = 8;
+/// Number of lines of the recipe book visible at once
+const RECIPE_BOOK_LINES :usize = 2;
 
 const SPACER_ID :usize = 999;
 
+/// The slots the mouse passed over while a button was held
+///
+/// On release, the stack held at `from` is distributed over them:
+/// evenly for the left button, one item per slot for the right one.
+struct SlotDrag {
+	button :MouseButton,
+	from :(usize, usize),
+	slots :Vec<(usize, usize)>,
+}
+
+impl SlotDrag {
+	fn new(button :MouseButton, from :(usize, usize)) -> Self {
+		Self {
+			button,
+			from,
+			slots : Vec::new(),
+		}
+	}
+	/// Adds the slot if it can take items of the held stack
+	fn hover(&mut self, invs :&[SelectableInventory], slot :(usize, usize)) {
+		if slot == self.from || self.slots.contains(&slot) {
+			return;
+		}
+		let held = invs[self.from.0].stacks()[self.from.1].content();
+		let compatible = match invs[slot.0].stacks()[slot.1].content() {
+			None => true,
+			Some((item, _)) => held.map(|(h, _)| h) == Some(item),
+		};
+		if compatible {
+			self.slots.push(slot);
+		}
+	}
+	/// Distributes the stack, returning the action for the server
+	fn finish(self, invs :&mut [SelectableInventory],
+			loc :impl Fn((usize, usize)) -> InvSlot) -> InventoryAction {
+		let count = if self.button == MouseButton::Right {
+			Some(1)
+		} else {
+			None
+		};
+		SelectableInventory::distribute(invs, self.from, &self.slots, count);
+		let slots = self.slots.iter().map(|slot| loc(*slot)).collect();
+		InventoryAction::Distribute(loc(self.from), slots, count)
+	}
+}
+
 pub struct InventoryMenu {
 	params :GameParamsHdl,
-	invs :[SelectableInventory; 3],
+	/// The crafting grid, its output, the inventory, and the
+	/// recipe book with the inputs of the hovered recipe
+	invs :[SelectableInventory; 5],
+	craft_grid :CraftingGrid,
 	last_mouse_pos :Option<PhysicalPosition<f64>>,
 	mouse_input_ev :Option<(ElementState, MouseButton)>,
 	from_pos : Option<(usize, usize)>,
+	drag :Option<SlotDrag>,
+	/// The first visible line of the recipe book
+	recipe_scroll :usize,
+	recipe_hover :Option<usize>,
+	/// The changes done since the last `take_actions` call
+	actions :Vec<InventoryAction>,
+}
+
+/// Moves the stacks of the crafting grid into the inventory
+///
+/// What doesn't fit into the inventory stays in the grid.
+fn return_crafting_items(craft_inv :&mut SelectableInventory, inv :&mut SelectableInventory) {
+	for st in craft_inv.stacks_mut().iter_mut() {
+		if !st.is_empty() {
+			*st = inv.put(*st);
+		}
+	}
+}
+
+/// Whether the inventory with the id is part of the recipe book,
+/// whose slots can't be taken from or put into
+fn is_recipe_book(inv_id :usize) -> bool {
+	inv_id == RECIPE_BOOK_ID || inv_id == RECIPE_INPUTS_ID
 }
 
 impl InventoryMenu {
 	pub fn new(params :GameParamsHdl,
 			inv :SelectableInventory,
-			craft_inv :SelectableInventory) -> Self {
+			craft_inv :SelectableInventory, craft_grid :CraftingGrid) -> Self {
 		let output_inv = SelectableInventory::from_stacks(vec![Stack::Empty].into_boxed_slice());
-		let invs = [craft_inv, output_inv, inv];
-		Self {
+		let book_inv = SelectableInventory::empty_with_size(RECIPE_BOOK_SLOTS_X * RECIPE_BOOK_LINES);
+		let inputs_inv = SelectableInventory::crafting_inv(CraftingGrid::TABLE);
+		let invs = [craft_inv, output_inv, inv, book_inv, inputs_inv];
+		let mut res = Self {
 			params,
 			invs,
+			craft_grid,
 			last_mouse_pos : None,
 			mouse_input_ev : None,
 			from_pos : None,
-		}
+			drag : None,
+			recipe_scroll : 0,
+			recipe_hover : None,
+			actions : Vec::new(),
+		};
+		res.update_recipe_book();
+		res
 	}
 	pub fn inventory(&self) -> &SelectableInventory {
 		&self.invs[NORMAL_INV_ID]
@@ -374,14 +1083,85 @@ impl InventoryMenu {
 	pub fn craft_inv(&self) -> &SelectableInventory {
 		&self.invs[CRAFTING_ID]
 	}
+	pub fn craft_grid(&self) -> CraftingGrid {
+		self.craft_grid
+	}
+	/// The changes to send to the server since the last call
+	pub fn take_actions(&mut self) -> Vec<InventoryAction> {
+		take(&mut self.actions)
+	}
+	fn slot_location(craft_grid :CraftingGrid, (id, slot) :(usize, usize)) -> InvSlot {
+		if id == CRAFTING_ID {
+			(InvLocation::Craft(craft_grid), slot)
+		} else {
+			(InvLocation::Player, slot)
+		}
+	}
+	/// Returns the items in the crafting grid to the inventory, to be called on close
+	pub fn close(&mut self) {
+		let (craft_inv, rest) = self.invs.split_at_mut(NORMAL_INV_ID);
+		return_crafting_items(&mut craft_inv[CRAFTING_ID], &mut rest[0]);
+		self.update_craft_output_inv();
+		self.actions.push(InventoryAction::ReturnCrafting(self.craft_grid));
+	}
 	pub fn handle_mouse_moved(&mut self, pos :PhysicalPosition<f64>)  {
 		self.last_mouse_pos = Some(pos);
 	}
 	pub fn handle_mouse_input(&mut self, state :ElementState, button :MouseButton) {
 		self.mouse_input_ev = Some((state, button));
 	}
+	/// Scrolls the recipe book by the given number of lines
+	pub fn handle_scroll(&mut self, lines :f32) {
+		let line_count = (self.params.recipes.len() + RECIPE_BOOK_SLOTS_X - 1) / RECIPE_BOOK_SLOTS_X;
+		let max_scroll = line_count.saturating_sub(RECIPE_BOOK_LINES);
+		if lines > 0.0 {
+			self.recipe_scroll = self.recipe_scroll.saturating_sub(1);
+		} else if lines < 0.0 {
+			self.recipe_scroll = (self.recipe_scroll + 1).min(max_scroll);
+		}
+		self.update_recipe_book();
+	}
+	/// The index of the recipe shown at the slot of the recipe book
+	fn recipe_at(&self, slot :usize) -> Option<usize> {
+		let idx = self.recipe_scroll * RECIPE_BOOK_SLOTS_X + slot;
+		Some(idx).filter(|idx| *idx < self.params.recipes.len())
+	}
+	/// Shows the outputs of the visible recipes and the inputs of the hovered one
+	fn update_recipe_book(&mut self) {
+		let mut book = SelectableInventory::empty_with_size(RECIPE_BOOK_SLOTS_X * RECIPE_BOOK_LINES);
+		for slot in 0 .. book.stacks().len() {
+			if let Some(idx) = self.recipe_at(slot) {
+				book.stacks_mut()[slot] = self.params.recipes[idx].output;
+			}
+		}
+		let inputs_grid = CraftingGrid::TABLE;
+		let mut inputs = SelectableInventory::crafting_inv(inputs_grid);
+		if let Some(recipe) = self.recipe_hover.and_then(|idx| self.params.recipes.get(idx)) {
+			for (i, input) in recipe.inputs.iter().enumerate() {
+				let idx = (i / recipe.width) * inputs_grid.width + i % recipe.width;
+				if let (Some((input, count)), Some(st)) = (input, inputs.stacks_mut().get_mut(idx)) {
+					*st = Stack::with(*input, *count);
+				}
+			}
+		}
+		self.invs[RECIPE_BOOK_ID] = book;
+		self.invs[RECIPE_INPUTS_ID] = inputs;
+	}
+	/// Fills the crafting grid with the inputs of the recipe
+	///
+	/// Does nothing if the inventory lacks any of them.
+	fn select_recipe(&mut self, idx :usize) {
+		let mut craft_inv = self.invs[CRAFTING_ID].clone();
+		let mut inv = self.invs[NORMAL_INV_ID].clone();
+		if fill_grid(&self.params.recipes[idx], self.craft_grid, &mut craft_inv, &mut inv) {
+			self.invs[CRAFTING_ID] = craft_inv;
+			self.invs[NORMAL_INV_ID] = inv;
+			self.actions.push(InventoryAction::FillGrid(self.craft_grid, idx));
+		}
+	}
 	fn update_craft_output_inv(&mut self) {
-		let recipe = get_matching_recipe(&self.invs[CRAFTING_ID], &self.params);
+		let recipe = get_matching_recipe(&self.invs[CRAFTING_ID],
+			self.craft_grid, &self.params);
 		let stack = recipe
 			.map(|r| r.output)
 			.unwrap_or(Stack::Empty);
@@ -398,12 +1178,13 @@ impl InventoryMenu {
 		let unit = unit_from_screen_dims(screen_dims.0);
 
 		const SLOT_COUNT_X :usize = 8;
-		const CRAFT_SLOT_COUNT_X :usize = 3;
 
 		let slot_counts_x :&[usize] = &[
-			CRAFT_SLOT_COUNT_X,
+			self.craft_grid.width,
 			1,
 			SLOT_COUNT_X,
+			RECIPE_BOOK_SLOTS_X,
+			CraftingGrid::TABLE.width,
 		];
 
 		macro_rules! inv {
@@ -425,6 +1206,15 @@ impl InventoryMenu {
 				}),
 				LayoutNode::spacer(SPACER_ID, (0.1 * unit * 1.1, 0.1 * unit * 1.1)),
 				inv!(NORMAL_INV_ID),
+				LayoutNode::spacer(SPACER_ID, (0.1 * unit * 1.1, 0.1 * unit * 1.1)),
+				LayoutNode::from_kind(LayoutNodeKind::Container {
+					horizontal : true,
+					children : vec![
+						inv!(RECIPE_BOOK_ID),
+						LayoutNode::spacer(SPACER_ID, (0.1 * unit * 1.1, 0.1 * unit * 1.1)),
+						inv!(RECIPE_INPUTS_ID),
+					],
+				}),
 			],
 		});
 		let mouse_pos = self.last_mouse_pos.map(|pos|(pos.x as f32, pos.y as f32));
@@ -435,10 +1225,58 @@ impl InventoryMenu {
 
 		let mut swap_command = None;
 
+		// TODO this is hacky, we change state in RENDERING code!!
+		let recipe_hover = match hover_idx {
+			Some((RECIPE_BOOK_ID, slot)) => self.recipe_at(slot),
+			_ => None,
+		};
+		if recipe_hover != self.recipe_hover {
+			self.recipe_hover = recipe_hover;
+			self.update_recipe_book();
+		}
+
 		// TODO this is hacky, we change state in RENDERING code!!
 		let input_ev = self.mouse_input_ev.take();
+		if let (Some((ElementState::Released, MouseButton::Left)), Some(recipe), None) =
+				(input_ev, recipe_hover, self.from_pos) {
+			// Clicking a recipe fills the crafting grid
+			self.select_recipe(recipe);
+		}
+		// The recipe book can't be taken from or put into
+		let hover_idx = hover_idx.filter(|hv| !is_recipe_book(hv.0));
+		if let (Some(drag), Some(hv)) = (self.drag.as_mut(), hover_idx) {
+			if hv.0 != CRAFTING_OUTPUT_ID {
+				drag.hover(&self.invs, hv);
+			}
+		}
+		// Releasing after a drag over multiple slots distributes
+		// the held stack, otherwise it counts as a click.
+		let mut drag_finished = false;
+		if let Some((ElementState::Released, button)) = input_ev {
+			if let Some(drag) = self.drag.take() {
+				if drag.button == button && drag.slots.len() > 1 {
+					let grid = self.craft_grid;
+					let action = drag.finish(&mut self.invs,
+						|slot| Self::slot_location(grid, slot));
+					self.actions.push(action);
+					if button == MouseButton::Left {
+						self.from_pos = None;
+					}
+					drag_finished = true;
+				}
+			}
+		}
 		// TODO this is hacky, we change state in RENDERING code!!
-		if let (Some((state, button)), Some(hv)) = (input_ev, hover_idx) {
+		if let (Some((state, button)), Some(hv), false) = (input_ev, hover_idx, drag_finished) {
+			if state == ElementState::Pressed {
+				if let Some(from_pos) = self.from_pos {
+					if hv.0 != CRAFTING_OUTPUT_ID {
+						let mut drag = SlotDrag::new(button, from_pos);
+						drag.hover(&self.invs, hv);
+						self.drag = Some(drag);
+					}
+				}
+			}
 			if state == ElementState::Released {
 				if let Some(from_pos) = self.from_pos {
 					if button == MouseButton::Left {
@@ -450,12 +1288,16 @@ impl InventoryMenu {
 						// If we click onto the crafting output menu,
 						// add the output to the inventory immediately.
 						// Only do something if there is something to craft
-						if self.invs[CRAFTING_OUTPUT_ID].stacks()[0] != Stack::Empty {
-							// TODO figure out something for the remainder stack
-							self.invs[NORMAL_INV_ID].put(self.invs[CRAFTING_OUTPUT_ID].stacks()[0]);
-							// Reduce inputs.
-							for st in self.invs[CRAFTING_ID].stacks_mut().iter_mut() {
-								st.take_n(1);
+						// and the output fits into the inventory entirely.
+						let recipe = get_matching_recipe(&self.invs[CRAFTING_ID],
+							self.craft_grid, &self.params);
+						let mut inv = self.invs[NORMAL_INV_ID].clone();
+						if let Some(recipe) = recipe {
+							if inv.put(recipe.output).is_empty() {
+								self.invs[NORMAL_INV_ID] = inv;
+								// Reduce inputs by what the recipe needs
+								recipe.consume_inputs(&mut self.invs[CRAFTING_ID], self.craft_grid);
+								self.actions.push(InventoryAction::Craft(self.craft_grid));
 							}
 						}
 					} else {
@@ -470,15 +1312,19 @@ impl InventoryMenu {
 			if to_pos.0 == CRAFTING_OUTPUT_ID {
 				// Putting into the crafting menu is not possible
 			} else {
+				let from = Self::slot_location(self.craft_grid, from_pos);
+				let to = Self::slot_location(self.craft_grid, to_pos);
 				if button == MouseButton::Left {
 					SelectableInventory::merge_or_swap(
 						&mut self.invs,
 						from_pos, to_pos);
+					self.actions.push(InventoryAction::MergeOrSwap(from, to));
 				}
 				if button == MouseButton::Right {
 					SelectableInventory::move_n_if_possible(
 						&mut self.invs,
 						from_pos, to_pos, 1);
+					self.actions.push(InventoryAction::MoveN(from, to, 1));
 				}
 			}
 		}
@@ -488,13 +1334,21 @@ impl InventoryMenu {
 	}
 }
 
+/// The maximum time between the two clicks of a double click
+const DOUBLE_CLICK_TIME :Duration = Duration::from_millis(400);
+
 pub struct ChestMenu {
 	params :GameParamsHdl,
 	invs :[SelectableInventory; 2],
 	chest_pos :Vector3<isize>,
 	last_mouse_pos :Option<PhysicalPosition<f64>>,
 	mouse_input_ev :Option<(ElementState, MouseButton)>,
+	shift_pressed :bool,
+	/// The time and slot of the last left click, to detect double clicks
+	last_click :Option<(Instant, (usize, usize))>,
 	from_pos : Option<(usize, usize)>,
+	/// The changes done since the last `take_actions` call
+	actions :Vec<InventoryAction>,
 }
 
 impl ChestMenu {
@@ -509,12 +1363,46 @@ impl ChestMenu {
 			chest_pos,
 			last_mouse_pos : None,
 			mouse_input_ev : None,
+			shift_pressed : false,
+			last_click : None,
 			from_pos : None,
+			actions : Vec::new(),
 		}
 	}
 	pub fn inventory(&self) -> &SelectableInventory {
 		&self.invs[CRAFTING_OUTPUT_ID]
 	}
+	/// The changes to send to the server since the last call
+	pub fn take_actions(&mut self) -> Vec<InventoryAction> {
+		take(&mut self.actions)
+	}
+	fn slot_location(&self, (id, slot) :(usize, usize)) -> InvSlot {
+		if id == CRAFTING_ID {
+			(InvLocation::Chest(self.chest_pos), slot)
+		} else {
+			(InvLocation::Player, slot)
+		}
+	}
+	/// Moves everything from the chest into the player inventory, as far as it fits
+	pub fn take_all(&mut self) -> bool {
+		let (chest, inv) = self.invs.split_at_mut(CRAFTING_OUTPUT_ID);
+		let moved = chest[0].move_all(&mut inv[0]);
+		if moved {
+			let chest = InvLocation::Chest(self.chest_pos);
+			self.actions.push(InventoryAction::MoveAll(chest, InvLocation::Player));
+		}
+		moved
+	}
+	/// Moves everything from the player inventory into the chest, as far as it fits
+	pub fn deposit_all(&mut self) -> bool {
+		let (chest, inv) = self.invs.split_at_mut(CRAFTING_OUTPUT_ID);
+		let moved = inv[0].move_all(&mut chest[0]);
+		if moved {
+			let chest = InvLocation::Chest(self.chest_pos);
+			self.actions.push(InventoryAction::MoveAll(InvLocation::Player, chest));
+		}
+		moved
+	}
 	pub fn chest_inv(&self) -> &SelectableInventory {
 		&self.invs[CRAFTING_ID]
 	}
@@ -524,8 +1412,10 @@ impl ChestMenu {
 	pub fn handle_mouse_moved(&mut self, pos :PhysicalPosition<f64>)  {
 		self.last_mouse_pos = Some(pos);
 	}
-	pub fn handle_mouse_input(&mut self, state :ElementState, button :MouseButton) {
+	pub fn handle_mouse_input(&mut self, state :ElementState, button :MouseButton,
+			shift_pressed :bool) {
 		self.mouse_input_ev = Some((state, button));
+		self.shift_pressed = shift_pressed;
 	}
 	pub fn render<'a, 'b>(&mut self,
 			ui_colors :&UiColors,
@@ -566,7 +1456,25 @@ impl ChestMenu {
 		let mut swap_command = None;
 
 		// TODO this is hacky, we change state in RENDERING code!!
-		let input_ev = self.mouse_input_ev.take();
+		let mut input_ev = self.mouse_input_ev.take();
+		// Shift double clicking a slot moves everything to the other inventory
+		if let (Some((ElementState::Released, MouseButton::Left)), Some(hv)) = (input_ev, hover_idx) {
+			let now = Instant::now();
+			let double_click = self.last_click
+				.map(|(time, slot)| slot == hv && now - time <= DOUBLE_CLICK_TIME)
+				.unwrap_or(false);
+			self.last_click = Some((now, hv));
+			if double_click && self.shift_pressed {
+				if hv.0 == CRAFTING_ID {
+					self.take_all();
+				} else {
+					self.deposit_all();
+				}
+				self.from_pos = None;
+				self.last_click = None;
+				input_ev = None;
+			}
+		}
 		// TODO this is hacky, we change state in RENDERING code!!
 		if let (Some((state, button)), Some(hv)) = (input_ev, hover_idx) {
 			if state == ElementState::Released {
@@ -583,15 +1491,19 @@ impl ChestMenu {
 
 		// TODO this is hacky, we change state in RENDERING code!!
 		if let Some((from_pos, to_pos, button)) = swap_command {
+			let from = self.slot_location(from_pos);
+			let to = self.slot_location(to_pos);
 			if button == MouseButton::Left {
 				SelectableInventory::merge_or_swap(
 					&mut self.invs,
 					from_pos, to_pos);
+				self.actions.push(InventoryAction::MergeOrSwap(from, to));
 			}
 			if button == MouseButton::Right {
 				SelectableInventory::move_n_if_possible(
 					&mut self.invs,
 					from_pos, to_pos, 1);
+				self.actions.push(InventoryAction::MoveN(from, to, 1));
 			}
 		}
 	}
@@ -707,8 +1619,7 @@ fn inventory_slots_mesh<'a, 'b>(inv :&SelectableInventory,
 		let col = i % slot_count_x;
 		let line = i / slot_count_x;
 		let dims = (unit as i32, unit as i32);
-		let mesh_x = offsets.0 as i32 +
-			(-ui_width / 2.0 + (unit * 1.1 * col as f32) + unit * 0.1) as i32;
+		let mesh_x = slot_mesh_x(offsets.0, ui_width, unit, col);
 		let mesh_y = -offsets.1 as i32 + mesh_y_fn(line);
 		let tx = texture_fn(i, mesh_x, mesh_y);
 		vertices.extend_from_slice(&square_mesh_xy(mesh_x, mesh_y,
@@ -716,8 +1627,12 @@ fn inventory_slots_mesh<'a, 'b>(inv :&SelectableInventory,
 		let content = inv.stacks()
 			.get(i)
 			.unwrap_or(&Stack::Empty);
-		let text = if let Stack::Content { item, count } = content {
-			format!("{} ({})", params.block_display_name(*item), count)
+		let text = if let Stack::Content { item, count, durability } = content {
+			if let Some((cur, max)) = durability {
+				format!("{} [{}/{}]", params.block_display_name(*item), cur, max)
+			} else {
+				format!("{} ({})", params.block_display_name(*item), count)
+			}
 		} else {
 			String::from("")
 		};
@@ -738,7 +1653,52 @@ fn inventory_slots_mesh<'a, 'b>(inv :&SelectableInventory,
 	vertices
 }
 
+/// The x coordinate of the left edge of the slot in the given column
+fn slot_mesh_x(offset_x :f32, ui_width :f32, unit :f32, col :usize) -> i32 {
+	offset_x as i32 + (-ui_width / 2.0 + (unit * 1.1 * col as f32) + unit * 0.1) as i32
+}
+
+/// The unit, width and height of the hud with the given number of slots
+fn hud_dims(screen_dims :(u32, u32), slot_count :usize) -> (f32, f32, f32) {
+	let unit = unit_from_screen_dims(screen_dims.0);
+	let hud_width = slot_count as f32 * unit * 1.10 + 0.1 * unit;
+	let hud_height = unit * 1.10;
+	(unit, hud_width, hud_height)
+}
+
+#[cfg(test)]
+#[test]
+fn test_hud_dims() {
+	let screen_dims = (1500, 900);
+	let (unit, width, height) = hud_dims(screen_dims, 8);
+	assert_eq!(unit, 128.0);
+	assert!((width - 1139.2).abs() < 0.01, "{}", width);
+	assert!((height - 140.8).abs() < 0.01, "{}", height);
+
+	// Each additional slot widens the hud by a slot and its spacing
+	let (_, width_5, _) = hud_dims(screen_dims, 5);
+	let (_, width_10, _) = hud_dims(screen_dims, 10);
+	assert!((width_10 - width_5 - 5.0 * 1.1 * unit).abs() < 0.01);
+
+	// The slots are spaced evenly and centered in the hud
+	let xs = (0 .. 10)
+		.map(|col| slot_mesh_x(0.0, width_10, unit, col))
+		.collect::<Vec<_>>();
+	assert_eq!(xs[0], (-width_10 / 2.0 + 0.1 * unit) as i32);
+	for w in xs.windows(2) {
+		assert!((w[1] - w[0] - (1.1 * unit) as i32).abs() <= 1, "{:?}", xs);
+	}
+	let right_margin = width_10 / 2.0 - (xs[9] as f32 + unit);
+	assert!((right_margin - 0.1 * unit).abs() <= 1.0, "{}", right_margin);
+
+	// Small screens scale the unit down
+	let (unit, width, _) = hud_dims((600, 400), 9);
+	assert_eq!(unit, 80.0);
+	assert!((width - (9.0 * 88.0 + 8.0)).abs() < 0.01, "{}", width);
+}
+
 pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
+		slot_count :usize,
 		ui_colors :&UiColors,
 		display :&glium::Display, program :&glium::Program,
 		glyph_brush :&mut GlyphBrush<'a, 'b>, gm_params :&GameParamsHdl,
@@ -746,12 +1706,7 @@ pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
 
 	let screen_dims = display.get_framebuffer_dimensions();
 
-	let unit = unit_from_screen_dims(screen_dims.0);
-
-	const SLOT_COUNT_F32 :f32 = HUD_SLOT_COUNT as f32;
-
-	let hud_width = SLOT_COUNT_F32 * unit * 1.10 + 0.1 * unit;
-	let hud_height = unit * 1.10;
+	let (unit, hud_width, hud_height) = hud_dims(screen_dims, slot_count);
 
 	let mut vertices = Vec::new();
 
@@ -766,8 +1721,8 @@ pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
 	// Item slots
 	vertices.extend_from_slice(&inventory_slots_mesh(
 		inv,
-		HUD_SLOT_COUNT,
-		HUD_SLOT_COUNT,
+		slot_count,
+		slot_count,
 		unit,
 		(0.0, screen_dims.1 as f32),
 		hud_width,
@@ -793,13 +1748,92 @@ pub fn render_inventory_hud<'a, 'b>(inv :&SelectableInventory,
 	glyph_brush.draw_queued(display, target);
 }
 
+/// Renders the hunger bar above the inventory hud
+///
+/// The fraction is the filling of the bar, between 0 and 1.
+pub fn render_hunger_bar(fraction :f32, hud_slot_count :usize, ui_colors :&UiColors,
+		display :&glium::Display, program :&glium::Program,
+		target :&mut glium::Frame) {
+
+	let screen_dims = display.get_framebuffer_dimensions();
+
+	let (unit, hud_width, hud_height) = hud_dims(screen_dims, hud_slot_count);
+	let bar_height = unit * 0.15;
+
+	let mut vertices = Vec::new();
+
+	let mesh_x = -(hud_width / 2.0) as i32;
+	let mesh_y = -(screen_dims.1 as i32) + (hud_height * 1.20) as i32;
+
+	// Background
+	let dims = (hud_width as i32, bar_height as i32);
+	vertices.extend_from_slice(&square_mesh_xy(mesh_x, mesh_y,
+		dims, screen_dims, ui_colors.background_color));
+
+	// Filling
+	let fraction = fraction.clamp(0.0, 1.0);
+	let dims = ((hud_width * fraction) as i32, bar_height as i32);
+	vertices.extend_from_slice(&square_mesh_xy(mesh_x, mesh_y,
+		dims, screen_dims, ui_colors.hunger_color));
+
+	draw_ui_vertices(&vertices, display, program, target);
+}
+
+/// The vertices of the crosshair, centered on the screen
+///
+/// The pieces of the cross don't overlap, as an
+/// inverting blend would undo itself where they do.
+pub fn crosshair_mesh(style :CrosshairStyle, screen_dims :(u32, u32),
+		tx :TextureId) -> Vec<Vertex> {
+	match style {
+		CrosshairStyle::Dot => square_mesh((2, 2), screen_dims, tx),
+		CrosshairStyle::Cross => {
+			let mut vertices = square_mesh((20, 2), screen_dims, tx);
+			let x = 2.0 / screen_dims.0 as f32;
+			let y_inner = 2.0 / screen_dims.1 as f32;
+			let y_outer = 20.0 / screen_dims.1 as f32;
+			vertices.extend_from_slice(&square_mesh_frac_limits(-x, y_inner, x, y_outer, tx));
+			vertices.extend_from_slice(&square_mesh_frac_limits(-x, -y_outer, x, -y_inner, tx));
+			vertices
+		},
+	}
+}
+
+/// Renders the crosshair in the center of the screen
+pub fn render_crosshair(style :CrosshairStyle, inverted :bool, ui_colors :&UiColors,
+		display :&glium::Display, program :&glium::Program,
+		target :&mut glium::Frame) {
+	let screen_dims = display.get_framebuffer_dimensions();
+	let vertices = crosshair_mesh(style, screen_dims, ui_colors.crosshair_color);
+	let blend = if inverted {
+		// Mixes the color and its inverse, weighted by the color behind
+		glium::Blend {
+			color : BlendingFunction::Addition {
+				source : LinearBlendingFactor::OneMinusDestinationColor,
+				destination : LinearBlendingFactor::OneMinusSourceColor,
+			},
+			.. glium::Blend::alpha_blending()
+		}
+	} else {
+		glium::Blend::alpha_blending()
+	};
+	draw_ui_vertices_blend(&vertices, blend, display, program, target);
+}
+
 fn draw_ui_vertices<'a, 'b>(vertices :&[Vertex],
 		display :&glium::Display, program :&glium::Program,
 		target :&mut glium::Frame) {
+	draw_ui_vertices_blend(vertices, glium::Blend::alpha_blending(),
+		display, program, target);
+}
+
+fn draw_ui_vertices_blend(vertices :&[Vertex], blend :glium::Blend,
+		display :&glium::Display, program :&glium::Program,
+		target :&mut glium::Frame) {
 	let uniforms = uniform! {
 		vmatrix : IDENTITY,
 		pmatrix : IDENTITY,
-		fog_near_far : [40.0f32, 60.0]
+		fog_near_far : NO_FOG
 	};
 	let params = glium::draw_parameters::DrawParameters {
 		/*depth : glium::Depth {
@@ -808,7 +1842,7 @@ fn draw_ui_vertices<'a, 'b>(vertices :&[Vertex],
 			.. Default::default()
 		},
 		backface_culling : glium::draw_parameters::BackfaceCullingMode::CullCounterClockwise,*/
-		blend :glium::Blend::alpha_blending(),
+		blend,
 		//polygon_mode : glium::draw_parameters::PolygonMode::Line,
 		.. Default::default()
 	};
@@ -890,3 +1924,42 @@ pub fn square_mesh_frac_limits(
 	});
 	vertices
 }
+
+#[cfg(test)]
+#[test]
+fn test_crosshair_mesh() {
+	let tx = TextureId::new(0, 0);
+	for &screen_dims in &[(1024, 768), (1920, 1080)] {
+		for &style in &[CrosshairStyle::Cross, CrosshairStyle::Dot] {
+			let vertices = crosshair_mesh(style, screen_dims, tx);
+			assert_eq!(vertices.len() % 6, 0);
+			let extent = |i :usize| vertices.iter()
+				.fold((f32::MAX, f32::MIN), |(min, max), v| {
+					(min.min(v.position[i]), max.max(v.position[i]))
+				});
+			let (x_min, x_max) = extent(0);
+			let (y_min, y_max) = extent(1);
+			// Centered on the screen
+			assert!((x_min + x_max).abs() < 1e-6, "{:?} {:?}", style, screen_dims);
+			assert!((y_min + y_max).abs() < 1e-6, "{:?} {:?}", style, screen_dims);
+			// Of the same size in pixels on all screens
+			let size = match style {
+				CrosshairStyle::Cross => 20.0,
+				CrosshairStyle::Dot => 2.0,
+			};
+			assert!((x_max * screen_dims.0 as f32 - size).abs() < 1e-3);
+			assert!((y_max * screen_dims.1 as f32 - size).abs() < 1e-3);
+		}
+	}
+	// The pieces of the cross don't overlap in the center
+	let vertices = crosshair_mesh(CrosshairStyle::Cross, (1000, 1000), tx);
+	let covering_center = vertices.chunks(6)
+		.filter(|quad| {
+			let xs = quad.iter().map(|v| v.position[0]);
+			let ys = quad.iter().map(|v| v.position[1]);
+			xs.clone().fold(f32::MAX, f32::min) < 0.0 && xs.fold(f32::MIN, f32::max) > 0.0 &&
+				ys.clone().fold(f32::MAX, f32::min) < 0.0 && ys.fold(f32::MIN, f32::max) > 0.0
+		})
+		.count();
+	assert_eq!(covering_center, 1);
+}
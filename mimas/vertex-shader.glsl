@@ -8,6 +8,7 @@ out float vlamb;
 out vec2 vtex_pos;
 flat out uint vtex_ind;
 out vec4 vposition;
+out vec3 vnormal;
 
 uniform mat4 pmatrix;
 uniform mat4 vmatrix;
@@ -19,6 +20,7 @@ void main() {
 	vposition = vmatrix * vec4(position, 1.0);
 	gl_Position = pmatrix * vposition;
 	vec3 nnormal = normalize(normal);
+	vnormal = nnormal;
 
 	// Lambertian shading
 	vlamb = max(dot(dir_light_a, nnormal), 0.2) +
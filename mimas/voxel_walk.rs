@@ -5,6 +5,8 @@ pub struct VoxelWalker {
 	start :Vector3<f32>,
 	pos :Vector3<f32>,
 	direction :Vector3<f32>,
+	/// The distance from the start after which the walk ends
+	max_range :f32,
 }
 
 fn fmin(a: f32, b :f32) -> f32 {
@@ -16,12 +18,13 @@ fn fmin(a: f32, b :f32) -> f32 {
 }
 
 impl VoxelWalker {
-	pub fn new(start :Vector3<f32>, direction :Vector3<f32>) -> Self {
+	pub fn new(start :Vector3<f32>, direction :Vector3<f32>, max_range :f32) -> Self {
 		VoxelWalker {
 			first : true,
 			start,
 			pos : start,
 			direction,
+			max_range,
 		}
 	}
 	fn peek_next(&self) -> Vector3<f32> {
@@ -98,8 +101,7 @@ impl Iterator for VoxelWalker {
 			return Some((self.pos, self.pos));
 		}
 		let next_pos = self.peek_next();
-		const SELECTION_RANGE :f32 = 10.0;
-		if (next_pos - self.start).norm() < SELECTION_RANGE {
+		if (next_pos - self.start).norm() < self.max_range {
 			let old_pos = self.pos;
 			self.pos = next_pos;
 			Some((old_pos, self.pos))
@@ -108,3 +110,20 @@ impl Iterator for VoxelWalker {
 		}
 	}
 }
+
+#[cfg(test)]
+#[test]
+fn test_voxel_walker_reach() {
+	let start = Vector3::new(0.5, 0.5, 0.5);
+	let direction = Vector3::new(1.0, 0.0, 0.0);
+	let hit = |reach :f32| VoxelWalker::new(start, direction, reach)
+		.map(|(_vs, ve)| ve.map(|v| v.floor() as isize))
+		.find(|ve| ve.x == 7);
+	// No hit is returned beyond the reach
+	assert_eq!(hit(5.0), None);
+	assert_eq!(hit(10.0), Some(Vector3::new(7, 0, 0)));
+	for reach in [3.0, 5.0, 10.0].iter() {
+		assert!(VoxelWalker::new(start, direction, *reach)
+			.all(|(_vs, ve)| (ve - start).norm() < *reach));
+	}
+}